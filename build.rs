@@ -0,0 +1,16 @@
+use std::process::Command;
+
+fn main() {
+    // so `sbsevery --version` can say exactly what it was built from
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map_or_else(|| String::from("unknown"), |s| s.trim().to_string());
+
+    println!("cargo:rustc-env=SBSEVERY_GIT_COMMIT={commit}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,97 @@
+//! Finding out before anything is signed that the files can't be written, so a run started
+//! without root on an ESP only root may write says so once, rather than failing on every file
+//! with whatever the signer makes of `EACCES`.
+//!
+//! The first file found is checked, and the directory it is in, where signed copies are
+//! written before they are renamed over it. A permission denied or a read-only filesystem
+//! fails the run with [`Denied`]; anything else, an immutable file say, is left to signing,
+//! which handles it file by file.
+
+use std::{error::Error, fmt, path::Path};
+
+#[cfg(unix)]
+use crate::escape;
+
+/// What `main` exits with when the files can't be written, `EX_NOPERM`.
+pub const EXIT_NOPERM: i32 = 77;
+
+/// The files found can't be written, and what to do about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denied(pub String);
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for Denied {}
+
+/// Check `file` and its directory can be written by this process.
+///
+/// # Errors
+///
+/// Fails with [`Denied`] saying what to fix if either can't be, for want of permissions or
+/// because the filesystem is read-only.
+#[cfg(unix)]
+pub fn check(file: &Path) -> Result<(), Denied> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    for path in [file, dir] {
+        let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+            continue;
+        };
+        // asking rather than opening it for writing, which inotify would take for a write
+        // SAFETY: c_path is a valid string for the duration of the call
+        let got = unsafe {
+            libc::faccessat(
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+                libc::W_OK,
+                libc::AT_EACCESS,
+            )
+        };
+        if got == 0 {
+            continue;
+        }
+        match std::io::Error::last_os_error().raw_os_error() {
+            Some(libc::EACCES) => return Err(Denied(denied(path))),
+            Some(libc::EROFS) => {
+                return Err(Denied(format!(
+                    "{} is on a read-only filesystem, remount it read-write to sign anything \
+                     on it",
+                    escape::path(path)
+                )))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Can't tell without trying elsewhere, signing says.
+#[cfg(not(unix))]
+pub fn check(_file: &Path) -> Result<(), Denied> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn denied(path: &Path) -> String {
+    // SAFETY: geteuid can't fail
+    if unsafe { libc::geteuid() } == 0 {
+        format!(
+            "can't write {} even as root, fix its permissions or see what SELinux or AppArmor \
+             keep root from",
+            escape::path(path)
+        )
+    } else {
+        format!(
+            "can't write {}: permission denied, re-run as root or fix its permissions",
+            escape::path(path)
+        )
+    }
+}
@@ -0,0 +1,315 @@
+//! `sbsevery agent`, keeps decrypted keys in memory for a while, the way gpg-agent keeps
+//! passphrases, so runs started by every kernel update don't ask for the passphrase each time:
+//!
+//! ```text
+//! sbsevery agent --ttl 8h &
+//! sbsevery /efi -k /etc/efi-keys/DB.key -c /etc/efi-keys/DB.crt --askpass /usr/lib/ssh/ssh-askpass --agent /run/sbsevery/agent
+//! ```
+//!
+//! A run given `--agent` asks the agent for the key before asking for the passphrase. If the
+//! agent doesn't have it, the run asks for the passphrase as usual and hands the agent the key
+//! it decrypted, for the runs after it. Keys are known by the SHA-256 of the encrypted key
+//! file, a key file changed is a key the agent doesn't have. Each is wiped `--ttl` after it was
+//! handed over, `sbsevery agent --forget` wipes them all now.
+//!
+//! The agent can't be traced or dumped and keeps its memory out of swap where it is allowed
+//! to. Only processes of its own user may use the socket, and they only talk to an agent run by
+//! the same user or root. Keys for PKCS#11 tokens aren't
+//! decrypted by sbsevery and aren't kept.
+//!
+//! Clients send a line, and the agent answers with one:
+//!
+//! ```text
+//! GET <sha256>\n                 KEY <length>\n<key> or NONE\n
+//! PUT <sha256> <length>\n<key>   OK\n
+//! FORGET\n                       OK\n
+//! ```
+//!
+//! or `ERR <message>\n`. The key goes in and out as it is, never buffered where it wouldn't be
+//! wiped.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    os::{
+        fd::AsRawFd,
+        unix::{
+            fs::{DirBuilderExt, FileTypeExt},
+            net::UnixStream,
+        },
+    },
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    escape,
+    secret::Secret,
+    state::{hex, sha256_file},
+};
+
+/// Where the agent listens unless told otherwise.
+pub const SOCKET: &str = "/run/sbsevery/agent";
+
+/// The longest key taken, a PEM key is a few kilobytes.
+const MAX_KEY: usize = 1 << 20;
+
+/// The longest line of a request or answer, without the key.
+const MAX_LINE: usize = 256;
+
+/// Keys, by the hash of the key file, and when they are wiped.
+type Keys = Mutex<HashMap<String, (Secret, Instant)>>;
+
+/// Listen on `path` and keep the keys handed over for `ttl`, until the process is killed. A
+/// socket left at `path` by an agent before is replaced.
+///
+/// # Errors
+///
+/// Fails if the socket can't be made.
+pub fn serve(path: &Path, ttl: Duration) -> io::Result<()> {
+    let context = |e: io::Error| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path)));
+    harden();
+    if path
+        .symlink_metadata()
+        .is_ok_and(|meta| meta.file_type().is_socket())
+    {
+        std::fs::remove_file(path).map_err(context)?;
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(context)?;
+    }
+    let listener = crate::socket::bind(path).map_err(context)?;
+
+    let keys: Arc<Keys> = Arc::default();
+    let wiping = Arc::clone(&keys);
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        if let Ok(mut keys) = wiping.lock() {
+            let now = Instant::now();
+            keys.retain(|_, (_, until)| *until > now);
+        }
+    });
+
+    for stream in listener.incoming() {
+        let stream = stream.map_err(context)?;
+        let keys = Arc::clone(&keys);
+        thread::spawn(move || {
+            if let Err(e) = connection(stream, &keys, ttl) {
+                eprintln!("agent: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Keep the keys out of core dumps, debuggers and swap, as far as the agent is allowed.
+fn harden() {
+    // SAFETY: no pointers involved, and failing leaves things as they were
+    unsafe {
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+        libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE);
+    }
+}
+
+/// Answer the request of one client.
+fn connection(mut stream: UnixStream, keys: &Keys, ttl: Duration) -> io::Result<()> {
+    let answer = match peer_uid(&stream) {
+        // SAFETY: geteuid can't fail
+        Ok(uid) if uid == unsafe { libc::geteuid() } => answer(&mut stream, keys, ttl),
+        Ok(uid) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("uid {uid} isn't allowed"),
+        )),
+        Err(e) => Err(e),
+    };
+    match answer {
+        Ok(answer) => stream.write_all(answer.as_bytes()),
+        Err(e) => {
+            stream.write_all(format!("ERR {e}\n").as_bytes())?;
+            Err(e)
+        }
+    }
+}
+
+/// Do what the client on `stream` asks, and the answer, with the key if it asked for one.
+fn answer(stream: &mut UnixStream, keys: &Keys, ttl: Duration) -> io::Result<Secret> {
+    let line = read_line(stream)?;
+    let lock = || {
+        keys.lock()
+            .map_err(|_| io::Error::from(io::ErrorKind::Other))
+    };
+    match line.split(' ').collect::<Vec<_>>()[..] {
+        ["GET", hash] => Ok(match lock()?.get(hash) {
+            Some((key, until)) if *until > Instant::now() => {
+                let key = key.as_bytes();
+                let mut answer = format!("KEY {}\n", key.len()).into_bytes();
+                answer.reserve_exact(key.len());
+                answer.extend_from_slice(key);
+                Secret::new(answer)
+            }
+            _ => Secret::new(b"NONE\n".to_vec()),
+        }),
+        ["PUT", hash, len] => {
+            let key = read_key(stream, len)?;
+            lock()?.insert(hash.to_string(), (key, Instant::now() + ttl));
+            Ok(Secret::new(b"OK\n".to_vec()))
+        }
+        ["FORGET"] => {
+            lock()?.clear();
+            Ok(Secret::new(b"OK\n".to_vec()))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a request, is this sbsevery's agent?",
+        )),
+    }
+}
+
+/// The uid of the process at the other end of `stream`.
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    #[allow(clippy::cast_possible_truncation)]
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred is writable for len bytes
+    let got = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            std::ptr::addr_of_mut!(cred).cast(),
+            std::ptr::addr_of_mut!(len),
+        )
+    };
+    if got < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/// Read up to a newline a byte at a time, leaving what comes after it unread.
+fn read_line(stream: &mut UnixStream) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    loop {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        if line.len() == MAX_LINE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+        }
+        line.push(byte[0]);
+    }
+    String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Read a key of `len` bytes straight into memory that is wiped.
+fn read_key(stream: &mut UnixStream, len: &str) -> io::Result<Secret> {
+    let len = len
+        .parse::<usize>()
+        .ok()
+        .filter(|&len| len <= MAX_KEY)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad key length"))?;
+    let mut key = Secret::new(vec![0; len]);
+    stream.read_exact(key.as_mut_bytes())?;
+    Ok(key)
+}
+
+/// Ask the agent on `socket` for the decrypted `key`, `None` if it doesn't have it.
+///
+/// # Errors
+///
+/// Fails if there is no agent on the socket or it won't say.
+pub fn fetch(socket: &Path, key: &Path) -> io::Result<Option<Secret>> {
+    let mut stream = connect(socket)?;
+    stream.write_all(format!("GET {}\n", fingerprint(key)?).as_bytes())?;
+    let line = read_line(&mut stream)?;
+    match line.split_once(' ') {
+        Some(("KEY", len)) => read_key(&mut stream, len).map(Some),
+        _ if line == "NONE" => Ok(None),
+        _ => Err(refused(&line)),
+    }
+}
+
+/// Hand the agent on `socket` the decrypted `key`, for runs after this one.
+///
+/// # Errors
+///
+/// Fails if there is no agent on the socket or it won't take the key.
+pub fn store(socket: &Path, key: &Path, decrypted: &Secret) -> io::Result<()> {
+    let mut stream = connect(socket)?;
+    let bytes = decrypted.as_bytes();
+    stream.write_all(format!("PUT {} {}\n", fingerprint(key)?, bytes.len()).as_bytes())?;
+    stream.write_all(bytes)?;
+    ok(&mut stream)
+}
+
+/// Have the agent on `socket` wipe every key it has.
+///
+/// # Errors
+///
+/// Fails if there is no agent on the socket.
+pub fn forget(socket: &Path) -> io::Result<()> {
+    let mut stream = connect(socket)?;
+    stream.write_all(b"FORGET\n")?;
+    ok(&mut stream)
+}
+
+/// Connect to the agent on `socket`, if it is run by this user or root. Whoever listens there
+/// is handed keys or hands them out, so anyone else got there first.
+fn connect(socket: &Path) -> io::Result<UnixStream> {
+    let stream = UnixStream::connect(socket).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "can't reach the agent at {}: {e}, is sbsevery agent running?",
+                escape::path(socket)
+            ),
+        )
+    })?;
+    let uid = peer_uid(&stream)?;
+    // SAFETY: geteuid can't fail
+    if uid != 0 && uid != unsafe { libc::geteuid() } {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "the agent at {} is run by uid {uid}, not by this user or root",
+                escape::path(socket)
+            ),
+        ));
+    }
+    Ok(stream)
+}
+
+fn ok(stream: &mut UnixStream) -> io::Result<()> {
+    match read_line(stream)? {
+        line if line == "OK" => Ok(()),
+        line => Err(refused(&line)),
+    }
+}
+
+fn refused(line: &str) -> io::Error {
+    io::Error::other(format!(
+        "the agent said: {}",
+        line.strip_prefix("ERR ").unwrap_or(line)
+    ))
+}
+
+/// What the agent knows `key` by.
+fn fingerprint(key: &Path) -> io::Result<String> {
+    sha256_file(key)
+        .map(|hash| hex(&hash))
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(key))))
+}
@@ -0,0 +1,45 @@
+//! `--allow-hashes`, only sign files the build system says it built. The list is what
+//! `sha256sum` prints, of the files as they are before they are signed:
+//!
+//! ```text
+//! 9b2e6c0d…  EFI/Linux/arch-linux.efi
+//! # lines like this one and blank ones are left out
+//! 1586394c…  EFI/BOOT/BOOTX64.EFI
+//! ```
+//!
+//! Only the hashes count, the names are for whoever reads it. Files with hashes that aren't in
+//! it fail as policy violations rather than being signed, checked just before the signer is run
+//! on them.
+
+use std::{io, path::Path};
+
+use crate::{escape, state::Hash};
+
+/// Read the hashes of the allowlist at `path`, sorted.
+pub(crate) fn load(path: &Path) -> io::Result<Vec<Hash>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+    let mut hashes = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let hash = line
+            .split_whitespace()
+            .next()
+            // sha256sum marks lines with names it escaped
+            .map(|hash| hash.strip_prefix('\\').unwrap_or(hash))
+            .and_then(crate::state::unhex)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: not a SHA-256 hash", escape::path(path), n + 1),
+                )
+            })?;
+        hashes.push(hash);
+    }
+
+    hashes.sort_unstable();
+    Ok(hashes)
+}
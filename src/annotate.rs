@@ -0,0 +1,169 @@
+//! `--output-format github` and `--output-format gitlab`, the files a run failed on or that
+//! `sbsevery verify` found unsigned, as annotations a CI system shows next to the job, for
+//! image builds to say which boot file broke.
+//!
+//! For GitHub Actions they are workflow commands on stdout:
+//!
+//! ```text
+//! ::error file=/efi/EFI/BOOT/fbx64.efi,title=sbsevery::signing failed: exit status 1
+//! ```
+//!
+//! For GitLab a Code Quality report, the JSON array on stdout to save as the
+//! `artifacts:reports:codequality` of the job.
+
+use std::{fmt::Write, path::PathBuf};
+
+use serde_json::{json, Value};
+use sha2::Digest;
+
+use crate::{
+    escape,
+    report::Action,
+    state::hex,
+    verify::{Compliance, Verdict},
+    Report,
+};
+
+/// How bad an [`Annotation`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+/// Something to point out about a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub path: PathBuf,
+    pub level: Level,
+    /// What is wrong, in a few words.
+    pub check: &'static str,
+    pub message: String,
+}
+
+/// The files of `report` that failed, the paths that couldn't be searched, and the signer's
+/// warnings.
+#[must_use]
+pub fn run(report: &Report) -> Vec<Annotation> {
+    let mut annotations: Vec<_> = report
+        .entries
+        .iter()
+        .filter(|entry| entry.action == Action::Failed)
+        .map(|entry| Annotation {
+            path: entry.path.clone(),
+            level: Level::Error,
+            check: "signing failed",
+            message: entry
+                .error
+                .as_ref()
+                .map_or_else(|| "failed".to_string(), ToString::to_string),
+        })
+        .collect();
+    annotations.extend(errors(&report.errors));
+    annotations.extend(report.warnings.iter().map(|(path, warning)| Annotation {
+        path: path.clone(),
+        level: Level::Warning,
+        check: "warning",
+        message: warning.to_string(),
+    }));
+    annotations
+}
+
+/// The files `sbsevery verify` found not signed as the policy says or couldn't check, and the
+/// paths it couldn't search.
+#[must_use]
+pub fn verify(compliance: &Compliance) -> Vec<Annotation> {
+    let mut annotations: Vec<_> = compliance
+        .checked
+        .iter()
+        .filter_map(|(path, verdict)| {
+            let (check, message) = match verdict {
+                Verdict::Violation(missing) => {
+                    let missing: Vec<_> = missing
+                        .iter()
+                        .map(|cert| escape::path(cert).to_string())
+                        .collect();
+                    (
+                        "not signed",
+                        format!("not signed by {}", missing.join(", ")),
+                    )
+                }
+                Verdict::Error(e) => ("verify failed", e.clone()),
+                Verdict::Compliant | Verdict::Unmatched => return None,
+            };
+            Some(Annotation {
+                path: path.clone(),
+                level: Level::Error,
+                check,
+                message,
+            })
+        })
+        .collect();
+    annotations.extend(errors(&compliance.errors));
+    annotations
+}
+
+fn errors(errors: &[(PathBuf, String)]) -> impl Iterator<Item = Annotation> + '_ {
+    errors.iter().map(|(path, e)| Annotation {
+        path: path.clone(),
+        level: Level::Error,
+        check: "search failed",
+        message: e.clone(),
+    })
+}
+
+/// `annotations` as GitHub Actions workflow commands, a line each.
+#[must_use]
+pub fn github(annotations: &[Annotation]) -> String {
+    let mut commands = String::new();
+    for a in annotations {
+        let command = match a.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        };
+        let _ = writeln!(
+            commands,
+            "::{command} file={},title=sbsevery::{}: {}",
+            property(&escape::path(&a.path).to_string()),
+            data(a.check),
+            data(&a.message)
+        );
+    }
+    commands
+}
+
+/// `annotations` as a GitLab Code Quality report.
+#[must_use]
+pub fn gitlab(annotations: &[Annotation]) -> String {
+    let issues: Vec<Value> = annotations
+        .iter()
+        .map(|a| {
+            let path = escape::path(&a.path).to_string();
+            // the same problem with the same file, run after run
+            let fingerprint = hex(&sha2::Sha256::digest(format!("{}\0{path}", a.check)));
+            json!({
+                "description": format!("{}: {}", a.check, a.message),
+                "check_name": a.check,
+                "fingerprint": fingerprint,
+                "severity": match a.level {
+                    Level::Error => "major",
+                    Level::Warning => "minor",
+                },
+                "location": { "path": path, "lines": { "begin": 1 } },
+            })
+        })
+        .collect();
+    format!("{}\n", Value::from(issues))
+}
+
+/// `s` as the message of a workflow command, which ends at a newline.
+fn data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// `s` as a property of a workflow command, where `,` and `:` end it too.
+fn property(s: &str) -> String {
+    data(s).replace(':', "%3A").replace(',', "%2C")
+}
@@ -0,0 +1,244 @@
+//! `--audit-log`, a record of every time the key was used, for compliance regimes that want
+//! tamper-evident records of key usage.
+//!
+//! The log is a file of JSON lines, one for each file the signer was run on, only ever appended
+//! to:
+//!
+//! ```text
+//! {"backend":"sbsign","cert":"/etc/efi-keys/DB.crt","cert_sha256":"5d1e…","host":"build7",
+//!  "path":"/efi/EFI/BOOT/BOOTX64.EFI","pid":4242,"prev":"0f3a…","result":"signed",
+//!  "sha256_after":"c0ff…","sha256_before":"9b2e…","time":1760443200,"uid":0}
+//! ```
+//!
+//! `cert_sha256` is the fingerprint of the cert, the SHA-256 of it as DER. `prev` is the
+//! SHA-256 of the line before, all zeros for the first, so a line changed or taken out breaks
+//! the chain from there on; `sbsevery audit-verify` checks it, see [`verify`]. Keep a copy of the
+//! last hash elsewhere now and then and the end can't be cut off unnoticed either, `chattr +a`
+//! keeps anyone but root from rewriting it.
+//!
+//! Runs appending to the same log at once take turns, with `flock`.
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{escape, state::hex};
+
+/// The `prev` of the first line.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The longest line looked for at the end of the log, far longer than any record.
+const LONGEST: u64 = 64 * 1024;
+
+/// A use of the key on a file.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Operation<'a> {
+    pub(crate) path: &'a Path,
+    /// Whether the file was signed.
+    pub(crate) signed: bool,
+    pub(crate) before: Option<[u8; 32]>,
+    pub(crate) after: Option<[u8; 32]>,
+    pub(crate) cert: &'a Path,
+    pub(crate) backend: &'a str,
+}
+
+/// An audit log open for appending.
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+    /// The fingerprint of each cert, worked out once.
+    fingerprints: Mutex<HashMap<PathBuf, Option<String>>>,
+    host: String,
+}
+
+impl AuditLog {
+    /// Open the log at `path`, making it if there is none.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let mut open = OpenOptions::new();
+        open.read(true).append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut open, 0o600);
+        let file = open
+            .open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            fingerprints: Mutex::default(),
+            host: hostname(),
+        })
+    }
+
+    /// Append `operation`, chained to the line before.
+    pub(crate) fn record(&self, operation: &Operation<'_>) -> io::Result<()> {
+        let context = |e: io::Error| {
+            io::Error::new(
+                e.kind(),
+                format!("audit log {}: {e}", escape::path(&self.path)),
+            )
+        };
+        let fingerprint = self.fingerprint(operation.cert);
+        let mut file = self.file.lock().map_err(|_| io::ErrorKind::Other)?;
+        let _lock = Lock::new(&file).map_err(context)?;
+
+        let record = json!({
+            "time": SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            "path": escape::path(operation.path).to_string(),
+            "result": if operation.signed { "signed" } else { "failed" },
+            "sha256_before": operation.before.map(|hash| hex(&hash)),
+            "sha256_after": operation.after.map(|hash| hex(&hash)),
+            "cert": escape::path(operation.cert).to_string(),
+            "cert_sha256": fingerprint,
+            "backend": operation.backend,
+            "uid": uid(),
+            "host": self.host,
+            "pid": std::process::id(),
+            "prev": last_hash(&mut file).map_err(context)?,
+        });
+        file.write_all(format!("{record}\n").as_bytes())
+            .and_then(|()| file.sync_data())
+            .map_err(context)
+    }
+
+    fn fingerprint(&self, cert: &Path) -> Option<String> {
+        let mut fingerprints = self.fingerprints.lock().ok()?;
+        fingerprints
+            .entry(cert.to_path_buf())
+            .or_insert_with(|| {
+                crate::pcr::der(cert)
+                    .ok()
+                    .map(|der| hex(&Sha256::digest(der)))
+            })
+            .clone()
+    }
+}
+
+/// Holds `flock` on a file, for other processes appending to wait their turn.
+pub(crate) struct Lock(i32);
+
+impl Lock {
+    #[cfg(unix)]
+    pub(crate) fn new(file: &File) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        let fd = file.as_raw_fd();
+        // SAFETY: no pointers involved
+        if unsafe { libc::flock(fd, libc::LOCK_EX) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(fd))
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn new(_: &File) -> io::Result<Self> {
+        Ok(Self(-1))
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        // SAFETY: no pointers involved
+        unsafe {
+            libc::flock(self.0, libc::LOCK_UN);
+        }
+    }
+}
+
+/// The hash of the last line of the log, [`GENESIS`] if it is empty.
+fn last_hash(file: &mut File) -> io::Result<String> {
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(GENESIS.to_string());
+    }
+    file.seek(SeekFrom::Start(len.saturating_sub(LONGEST)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+
+    let Some(tail) = tail.strip_suffix(b"\n") else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the last line is cut off, run sbsevery audit-verify",
+        ));
+    };
+    let line = match tail.iter().rposition(|&b| b == b'\n') {
+        Some(at) => &tail[at + 1..],
+        None if len <= LONGEST => tail,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the last line is longer than any record",
+            ))
+        }
+    };
+    Ok(hex(&Sha256::digest(line)))
+}
+
+/// Check the chain of the log at `path`, returning how many records it has and the hash of the
+/// last, to keep elsewhere.
+///
+/// # Errors
+///
+/// Fails if it can't be read, or with the first line that isn't what the one before says.
+pub fn verify(path: &Path) -> Result<(usize, String), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {e}", escape::path(path)))?;
+    let mut reader = BufReader::new(file);
+    let mut expected = GENESIS.to_string();
+    let mut records = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| format!("{}: {e}", escape::path(path)))?;
+        if read == 0 {
+            return Ok((records, expected));
+        }
+        let n = records + 1;
+        let Some(record) = line.strip_suffix(b"\n") else {
+            return Err(format!("line {n} is cut off"));
+        };
+        let json: Value =
+            serde_json::from_slice(record).map_err(|e| format!("line {n} isn't a record: {e}"))?;
+        if json["prev"].as_str() != Some(expected.as_str()) {
+            return Err(format!(
+                "line {n} doesn't follow the one before, it or the one before was changed, \
+                 or lines were taken out"
+            ));
+        }
+        expected = hex(&Sha256::digest(record));
+        records = n;
+    }
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)]
+fn uid() -> Option<u32> {
+    // SAFETY: getuid can't fail
+    Some(unsafe { libc::getuid() })
+}
+
+#[cfg(not(unix))]
+fn uid() -> Option<u32> {
+    None
+}
+
+fn hostname() -> String {
+    let mut name = [0_u8; 256];
+    #[cfg(unix)]
+    // SAFETY: the buffer is writable for its length
+    unsafe {
+        libc::gethostname(name.as_mut_ptr().cast(), name.len());
+    }
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
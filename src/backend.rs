@@ -0,0 +1,267 @@
+use std::{ffi::OsString, path::Path, process::Command, str::FromStr};
+
+use crate::{mock::MockSigner, plugin, ssh::Ssh, vault::Vault, Options};
+
+/// Which external tool does the actual signing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// `sbsign` from sbsigntools, re-reads the key and cert for every file.
+    #[default]
+    Sbsign,
+    /// `pesign-client` talking to a running `pesignd`, which keeps the key unlocked in its NSS
+    /// database so nothing is loaded per file. The token has to be unlocked beforehand with
+    /// `pesign-client --unlock`.
+    Pesign {
+        /// NSS token holding the key.
+        token: String,
+        /// Nickname of the certificate in the NSS database.
+        certificate: String,
+    },
+    /// `osslsigncode`, which also runs on Windows and macOS build machines.
+    Osslsigncode,
+    /// `signtool.exe` from the Windows SDK. It wants the key and certificate in one PKCS#12
+    /// bundle, which is passed as the cert.
+    Signtool,
+    /// A signer plugin from the plugin directory, see [`crate::plugin`].
+    Plugin {
+        /// File name of the plugin in the directory.
+        name: String,
+    },
+    /// Nothing is signed, see [`crate::mock`].
+    Mock(MockSigner),
+    /// A key in `HashiCorp` Vault's transit engine signs, see [`crate::vault`].
+    Vault(Vault),
+    /// Another machine signs, over SSH, see [`crate::ssh`].
+    Ssh(Ssh),
+}
+
+impl Backend {
+    /// Name used on the command line.
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Sbsign => "sbsign",
+            Backend::Pesign { .. } => "pesign",
+            Backend::Osslsigncode => "osslsigncode",
+            Backend::Signtool => "signtool",
+            Backend::Plugin { .. } => "plugin",
+            Backend::Mock(_) => "mock",
+            Backend::Vault(_) => "vault",
+            Backend::Ssh(_) => "ssh",
+        }
+    }
+
+    /// Program the backend runs and the flag that makes it print its version, for the
+    /// backends where that is fixed.
+    pub(crate) fn tool(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Backend::Sbsign => Some(("sbsign", "--version")),
+            Backend::Pesign { .. } => Some(("pesign-client", "--version")),
+            Backend::Osslsigncode => Some(("osslsigncode", "--version")),
+            Backend::Signtool
+            | Backend::Plugin { .. }
+            | Backend::Mock(_)
+            | Backend::Vault(_)
+            | Backend::Ssh(_) => None,
+        }
+    }
+
+    /// Whether the tool takes the key as a PEM file it opens itself, so a decrypted key can be
+    /// handed to it over a pipe.
+    pub(crate) fn pem_key(&self) -> bool {
+        matches!(self, Backend::Sbsign | Backend::Osslsigncode)
+    }
+
+    /// Whether the tool warns on stderr about images that are already signed or malformed, see
+    /// [`crate::diagnose`].
+    pub(crate) fn warns_signed(&self) -> bool {
+        matches!(self, Backend::Sbsign | Backend::Mock(_))
+    }
+
+    /// Whether the tool can be told the signing time, so that signing the same image twice gives
+    /// the same bytes, see [`signing_time`].
+    pub(crate) fn deterministic(&self) -> bool {
+        matches!(
+            self,
+            Backend::Osslsigncode | Backend::Plugin { .. } | Backend::Mock(_) | Backend::Vault(_)
+        )
+    }
+
+    /// Whether intermediate certs can be put in the signatures, see [`Options::chain`]. The
+    /// NSS database of pesignd has its own, a remote signing images its own settings.
+    pub(crate) fn chains(&self) -> bool {
+        !matches!(self, Backend::Pesign { .. }) && !self.to_stdout()
+    }
+
+    /// Whether the tool writes the signed image to stdout rather than to the output it is given.
+    pub(crate) fn to_stdout(&self) -> bool {
+        matches!(self, Backend::Ssh(ssh) if !ssh.digest)
+    }
+
+    /// Whether sbsevery builds the signature itself, the backend only signing what it signs.
+    pub(crate) fn in_process(&self) -> bool {
+        matches!(self, Backend::Vault(_)) || matches!(self, Backend::Ssh(ssh) if ssh.digest)
+    }
+
+    /// Whether the tool can write its output over its input.
+    pub(crate) fn in_place(&self) -> bool {
+        matches!(self, Backend::Sbsign | Backend::Signtool)
+    }
+
+    pub(crate) fn command(
+        &self,
+        file: &Path,
+        output: &Path,
+        key: &Path,
+        options: &Options,
+    ) -> Command {
+        match self {
+            Backend::Sbsign => {
+                let mut cmd = Command::new("sbsign");
+                cmd.arg("--key")
+                    .arg(key)
+                    .arg("--cert")
+                    .arg(options.cert.as_os_str());
+                if let Some(chain) = &options.chain {
+                    cmd.arg("--addcert").arg(chain);
+                }
+                cmd.arg("--output").arg(output).arg(file);
+                cmd
+            }
+            Backend::Pesign { token, certificate } => {
+                let mut cmd = Command::new("pesign-client");
+                cmd.arg("--sign")
+                    .arg(format!("--token={token}"))
+                    .arg(format!("--certificate={certificate}"))
+                    .arg(flag_path("--infile=", file))
+                    .arg(flag_path("--outfile=", output));
+                cmd
+            }
+            Backend::Osslsigncode => {
+                let mut cmd = Command::new("osslsigncode");
+                cmd.arg("sign")
+                    .arg("-h")
+                    .arg("sha256")
+                    .arg("-certs")
+                    .arg(options.cert.as_os_str())
+                    .arg("-key")
+                    .arg(key);
+                if let Some(chain) = &options.chain {
+                    cmd.arg("-ac").arg(chain);
+                }
+                cmd.arg("-in").arg(file).arg("-out").arg(output);
+                if options.deterministic {
+                    cmd.arg("-time").arg(signing_time().to_string());
+                }
+                cmd
+            }
+            Backend::Signtool => {
+                let mut cmd = Command::new("signtool");
+                cmd.arg("sign")
+                    .arg("/q")
+                    .arg("/fd")
+                    .arg("sha256")
+                    .arg("/f")
+                    .arg(options.cert.as_os_str());
+                if let Some(chain) = &options.chain {
+                    cmd.arg("/ac").arg(chain);
+                }
+                cmd.arg(file);
+                cmd
+            }
+            Backend::Plugin { name } => plugin::sign_command(name, options),
+            Backend::Ssh(ssh) => ssh.command(options),
+            // never run, there is nothing to sign with or it is signed in process
+            Backend::Mock(_) | Backend::Vault(_) => Command::new("true"),
+        }
+    }
+
+    /// What goes to the signer's stdin, if anything.
+    pub(crate) fn request(
+        &self,
+        file: &Path,
+        output: &Path,
+        key: &Path,
+        options: &Options,
+    ) -> Option<Vec<u8>> {
+        match self {
+            Backend::Plugin { .. } => Some(plugin::sign_request(file, output, key, options)),
+            // unreadable, it fails on the other end
+            Backend::Ssh(_) => Some(std::fs::read(file).unwrap_or_default()),
+            _ => None,
+        }
+    }
+}
+
+impl Backend {
+    /// Whether there is a [`Backend::verify_command`].
+    pub(crate) fn verifies(&self) -> bool {
+        matches!(self, Backend::Sbsign | Backend::Osslsigncode)
+    }
+
+    /// Command checking that `file` carries a valid signature by `cert`, for the backends
+    /// whose toolchain has a verifier that takes the cert as a file.
+    pub(crate) fn verify_command(&self, file: &Path, cert: &Path) -> Option<Command> {
+        match self {
+            Backend::Sbsign => {
+                let mut cmd = Command::new("sbverify");
+                cmd.arg("--cert").arg(cert).arg(file);
+                Some(cmd)
+            }
+            Backend::Osslsigncode => {
+                let mut cmd = Command::new("osslsigncode");
+                cmd.arg("verify")
+                    .arg("-CAfile")
+                    .arg(cert)
+                    .arg("-in")
+                    .arg(file);
+                Some(cmd)
+            }
+            Backend::Pesign { .. }
+            | Backend::Signtool
+            | Backend::Plugin { .. }
+            | Backend::Mock(_)
+            | Backend::Vault(_)
+            | Backend::Ssh(_) => None,
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    /// Parse a backend name, backend specific settings are left at their defaults.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sbsign" => Ok(Backend::Sbsign),
+            "pesign" => Ok(Backend::Pesign {
+                token: String::from("NSS Certificate DB"),
+                certificate: String::new(),
+            }),
+            "osslsigncode" => Ok(Backend::Osslsigncode),
+            "signtool" => Ok(Backend::Signtool),
+            "mock" => Ok(Backend::Mock(MockSigner::new())),
+            "vault" => Ok(Backend::Vault(Vault::default())),
+            "ssh" => Ok(Backend::Ssh(Ssh::default())),
+            _ if s.starts_with("plugin:") => Ok(Backend::Plugin {
+                name: s["plugin:".len()..].to_string(),
+            }),
+            _ => Err(format!("unknown backend: '{s}'")),
+        }
+    }
+}
+
+/// The signing time recorded with `--deterministic`: `SOURCE_DATE_EPOCH` as reproducible builds
+/// set it, the epoch itself otherwise.
+pub(crate) fn signing_time() -> u64 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|t| t.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn flag_path(flag: &str, path: &Path) -> OsString {
+    let mut arg = OsString::from(flag);
+    arg.push(path);
+    arg
+}
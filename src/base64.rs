@@ -0,0 +1,45 @@
+//! Base64, as PEM and JSON APIs carry binary.
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0_u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            out.push(if i <= chunk.len() {
+                char::from(BASE64[(n >> (18 - 6 * i) & 63) as usize])
+            } else {
+                '='
+            });
+        }
+    }
+    out
+}
+
+pub(crate) fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut n = 0_u32;
+    for (i, c) in text.bytes().enumerate() {
+        let value = BASE64.iter().position(|&b| b == c)?;
+        n = n << 6 | u32::try_from(value).ok()?;
+        if i % 4 == 3 {
+            out.extend_from_slice(&n.to_be_bytes()[1..]);
+            n = 0;
+        }
+    }
+    match text.len() % 4 {
+        0 => {}
+        1 => return None,
+        // the 2 or 3 characters left over are a byte or two, and bits of padding
+        left => {
+            n <<= 6 * (4 - left);
+            out.extend_from_slice(&n.to_be_bytes()[1..left]);
+        }
+    }
+    Some(out)
+}
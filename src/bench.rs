@@ -0,0 +1,47 @@
+//! `sbsevery bench`, time the whole pipeline against a generated tree.
+
+use std::{fs, io, path::Path};
+
+use crate::{
+    pe::{self, Machine},
+    Options, Report,
+};
+
+/// Images per generated directory, so the walker has some directories to spread over.
+const PER_DIR: usize = 64;
+
+/// Generate `files` dummy images of `size` bytes in a temporary tree, sign them all with
+/// `options` and remove the tree again.
+///
+/// # Errors
+///
+/// Fails if the tree can't be written or removed, signing failures only show up in the report.
+pub fn bench(
+    options: &Options,
+    files: usize,
+    size: u64,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("sbsevery-bench-{}", std::process::id()));
+    generate(&dir, files, size)?;
+
+    let options = Options {
+        roots: vec![dir.clone()],
+        ..options.clone()
+    };
+    let report = crate::run(&options)?;
+
+    fs::remove_dir_all(&dir)?;
+    Ok(report)
+}
+
+fn generate(dir: &Path, files: usize, size: u64) -> io::Result<()> {
+    let image = pe::dummy(Machine::X64, usize::try_from(size).unwrap_or(usize::MAX));
+
+    for i in 0..files {
+        let sub = dir.join(format!("{:03}", i / PER_DIR));
+        fs::create_dir_all(&sub)?;
+        fs::write(sub.join(format!("bench{i}.efi")), &image)?;
+    }
+
+    Ok(())
+}
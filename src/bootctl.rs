@@ -0,0 +1,188 @@
+//! `sbsevery bootctl-update`, what `bootctl update` does, signed. `bootctl update` copies the
+//! systemd-boot of the installed package over the one on the ESP, leaving an unsigned loader
+//! there until the next `sbsevery`, and one that doesn't boot if that never comes. This copies
+//! and signs in one step with `--install-to`, so the ESP only ever has a signed loader.
+//!
+//! Like `bootctl update`, only loaders already on the ESP are updated: `EFI/systemd`, and the
+//! fallback in `EFI/BOOT` if it is systemd-boot, each left alone if it is the version shipped
+//! already. Unlike it, EFI variables and the random seed aren't touched.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::{escape, Options};
+
+/// Where systemd ships the loaders, `systemd-bootx64.efi` and the like.
+pub const SOURCE: &str = "/usr/lib/systemd/boot/efi";
+
+/// Where the ESP is looked for if `bootctl` can't say.
+const ESPS: &[&str] = &["/efi", "/boot/efi", "/boot"];
+
+/// A loader on the ESP and what it would be updated from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Update {
+    /// The loader shipped.
+    pub source: PathBuf,
+    /// The one on the ESP.
+    pub dest: PathBuf,
+    /// The version of `source`, as its `LoaderInfo` says.
+    pub version: Option<String>,
+    /// The version of `dest`.
+    pub installed: Option<String>,
+}
+
+impl Update {
+    /// Whether `dest` is the version shipped already.
+    #[must_use]
+    pub fn current(&self) -> bool {
+        self.version.is_some() && self.version == self.installed
+    }
+}
+
+/// How updating went.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Updated {
+    /// Loaders signed and installed.
+    pub installed: Vec<PathBuf>,
+    /// Loaders that are the version shipped already.
+    pub current: Vec<PathBuf>,
+    /// Loaders that couldn't be updated and why, left as they were.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// The ESP, as `bootctl --print-esp-path` says, or the first of the usual places with a
+/// systemd-boot in it.
+#[must_use]
+pub fn find_esp() -> Option<PathBuf> {
+    let printed = Command::new("bootctl")
+        .arg("--print-esp-path")
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|path| PathBuf::from(path.trim()))
+        .filter(|path| !path.as_os_str().is_empty());
+
+    printed.or_else(|| {
+        ESPS.iter()
+            .map(PathBuf::from)
+            .find(|esp| esp.join("EFI/systemd").is_dir())
+    })
+}
+
+/// Every loader under `source` and where it goes on `esp`, for the ones the ESP has.
+///
+/// # Errors
+///
+/// Fails if `source` can't be read.
+pub fn plan(source: &Path, esp: &Path) -> io::Result<Vec<Update>> {
+    let mut updates = Vec::new();
+    let mut shipped: Vec<_> = std::fs::read_dir(source)?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    shipped.sort();
+
+    for loader in shipped {
+        let Some(name) = loader.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some(arch) = name
+            .strip_prefix("systemd-boot")
+            .and_then(|rest| rest.strip_suffix(".efi"))
+        else {
+            continue;
+        };
+
+        let version = loader_info(&loader);
+        let ours = esp.join("EFI/systemd").join(name);
+        let fallback = esp
+            .join("EFI/BOOT")
+            .join(format!("BOOT{}.EFI", arch.to_ascii_uppercase()));
+        for dest in [ours, fallback] {
+            let Some(installed) = dest.is_file().then(|| loader_info(&dest)) else {
+                continue;
+            };
+            // the fallback might be shim or another loader, that isn't ours to replace
+            if !installed
+                .as_deref()
+                .is_some_and(|info| info.starts_with("systemd-boot "))
+            {
+                continue;
+            }
+            updates.push(Update {
+                source: loader.clone(),
+                dest,
+                version: version.clone(),
+                installed,
+            });
+        }
+    }
+
+    Ok(updates)
+}
+
+/// Sign and install every loader under `source` onto `esp` that isn't current, with the key
+/// and cert of `options`.
+///
+/// # Errors
+///
+/// Fails if `source` can't be read. Loaders that fail to sign are in [`Updated::failed`].
+pub fn update(options: &Options, source: &Path, esp: &Path) -> io::Result<Updated> {
+    let mut updated = Updated::default();
+
+    for update in plan(source, esp)? {
+        if update.current() {
+            dprintln!(
+                options.verbose,
+                "current:\t{} ({})",
+                escape::path(&update.dest),
+                update.version.as_deref().unwrap_or_default()
+            );
+            updated.current.push(update.dest);
+            continue;
+        }
+
+        let options = Options {
+            roots: vec![update.source.clone()],
+            install_to: Some(update.dest.clone()),
+            state: None,
+            journal: None,
+            // the shipped loader may come signed by the distribution
+            resign: true,
+            ..options.clone()
+        };
+        match crate::run(&options) {
+            Ok(report) if report.succeeded() && report.files > 0 => {
+                updated.installed.push(update.dest);
+            }
+            Ok(report) => {
+                let why = match report.failed.first() {
+                    Some((_, failure)) => failure.to_string(),
+                    None if report.cancelled => String::from("cancelled"),
+                    None if report.rolled_back => String::from("rolled back"),
+                    None => String::from("nothing signed"),
+                };
+                updated.failed.push((update.dest, why));
+            }
+            Err(e) => updated.failed.push((update.dest, e.to_string())),
+        }
+    }
+
+    Ok(updated)
+}
+
+/// The `LoaderInfo` systemd-boot embeds, `systemd-boot 255.4-1` say.
+fn loader_info(file: &Path) -> Option<String> {
+    const MARK: &[u8] = b"#### LoaderInfo: ";
+
+    let data = std::fs::read(file).ok()?;
+    let start = data.windows(MARK.len()).position(|w| w == MARK)? + MARK.len();
+    let rest = &data[start..];
+    let end = rest.windows(5).position(|w| w == b" ####")?;
+    String::from_utf8(rest[..end].to_vec()).ok()
+}
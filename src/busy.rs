@@ -0,0 +1,152 @@
+//! Files another process still has open for writing, a kernel install copying a new vmlinuz
+//! into place say. Signing one of those signs whatever half of it is there, so workers wait
+//! for the writer to be done first, for up to `--busy-wait`.
+//!
+//! A read lease says it in one call: the kernel refuses one on a file open for writing. Where
+//! leases aren't to be had, not owning the file and without `CAP_LEASE`, the file descriptors
+//! in `/proc` are looked through instead.
+
+use std::{
+    io,
+    path::Path,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::escape;
+
+/// How long to wait between looking again.
+const POLL: Duration = Duration::from_millis(250);
+
+/// A process writing to a file, as far as can be told.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Writer {
+    pid: Option<u32>,
+}
+
+impl std::fmt::Display for Writer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.pid {
+            Some(pid) => {
+                let comm = std::fs::read_to_string(format!("/proc/{pid}/comm")).unwrap_or_default();
+                match comm.trim() {
+                    "" => write!(f, "process {pid}"),
+                    comm => write!(f, "{comm} ({pid})"),
+                }
+            }
+            None => f.write_str("another process"),
+        }
+    }
+}
+
+/// Wait until nothing else has `file` open for writing, for up to `timeout`.
+///
+/// # Errors
+///
+/// Fails if something still does after that.
+pub(crate) fn settle(file: &Path, timeout: Duration, verbose: bool) -> io::Result<()> {
+    let start = Instant::now();
+    let mut told = false;
+
+    while let Some(writer) = writer(file) {
+        if start.elapsed() >= timeout {
+            return Err(io::Error::new(
+                io::ErrorKind::ResourceBusy,
+                format!("still open for writing by {writer}"),
+            ));
+        }
+        if !told {
+            dprintln!(
+                verbose,
+                "waiting:\t{} (open for writing by {writer})",
+                escape::path(file)
+            );
+            told = true;
+        }
+        sleep(POLL);
+    }
+
+    Ok(())
+}
+
+/// Who else has `file` open for writing, if anyone.
+#[cfg(target_os = "linux")]
+pub(crate) fn writer(file: &Path) -> Option<Writer> {
+    match lease(file) {
+        Some(false) => None,
+        // found busy, or leases say nothing here, either way /proc knows more
+        Some(true) => Some(scan(file).unwrap_or(Writer { pid: None })),
+        None => scan(file),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn writer(_: &Path) -> Option<Writer> {
+    None
+}
+
+/// Whether a read lease on `file` is refused for it being open for writing, `None` if leases
+/// can't tell.
+#[cfg(target_os = "linux")]
+fn lease(file: &Path) -> Option<bool> {
+    use std::os::fd::AsRawFd;
+
+    let opened = std::fs::File::open(file).ok()?;
+    let fd = opened.as_raw_fd();
+
+    // SAFETY: fd is open for as long as `opened` lives
+    if unsafe { libc::fcntl(fd, libc::F_SETLEASE, libc::F_RDLCK) } == 0 {
+        // SAFETY: as above
+        unsafe { libc::fcntl(fd, libc::F_SETLEASE, libc::F_UNLCK) };
+        return Some(false);
+    }
+    match io::Error::last_os_error().raw_os_error() {
+        Some(libc::EAGAIN) => Some(true),
+        _ => None,
+    }
+}
+
+/// Look through the file descriptors of every process for one writing to `file`.
+#[cfg(target_os = "linux")]
+fn scan(file: &Path) -> Option<Writer> {
+    let file = file.canonicalize().ok()?;
+    let me = std::process::id();
+
+    for proc in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = proc
+            .file_name()
+            .to_str()
+            .and_then(|pid| pid.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid == me {
+            continue;
+        }
+        // processes come and go and many aren't ours to look at
+        let Ok(fds) = std::fs::read_dir(proc.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if std::fs::read_link(fd.path()).ok().as_deref() != Some(&file) {
+                continue;
+            }
+            let info = proc.path().join("fdinfo").join(fd.file_name());
+            if writable(&std::fs::read_to_string(info).unwrap_or_default()) {
+                return Some(Writer { pid: Some(pid) });
+            }
+        }
+    }
+
+    None
+}
+
+/// Whether the `flags` line of an fdinfo file says it was opened for writing.
+#[cfg(target_os = "linux")]
+fn writable(fdinfo: &str) -> bool {
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("flags:"))
+        .and_then(|flags| i32::from_str_radix(flags.trim(), 8).ok())
+        .is_some_and(|flags| flags & libc::O_ACCMODE != libc::O_RDONLY)
+}
@@ -0,0 +1,38 @@
+//! Stopping a run from outside, for programs that embed sbsevery, an installer with a cancel
+//! button say, rather than having to kill the process.
+//!
+//! Set [`Options::cancel`](crate::Options::cancel) to a [`Cancel`] and call [`Cancel::cancel`]
+//! on a clone of it from another thread: no new directory is searched and no new file handed to
+//! a signer, the files already being signed are let finish so none is left half written, and
+//! [`run`](crate::run) returns with what was done so far and
+//! [`Report::cancelled`](crate::Report::cancelled) set. A cancelled transactional run commits
+//! nothing, and a `--journal` is kept to resume from.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// A handle to cancel a run with, shared by all its clones.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// A handle that hasn't been cancelled.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the run, or the next one if there is none going. There is no taking it back, a
+    /// fresh handle is needed for runs after.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether [`Cancel::cancel`] has been called on this handle or a clone.
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
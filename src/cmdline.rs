@@ -0,0 +1,124 @@
+//! `--cmdline-policy`, check the kernel command line embedded in a UKI before signing it.
+//! Whoever can boot a signed UKI gets its `.cmdline` along with it, so one with `init=/bin/sh`
+//! in it is as good as a root shell.
+//!
+//! A policy is a TOML file of globs matched against each parameter of the command line:
+//!
+//! ```toml
+//! # none of these may be there
+//! deny = ["init=*", "rd.break", "systemd.debug_shell*"]
+//! # and every one of these must be
+//! require = ["lockdown=*"]
+//! ```
+
+use std::{fmt, io, path::Path};
+
+use serde::Deserialize;
+
+use crate::{escape, glob, pe::Layout};
+
+/// Which parameters an embedded command line may and must have.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Parameters matching any of these are refused.
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Each of these has to match some parameter.
+    #[serde(default)]
+    pub require: Vec<String>,
+}
+
+/// How a command line breaks a [`Policy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// This parameter matches this `deny` glob.
+    Denied(String, String),
+    /// Nothing matches this `require` glob.
+    Missing(String),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Denied(param, pattern) => write!(f, "{param} is denied by {pattern}"),
+            Violation::Missing(pattern) => write!(f, "nothing matches {pattern}"),
+        }
+    }
+}
+
+impl Policy {
+    /// Read a policy from a TOML file.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or isn't a valid policy.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+        toml::from_str(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", escape::path(path)),
+            )
+        })
+    }
+
+    /// The first way `cmdline` breaks the policy, if it does.
+    #[must_use]
+    pub fn check(&self, cmdline: &str) -> Option<Violation> {
+        let params = params(cmdline);
+
+        for param in &params {
+            if let Some(pattern) = self.deny.iter().find(|p| glob::matches(p, param)) {
+                return Some(Violation::Denied(param.clone(), pattern.clone()));
+            }
+        }
+        self.require
+            .iter()
+            .find(|pattern| !params.iter().any(|param| glob::matches(pattern, param)))
+            .map(|pattern| Violation::Missing(pattern.clone()))
+    }
+}
+
+/// The `.cmdline` section of the image `data`, `None` if it isn't a UKI.
+#[must_use]
+pub fn embedded(data: &[u8]) -> Option<String> {
+    Some(of_section(Layout::parse(data)?.section(data, b".cmdline")?))
+}
+
+/// The command line in a `.cmdline` section, up to the NUL padding.
+#[must_use]
+pub fn of_section(section: &[u8]) -> String {
+    let end = section
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(section.len());
+    String::from_utf8_lossy(&section[..end]).trim().to_string()
+}
+
+/// The parameters of a command line as the kernel splits it: on whitespace, except inside
+/// double quotes, which are dropped.
+#[must_use]
+pub fn params(cmdline: &str) -> Vec<String> {
+    let mut params = Vec::new();
+    let mut param = String::new();
+    let mut quoted = false;
+
+    for c in cmdline.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !param.is_empty() {
+                    params.push(std::mem::take(&mut param));
+                }
+            }
+            c => param.push(c),
+        }
+    }
+    if !param.is_empty() {
+        params.push(param);
+    }
+
+    params
+}
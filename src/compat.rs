@@ -0,0 +1,201 @@
+//! `--compat`, checking signed images against what firmware with known bugs in its Authenticode
+//! parser has been reported to choke on, so an image that would boot anywhere else but not on
+//! the machine down the hall is caught before it is shipped:
+//!
+//! ```text
+//! sbsevery /efi -k /etc/efi-keys/DB.key -c /etc/efi-keys/DB.crt --compat insydeh2o,aptio
+//! ```
+//!
+//! The checklist is the firmware of [`FIRMWARE`] to check for, by name, or `all`. Each image is
+//! checked once signed, and what some of it is likely to refuse is a warning, in the report as
+//! `compat-<firmware>-<check>`; the image stays signed.
+
+use std::{fmt, io, ops::Range};
+
+use crate::pe::{Image, Layout};
+
+/// Something about the certificate table some firmware gets wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Check {
+    /// The certificate table doesn't start on an 8 byte boundary, and is read from the
+    /// boundary before it.
+    TableAlignment,
+    /// The size of the certificate table in the data directory isn't a multiple of 8.
+    TablePadding,
+    /// A signature with another after it has a length that isn't a multiple of 8, and the
+    /// next is looked for where the length says rather than at the boundary after it.
+    EntryLength,
+    /// More signatures than the firmware looks at, those after the first few are ignored and
+    /// an image signed by a key in them doesn't boot.
+    SignatureCount,
+    /// Data after the certificate table, which the firmware takes for part of it.
+    TrailingData,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Check::TableAlignment => "table-alignment",
+            Check::TablePadding => "table-padding",
+            Check::EntryLength => "entry-length",
+            Check::SignatureCount => "signature-count",
+            Check::TrailingData => "trailing-data",
+        })
+    }
+}
+
+/// Firmware with bugs in how it reads signatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Firmware {
+    /// What `--compat` knows it by.
+    pub name: &'static str,
+    /// What it gets wrong.
+    pub checks: &'static [Check],
+    /// The most signatures it looks at, checked as [`Check::SignatureCount`].
+    pub max_signatures: Option<usize>,
+}
+
+/// The firmware `--compat` knows, from what has been reported against some of its builds.
+pub const FIRMWARE: &[Firmware] = &[
+    Firmware {
+        name: "insydeh2o",
+        checks: &[
+            Check::TableAlignment,
+            Check::TablePadding,
+            Check::EntryLength,
+        ],
+        max_signatures: Some(1),
+    },
+    Firmware {
+        name: "aptio",
+        checks: &[Check::TablePadding, Check::TrailingData],
+        max_signatures: None,
+    },
+    Firmware {
+        name: "phoenix",
+        checks: &[Check::TableAlignment],
+        max_signatures: Some(2),
+    },
+];
+
+/// Something firmware is likely to refuse a signed image for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Quirk {
+    /// The name of the [`Firmware`].
+    pub firmware: &'static str,
+    pub check: Check,
+}
+
+impl fmt::Display for Quirk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "compat-{}-{}", self.firmware, self.check)
+    }
+}
+
+/// The firmware of a `--compat` checklist, names separated by commas or `all`.
+///
+/// # Errors
+///
+/// Fails on a name not in [`FIRMWARE`].
+pub fn checklist(names: &str) -> Result<Vec<Firmware>, String> {
+    if names == "all" {
+        return Ok(FIRMWARE.to_vec());
+    }
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            FIRMWARE
+                .iter()
+                .find(|firmware| firmware.name.eq_ignore_ascii_case(name))
+                .copied()
+                .ok_or_else(|| {
+                    let known: Vec<_> = FIRMWARE.iter().map(|firmware| firmware.name).collect();
+                    format!(
+                        "no firmware called {name} for --compat, it knows {} and all",
+                        known.join(", ")
+                    )
+                })
+        })
+        .collect()
+}
+
+/// What the firmware of `checklist` is likely to refuse the signed image `data` for, nothing if
+/// it isn't signed.
+#[must_use]
+pub fn check(data: &[u8], checklist: &[Firmware]) -> Vec<Quirk> {
+    let Some(layout) = Layout::parse(data) else {
+        return Vec::new();
+    };
+    let Some(table) = layout.cert_table.clone() else {
+        return Vec::new();
+    };
+    quirks(
+        &table,
+        layout.signatures(data).unwrap_or_default(),
+        data.len(),
+        checklist,
+    )
+}
+
+/// [`check`] for an image on disk, of which only the certificate table is read.
+///
+/// # Errors
+///
+/// Fails if the image can't be read.
+pub fn check_image(image: &mut Image, checklist: &[Firmware]) -> io::Result<Vec<Quirk>> {
+    let Some(table) = image.layout.cert_table.clone() else {
+        return Ok(Vec::new());
+    };
+    let bytes = image.signatures()?.unwrap_or_default();
+    Ok(quirks(&table, &bytes, image.size(), checklist))
+}
+
+/// What the firmware of `checklist` is likely to refuse the certificate table `bytes` at `table`
+/// in an image `len` bytes long for.
+fn quirks(table: &Range<usize>, bytes: &[u8], len: usize, checklist: &[Firmware]) -> Vec<Quirk> {
+    let lengths = lengths(bytes);
+
+    let mut quirks = Vec::new();
+    for firmware in checklist {
+        let count = firmware.max_signatures.map(|_| Check::SignatureCount);
+        for &check in firmware.checks.iter().chain(&count) {
+            let found = match check {
+                Check::TableAlignment => !table.start.is_multiple_of(8),
+                Check::TablePadding => !table.len().is_multiple_of(8),
+                Check::EntryLength => lengths
+                    .iter()
+                    .rev()
+                    .skip(1)
+                    .any(|len| !len.is_multiple_of(8)),
+                Check::SignatureCount => firmware
+                    .max_signatures
+                    .is_some_and(|max| lengths.len() > max),
+                Check::TrailingData => table.end < len,
+            };
+            if found {
+                quirks.push(Quirk {
+                    firmware: firmware.name,
+                    check,
+                });
+            }
+        }
+    }
+    quirks
+}
+
+/// The length each `WIN_CERTIFICATE` of `table` says it has, as [`Layout::pkcs7`] walks them.
+fn lengths(table: &[u8]) -> Vec<usize> {
+    let mut lengths = Vec::new();
+    let mut at = 0;
+    while let Some(len) = table
+        .get(at..at + 4)
+        .and_then(|len| usize::try_from(u32::from_le_bytes(len.try_into().ok()?)).ok())
+        .filter(|&len| len >= 8 && at + len <= table.len())
+    {
+        lengths.push(len);
+        at = (at + len).next_multiple_of(8);
+    }
+    lengths
+}
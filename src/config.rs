@@ -0,0 +1,257 @@
+//! The configuration file, `/etc/sbsevery.toml` unless `--config` names another. It holds
+//! settings that belong to the machine rather than to a single run:
+//!
+//! ```toml
+//! [warnings]
+//! # don't warn that signing may set off BitLocker recovery, see `sbsevery::pcr`
+//! bitlocker = false
+//!
+//! # sign ARM images with their own key, everything else with --key and --cert
+//! [signers.aa64]
+//! key = "/etc/efi-keys/arm/DB.key"
+//! cert = "/etc/efi-keys/arm/DB.crt"
+//!
+//! # and everything under these directories with theirs, whatever the architecture
+//! [[directory]]
+//! path = "/efi/EFI/Lab"
+//! key = "/etc/efi-keys/lab/DB.key"
+//! cert = "/etc/efi-keys/lab/DB.crt"
+//!
+//! # refuse to sign builds revoked below these SBAT generations, see `sbsevery::sbat`
+//! [sbat]
+//! shim = 4
+//! grub = 4
+//!
+//! # never sign anything outside these directories, nor more than 100 files a day
+//! [usage]
+//! allow = ["/efi/EFI/Linux", "/efi/EFI/BOOT"]
+//! max_per_day = 100
+//!
+//! # sign with this cert only
+//! [pin]
+//! cert_sha256 = ["5d1e0f3a…"]
+//! ```
+//!
+//! Relative paths of keys and certs are relative to the configuration file. Those of directories
+//! are compared with the paths files are found at, so they are given like the roots are.
+//! The innermost directory a file is under wins, over its architecture too. See [`crate::usage`]
+//! for the `[usage]` table and [`crate::pin`] for `[pin]`.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{escape, pe::Machine, pin::Pin, usage, Options};
+
+/// Where the configuration is read from by default.
+pub const SYSTEM: &str = "/etc/sbsevery.toml";
+
+/// Everything the configuration file can set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub warnings: Warnings,
+    #[serde(default)]
+    pub signers: Signers,
+    /// Directories whose files are signed with a key and cert of their own.
+    #[serde(rename = "directory", default)]
+    pub directories: Vec<Directory>,
+    /// Lowest SBAT generation of each component that may be signed.
+    #[serde(default)]
+    pub sbat: BTreeMap<String, u32>,
+    /// What the key may be used for.
+    #[serde(default)]
+    pub usage: usage::Policy,
+    /// The certs and keys that may be signed with.
+    #[serde(default)]
+    pub pin: Pin,
+}
+
+/// Warnings that can be turned off once they have been read.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Warnings {
+    /// Warn before signing on a machine that dual boots Windows and has a TPM.
+    #[serde(default = "yes")]
+    pub bitlocker: bool,
+}
+
+/// Keys and certs to sign the images of an architecture with instead of `--key` and `--cert`,
+/// for trees holding ESPs for several platforms.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Signers {
+    pub ia32: Option<Signer>,
+    pub x64: Option<Signer>,
+    pub aa64: Option<Signer>,
+}
+
+/// A key and the cert that goes with it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Signer {
+    pub key: PathBuf,
+    pub cert: PathBuf,
+}
+
+/// Files under `path` are signed with `key` and `cert`, for ESPs shared by several signing
+/// domains.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Directory {
+    pub path: PathBuf,
+    pub key: PathBuf,
+    pub cert: PathBuf,
+}
+
+impl Signers {
+    /// Every architecture with a signer of its own.
+    pub fn iter(&self) -> impl Iterator<Item = (Machine, &Signer)> {
+        [
+            (Machine::Ia32, &self.ia32),
+            (Machine::X64, &self.x64),
+            (Machine::Aa64, &self.aa64),
+        ]
+        .into_iter()
+        .filter_map(|(machine, signer)| Some((machine, signer.as_ref()?)))
+    }
+}
+
+impl Default for Warnings {
+    fn default() -> Self {
+        Self { bitlocker: true }
+    }
+}
+
+fn yes() -> bool {
+    true
+}
+
+impl Config {
+    /// Read the configuration from `path`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or isn't valid.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+        let mut config: Config = toml::from_str(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", escape::path(path)),
+            )
+        })?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for signer in [
+            &mut config.signers.ia32,
+            &mut config.signers.x64,
+            &mut config.signers.aa64,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            signer.key = dir.join(&signer.key);
+            signer.cert = dir.join(&signer.cert);
+        }
+        for directory in &mut config.directories {
+            directory.key = dir.join(&directory.key);
+            directory.cert = dir.join(&directory.cert);
+        }
+        if let Some(counts) = &mut config.usage.counts {
+            *counts = dir.join(&*counts);
+        }
+
+        Ok(config)
+    }
+
+    /// Read [`SYSTEM`], a missing file is the default configuration.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file exists but can't be read or isn't valid.
+    pub fn system() -> io::Result<Self> {
+        let path = Path::new(SYSTEM);
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Read the configuration of a run given `--config path`, or [`SYSTEM`] without one. The
+    /// usage policy and pins of [`SYSTEM`] hold either way.
+    ///
+    /// # Errors
+    ///
+    /// Fails if either file can't be read or isn't valid.
+    pub fn given(path: Option<&Path>) -> io::Result<Self> {
+        let Some(path) = path else {
+            return Self::system();
+        };
+        let mut config = Self::load(path)?;
+        // where the system counts the day, another file can't start counting over, see
+        // usage::Policy::and
+        let system = Self::system()?;
+        config.usage = system.usage.and(config.usage);
+        config.pin = system.pin.and(config.pin);
+        Ok(config)
+    }
+
+    /// Carry the settings over into `options`.
+    pub fn apply(&self, options: &mut Options) {
+        options.warn_bitlocker = self.warnings.bitlocker;
+        options.sbat_minimum.clone_from(&self.sbat);
+        options.usage.clone_from(&self.usage);
+        options.pin.clone_from(&self.pin);
+        options.signers = self
+            .signers
+            .iter()
+            .map(|(machine, signer)| (machine, signer.clone()))
+            .collect();
+        options.directories = self
+            .directories
+            .iter()
+            .map(|d| {
+                let signer = Signer {
+                    key: d.key.clone(),
+                    cert: d.cert.clone(),
+                };
+                (d.path.clone(), signer)
+            })
+            .collect();
+    }
+}
+
+/// The signer `options` gives `file` other than `--key` and `--cert`, if any: that of the
+/// innermost directory it is under, or that of its architecture, `machine` fetching it.
+pub(crate) fn signer<'a>(
+    options: &'a Options,
+    file: &Path,
+    machine: impl FnOnce() -> Option<u16>,
+) -> Option<&'a Signer> {
+    let directory = options
+        .directories
+        .iter()
+        .filter(|(dir, _)| file.starts_with(dir))
+        .max_by_key(|(dir, _)| dir.components().count());
+    if let Some((_, signer)) = directory {
+        return Some(signer);
+    }
+    if options.signers.is_empty() {
+        return None;
+    }
+
+    let machine = machine()?;
+    options
+        .signers
+        .iter()
+        .find(|(m, _)| m.value() == machine)
+        .map(|(_, signer)| signer)
+}
@@ -0,0 +1,734 @@
+//! `sbsevery watch --dbus`, the watcher as the `org.sbsevery.Manager` D-Bus service, for
+//! desktop frontends and other services to have files signed and checked by it without a
+//! protocol of their own.
+//!
+//! The object is `/org/sbsevery/Manager`, with the interface `org.sbsevery.Manager`:
+//!
+//! ```text
+//! Sign(s path) -> (s report)            sign a file or directory under the roots now
+//! Verify(s path) -> (s compliance)      check it is signed by the cert
+//! GetStatus() -> (s status)             how the watcher is doing
+//! Reload()                              read the configuration file again
+//! signal Completed(as paths, b ok, u signed, u failed)
+//! ```
+//!
+//! What comes back is JSON: the report of a run, the compliance report of `sbsevery verify` and
+//! what the `--health` file says. `Completed` is sent after each pass, with the roots, and after
+//! each `Sign`. Failing requests return the error `org.sbsevery.Error.Failed`.
+//!
+//! Who may own the name and call what is up to the bus. On the system bus
+//! `data/org.sbsevery.Manager.conf` lets root do everything and anyone ask for the status.
+//!
+//! The protocol is spoken here, over the bus socket, with only what the service needs of it:
+//! methods taking strings, and `Introspect` and `Ping` for `busctl` and `gdbus`.
+
+use std::{
+    ffi::OsStr,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixStream},
+        },
+    },
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::watch::{Completed, Request, Requests};
+
+/// The bus name of the service, and its interface.
+pub const NAME: &str = "org.sbsevery.Manager";
+
+/// The path of its object.
+pub const PATH: &str = "/org/sbsevery/Manager";
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+
+const NO_REPLY_EXPECTED: u8 = 0x1;
+
+/// Header field codes.
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// The largest message the specification allows.
+const MAX_MESSAGE: usize = 128 << 20;
+
+/// Which bus to be on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    /// The system bus, `DBUS_SYSTEM_BUS_ADDRESS` or the usual socket.
+    System,
+    /// The bus of the session, `DBUS_SESSION_BUS_ADDRESS`.
+    Session,
+}
+
+impl FromStr for Bus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "system" => Ok(Self::System),
+            "session" => Ok(Self::Session),
+            _ => Err(format!("unknown bus '{s}', expected system or session")),
+        }
+    }
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::System => "system",
+            Self::Session => "session",
+        })
+    }
+}
+
+/// Connect to `bus`, take [`NAME`] and answer calls with `requests`, on threads of their own
+/// for as long as the process runs.
+///
+/// # Errors
+///
+/// Fails if the bus can't be reached, doesn't let us in, or someone has the name already.
+pub fn serve(bus: Bus, requests: Requests) -> io::Result<()> {
+    let stream = UnixStream::connect_addr(&address(bus)?)
+        .map_err(|e| io::Error::new(e.kind(), format!("can't reach the {bus} bus: {e}")))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let sender = Arc::new(Mutex::new(Sender { stream, serial: 0 }));
+    authenticate(&mut reader, &sender)?;
+
+    call_bus(&mut reader, &sender, "Hello", "", Encoder::default())?;
+    let mut body = Encoder::default();
+    body.string(NAME);
+    // DBUS_NAME_FLAG_DO_NOT_QUEUE
+    body.u32(4);
+    let reply = call_bus(&mut reader, &sender, "RequestName", "su", body)?;
+    // DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER, or _ALREADY_OWNER
+    if !matches!(
+        Decoder::new(&reply.body, reply.big_endian).u32(),
+        Some(1 | 4)
+    ) {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{NAME} is taken on the {bus} bus, is another watcher running?"),
+        ));
+    }
+
+    let completions = requests.completions();
+    let signals = Arc::clone(&sender);
+    thread::spawn(move || {
+        for completed in completions {
+            let message = completed_signal(&completed);
+            let Ok(mut sender) = signals.lock() else {
+                return;
+            };
+            if sender.send(SIGNAL, &message).is_err() {
+                return;
+            }
+        }
+    });
+    thread::spawn(move || loop {
+        let call = match read(&mut reader) {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("lost the {bus} bus: {e}");
+                return;
+            }
+        };
+        if call.kind != METHOD_CALL {
+            continue;
+        }
+        let reply = answer(&call, &requests);
+        if call.flags & NO_REPLY_EXPECTED != 0 {
+            continue;
+        }
+        let Ok(mut sender) = sender.lock() else {
+            return;
+        };
+        let sent = match reply {
+            Ok((signature, body)) => {
+                let mut message = Outgoing::reply(&call);
+                message.body(signature, body);
+                sender.send(METHOD_RETURN, &message)
+            }
+            Err((name, text)) => {
+                let mut message = Outgoing::reply(&call);
+                message.field(FIELD_ERROR_NAME, Field::String(name));
+                let mut body = Encoder::default();
+                body.string(&text);
+                message.body("s", body);
+                sender.send(ERROR, &message)
+            }
+        };
+        if sent.is_err() {
+            return;
+        }
+    });
+
+    Ok(())
+}
+
+/// The signature and body of the reply to `call`, or the name and message of the error.
+fn answer(
+    call: &Incoming,
+    requests: &Requests,
+) -> Result<(&'static str, Encoder), (&'static str, String)> {
+    let path = call.path.as_deref().unwrap_or_default();
+    let member = call.member.as_deref().unwrap_or_default();
+    let interface = call.interface.as_deref();
+
+    if interface == Some("org.freedesktop.DBus.Peer") && member == "Ping" {
+        return Ok(("", Encoder::default()));
+    }
+    if matches!(
+        interface,
+        Some("org.freedesktop.DBus.Introspectable") | None
+    ) && member == "Introspect"
+    {
+        let xml = introspect(path).ok_or_else(|| unknown_object(path))?;
+        let mut body = Encoder::default();
+        body.string(&xml);
+        return Ok(("s", body));
+    }
+    if path != PATH {
+        return Err(unknown_object(path));
+    }
+    if !matches!(interface, Some(NAME) | None) {
+        return Err(unknown_method(call));
+    }
+
+    let request = match member {
+        "Sign" | "Verify" => {
+            let strings = call.strings();
+            let Some([arg]) = strings.as_deref() else {
+                return Err((
+                    "org.freedesktop.DBus.Error.InvalidArgs",
+                    format!("{member} takes a path, a string"),
+                ));
+            };
+            let path = PathBuf::from(OsStr::from_bytes(arg.as_bytes()));
+            if member == "Sign" {
+                Request::Sign(path)
+            } else {
+                Request::Verify(path)
+            }
+        }
+        "GetStatus" if call.signature.is_empty() => Request::Status,
+        "Reload" if call.signature.is_empty() => {
+            requests
+                .call(Request::Reload)
+                .map_err(|e| ("org.sbsevery.Error.Failed", e))?;
+            return Ok(("", Encoder::default()));
+        }
+        _ => return Err(unknown_method(call)),
+    };
+    let answer = requests
+        .call(request)
+        .map_err(|e| ("org.sbsevery.Error.Failed", e))?;
+    let mut body = Encoder::default();
+    body.string(&answer.to_string());
+    Ok(("s", body))
+}
+
+fn unknown_object(path: &str) -> (&'static str, String) {
+    (
+        "org.freedesktop.DBus.Error.UnknownObject",
+        format!("no object at {path}"),
+    )
+}
+
+fn unknown_method(call: &Incoming) -> (&'static str, String) {
+    (
+        "org.freedesktop.DBus.Error.UnknownMethod",
+        format!(
+            "no method {}.{} taking '{}'",
+            call.interface.as_deref().unwrap_or(NAME),
+            call.member.as_deref().unwrap_or_default(),
+            call.signature
+        ),
+    )
+}
+
+/// The introspection data of the object at `path`, or of the nodes on the way to it.
+fn introspect(path: &str) -> Option<String> {
+    const DOCTYPE: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">"#;
+
+    if path == PATH {
+        return Some(format!(
+            r#"{DOCTYPE}
+<node>
+  <interface name="{NAME}">
+    <method name="Sign"><arg name="path" type="s" direction="in"/><arg name="report" type="s" direction="out"/></method>
+    <method name="Verify"><arg name="path" type="s" direction="in"/><arg name="compliance" type="s" direction="out"/></method>
+    <method name="GetStatus"><arg name="status" type="s" direction="out"/></method>
+    <method name="Reload"/>
+    <signal name="Completed"><arg name="paths" type="as"/><arg name="ok" type="b"/><arg name="signed" type="u"/><arg name="failed" type="u"/></signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect"><arg name="xml" type="s" direction="out"/></method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#
+        ));
+    }
+    let rest = if path == "/" {
+        &PATH[1..]
+    } else {
+        PATH.strip_prefix(path)?.strip_prefix('/')?
+    };
+    let child = rest.split('/').next()?;
+    Some(format!(
+        "{DOCTYPE}\n<node>\n  <node name=\"{child}\"/>\n</node>\n"
+    ))
+}
+
+fn completed_signal(completed: &Completed) -> Outgoing {
+    let mut message = Outgoing::default();
+    message.field(FIELD_PATH, Field::Path(PATH));
+    message.field(FIELD_INTERFACE, Field::String(NAME));
+    message.field(FIELD_MEMBER, Field::String("Completed"));
+    let paths: Vec<String> = completed
+        .paths
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    let mut body = Encoder::default();
+    body.strings(&paths);
+    body.u32(u32::from(completed.ok));
+    body.u32(u32::try_from(completed.signed).unwrap_or(u32::MAX));
+    body.u32(u32::try_from(completed.failed).unwrap_or(u32::MAX));
+    message.body("asbuu", body);
+    message
+}
+
+/// The socket of `bus`, the first `unix:` one of its address.
+fn address(bus: Bus) -> io::Result<SocketAddr> {
+    let address = match bus {
+        Bus::System => std::env::var("DBUS_SYSTEM_BUS_ADDRESS")
+            .unwrap_or_else(|_| "unix:path=/run/dbus/system_bus_socket".to_string()),
+        Bus::Session => std::env::var("DBUS_SESSION_BUS_ADDRESS").map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "DBUS_SESSION_BUS_ADDRESS isn't set, is there a session bus?",
+            )
+        })?,
+    };
+
+    for transport in address.split(';') {
+        let Some(keys) = transport.strip_prefix("unix:") else {
+            continue;
+        };
+        for key in keys.split(',') {
+            if let Some(path) = key.strip_prefix("path=") {
+                return SocketAddr::from_pathname(OsStr::from_bytes(&unescape(path)));
+            }
+            if let Some(name) = key.strip_prefix("abstract=") {
+                return SocketAddr::from_abstract_name(unescape(name));
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("no unix socket in the {bus} bus address '{address}'"),
+    ))
+}
+
+/// An address value with its `%xx` escapes undone.
+fn unescape(value: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let hex = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) if b == b'%' => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    bytes
+}
+
+/// Log in as who we are, `EXTERNAL` auth being the bus checking our uid on the socket.
+fn authenticate(reader: &mut BufReader<UnixStream>, sender: &Mutex<Sender>) -> io::Result<()> {
+    // SAFETY: no pointers involved
+    let uid = unsafe { libc::getuid() }.to_string();
+    let uid = crate::state::hex(uid.as_bytes());
+    let mut sender = sender.lock().map_err(|_| io::ErrorKind::Other)?;
+    sender
+        .stream
+        .write_all(format!("\0AUTH EXTERNAL {uid}\r\n").as_bytes())?;
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("OK ") {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("the bus didn't let us in: {}", line.trim_end()),
+        ));
+    }
+    sender.stream.write_all(b"BEGIN\r\n")
+}
+
+/// Call `member` of the bus itself and wait for the reply, before anything else is going on.
+fn call_bus(
+    reader: &mut BufReader<UnixStream>,
+    sender: &Mutex<Sender>,
+    member: &'static str,
+    signature: &'static str,
+    body: Encoder,
+) -> io::Result<Incoming> {
+    let mut message = Outgoing::default();
+    message.field(FIELD_PATH, Field::Path("/org/freedesktop/DBus"));
+    message.field(FIELD_INTERFACE, Field::String("org.freedesktop.DBus"));
+    message.field(FIELD_MEMBER, Field::String(member));
+    message.field(FIELD_DESTINATION, Field::String("org.freedesktop.DBus"));
+    message.body(signature, body);
+    let serial = sender
+        .lock()
+        .map_err(|_| io::ErrorKind::Other)?
+        .send(METHOD_CALL, &message)?;
+
+    loop {
+        let reply = read(reader)?;
+        if reply.reply_serial != Some(serial) {
+            continue;
+        }
+        if reply.kind == ERROR {
+            let text = reply.strings().and_then(|mut strings| strings.pop());
+            return Err(io::Error::other(format!(
+                "{member}: {} {}",
+                reply.error_name.unwrap_or_default(),
+                text.unwrap_or_default()
+            )));
+        }
+        return Ok(reply);
+    }
+}
+
+/// Our end of the connection, numbering what it sends.
+struct Sender {
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Sender {
+    /// Send `message` as a message of `kind`, returning its serial.
+    fn send(&mut self, kind: u8, message: &Outgoing) -> io::Result<u32> {
+        self.serial = self.serial.wrapping_add(1).max(1);
+        self.stream.write_all(&message.encode(kind, self.serial))?;
+        Ok(self.serial)
+    }
+}
+
+/// A header field value.
+enum Field {
+    Path(&'static str),
+    String(&'static str),
+    Owned(String),
+    Signature(&'static str),
+    U32(u32),
+}
+
+/// A message about to be sent.
+#[derive(Default)]
+struct Outgoing {
+    fields: Vec<(u8, Field)>,
+    body: Encoder,
+}
+
+impl Outgoing {
+    /// The start of a reply to `call`.
+    fn reply(call: &Incoming) -> Self {
+        let mut message = Self::default();
+        message.field(FIELD_REPLY_SERIAL, Field::U32(call.serial));
+        if let Some(sender) = &call.sender {
+            message.field(FIELD_DESTINATION, Field::Owned(sender.clone()));
+        }
+        message
+    }
+
+    fn field(&mut self, code: u8, value: Field) {
+        self.fields.push((code, value));
+    }
+
+    fn body(&mut self, signature: &'static str, body: Encoder) {
+        if !signature.is_empty() {
+            self.field(FIELD_SIGNATURE, Field::Signature(signature));
+        }
+        self.body = body;
+    }
+
+    fn encode(&self, kind: u8, serial: u32) -> Vec<u8> {
+        let mut header = Encoder::default();
+        header.0.extend([b'l', kind, 0, 1]);
+        header.u32(u32::try_from(self.body.0.len()).unwrap_or(u32::MAX));
+        header.u32(serial);
+
+        // an array of (byte code, variant value), its length not counting the padding
+        let at = header.0.len();
+        header.u32(0);
+        header.align(8);
+        let start = header.0.len();
+        for (code, value) in &self.fields {
+            header.align(8);
+            header.0.push(*code);
+            match value {
+                Field::Path(path) => {
+                    header.signature("o");
+                    header.string(path);
+                }
+                Field::String(s) => {
+                    header.signature("s");
+                    header.string(s);
+                }
+                Field::Owned(s) => {
+                    header.signature("s");
+                    header.string(s);
+                }
+                Field::Signature(signature) => {
+                    header.signature("g");
+                    header.signature(signature);
+                }
+                Field::U32(n) => {
+                    header.signature("u");
+                    header.u32(*n);
+                }
+            }
+        }
+        let len = u32::try_from(header.0.len() - start).unwrap_or(u32::MAX);
+        header.0[at..at + 4].copy_from_slice(&len.to_le_bytes());
+
+        header.align(8);
+        header.0.extend(&self.body.0);
+        header.0
+    }
+}
+
+/// Marshals values in little endian, aligned from the start of the buffer.
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn align(&mut self, to: usize) {
+        self.0.resize(self.0.len().next_multiple_of(to), 0);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.align(4);
+        self.0.extend(n.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(u32::try_from(s.len()).unwrap_or(u32::MAX));
+        self.0.extend(s.as_bytes());
+        self.0.push(0);
+    }
+
+    fn signature(&mut self, signature: &str) {
+        self.0
+            .push(u8::try_from(signature.len()).unwrap_or(u8::MAX));
+        self.0.extend(signature.as_bytes());
+        self.0.push(0);
+    }
+
+    fn strings(&mut self, strings: &[String]) {
+        self.align(4);
+        let at = self.0.len();
+        self.u32(0);
+        let start = self.0.len();
+        for s in strings {
+            self.string(s);
+        }
+        let len = u32::try_from(self.0.len() - start).unwrap_or(u32::MAX);
+        self.0[at..at + 4].copy_from_slice(&len.to_le_bytes());
+    }
+}
+
+/// A message received.
+#[derive(Default)]
+struct Incoming {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    big_endian: bool,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    sender: Option<String>,
+    signature: String,
+    body: Vec<u8>,
+}
+
+impl Incoming {
+    /// The arguments, if they are all strings.
+    fn strings(&self) -> Option<Vec<String>> {
+        let mut decoder = Decoder::new(&self.body, self.big_endian);
+        self.signature
+            .chars()
+            .map(|c| if c == 's' { decoder.string() } else { None })
+            .collect()
+    }
+}
+
+/// Read the next message. One whose header we can't make sense of comes back with nothing set.
+fn read(reader: &mut impl Read) -> io::Result<Incoming> {
+    let mut fixed = [0; 16];
+    reader.read_exact(&mut fixed)?;
+    let big_endian = match fixed[0] {
+        b'l' => false,
+        b'B' => true,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a D-Bus message",
+            ))
+        }
+    };
+    let decoder = Decoder::new(&fixed, big_endian);
+    let word = |at: usize| decoder.word(at).map_or(usize::MAX, |n| n as usize);
+    let (body_len, fields_len) = (word(4), word(12));
+    let header_len = (16 + fields_len).next_multiple_of(8);
+    if header_len.saturating_add(body_len) > MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too long",
+        ));
+    }
+    let mut data = fixed.to_vec();
+    data.resize(header_len + body_len, 0);
+    reader.read_exact(&mut data[16..])?;
+
+    let mut message = Incoming {
+        kind: fixed[1],
+        flags: fixed[2],
+        serial: u32::try_from(word(8)).unwrap_or_default(),
+        big_endian,
+        body: data[header_len..].to_vec(),
+        ..Incoming::default()
+    };
+    if fields(&data[..16 + fields_len], &mut message).is_none() {
+        return Ok(Incoming::default());
+    }
+    Ok(message)
+}
+
+/// Take the header fields of `header` into `message`.
+fn fields(header: &[u8], message: &mut Incoming) -> Option<()> {
+    let mut decoder = Decoder::new(header, message.big_endian);
+    decoder.at = 16;
+    while decoder.at < header.len() {
+        decoder.align(8);
+        let code = decoder.byte()?;
+        let signature = decoder.signature()?;
+        match (code, signature.as_str()) {
+            (FIELD_REPLY_SERIAL, "u") => message.reply_serial = Some(decoder.u32()?),
+            (FIELD_SIGNATURE, "g") => message.signature = decoder.signature()?,
+            (_, "s" | "o") => {
+                let value = Some(decoder.string()?);
+                match code {
+                    FIELD_PATH => message.path = value,
+                    FIELD_INTERFACE => message.interface = value,
+                    FIELD_MEMBER => message.member = value,
+                    FIELD_ERROR_NAME => message.error_name = value,
+                    FIELD_SENDER => message.sender = value,
+                    _ => {}
+                }
+            }
+            (_, "g") => {
+                decoder.signature()?;
+            }
+            (_, "u") => {
+                decoder.u32()?;
+            }
+            // nothing else is in a header
+            _ => return None,
+        }
+    }
+    Some(())
+}
+
+/// Unmarshals values, aligned from the start of `data`.
+struct Decoder<'a> {
+    data: &'a [u8],
+    at: usize,
+    big_endian: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8], big_endian: bool) -> Self {
+        Self {
+            data,
+            at: 0,
+            big_endian,
+        }
+    }
+
+    fn align(&mut self, to: usize) {
+        self.at = self.at.next_multiple_of(to);
+    }
+
+    fn word(&self, at: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(at..at.checked_add(4)?)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let b = *self.data.get(self.at)?;
+        self.at += 1;
+        Some(b)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.align(4);
+        let n = self.word(self.at)?;
+        self.at += 4;
+        Some(n)
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<String> {
+        let bytes = self.data.get(self.at..self.at.checked_add(len)?)?;
+        // and the NUL after them
+        self.at += len + 1;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    fn signature(&mut self) -> Option<String> {
+        let len = usize::from(self.byte()?);
+        self.bytes(len)
+    }
+}
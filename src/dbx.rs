@@ -0,0 +1,79 @@
+//! `--dbx`, the UEFI forbidden signature database. Signing an image whose hash is in there is
+//! pointless, firmware refuses to run it whatever signs it. The db is read the same way, to
+//! find the entry of a cert for `--predict-pcrs`.
+
+use std::{io, path::Path};
+
+/// `EFI_CERT_SHA256_GUID`, as it is laid out on disk.
+pub const CERT_SHA256: [u8; 16] = [
+    0x26, 0x16, 0xc4, 0xc1, 0x4c, 0x50, 0x92, 0x40, 0xac, 0xa9, 0x41, 0xf9, 0x36, 0x93, 0x43, 0x28,
+];
+
+/// `EFI_CERT_X509_GUID`, as it is laid out on disk.
+pub const CERT_X509: [u8; 16] = [
+    0xa1, 0x59, 0xc0, 0xa5, 0xe4, 0x94, 0xa7, 0x4a, 0x87, 0xb5, 0xab, 0x15, 0x5c, 0x2b, 0xf0, 0x72,
+];
+
+/// Where Linux exposes the firmware's dbx.
+pub const SYSTEM: &str = "/sys/firmware/efi/efivars/dbx-d719b2cb-3d3a-4596-a3bc-dad00e67656f";
+
+/// Where Linux exposes the firmware's db, the signature database images are checked against.
+pub const DB: &str = "/sys/firmware/efi/efivars/db-d719b2cb-3d3a-4596-a3bc-dad00e67656f";
+
+/// Read the SHA-256 entries out of a dbx, either an EFI signature list file as written by
+/// `sbsiglist` and friends or the efivar itself, which is the same with 4 bytes of attributes
+/// in front. Certificate entries are ignored.
+pub(crate) fn load(path: &Path) -> io::Result<Vec<[u8; 32]>> {
+    let mut hashes: Vec<[u8; 32]> = entries(path, &CERT_SHA256)?
+        .iter()
+        // a 16 byte owner GUID, then the hash
+        .filter_map(|entry| entry.get(16..)?.try_into().ok())
+        .collect();
+
+    hashes.sort_unstable();
+    Ok(hashes)
+}
+
+/// Every `EFI_SIGNATURE_DATA` of type `kind` in a signature database file or efivar, owner
+/// GUID and all.
+pub(crate) fn entries(path: &Path, kind: &[u8; 16]) -> io::Result<Vec<Vec<u8>>> {
+    let data = std::fs::read(path)?;
+    let mut lists = &data[..];
+    if path.starts_with("/sys/firmware/efi/efivars") {
+        lists = lists.get(4..).unwrap_or_default();
+    }
+
+    parse(lists, kind).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not an EFI signature list", crate::escape::path(path)),
+        )
+    })
+}
+
+/// Every `EFI_SIGNATURE_DATA` of type `kind` in `lists`, back to back `EFI_SIGNATURE_LIST`s
+/// as a file has them, `None` if the size of one doesn't add up.
+#[must_use]
+pub fn parse(mut lists: &[u8], kind: &[u8; 16]) -> Option<Vec<Vec<u8>>> {
+    let mut found = Vec::new();
+    while lists.len() >= 28 {
+        let le32 = |at: usize| {
+            u32::from_le_bytes([lists[at], lists[at + 1], lists[at + 2], lists[at + 3]]) as usize
+        };
+        let (list_size, header_size, entry_size) = (le32(16), le32(20), le32(24));
+        let first = header_size.checked_add(28)?;
+        if list_size < first || list_size > lists.len() || entry_size <= 16 {
+            return None;
+        }
+
+        if lists[..16] == *kind {
+            for entry in lists[first..list_size].chunks_exact(entry_size) {
+                found.push(entry.to_vec());
+            }
+        }
+
+        lists = &lists[list_size..];
+    }
+
+    Some(found)
+}
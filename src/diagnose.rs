@@ -0,0 +1,86 @@
+//! Making sense of what signers print. Their exit status only says whether a signature was
+//! written, the warnings on stderr say what else happened while doing so. Whatever sbsevery
+//! finds wrong with an image itself before signing it ends up alongside, see
+//! [`Layout::problems`].
+
+use std::fmt;
+
+use crate::{
+    compat::Quirk,
+    pe::{Layout, Problem},
+};
+
+/// Something a signer warned about while signing a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Warning {
+    /// The file already had a signature, the new one was added next to it. Unless `--resign`
+    /// was given the file is left as it was and counted as skipped instead.
+    AlreadySigned,
+    /// sbsign found gaps between the sections. Some firmware refuses to load such images, and
+    /// others measure them differently, `--strict-pe` makes it a failure.
+    SectionGaps,
+    /// Found by looking at the image before signing it.
+    Image(Problem),
+    /// Found by looking at the image once signed, with `--compat`. Printed without `--verbose`
+    /// but not failed on, the image is signed by then.
+    Compat(Quirk),
+}
+
+impl Warning {
+    /// Whether it is about the image itself rather than how it was signed, these are the
+    /// warnings `--strict-pe` fails on and the ones printed without `--verbose`.
+    #[must_use]
+    pub fn pe(self) -> bool {
+        matches!(self, Warning::SectionGaps | Warning::Image(_))
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::AlreadySigned => f.write_str("already-signed"),
+            Warning::SectionGaps => f.write_str("section-gaps"),
+            Warning::Image(problem) => problem.fmt(f),
+            Warning::Compat(quirk) => quirk.fmt(f),
+        }
+    }
+}
+
+/// What sbsign says, in its various versions, when it stacks a signature on an old one.
+const ALREADY_SIGNED: &[&str] = &["already signed", "overwriting existing signature"];
+
+/// What it says when the section table doesn't cover the image contiguously.
+const SECTION_GAPS: &[&str] = &[
+    "gap in section table",
+    "gaps in the section table",
+    "gaps between sections",
+];
+
+/// The warnings in a signer's `stderr`, each once, in the order of [`Warning`].
+pub(crate) fn classify(stderr: &[u8]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for line in String::from_utf8_lossy(stderr).lines() {
+        let line = line.to_lowercase();
+        if ALREADY_SIGNED.iter().any(|s| line.contains(s)) {
+            warnings.push(Warning::AlreadySigned);
+        }
+        if SECTION_GAPS.iter().any(|s| line.contains(s)) {
+            warnings.push(Warning::SectionGaps);
+        }
+    }
+
+    warnings.sort();
+    warnings.dedup();
+    warnings
+}
+
+/// The problems of the image `data`, none if it isn't one.
+#[must_use]
+pub fn validate(data: &[u8]) -> Vec<Warning> {
+    Layout::parse(data)
+        .map(|layout| layout.problems(data))
+        .unwrap_or_default()
+        .into_iter()
+        .map(Warning::Image)
+        .collect()
+}
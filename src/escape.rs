@@ -0,0 +1,187 @@
+//! Printing paths so that nothing gets lost or mangled on the way: file systems touched by
+//! other operating systems turn up names that aren't UTF-8, and a name with a newline or an
+//! escape sequence in it shouldn't be able to forge log lines or mess up the terminal.
+//!
+//! Backslashes are doubled, tabs, newlines and carriage returns become `\t`, `\n` and `\r`,
+//! other control characters `\u{..}` and bytes that aren't valid UTF-8 `\x..`. So do the
+//! characters that can't be seen but move or hide what is around them: bidirectional overrides
+//! and isolates, zero width spaces and joiners, the line and paragraph separators and byte order
+//! marks, which would have a name read differently than it is, or split a log line for a parser
+//! that knows Unicode. Everything else is printed as is, so ordinary paths look no different,
+//! and [`unescape`] gets the original back.
+//!
+//! Text that isn't a path but may come from anywhere, a signer's stderr or what an image says
+//! about itself, is escaped with [`text`], and [`width`] and [`pad`] line it up by the columns
+//! a terminal gives it rather than by its length in bytes.
+
+use std::{
+    ffi::OsStr,
+    fmt::{self, Write},
+};
+
+/// A name or path, displayed escaped.
+#[derive(Debug, Clone, Copy)]
+pub struct Escaped<'a>(pub &'a OsStr);
+
+/// Display `path` escaped.
+pub fn path<P: AsRef<OsStr> + ?Sized>(path: &P) -> Escaped<'_> {
+    Escaped(path.as_ref())
+}
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.0.as_encoded_bytes().utf8_chunks() {
+            for c in chunk.valid().chars() {
+                match c {
+                    '\\' => f.write_str("\\\\")?,
+                    c => escape_char(f, c)?,
+                }
+            }
+            for b in chunk.invalid() {
+                write!(f, "\\x{b:02x}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Text, displayed escaped as a path is but for backslashes, which are left alone: it isn't
+/// read back, and is often a message with escaped paths in it already.
+#[derive(Debug, Clone, Copy)]
+pub struct Text<'a>(pub &'a str);
+
+/// Display `text` escaped.
+#[must_use]
+pub fn text(text: &str) -> Text<'_> {
+    Text(text)
+}
+
+impl fmt::Display for Text<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.chars().try_for_each(|c| escape_char(f, c))
+    }
+}
+
+fn escape_char(f: &mut fmt::Formatter<'_>, c: char) -> fmt::Result {
+    match c {
+        '\t' => f.write_str("\\t"),
+        '\n' => f.write_str("\\n"),
+        '\r' => f.write_str("\\r"),
+        c if c.is_control() || invisible(c) => write!(f, "\\u{{{:x}}}", u32::from(c)),
+        c => f.write_char(c),
+    }
+}
+
+/// Whether `c` is one of the characters that show as nothing but change how what is around
+/// them reads.
+fn invisible(c: char) -> bool {
+    matches!(
+        c,
+        // soft hyphen, Arabic letter mark, Mongolian vowel separator
+        '\u{ad}' | '\u{61c}' | '\u{180e}'
+        // zero width space, non-joiner, joiner, left-to-right and right-to-left marks
+        | '\u{200b}'..='\u{200f}'
+        // line and paragraph separators, embeddings and overrides
+        | '\u{2028}'..='\u{202e}'
+        // word joiner, invisible operators, isolates, deprecated format characters
+        | '\u{2060}'..='\u{206f}'
+        | '\u{feff}'
+        // interlinear annotation
+        | '\u{fff9}'..='\u{fffb}'
+        | '\u{e0000}'..='\u{e007f}'
+    )
+}
+
+/// The columns a terminal shows `text` in: two for East Asian wide characters and most emoji,
+/// none for combining marks, one for anything else. `text` is taken to be escaped already,
+/// control characters aren't counted for anything.
+#[must_use]
+pub fn width(text: &str) -> usize {
+    text.chars().map(columns).sum()
+}
+
+fn columns(c: char) -> usize {
+    match u32::from(c) {
+        0x0300..=0x036f
+        | 0x0483..=0x0489
+        | 0x0591..=0x05bd
+        | 0x0610..=0x061a
+        | 0x064b..=0x065f
+        | 0x1ab0..=0x1aff
+        | 0x1dc0..=0x1dff
+        | 0x200b..=0x200f
+        | 0x20d0..=0x20ff
+        | 0xfe00..=0xfe0f
+        | 0xfe20..=0xfe2f => 0,
+        _ if c.is_control() => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x2fffd
+        | 0x30000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+/// `text` with spaces after it up to `columns` [`width`], as it is if it is that wide already.
+#[must_use]
+pub fn pad(text: &str, columns: usize) -> String {
+    let mut padded = text.to_string();
+    padded.extend(std::iter::repeat_n(
+        ' ',
+        columns.saturating_sub(width(text)),
+    ));
+    padded
+}
+
+/// Undo the escaping, `None` if `s` isn't something [`Escaped`] could have printed.
+#[cfg(unix)]
+#[must_use]
+pub fn unescape(s: &str) -> Option<std::ffi::OsString> {
+    use std::os::unix::ffi::OsStringExt;
+
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next()? {
+            '\\' => bytes.push(b'\\'),
+            't' => bytes.push(b'\t'),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+            }
+            'u' => {
+                if chars.next()? != '{' {
+                    return None;
+                }
+                let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let c = char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?;
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            _ => return None,
+        }
+    }
+
+    Some(std::ffi::OsString::from_vec(bytes))
+}
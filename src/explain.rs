@@ -0,0 +1,231 @@
+//! `sbsevery explain`, what a run with the same config and flags would do with a file and why,
+//! step by step, for working out why a filter or rule doesn't do what it was meant to.
+//!
+//! ```text
+//! /efi/EFI/Linux/arch.efi
+//!     size:       24117248 bytes
+//!     include:    EFI/** matches
+//!     exclude:    EFI/Microsoft/** doesn't match
+//!     pe:         x64
+//!     signature:  CN=Old Arch Key
+//!     rule:       rule 3 (resign path EFI/Linux/*.efi): resign
+//!     action:     resign
+//! ```
+//!
+//! The action is decided by the same filters a run uses, the steps before it only show what
+//! they had to go on.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    escape,
+    filter::{self, Filter, Skip},
+    glob, inventory, pe, plugin,
+    rules::Rules,
+    state::{sha256_file, State},
+    Options,
+};
+
+/// What a run would do with a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Sign,
+    /// Sign it even though it is signed already.
+    Resign,
+    Skip(Skip),
+    /// Fail it, as an `error` rule says.
+    Refuse(String),
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Sign => f.write_str("sign"),
+            Decision::Resign => f.write_str("resign"),
+            Decision::Skip(reason) => write!(f, "skip ({reason})"),
+            Decision::Refuse(why) => write!(f, "fail ({why})"),
+        }
+    }
+}
+
+/// How a file was decided on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub file: PathBuf,
+    /// What was found out about it on the way, in order, as a kind and what it said.
+    pub steps: Vec<(&'static str, String)>,
+    pub decision: Decision,
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", escape::path(&self.file))?;
+        // what the steps say can come from the image, a command line or .sbat
+        for (kind, what) in &self.steps {
+            let kind = escape::pad(&format!("{kind}:"), 12);
+            writeln!(f, "    {kind}{}", escape::text(what))?;
+        }
+        let decision = self.decision.to_string();
+        write!(
+            f,
+            "    {}{}",
+            escape::pad("action:", 12),
+            escape::text(&decision)
+        )
+    }
+}
+
+/// Trace what a run with `options` would do with `file`.
+///
+/// # Errors
+///
+/// Fails if the filters can't be set up, a rules file or dbx that can't be read say.
+pub fn explain(file: &Path, options: &Options) -> io::Result<Trace> {
+    let plugins = match &options.plugins {
+        Some(dir) => plugin::discover(dir)?,
+        None => Vec::new(),
+    };
+    let filter = Filter::new(options, &plugins)?;
+
+    let mut steps = Vec::new();
+    match file.metadata() {
+        Ok(meta) => steps.push(("size", format!("{} bytes", meta.len()))),
+        Err(e) => steps.push(("size", format!("unreadable, {e}"))),
+    }
+    names(file, options, &mut steps);
+    contents(file, &mut steps);
+    if let Some(path) = &options.rules {
+        let rules = Rules::load(path)?;
+        steps.push((
+            "rule",
+            match rules.decide(file, options) {
+                Some(rule) => format!("{rule}: {}", rule.action),
+                None => "no rule applies".to_string(),
+            },
+        ));
+    }
+
+    let decision = match filter.check(file, options) {
+        Some(Skip::Refused) => Decision::Refuse(
+            filter
+                .refused()
+                .into_iter()
+                .map(|(_, why)| why)
+                .next()
+                .unwrap_or_default(),
+        ),
+        Some(reason) => Decision::Skip(reason),
+        None => passed(file, options, &filter, &mut steps)?,
+    };
+
+    Ok(Trace {
+        file: file.to_path_buf(),
+        steps,
+        decision,
+    })
+}
+
+/// What `--ext`, `--include` and `--exclude` make of the name of `file`.
+fn names(file: &Path, options: &Options, steps: &mut Vec<(&'static str, String)>) {
+    let fold = filter::folds(file, options.case);
+
+    if !options.extensions.is_empty() {
+        let ext = file.extension().unwrap_or_default().to_string_lossy();
+        let given = options.extensions.iter().any(|e| {
+            if fold {
+                e.eq_ignore_ascii_case(&ext)
+            } else {
+                *e == ext
+            }
+        });
+        let given = if given { "given" } else { "not given" };
+        steps.push(("ext", format!("'{}' {given}", escape::path(&*ext))));
+    }
+
+    for (kind, patterns) in [("include", &options.include), ("exclude", &options.exclude)] {
+        for pattern in patterns {
+            let hit = glob::matches_file(pattern, file, &options.roots, fold);
+            let hit = if hit { "matches" } else { "doesn't match" };
+            steps.push((kind, format!("{pattern} {hit}")));
+        }
+    }
+}
+
+/// What is in `file`: whether it is a PE image, for which machine and who signed it.
+fn contents(file: &Path, steps: &mut Vec<(&'static str, String)>) {
+    let data = match std::fs::read(file) {
+        Ok(data) => data,
+        Err(e) => {
+            steps.push(("pe", format!("unreadable, {e}")));
+            return;
+        }
+    };
+    let Some(layout) = pe::Layout::parse(&data) else {
+        let what = if data.starts_with(b"MZ") {
+            "malformed PE image"
+        } else {
+            "not a PE image"
+        };
+        steps.push(("pe", what.to_string()));
+        return;
+    };
+
+    steps.push((
+        "pe",
+        match pe::Machine::from_value(layout.machine) {
+            Some(machine) => machine.to_string(),
+            None => format!("machine {:#06x}", layout.machine),
+        },
+    ));
+
+    let signatures = layout.pkcs7(&data);
+    if signatures.is_empty() {
+        steps.push(("signature", "unsigned".to_string()));
+    }
+    for pkcs7 in signatures {
+        let subjects = inventory::subjects(pkcs7);
+        if subjects.is_empty() {
+            steps.push(("signature", "signer unknown".to_string()));
+        }
+        for subject in subjects {
+            steps.push(("signature", subject.replace(" = ", "=")));
+        }
+    }
+}
+
+/// What becomes of `file` once it got through the filters: what `--state` says about it, and
+/// whether a signature it already has is kept.
+fn passed(
+    file: &Path,
+    options: &Options,
+    filter: &Filter,
+    steps: &mut Vec<(&'static str, String)>,
+) -> io::Result<Decision> {
+    if let Some(path) = &options.state {
+        let state = State::load(path)?;
+        if sha256_file(file).is_ok_and(|hash| state.unchanged(file, &hash)) {
+            steps.push(("state", "unchanged since it was signed".to_string()));
+            return Ok(Decision::Skip(Skip::Unchanged));
+        }
+        steps.push(("state", "new or changed".to_string()));
+    }
+
+    let resign = options.resign
+        || filter
+            .resigning()
+            .lock()
+            .is_ok_and(|resign| resign.contains(file));
+    let signed = std::fs::read(file).is_ok_and(|data| {
+        pe::Layout::parse(&data).is_some_and(|layout| !layout.pkcs7(&data).is_empty())
+    });
+
+    Ok(match (signed, resign) {
+        (true, true) => Decision::Resign,
+        // the signer refuses, see sign::Signed::skipped
+        (true, false) if options.backend.warns_signed() => Decision::Skip(Skip::AlreadySigned),
+        _ => Decision::Sign,
+    })
+}
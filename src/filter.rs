@@ -0,0 +1,618 @@
+//! Deciding which of the files found are worth handing to a signer.
+
+use std::{
+    collections::HashSet,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    cmdline, dbx, escape, fwupd, glob, hook, inventory, janitor,
+    observe::Observers,
+    pe,
+    plugin::Plugin,
+    report,
+    rules::{Action, Rules},
+    sbat, Entry, Failure, Options,
+};
+
+/// How `--include`, `--exclude` and `--ext` compare names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Case {
+    /// Ignore case on FAT filesystems, where `BOOTX64.EFI` and `bootx64.efi` are the same file,
+    /// and respect it everywhere else.
+    #[default]
+    Auto,
+    /// Always respect case.
+    Sensitive,
+    /// Always ignore case.
+    Insensitive,
+}
+
+/// Why a file was left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Skip {
+    /// Smaller than `--min-size`.
+    TooSmall,
+    /// Larger than `--max-size`.
+    TooLarge,
+    /// Extension not given to `--ext`.
+    Extension,
+    /// Matches no `--include` pattern.
+    NotIncluded,
+    /// Matches an `--exclude` pattern.
+    Excluded,
+    /// A root that looks like a mistake, see [`suspicious`].
+    SuspiciousRoot,
+    /// Not a PE image, so nothing a signer could sign.
+    NotPe,
+    /// Signed by Microsoft, left alone with `--protect-microsoft`.
+    MicrosoftProtected,
+    /// Its hash is in the dbx given to `--dbx`, firmware won't run it however it is signed.
+    InDbx,
+    /// Unchanged since it was last signed, according to `--state`.
+    Unchanged,
+    /// Turned down by the `--filter-cmd`.
+    FilterCmd,
+    /// Turned down by a filter plugin.
+    Plugin,
+    /// One of sbsevery's own temporary files, see [`crate::janitor`].
+    Temporary,
+    /// A backup made by `--backup`, see [`crate::janitor`].
+    Backup,
+    /// The signer found it already signed, left alone without `--resign`.
+    AlreadySigned,
+    /// Built for an architecture not given to `--arch`.
+    Architecture,
+    /// Already signed by the run being resumed, see [`crate::journal`].
+    Completed,
+    /// An SBAT generation below the configured minimum, a build that has been revoked.
+    SbatRevoked,
+    /// A UKI whose embedded command line breaks the `--cmdline-policy`.
+    CmdlinePolicy,
+    /// A firmware capsule staged by fwupd, which signing would break, see [`crate::fwupd`].
+    FwupdCapsule,
+    /// The fwupd loader, left alone with `--fwupd skip`.
+    Fwupd,
+    /// Signed by a cert with a subject given to `--skip-signed-by`.
+    SignedBy,
+    /// Carries a signature already, left alone with `--only-unsigned`.
+    Signed,
+    /// Left alone by a `skip` rule, see [`crate::rules`].
+    Rule,
+    /// Failed by an `error` rule, or for a check that couldn't be made. These are reported as
+    /// failures rather than skipped.
+    Refused,
+}
+
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Skip::TooSmall => "too-small",
+            Skip::TooLarge => "too-large",
+            Skip::Extension => "extension",
+            Skip::NotIncluded => "not-included",
+            Skip::Excluded => "excluded-by-glob",
+            Skip::SuspiciousRoot => "suspicious-root",
+            Skip::NotPe => "not-pe",
+            Skip::MicrosoftProtected => "microsoft-protected",
+            Skip::InDbx => "in-dbx",
+            Skip::Unchanged => "unchanged",
+            Skip::FilterCmd => "filter-cmd",
+            Skip::Plugin => "plugin",
+            Skip::Temporary => "temporary",
+            Skip::Backup => "backup",
+            Skip::AlreadySigned => "already-signed",
+            Skip::Architecture => "other-arch",
+            Skip::Completed => "completed",
+            Skip::SbatRevoked => "sbat-revoked",
+            Skip::CmdlinePolicy => "cmdline-policy",
+            Skip::FwupdCapsule => "fwupd-capsule",
+            Skip::Fwupd => "fwupd",
+            Skip::SignedBy => "signed-by",
+            Skip::Signed => "signed",
+            Skip::Rule => "rule",
+            Skip::Refused => "refused",
+        })
+    }
+}
+
+/// Directories nobody wants every file under signed, but which are easy to end up passing by
+/// accident, an unset variable in a hook turning `$ESP/EFI` into `/EFI` and so on.
+const SUSPICIOUS: &[&str] = &[
+    "/", "/bin", "/etc", "/home", "/lib", "/lib64", "/opt", "/root", "/sbin", "/srv", "/usr",
+    "/var",
+];
+
+/// Why signing everything under `root` is probably not what was meant, if it is a directory
+/// like `/` or `$HOME`.
+#[must_use]
+pub fn suspicious(root: &Path) -> Option<String> {
+    if !root.is_dir() {
+        return None;
+    }
+    let root = root.canonicalize().ok()?;
+
+    if SUSPICIOUS.iter().any(|dir| root == Path::new(dir)) {
+        return Some(format!("{} is a system directory", escape::path(&root)));
+    }
+    let home = std::env::var_os("HOME").map(PathBuf::from)?;
+    if home
+        .canonicalize()
+        .is_ok_and(|home| home.starts_with(&root))
+    {
+        return Some(format!(
+            "{} contains the home directory",
+            escape::path(&root)
+        ));
+    }
+
+    None
+}
+
+/// Strings that only turn up in the signature of an image Microsoft signed, in the subjects of
+/// its signing and CA certificates.
+const MICROSOFT: &[&[u8]] = &[b"Microsoft Corporation", b"Microsoft Windows"];
+
+/// The filters of one run, plus what they skipped so far and what couldn't be looked at.
+#[derive(Debug, Default)]
+pub(crate) struct Filter {
+    skipped: Mutex<Vec<(PathBuf, Skip)>>,
+    errors: Mutex<Vec<(PathBuf, String)>>,
+    /// Don't print errors, someone else will.
+    quiet: bool,
+    /// Remove stale temporary files that turn up, see [`Filter::sweeping`].
+    sweep: bool,
+    /// Sorted, for binary searching.
+    dbx: Vec<[u8; 32]>,
+    cmdline: Option<cmdline::Policy>,
+    rules: Option<Rules>,
+    /// Files a `resign` rule let through, for the workers.
+    resign: Arc<Mutex<HashSet<PathBuf>>>,
+    /// Files an `error` rule or a check that couldn't be made failed, and why.
+    refused: Mutex<Vec<(PathBuf, String)>>,
+    plugins: Vec<Plugin>,
+    observers: Observers,
+    /// The roots of the run, for [`crate::report::shown`].
+    roots: Vec<PathBuf>,
+}
+
+impl Filter {
+    /// The filters given in `options`, plus `plugins` as far as they are filters.
+    pub(crate) fn new(options: &Options, plugins: &[Plugin]) -> io::Result<Self> {
+        let dbx = match &options.dbx {
+            Some(path) => dbx::load(path)?,
+            None => Vec::new(),
+        };
+        let cmdline = match &options.cmdline_policy {
+            Some(path) => Some(cmdline::Policy::load(path)?),
+            None => None,
+        };
+        let rules = match &options.rules {
+            Some(path) => Some(Rules::load(path)?),
+            None => None,
+        };
+        let plugins = plugins
+            .iter()
+            .filter(|p| p.provides("filter"))
+            .cloned()
+            .collect();
+
+        Ok(Self {
+            dbx,
+            cmdline,
+            rules,
+            plugins,
+            observers: options.observers.clone(),
+            roots: options.roots.clone(),
+            ..Self::default()
+        })
+    }
+
+    /// No filters, collecting errors without printing them.
+    pub(crate) fn quiet() -> Self {
+        Self {
+            quiet: true,
+            ..Self::default()
+        }
+    }
+
+    /// Also remove stale temporary files, which only signing runs do.
+    pub(crate) fn sweeping(self) -> Self {
+        Self {
+            sweep: true,
+            ..self
+        }
+    }
+
+    /// Run [`Filter::check`] on `file`, recording it as skipped if it doesn't pass.
+    pub(crate) fn pass(&self, file: &Path, options: &Options, verbose: bool) -> bool {
+        self.observers.discovered(file);
+        match self.check(file, options) {
+            Some(Skip::Refused) => false,
+            Some(reason) => {
+                self.skip(file, reason, verbose);
+                false
+            }
+            None => true,
+        }
+    }
+
+    pub(crate) fn skip(&self, file: &Path, reason: Skip, verbose: bool) {
+        dprintln!(
+            verbose,
+            "skipping:\t{} ({})",
+            crate::report::shown(&self.roots, file),
+            reason
+        );
+        if let Ok(mut skipped) = self.skipped.lock() {
+            skipped.push((file.to_path_buf(), reason));
+        }
+        self.observers.skipped(file, reason);
+    }
+
+    /// Record that `path` couldn't be searched. These are always printed, a directory that
+    /// can't be read may well hold something that should have been signed.
+    pub(crate) fn error(&self, path: &Path, e: &io::Error) {
+        if !self.quiet {
+            eprintln!("error:\t{}: {e}", escape::path(path));
+        }
+        if let Ok(mut errors) = self.errors.lock() {
+            errors.push((path.to_path_buf(), e.to_string()));
+        }
+    }
+
+    /// Every error so far, in path order.
+    pub(crate) fn errors(&self) -> Vec<(PathBuf, String)> {
+        let mut errors = self.errors.lock().map(|e| e.clone()).unwrap_or_default();
+        errors.sort();
+        errors
+    }
+
+    /// Files to sign even if they are signed already, as `resign` rules say.
+    pub(crate) fn resigning(&self) -> Arc<Mutex<HashSet<PathBuf>>> {
+        Arc::clone(&self.resign)
+    }
+
+    /// Every file an `error` rule or a check that couldn't be made failed so far and why, in
+    /// path order.
+    pub(crate) fn refused(&self) -> Vec<(PathBuf, String)> {
+        let mut refused = self.refused.lock().map(|r| r.clone()).unwrap_or_default();
+        refused.sort();
+        refused
+    }
+
+    /// What the first rule that applies to `file` says about it, `None` if it may go on to
+    /// the other filters.
+    fn check_rules(&self, file: &Path, options: &Options) -> Option<Skip> {
+        let rule = self.rules.as_ref()?.decide(file, options)?;
+        dprintln!(options.verbose, "rule:\t{} ({rule})", escape::path(file));
+
+        match rule.action {
+            Action::Sign => None,
+            Action::Resign => {
+                if let Ok(mut resign) = self.resign.lock() {
+                    resign.insert(file.to_path_buf());
+                }
+                None
+            }
+            Action::Skip => Some(Skip::Rule),
+            Action::Error => {
+                eprintln!("refusing:\t{} ({rule})", escape::path(file));
+                self.refuse(file, format!("refused by {rule}"));
+                Some(Skip::Refused)
+            }
+        }
+    }
+
+    /// Fail `file` for `why` rather than hand it to a signer, see [`Filter::refused`].
+    fn refuse(&self, file: &Path, why: String) {
+        self.observers.failed(&Entry {
+            path: file.to_path_buf(),
+            action: report::Action::Failed,
+            duration: Duration::ZERO,
+            backend: None,
+            error: Some(Failure::Error(why.clone())),
+        });
+        if let Ok(mut refused) = self.refused.lock() {
+            refused.push((file.to_path_buf(), why));
+        }
+    }
+
+    /// Everything skipped so far, in path order.
+    pub(crate) fn skipped(&self) -> Vec<(PathBuf, Skip)> {
+        let mut skipped = self.skipped.lock().map(|s| s.clone()).unwrap_or_default();
+        skipped.sort();
+        skipped
+    }
+
+    /// Whether `file` should be skipped, and why. The cheap checks on the name and size come
+    /// first, the contents are only read if they pass.
+    pub(crate) fn check(&self, file: &Path, options: &Options) -> Option<Skip> {
+        if janitor::is_temp(file) {
+            if self.sweep {
+                janitor::sweep(file);
+            }
+            return Some(Skip::Temporary);
+        }
+        if janitor::is_backup(file) {
+            if self.sweep {
+                janitor::report_backup(file);
+            }
+            return Some(Skip::Backup);
+        }
+        if options.completed.contains(file) {
+            return Some(Skip::Completed);
+        }
+        // before anything else, --all-files mustn't get a capsule signed
+        if fwupd::is_capsule(file) {
+            return Some(Skip::FwupdCapsule);
+        }
+        if options.fwupd == fwupd::Policy::Skip && fwupd::is_loader(file) {
+            return Some(Skip::Fwupd);
+        }
+        if let Some(reason) = self.check_rules(file, options) {
+            return Some(reason);
+        }
+
+        if let Some(reason) = check_size(file, options).or_else(|| check_name(file, options)) {
+            return Some(reason);
+        }
+
+        if !options.all_files && pe::sniff(file).is_ok_and(|pe| !pe) {
+            return Some(Skip::NotPe);
+        }
+
+        if options.protect_microsoft
+            || !options.skip_signed_by.is_empty()
+            || options.only_unsigned
+            || !self.dbx.is_empty()
+            || !options.arch.is_empty()
+            || !options.sbat_minimum.is_empty()
+            || self.cmdline.is_some()
+        {
+            // a check that can't be made fails the file, it mustn't let it through
+            match self.check_image(file, options) {
+                Ok(Some(reason)) => return Some(reason),
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!(
+                        "refusing:\t{} (can't check it: {})",
+                        escape::path(file),
+                        escape::text(&e.to_string())
+                    );
+                    self.refuse(file, format!("can't check it: {e}"));
+                    return Some(Skip::Refused);
+                }
+            }
+        }
+
+        // last, it is by far the most expensive
+        if let Some(template) = &options.filter_cmd {
+            match hook::filter(template, file) {
+                Ok(true) => {}
+                Ok(false) => return Some(Skip::FilterCmd),
+                Err(e) => {
+                    eprintln!("filter command for {}: {e}", escape::path(file));
+                    return Some(Skip::FilterCmd);
+                }
+            }
+        }
+        for plugin in &self.plugins {
+            match plugin.filter(file) {
+                Ok(true) => {}
+                Ok(false) => return Some(Skip::Plugin),
+                Err(e) => {
+                    eprintln!("{e}");
+                    return Some(Skip::Plugin);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// The checks of [`Filter::check`] that look inside the image `file`, failing if it can't
+    /// be read or isn't a well formed image, or the parts looked at can't be.
+    fn check_image(&self, file: &Path, options: &Options) -> io::Result<Option<Skip>> {
+        let mut image = pe::Image::open(file)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed PE image"))?;
+
+        if !options.arch.is_empty()
+            && !options
+                .arch
+                .iter()
+                .any(|m| m.value() == image.layout.machine)
+        {
+            return Ok(Some(Skip::Architecture));
+        }
+
+        let table = image.signatures()?;
+        if let Some(reason) = check_signatures(file, table.as_deref(), options) {
+            return Ok(Some(reason));
+        }
+        // hashing reads all of it, only for a dbx that may have it
+        if !self.dbx.is_empty() && self.dbx.binary_search(&image.authenticode()?).is_ok() {
+            return Ok(Some(Skip::InDbx));
+        }
+        if !options.sbat_minimum.is_empty() {
+            let section = image.section(b".sbat")?.unwrap_or_default();
+            if let Some((entry, min)) = sbat::below(&sbat::parse(&section), &options.sbat_minimum) {
+                eprintln!(
+                    "refusing:\t{} ({entry} is below the minimum of {min})",
+                    escape::path(file)
+                );
+                return Ok(Some(Skip::SbatRevoked));
+            }
+        }
+        if let Some(policy) = &self.cmdline {
+            if let Some(section) = image.section(b".cmdline")? {
+                return Ok(check_cmdline(
+                    file,
+                    policy,
+                    &cmdline::of_section(&section),
+                    options,
+                ));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Whether the command line `embedded` in `file` goes against `policy`.
+fn check_cmdline(
+    file: &Path,
+    policy: &cmdline::Policy,
+    embedded: &str,
+    options: &Options,
+) -> Option<Skip> {
+    dprintln!(
+        options.verbose,
+        "cmdline:\t{} ({})",
+        escape::path(file),
+        escape::text(embedded)
+    );
+    let violation = policy.check(embedded)?;
+    eprintln!(
+        "refusing:\t{} ({})",
+        escape::path(file),
+        escape::text(&violation.to_string())
+    );
+    Some(Skip::CmdlinePolicy)
+}
+
+/// Whether the signatures `file` has already, in its certificate table `table`, make it one to
+/// leave alone.
+fn check_signatures(file: &Path, table: Option<&[u8]>, options: &Options) -> Option<Skip> {
+    let microsoft = |sigs: &[u8]| {
+        MICROSOFT
+            .iter()
+            .any(|name| sigs.windows(name.len()).any(|w| w == *name))
+    };
+    if options.protect_microsoft && table.is_some_and(microsoft) {
+        return Some(Skip::MicrosoftProtected);
+    }
+    let pkcs7 = table.map(pe::signed_data).unwrap_or_default();
+    if options.only_unsigned && !pkcs7.is_empty() {
+        return Some(Skip::Signed);
+    }
+    if let Some(subject) = signed_by(&pkcs7, &options.skip_signed_by) {
+        dprintln!(
+            options.verbose,
+            "signer:\t{} ({subject})",
+            escape::path(file)
+        );
+        return Some(Skip::SignedBy);
+    }
+
+    None
+}
+
+/// The subject of a cert in one of `signatures` that matches one of `patterns`, compared with
+/// the whole subject and each of its parts. openssl writes `CN = x` or `CN=x` depending on the
+/// version, both are `CN=x` here.
+pub(crate) fn signed_by(signatures: &[&[u8]], patterns: &[String]) -> Option<String> {
+    if patterns.is_empty() {
+        return None;
+    }
+
+    signatures
+        .iter()
+        .flat_map(|pkcs7| inventory::subjects(pkcs7))
+        .map(|subject| subject.replace(" = ", "="))
+        .find(|subject| {
+            patterns.iter().any(|pattern| {
+                glob::matches(pattern, subject)
+                    || subject.split(", ").any(|part| glob::matches(pattern, part))
+            })
+        })
+}
+
+fn check_size(file: &Path, options: &Options) -> Option<Skip> {
+    if options.min_size.is_some() || options.max_size.is_some() {
+        let len = file.metadata().map_or(0, |m| m.len());
+
+        if options.min_size.is_some_and(|min| len < min) {
+            return Some(Skip::TooSmall);
+        }
+        if options.max_size.is_some_and(|max| len > max) {
+            return Some(Skip::TooLarge);
+        }
+    }
+
+    None
+}
+
+fn check_name(file: &Path, options: &Options) -> Option<Skip> {
+    if options.extensions.is_empty() && options.include.is_empty() && options.exclude.is_empty() {
+        return None;
+    }
+
+    let fold = folds(file, options.case);
+
+    if !options.extensions.is_empty() {
+        let ext = file.extension().unwrap_or_default().to_string_lossy();
+        let folded = |s: &str| {
+            if fold {
+                s.to_lowercase()
+            } else {
+                s.to_string()
+            }
+        };
+        let ext = folded(&ext);
+        if !options.extensions.iter().any(|e| folded(e) == ext) {
+            return Some(Skip::Extension);
+        }
+    }
+
+    let hit = |pattern: &String| glob::matches_file(pattern, file, &options.roots, fold);
+
+    if !options.include.is_empty() && !options.include.iter().any(hit) {
+        return Some(Skip::NotIncluded);
+    }
+    if options.exclude.iter().any(hit) {
+        return Some(Skip::Excluded);
+    }
+
+    None
+}
+
+/// Whether names of `file` compare without case under `case`.
+pub(crate) fn folds(file: &Path, case: Case) -> bool {
+    match case {
+        Case::Auto => on_fat(file),
+        Case::Sensitive => false,
+        Case::Insensitive => true,
+    }
+}
+
+/// Whether `path` is on a FAT filesystem, as ESPs almost always are.
+#[cfg(target_os = "linux")]
+fn on_fat(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    const MSDOS_SUPER_MAGIC: i64 = 0x4d44;
+
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: statfs fills in buf and nothing else, path is NUL terminated
+    unsafe {
+        let mut buf: libc::statfs = std::mem::zeroed();
+        if libc::statfs(path.as_ptr(), std::ptr::addr_of_mut!(buf)) != 0 {
+            return false;
+        }
+        // f_type is 32 bits wide on some targets
+        #[allow(clippy::useless_conversion)]
+        let magic = i64::from(buf.f_type);
+        magic == MSDOS_SUPER_MAGIC
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn on_fat(_: &Path) -> bool {
+    false
+}
@@ -0,0 +1,66 @@
+//! What fwupd puts on the ESP to update firmware from: its loader, `fwupdx64.efi` and the like,
+//! and the capsules it hands the firmware, `EFI/<os>/fw/fwupd-<guid>.cap`.
+//!
+//! The loader is an EFI program like any other and has to be signed to run, `--fwupd skip`
+//! leaves it to whoever provides a signed one. Capsules are never signed: the firmware checks
+//! the vendor's signature inside them, and one with an Authenticode signature appended no longer
+//! parses as the capsule it was.
+
+use std::{fmt, path::Path, str::FromStr};
+
+/// What to do with the fwupd loader.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Sign it like everything else.
+    #[default]
+    Sign,
+    /// Leave it alone.
+    Skip,
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Policy::Sign => "sign",
+            Policy::Skip => "skip",
+        })
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sign" => Ok(Policy::Sign),
+            "skip" => Ok(Policy::Skip),
+            _ => Err(format!(
+                "invalid fwupd policy: '{s}', expected sign or skip"
+            )),
+        }
+    }
+}
+
+/// Whether `file` is the fwupd loader of some architecture.
+pub(crate) fn is_loader(file: &Path) -> bool {
+    let Some(name) = file.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let name = name.to_ascii_lowercase();
+    name.strip_prefix("fwupd")
+        .and_then(|rest| rest.strip_suffix(".efi"))
+        .is_some_and(|arch| matches!(arch, "ia32" | "x64" | "arm" | "aa64" | "riscv64"))
+}
+
+/// Whether `file` is a capsule waiting for the firmware: a `.cap` file, or anything in the
+/// `fw` directory fwupd stages them in.
+pub(crate) fn is_capsule(file: &Path) -> bool {
+    let cap = file
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cap"));
+    let staged = file
+        .parent()
+        .and_then(Path::file_name)
+        .is_some_and(|dir| dir.eq_ignore_ascii_case("fw"));
+    cap || staged
+}
@@ -0,0 +1,186 @@
+//! Shell style wildcards for `--include` and `--exclude`.
+//!
+//! `*` matches any run of characters, `?` matches one character and `[...]` one of a set
+//! (`[!...]` or `[^...]` negated, `a-z` ranges). Anything else matches itself.
+//!
+//! Patterns without a `/` are matched against the file name. Patterns with one are matched
+//! against the path, relative to the root the file was found under unless they start with
+//! `/`; in those, wildcards stay within one path component and a `**` component matches any
+//! number of directories, so `EFI/Linux/**` is everything under `EFI/Linux` and
+//! `EFI/*/grubx64.efi` only looks one directory deep.
+//!
+//! Matching takes time linear in the pattern times the path however many wildcards there are,
+//! the paths coming from whatever is on the ESP.
+
+use std::path::{Path, PathBuf};
+
+/// Whether `file`, found under one of `roots`, matches `pattern`, see the module docs. With
+/// `fold` case is ignored.
+#[must_use]
+pub fn matches_file(pattern: &str, file: &Path, roots: &[PathBuf], fold: bool) -> bool {
+    let folded = |s: &str| {
+        if fold {
+            s.to_lowercase()
+        } else {
+            s.to_string()
+        }
+    };
+
+    let pattern = folded(pattern);
+    if !pattern.contains('/') {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        return matches(&pattern, &folded(&name));
+    }
+
+    let path = if pattern.starts_with('/') {
+        file
+    } else {
+        relative(file, roots)
+    };
+    let path = folded(&path.to_string_lossy());
+
+    let pattern: Vec<&str> = components(&pattern).collect();
+    let path: Vec<&str> = components(&path).collect();
+    matches_components(&pattern, &path)
+}
+
+/// `file` relative to the deepest of `roots` it is under. A root that is the file itself
+/// leaves its name.
+fn relative<'a>(file: &'a Path, roots: &[PathBuf]) -> &'a Path {
+    roots
+        .iter()
+        .filter(|root| file.starts_with(root))
+        .max_by_key(|root| root.as_os_str().len())
+        .and_then(|root| {
+            let rest = file.strip_prefix(root).ok()?;
+            if rest.as_os_str().is_empty() {
+                file.file_name().map(Path::new)
+            } else {
+                Some(rest)
+            }
+        })
+        .unwrap_or(file)
+}
+
+/// The non-empty components of a `/` separated path, without `.`.
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".")
+}
+
+/// [`matches`] a component at a time, `**` being the `*` and every other component of
+/// `pattern` matching one of `path`.
+fn matches_components(pattern: &[&str], path: &[&str]) -> bool {
+    let (mut p, mut c) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while c < path.len() {
+        match pattern.get(p) {
+            Some(&"**") => {
+                star = Some((p, c));
+                p += 1;
+                continue;
+            }
+            Some(first) if matches(first, path[c]) => {
+                p += 1;
+                c += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        match star {
+            Some((sp, sc)) => {
+                p = sp + 1;
+                c = sc + 1;
+                star = Some((sp, sc + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == "**")
+}
+
+/// Whether `text` matches `pattern` as a whole.
+#[must_use]
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // backtracking to the last `*` is enough, no pathological blowup
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((hit, len)) = class(&pattern[p..], text[t]) {
+                    if hit {
+                        p += len;
+                        t += 1;
+                        continue;
+                    }
+                } else if text[t] == '[' {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+            }
+            Some(&c) if c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        match star {
+            Some((sp, st)) => {
+                p = sp + 1;
+                t = st + 1;
+                star = Some((sp, st + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Match `c` against the class at the start of `pattern`. Returns whether it matched and how
+/// long the class is, `None` if the `[` is never closed and so is a literal.
+fn class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut hit = false;
+    let mut first = true;
+    loop {
+        let &lo = pattern.get(i)?;
+        if lo == ']' && !first {
+            return Some((hit != negated, i + 1));
+        }
+        first = false;
+
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&hi| hi != ']') {
+            hit |= (lo..=pattern[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            hit |= lo == c;
+            i += 1;
+        }
+    }
+}
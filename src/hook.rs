@@ -0,0 +1,98 @@
+//! `--post-sign`, `--post-run` and `--filter-cmd`, shell commands with `{name}` placeholders
+//! filled in.
+
+use std::{
+    ffi::{OsStr, OsString},
+    io::{self, Write},
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+};
+
+/// Run `template` through the shell, every `{name}` with a value in `vars` replaced by that
+/// value, quoted so that whatever is in a file name stays one word. Unknown placeholders are left
+/// as they are.
+pub(crate) fn run(template: &str, vars: &[(&str, &OsStr)]) -> io::Result<ExitStatus> {
+    command(template, vars).status()
+}
+
+/// Ask `template` whether `file` should be signed. The path is both the `{path}` placeholder
+/// and the one line on stdin, so the command can take it either way, and exiting 0 means yes.
+pub(crate) fn filter(template: &str, file: &Path) -> io::Result<bool> {
+    let mut child = command(template, &[("path", file.as_os_str())])
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let mut line = file.as_os_str().as_encoded_bytes().to_vec();
+        line.push(b'\n');
+        // a command that only looks at argv may well exit without reading
+        let _ = stdin.write_all(&line);
+    }
+
+    Ok(child.wait()?.success())
+}
+
+fn command(template: &str, vars: &[(&str, &OsStr)]) -> Command {
+    let mut script = OsString::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        script.push(&rest[..open]);
+        rest = &rest[open..];
+
+        let var = rest
+            .find('}')
+            .and_then(|close| vars.iter().find(|(name, _)| rest[1..close] == **name));
+        if let Some((name, value)) = var {
+            script.push(quote(value));
+            rest = &rest[name.len() + 2..];
+        } else {
+            script.push("{");
+            rest = &rest[1..];
+        }
+    }
+    script.push(rest);
+
+    shell(&script)
+}
+
+#[cfg(unix)]
+fn shell(script: &OsStr) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(script);
+    cmd
+}
+
+#[cfg(not(unix))]
+fn shell(script: &OsStr) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(script);
+    cmd
+}
+
+/// Single quotes, with any single quote inside closed, escaped and reopened.
+#[cfg(unix)]
+fn quote(value: &OsStr) -> OsString {
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    let mut quoted = vec![b'\''];
+    for &b in value.as_bytes() {
+        if b == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(b);
+        }
+    }
+    quoted.push(b'\'');
+
+    OsString::from_vec(quoted)
+}
+
+/// Double quotes, which can't appear in Windows file names anyway.
+#[cfg(not(unix))]
+fn quote(value: &OsStr) -> OsString {
+    let mut quoted = OsString::from("\"");
+    quoted.push(value);
+    quoted.push("\"");
+    quoted
+}
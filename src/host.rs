@@ -0,0 +1,76 @@
+//! What sbsevery reads about the machine it runs on, rather than the files it signs: efivarfs
+//! for db and dbx, the TPM and its event log. Inside a container building an image with the
+//! ESP bind mounted that is the build host, not the machine the ESP will boot, so `--offline`
+//! rules all of it out and anything that needs it fails with [`Unavailable`].
+
+use std::{error::Error, fmt, path::Path};
+
+use crate::{dbx, escape, Options};
+
+/// What `main` exits with when something needs the host and can't have it, `EX_UNAVAILABLE`.
+pub const EXIT_UNAVAILABLE: i32 = 69;
+
+/// Something was asked for that needs the host, with `--offline` or in a container that doesn't
+/// have it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unavailable(pub String);
+
+impl fmt::Display for Unavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for Unavailable {}
+
+/// Whether this runs in a container, as podman, docker and systemd-nspawn mark them.
+#[must_use]
+pub fn in_container() -> bool {
+    std::env::var_os("container").is_some()
+        || Path::new("/run/.containerenv").exists()
+        || Path::new("/.dockerenv").exists()
+}
+
+/// Whether `path` is a view of the running machine rather than a file.
+fn from_host(path: &Path) -> bool {
+    path.starts_with("/sys") || path.starts_with("/proc")
+}
+
+/// Check that whatever `options` needs from the host can be had.
+///
+/// # Errors
+///
+/// Fails with [`Unavailable`] if `options.offline` is set and something is read from the host,
+/// or in a container that doesn't have it.
+pub fn check(options: &Options) -> Result<(), Unavailable> {
+    let db = options
+        .event_log
+        .as_ref()
+        .map(|_| options.db.as_deref().unwrap_or(Path::new(dbx::DB)));
+    let wanted = [
+        ("--dbx", options.dbx.as_deref()),
+        ("--event-log", options.event_log.as_deref()),
+        ("--db", db),
+    ];
+
+    for (flag, path) in wanted {
+        let Some(path) = path.filter(|path| from_host(path)) else {
+            continue;
+        };
+        if options.offline {
+            return Err(Unavailable(format!(
+                "{flag} reads {} from the host, which --offline rules out, pass a copy instead",
+                escape::path(path)
+            )));
+        }
+        if !path.exists() && in_container() {
+            return Err(Unavailable(format!(
+                "{flag} reads {} from the host, which this container doesn't have, pass a copy \
+                 instead",
+                escape::path(path)
+            )));
+        }
+    }
+
+    Ok(())
+}
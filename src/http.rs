@@ -0,0 +1,954 @@
+//! `sbsevery watch --http ADDR`, the watcher as an HTTP API, for build machines to have
+//! files signed by the machine holding the key.
+//!
+//! ```text
+//! GET  /v1/status                            how the watcher is doing
+//! POST /v1/sign    {"path": "/efi/..."}      sign a file or directory under the roots now
+//! POST /v1/verify  {"path": "/efi/..."}      check it is signed by the cert
+//!
+//! GET  /v1/requests                          sign requests waiting for approval
+//! GET  /v1/requests/ID                       what came of one
+//! POST /v1/requests/ID/approve               sign it
+//! POST /v1/requests/ID/deny                  don't
+//! ```
+//!
+//! The replies are the JSON the D-Bus and varlink services return, see [`crate::dbus`], or
+//! `{"error": "..."}`.
+//!
+//! Every request needs `Authorization: Bearer TOKEN`, with a token of one of the clients in
+//! `--http-tokens`, a TOML file of their names and the SHA-256 of their tokens, never the tokens
+//! themselves:
+//!
+//! ```toml
+//! [[client]]
+//! name = "build-farm"
+//! # printf %s "$token" | sha256sum
+//! token_sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//! ```
+//!
+//! Signing some paths can be made to need a second operator, for release keys: sign requests
+//! above the `[approval]` threshold get `202` and the ID of the request rather than being
+//! signed, and wait for one of the approvers, who have tokens of their own, to approve them.
+//!
+//! ```toml
+//! [approval]
+//! # requests for anything under these directories or holding one, or for more than 10 files
+//! # at once
+//! paths = ["/efi/EFI/Linux"]
+//! above = 10
+//! # seconds a request waits before it expires
+//! timeout = 3600
+//!
+//! [[approver]]
+//! name = "release-manager"
+//! token_sha256 = "60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752"
+//! ```
+//!
+//! A request is for its path with symlinks resolved, as the watcher signs it, and it needs
+//! approving if a symlink in what it signs leads under the `paths` too. What an approver approves
+//! is that resolved path and the files under it as they were asked for, each listed in the
+//! request with its SHA-256: if the path resolves elsewhere by the time it is approved it isn't
+//! signed, and a file that is new or has changed by the time it is signed fails.
+//!
+//! Approvers can only list, approve and deny requests, and clients only look up their own, so
+//! no single token can both ask for a signature and approve it.
+//!
+//! Each client may make [`Http::rate`] requests a minute, in bursts of as many, after that it
+//! gets `429` until it slows down; so does an address failing to authenticate. Requests are
+//! limited to [`MAX_HEAD`] of headers and [`MAX_BODY`] of body, sent within ten seconds all
+//! told or turned away with `408`, and the connections served at once to [`MAX_CONNECTIONS`].
+//!
+//! With `--http-audit` every request is a JSON line in that file: when, from where, which
+//! client or approver, what it asked and what came of it. A signing request is written down before
+//! anything is signed, along with who approved it, if that can't be done nothing is.
+//!
+//! There is no TLS here, so it only listens on a loopback address or a unix socket, the latter
+//! given as a path. Put a proxy doing TLS in front of it for other machines to reach it, one
+//! checking client certs if they should have them. Clients are still told apart by their
+//! tokens alone: nothing here knows which cert a client had, and there is no mTLS client auth.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Digest;
+
+use crate::{
+    escape,
+    state::{hex, sha256_file, Hash},
+    watch::{Request, Requests},
+};
+
+/// The request line and headers of a request may be this long at most.
+pub const MAX_HEAD: u64 = 8 * 1024;
+
+/// The body of a request may be this long at most.
+pub const MAX_BODY: usize = 64 * 1024;
+
+/// Connections served at once at most, more are turned away with `503`.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// How long a client may take sending its request, all of it, see [`Deadline`].
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long requests waiting for approval are kept once they are done with, to be looked up.
+const KEEP: Duration = Duration::from_hours(24);
+
+/// The HTTP API of a watcher.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Http {
+    /// A loopback address and port to listen on, or the path of a unix socket.
+    pub listen: String,
+    /// The clients that may make requests, see the module docs.
+    pub tokens: PathBuf,
+    /// Append a line here for every request.
+    pub audit: Option<PathBuf>,
+    /// Requests a client may make a minute.
+    pub rate: u32,
+}
+
+impl Http {
+    /// Requests a client may make a minute unless `--http-rate` says otherwise.
+    pub const RATE: u32 = 60;
+}
+
+/// The `--http-tokens` file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Tokens {
+    #[serde(rename = "client", default)]
+    clients: Vec<Client>,
+    approval: Option<Approval>,
+    #[serde(rename = "approver", default)]
+    approvers: Vec<Client>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Client {
+    name: String,
+    token_sha256: String,
+}
+
+/// Which sign requests need approving.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Approval {
+    /// Those for anything under these directories, or holding one of them. Resolved when the
+    /// file is loaded.
+    #[serde(default)]
+    paths: Vec<PathBuf>,
+    /// Those for more files than this.
+    above: Option<usize>,
+    /// Seconds they wait for approval.
+    #[serde(default = "Approval::timeout")]
+    timeout: u64,
+}
+
+impl Approval {
+    fn timeout() -> u64 {
+        60 * 60
+    }
+
+    /// Whether signing `path`, with its symlinks resolved, needs approving. What is under it is
+    /// looked at as the search would sign it, following symlinks, so a link under it to one of
+    /// the `paths` needs approving too.
+    fn needed(&self, path: &Path) -> bool {
+        let protected = |path: &Path| {
+            self.paths
+                .iter()
+                .any(|dir| path.starts_with(dir) || dir.starts_with(path))
+        };
+        if protected(path) {
+            return true;
+        }
+        if self.paths.is_empty() && self.above.is_none() {
+            return false;
+        }
+
+        let mut count = 0;
+        let mut seen = HashSet::new();
+        let mut dirs = vec![path.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(real) = dir.canonicalize() else {
+                continue;
+            };
+            if protected(&real) {
+                return true;
+            }
+            if !real.is_dir() {
+                count += 1;
+                if self.above.is_some_and(|above| count > above) {
+                    return true;
+                }
+            } else if seen.insert(real.clone()) {
+                if let Ok(entries) = std::fs::read_dir(&real) {
+                    dirs.extend(entries.flatten().map(|entry| entry.path()));
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Who made a request.
+#[derive(Debug, Clone, Copy)]
+enum Who<'a> {
+    Client(&'a str),
+    Approver(&'a str),
+}
+
+impl Tokens {
+    fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", escape::path(path)),
+            )
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+        let mut tokens: Tokens = toml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+        if let Some(approval) = &mut tokens.approval {
+            // compared with requests as they resolve, /efi/EFI/Linux being /boot/efi/EFI/Linux
+            // say
+            for dir in &mut approval.paths {
+                if let Ok(real) = dir.canonicalize() {
+                    *dir = real;
+                }
+            }
+        }
+        for client in tokens.clients.iter_mut().chain(&mut tokens.approvers) {
+            client.token_sha256.make_ascii_lowercase();
+            if client.token_sha256.len() != 64
+                || !client.token_sha256.bytes().all(|b| b.is_ascii_hexdigit())
+            {
+                return Err(invalid(format!(
+                    "the token_sha256 of {} isn't a SHA-256",
+                    client.name
+                )));
+            }
+        }
+        if tokens.clients.is_empty() {
+            return Err(invalid(
+                "no clients, nobody could make a request".to_string(),
+            ));
+        }
+        if tokens.approval.is_some() && tokens.approvers.is_empty() {
+            return Err(invalid(
+                "[approval] but no approvers, nobody could approve a request".to_string(),
+            ));
+        }
+        let shared = tokens.clients.iter().find(|client| {
+            tokens.approvers.iter().any(|approver| {
+                approver.name == client.name || approver.token_sha256 == client.token_sha256
+            })
+        });
+        if let Some(client) = shared {
+            return Err(invalid(format!(
+                "{} is both a client and an approver, they need tokens of their own",
+                client.name
+            )));
+        }
+        Ok(tokens)
+    }
+
+    /// The client or approver whose token `token` is.
+    fn identify(&self, token: &str) -> Option<Who<'_>> {
+        let hash = hex(&sha2::Sha256::digest(token.as_bytes()));
+        // looking at every token and every byte, for the time taken not to tell how close a
+        // guess was
+        let mut found = None;
+        let clients = self
+            .clients
+            .iter()
+            .map(|client| (client, Who::Client as fn(_) -> _));
+        let approvers = self
+            .approvers
+            .iter()
+            .map(|approver| (approver, Who::Approver as fn(_) -> _));
+        for (client, who) in clients.chain(approvers) {
+            let differ = client
+                .token_sha256
+                .bytes()
+                .zip(hash.bytes())
+                .fold(0, |differ, (a, b)| differ | (a ^ b));
+            if differ == 0 {
+                found = Some(who(client.name.as_str()));
+            }
+        }
+        found
+    }
+}
+
+/// Requests left to each client, refilled at the rate.
+struct Limiter {
+    rate: u32,
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl Limiter {
+    /// Whether `key` may make another request, taking it from what it has left.
+    fn allow(&self, key: &str) -> bool {
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return false;
+        };
+        let rate = f64::from(self.rate);
+        let now = Instant::now();
+        let (left, since) = buckets.entry(key.to_string()).or_insert((rate, now));
+        *left = (*left + now.duration_since(*since).as_secs_f64() * rate / 60.0).min(rate);
+        *since = now;
+        if *left >= 1.0 {
+            *left -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Where requests are written down.
+struct Audit(Option<Mutex<File>>);
+
+impl Audit {
+    fn open(path: Option<&Path>) -> io::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self(None));
+        };
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+        Ok(Self(Some(Mutex::new(file))))
+    }
+
+    fn record(&self, mut record: Value) -> io::Result<()> {
+        let Some(file) = &self.0 else {
+            return Ok(());
+        };
+        record["time"] = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+            .into();
+        let mut file = file.lock().map_err(|_| io::ErrorKind::Other)?;
+        file.write_all(format!("{record}\n").as_bytes())?;
+        file.sync_data()
+    }
+}
+
+/// A sign request that needed approving, and what came of it.
+struct Pending {
+    client: String,
+    path: PathBuf,
+    /// The files under `path` when it was asked for, by their paths with symlinks resolved,
+    /// and their SHA-256.
+    files: Arc<HashMap<PathBuf, Hash>>,
+    since: Instant,
+    state: State,
+}
+
+enum State {
+    Waiting,
+    /// Being signed, approved by this approver.
+    Approved(String),
+    Done(String, Result<Value, String>),
+    Denied(String),
+}
+
+impl Pending {
+    fn waiting(&self, timeout: Duration) -> bool {
+        matches!(self.state, State::Waiting) && self.since.elapsed() <= timeout
+    }
+
+    fn json(&self, id: &str, timeout: Duration) -> Value {
+        let mut json = json!({
+            "id": id,
+            "client": self.client,
+            "path": escape::path(&self.path).to_string(),
+        });
+        let mut files: Vec<_> = self.files.iter().collect();
+        files.sort();
+        json["files"] = files
+            .into_iter()
+            .map(|(path, hash)| json!({ "path": escape::path(path).to_string(), "sha256": hex(hash) }))
+            .collect();
+        let (status, approver) = match &self.state {
+            State::Waiting if self.since.elapsed() > timeout => ("expired", None),
+            State::Waiting => ("pending", None),
+            State::Approved(approver) => ("signing", Some(approver)),
+            State::Done(approver, Ok(report)) => {
+                json["report"] = report.clone();
+                ("done", Some(approver))
+            }
+            State::Done(approver, Err(e)) => {
+                json["error"] = e.as_str().into();
+                ("failed", Some(approver))
+            }
+            State::Denied(approver) => ("denied", Some(approver)),
+        };
+        json["status"] = status.into();
+        if let Some(approver) = approver {
+            json["approver"] = approver.as_str().into();
+        }
+        json
+    }
+}
+
+/// What a connection needs of the server.
+struct Server {
+    requests: Requests,
+    tokens: Tokens,
+    limiter: Limiter,
+    audit: Audit,
+    connections: AtomicUsize,
+    /// Sign requests that needed approving, by ID.
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+/// Listen as `http` says and answer requests with `requests`, on threads of their own for as
+/// long as the process runs.
+///
+/// # Errors
+///
+/// Fails if the tokens can't be read, the audit log can't be opened, or the address isn't one
+/// to listen on.
+pub fn serve(http: &Http, requests: Requests) -> io::Result<()> {
+    let server = Arc::new(Server {
+        requests,
+        tokens: Tokens::load(&http.tokens)?,
+        limiter: Limiter {
+            rate: http.rate.max(1),
+            buckets: Mutex::default(),
+        },
+        audit: Audit::open(http.audit.as_deref())?,
+        connections: AtomicUsize::new(0),
+        pending: Mutex::default(),
+    });
+
+    if http.listen.starts_with('/') {
+        let path = Path::new(&http.listen);
+        if path
+            .symlink_metadata()
+            .is_ok_and(|meta| meta.file_type().is_socket())
+        {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                accept(&server, stream, "unix".to_string());
+            }
+        });
+        return Ok(());
+    }
+
+    let addr: SocketAddr = http.listen.parse().map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--http {}: {e}", http.listen),
+        )
+    })?;
+    if !addr.ip().is_loopback() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "--http {addr}: there is no TLS, listen on a loopback address or a unix socket \
+                 and put a proxy doing TLS in front"
+            ),
+        ));
+    }
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| "unknown".to_string(), |peer| peer.ip().to_string());
+            accept(&server, stream, peer);
+        }
+    });
+    Ok(())
+}
+
+/// Serve `stream` on a thread of its own, or turn it away if there are too many.
+fn accept<S: Timeout + Write + Send + 'static>(server: &Arc<Server>, mut stream: S, peer: String) {
+    if server.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+        server.connections.fetch_sub(1, Ordering::SeqCst);
+        let _ = respond(
+            &mut stream,
+            503,
+            &json!({ "error": "too many connections" }),
+            "",
+        );
+        return;
+    }
+    let server = Arc::clone(server);
+    thread::spawn(move || {
+        connection(&server, stream, &peer);
+        server.connections.fetch_sub(1, Ordering::SeqCst);
+    });
+}
+
+/// A request as far as it got.
+#[derive(Default)]
+struct Incoming {
+    method: String,
+    target: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Answer the one request of a connection.
+fn connection<S: Timeout + Write>(server: &Server, mut stream: S, peer: &str) {
+    let mut record = json!({ "peer": peer });
+    let request = read(&mut Deadline {
+        stream: &mut stream,
+        until: Instant::now() + REQUEST_TIMEOUT,
+    });
+    let (status, body) = match request {
+        Ok(request) => {
+            record["method"] = request.method.clone().into();
+            record["target"] = request.target.clone().into();
+            handle(server, &request, peer, &mut record)
+        }
+        Err((status, e)) => (status, json!({ "error": e })),
+    };
+
+    record["status"] = status.into();
+    if let Some(e) = body.get("error") {
+        record["error"] = e.clone();
+    }
+    if let Err(e) = server.audit.record(record) {
+        eprintln!("can't write the audit log: {e}");
+    }
+    let challenge = if status == 401 {
+        "WWW-Authenticate: Bearer\r\n"
+    } else if status == 429 {
+        "Retry-After: 60\r\n"
+    } else {
+        ""
+    };
+    let _ = respond(&mut stream, status, &body, challenge);
+}
+
+/// The status and body of the reply to `request`, taking down who asked what in `record`.
+fn handle(server: &Server, request: &Incoming, peer: &str, record: &mut Value) -> (u16, Value) {
+    let error = |status, e: &str| (status, json!({ "error": e }));
+
+    // guessing tokens counts against the address guessing, before it gets to guess again
+    let failed_key = format!("unauthenticated {peer}");
+    let who = request
+        .authorization
+        .as_deref()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| server.tokens.identify(token.trim()));
+    let name = match who {
+        Some(Who::Client(name)) => {
+            record["client"] = name.into();
+            name
+        }
+        Some(Who::Approver(name)) => {
+            record["approver"] = name.into();
+            name
+        }
+        None => {
+            if !server.limiter.allow(&failed_key) {
+                return error(429, "too many requests");
+            }
+            return error(401, "a bearer token of a client is needed");
+        }
+    };
+    if !server.limiter.allow(name) {
+        return error(429, "too many requests");
+    }
+
+    if let Some(rest) = request.target.strip_prefix("/v1/requests") {
+        if rest.is_empty() || rest.starts_with('/') {
+            return approvals(server, &request.method, rest, who, record);
+        }
+    }
+    let Some(Who::Client(client)) = who else {
+        return error(403, "approvers can only list, approve and deny requests");
+    };
+
+    let request = match (request.method.as_str(), request.target.as_str()) {
+        ("GET", "/v1/status") => Request::Status,
+        ("POST", "/v1/sign" | "/v1/verify") => {
+            let Ok(body) = serde_json::from_slice::<Value>(&request.body) else {
+                return error(400, "the body isn't JSON");
+            };
+            let Some(path) = body["path"].as_str() else {
+                return error(400, "a path is needed");
+            };
+            record["path"] = path.into();
+            if request.target == "/v1/verify" {
+                return answer(server, Request::Verify(PathBuf::from(path)));
+            }
+            // decided on, and signed, as it resolves now
+            let path = match Path::new(path).canonicalize() {
+                Ok(path) => path,
+                Err(e) => return error(422, &format!("{}: {e}", escape::path(Path::new(path)))),
+            };
+            if let Some(approval) = server
+                .tokens
+                .approval
+                .as_ref()
+                .filter(|approval| approval.needed(&path))
+            {
+                return pend(server, approval, client, path, record);
+            }
+            // the record of it first, or it doesn't happen
+            let mut accepted = record.clone();
+            accepted["status"] = "accepted".into();
+            if let Err(e) = server.audit.record(accepted) {
+                eprintln!("can't write the audit log: {e}");
+                return error(503, "can't write the audit log");
+            }
+            Request::Sign(path)
+        }
+        (_, "/v1/status" | "/v1/sign" | "/v1/verify") => return error(405, "wrong method"),
+        _ => return error(404, "no such endpoint"),
+    };
+
+    answer(server, request)
+}
+
+/// What the watcher makes of `request`.
+fn answer(server: &Server, request: Request) -> (u16, Value) {
+    match server.requests.call(request) {
+        Ok(answer) => (200, answer),
+        Err(e) => (422, json!({ "error": e })),
+    }
+}
+
+/// Put off signing `path` for `client` until an approver approves it.
+fn pend(
+    server: &Server,
+    approval: &Approval,
+    client: &str,
+    path: PathBuf,
+    record: &mut Value,
+) -> (u16, Value) {
+    let Ok(id) = new_id() else {
+        return (503, json!({ "error": "can't make up an ID" }));
+    };
+    // what is approved is what is there now
+    let files = match files(&path) {
+        Ok(files) => Arc::new(files),
+        Err(e) => return (422, json!({ "error": e.to_string() })),
+    };
+    let Ok(mut pending) = server.pending.lock() else {
+        return (503, json!({ "error": "poisoned" }));
+    };
+    let request = Pending {
+        client: client.to_string(),
+        path,
+        files,
+        since: Instant::now(),
+        state: State::Waiting,
+    };
+    let reply = request.json(&id, Duration::from_secs(approval.timeout));
+    record["id"] = id.as_str().into();
+    pending.insert(id, request);
+    (202, reply)
+}
+
+/// `/v1/requests...`, the sign requests that need approving, `rest` being what follows.
+fn approvals(
+    server: &Server,
+    method: &str,
+    rest: &str,
+    who: Option<Who<'_>>,
+    record: &mut Value,
+) -> (u16, Value) {
+    let error = |status, e: &str| (status, json!({ "error": e }));
+    let Some(approval) = &server.tokens.approval else {
+        return error(404, "no requests need approving");
+    };
+    let timeout = Duration::from_secs(approval.timeout);
+    let Ok(mut pending) = server.pending.lock() else {
+        return error(503, "poisoned");
+    };
+    pending.retain(|_, request| request.since.elapsed() <= timeout + KEEP);
+
+    let (id, action) = match rest.strip_prefix('/') {
+        None => (None, ""),
+        Some(rest) => {
+            let (id, action) = rest.split_once('/').unwrap_or((rest, ""));
+            (Some(id), action)
+        }
+    };
+    if let Some(id) = id {
+        record["id"] = id.into();
+    }
+    if !matches!(action, "" | "approve" | "deny") {
+        return error(404, "no such endpoint");
+    }
+    match (method, id, action, who) {
+        ("GET", None, _, Some(Who::Approver(_))) => {
+            let waiting: Vec<_> = pending
+                .iter()
+                .filter(|(_, request)| request.waiting(timeout))
+                .map(|(id, request)| request.json(id, timeout))
+                .collect();
+            (200, json!({ "requests": waiting }))
+        }
+        ("GET", Some(id), "", _) => match pending.get(id) {
+            // nobody but the client that asked finds out what others asked
+            Some(request) if !matches!(who, Some(Who::Client(client)) if client != request.client) => {
+                (200, request.json(id, timeout))
+            }
+            _ => error(404, "no such request"),
+        },
+        ("POST", Some(id), "approve" | "deny", Some(Who::Approver(approver))) => {
+            let Some(request) = pending.get_mut(id) else {
+                return error(404, "no such request");
+            };
+            record["requested_by"] = request.client.as_str().into();
+            record["path"] = escape::path(&request.path).to_string().into();
+            if !request.waiting(timeout) {
+                return (409, request.json(id, timeout));
+            }
+            if action == "deny" {
+                request.state = State::Denied(approver.to_string());
+                return (200, request.json(id, timeout));
+            }
+            // what was approved is the path as it resolved when it was asked for
+            if request.path.canonicalize().ok().as_ref() != Some(&request.path) {
+                let e = format!(
+                    "{} has changed since it was asked for, ask again",
+                    escape::path(&request.path)
+                );
+                request.state = State::Done(approver.to_string(), Err(e.clone()));
+                return error(409, &e);
+            }
+
+            // the record of it first, or it doesn't happen
+            let mut accepted = record.clone();
+            accepted["status"] = "accepted".into();
+            if let Err(e) = server.audit.record(accepted) {
+                eprintln!("can't write the audit log: {e}");
+                return error(503, "can't write the audit log");
+            }
+            request.state = State::Approved(approver.to_string());
+            let sign = Request::SignApproved(request.path.clone(), Arc::clone(&request.files));
+            drop(pending);
+
+            let answer = server.requests.call(sign);
+            if let Some(request) = server
+                .pending
+                .lock()
+                .ok()
+                .as_mut()
+                .and_then(|pending| pending.get_mut(id))
+            {
+                request.state = State::Done(approver.to_string(), answer.clone());
+            }
+            match answer {
+                Ok(answer) => (200, answer),
+                Err(e) => error(422, &e),
+            }
+        }
+        ("GET", None, _, _) | ("POST", Some(_), _, _) => error(403, "only approvers can do that"),
+        _ => error(405, "wrong method"),
+    }
+}
+
+/// The files under `path`, or `path` itself if it is one, following symlinks as the search
+/// does, by their paths with symlinks resolved and their SHA-256.
+fn files(path: &Path) -> io::Result<HashMap<PathBuf, Hash>> {
+    let mut files = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(real) = dir.canonicalize() else {
+            continue;
+        };
+        if real.is_dir() {
+            if seen.insert(real.clone()) {
+                for entry in std::fs::read_dir(&real)? {
+                    dirs.push(entry?.path());
+                }
+            }
+        } else if let Entry::Vacant(file) = files.entry(real) {
+            let hash = sha256_file(file.key()).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}: {e}", escape::path(file.key())))
+            })?;
+            file.insert(hash);
+        }
+    }
+    Ok(files)
+}
+
+/// A new ID for a request, that can't be guessed.
+fn new_id() -> io::Result<String> {
+    let mut bytes = [0; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(hex(&bytes))
+}
+
+/// A stream that can be given a time to wait for each read at most.
+trait Timeout: Read {
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()>;
+}
+
+impl Timeout for TcpStream {
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(timeout))
+    }
+}
+
+impl Timeout for UnixStream {
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(timeout))
+    }
+}
+
+/// A stream to read a request from by `until`, however long each read takes: a client sending
+/// a byte at a time still has to be done by then, rather than keeping its connection forever.
+struct Deadline<'a, S> {
+    stream: &'a mut S,
+    until: Instant,
+}
+
+impl<S: Timeout> Read for Deadline<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_timeout(left)?;
+        self.stream.read(buf)
+    }
+}
+
+/// The status to turn a request away with for failing to read it with `e`.
+fn read_error(e: &io::Error, what: &str) -> (u16, String) {
+    match e.kind() {
+        // a read timing out is WouldBlock on unix
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => (
+            408,
+            format!("took over {}s sending it", REQUEST_TIMEOUT.as_secs()),
+        ),
+        _ => (400, what.to_string()),
+    }
+}
+
+/// Read a request from `stream`, within the limits. Or the status to turn it away with and why.
+fn read(stream: &mut impl Read) -> Result<Incoming, (u16, String)> {
+    let mut reader = BufReader::new(stream.take(MAX_HEAD));
+    let mut line = String::new();
+    let mut next = |line: &mut String| {
+        line.clear();
+        match reader.read_line(line) {
+            Ok(0) => Err((400, "cut off".to_string())),
+            Ok(_) if !line.ends_with('\n') => {
+                Err((431, "request line or headers too long".to_string()))
+            }
+            Ok(_) => Ok(line.trim_end().to_string()),
+            Err(e) => Err(read_error(&e, &e.to_string())),
+        }
+    };
+
+    let request_line = next(&mut line)?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err((400, "not an HTTP request".to_string()));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err((505, "only HTTP/1".to_string()));
+    }
+    let mut request = Incoming {
+        method: method.to_string(),
+        target: target.to_string(),
+        ..Incoming::default()
+    };
+
+    let mut length = 0;
+    loop {
+        let header = next(&mut line)?;
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err((400, "malformed header".to_string()));
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => {
+                length = value
+                    .parse()
+                    .map_err(|_| (400, "bad Content-Length".to_string()))?;
+            }
+            "transfer-encoding" => {
+                return Err((411, "send a Content-Length".to_string()));
+            }
+            "authorization" => request.authorization = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    if length > MAX_BODY {
+        return Err((413, format!("bodies are {MAX_BODY} bytes at most")));
+    }
+
+    // what is left of the head limit may sit buffered, the body comes after it
+    let buffered = reader.buffer().to_vec();
+    let stream = reader.into_inner().into_inner();
+    request.body = buffered;
+    request.body.truncate(length);
+    let missing = length - request.body.len();
+    let mut rest = vec![0; missing];
+    stream
+        .read_exact(&mut rest)
+        .map_err(|e| read_error(&e, "body cut off"))?;
+    request.body.extend(rest);
+    Ok(request)
+}
+
+fn respond(stream: &mut impl Write, status: u16, body: &Value, headers: &str) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        422 => "Unprocessable Content",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    };
+    let body = format!("{body}\n");
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n{headers}\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,121 @@
+//! Files made immutable with `chattr +i`, which some keep their bootloaders as so nothing
+//! overwrites them behind their back. Nothing can write or rename over one, root included, so
+//! rather than failing on it with a bare "operation not permitted" the file is turned down
+//! saying why, or with `--handle-immutable` the flag is cleared for signing and set again on
+//! the signed file.
+
+use std::{io, path::Path};
+
+use crate::escape;
+
+/// `FS_IMMUTABLE_FL`, from `linux/fs.h`.
+#[cfg(target_os = "linux")]
+const IMMUTABLE: libc::c_int = 0x10;
+
+/// A file whose immutable flag was cleared, to be set again once it is signed.
+#[derive(Debug)]
+#[must_use]
+pub(crate) struct Unlocked<'a>(&'a Path);
+
+impl Unlocked<'_> {
+    /// Set the flag again, on whatever is at the path by now: signing into a copy and renaming
+    /// it into place leaves a new file there without it.
+    pub(crate) fn restore(self) {
+        if let Err(e) = set(self.0, true) {
+            eprintln!(
+                "couldn't make {} immutable again: {e}",
+                escape::path(self.0)
+            );
+        }
+    }
+}
+
+/// Whether `file` is immutable, which fails unless `handle` says to deal with it.
+///
+/// # Errors
+///
+/// Fails if `file` is immutable and `handle` isn't set.
+pub(crate) fn check(file: &Path, handle: bool) -> io::Result<bool> {
+    let immutable = is_immutable(file);
+    if immutable && !handle {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "immutable (chattr +i), --handle-immutable clears it for signing",
+        ));
+    }
+    Ok(immutable)
+}
+
+/// Clear the immutable flag of `file` if it is set.
+///
+/// # Errors
+///
+/// Fails if the flag is set and can't be cleared, without `CAP_LINUX_IMMUTABLE` say.
+pub(crate) fn clear(file: &Path) -> io::Result<Option<Unlocked<'_>>> {
+    if !is_immutable(file) {
+        return Ok(None);
+    }
+    set(file, false)
+        .map_err(|e| io::Error::new(e.kind(), format!("couldn't clear the immutable flag: {e}")))?;
+    Ok(Some(Unlocked(file)))
+}
+
+#[cfg(target_os = "linux")]
+fn is_immutable(file: &Path) -> bool {
+    flags(file).is_some_and(|flags| flags & IMMUTABLE != 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_immutable(_: &Path) -> bool {
+    false
+}
+
+/// The inode flags of `file`, `None` where the filesystem has none to give.
+#[cfg(target_os = "linux")]
+fn flags(file: &Path) -> Option<libc::c_int> {
+    use std::os::fd::AsRawFd;
+
+    let opened = std::fs::File::open(file).ok()?;
+    let mut flags: libc::c_int = 0;
+    // SAFETY: the fd is open for as long as `opened` lives and the kernel writes an int
+    let res = unsafe {
+        libc::ioctl(
+            opened.as_raw_fd(),
+            libc::FS_IOC_GETFLAGS,
+            std::ptr::addr_of_mut!(flags),
+        )
+    };
+    (res == 0).then_some(flags)
+}
+
+/// Set or clear the immutable flag of `file`, leaving its other flags as they are.
+#[cfg(target_os = "linux")]
+fn set(file: &Path, immutable: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let opened = std::fs::File::open(file)?;
+    let flags = flags(file).unwrap_or_default();
+    let mut flags = if immutable {
+        flags | IMMUTABLE
+    } else {
+        flags & !IMMUTABLE
+    };
+    // SAFETY: as in `flags`, the kernel only reads the int
+    let res = unsafe {
+        libc::ioctl(
+            opened.as_raw_fd(),
+            libc::FS_IOC_SETFLAGS,
+            std::ptr::addr_of_mut!(flags),
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set(_: &Path, _: bool) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,373 @@
+//! `sbsevery init`, set a machine up for signing: find or make a key, find the ESP, pick the
+//! filters a dual boot wants, and write the configuration and a hook signing the ESP whenever
+//! the package manager installs a kernel.
+//!
+//! Every question has a default shown in brackets, taken on an empty answer. With `--root`
+//! everything is looked for and written under another tree, an image being built say, while
+//! the paths in what is written stay those the machine will see.
+
+use std::{
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{config, escape, host};
+
+/// Where keys are looked for, with the names of the key and cert in each: ours, then those of
+/// the usual guides and sbctl.
+const KEYS: &[(&str, &str, &str)] = &[
+    ("/etc/efi-keys", "DB.key", "DB.crt"),
+    ("/etc/secureboot/keys/db", "db.key", "db.pem"),
+    ("/var/lib/sbctl/keys/db", "db.key", "db.pem"),
+    ("/usr/share/secureboot/keys/db", "db.key", "db.pem"),
+];
+
+/// Where ESPs are usually mounted, tried when none is found in `/proc/mounts`.
+const ESPS: &[&str] = &["/efi", "/boot/efi", "/boot"];
+
+/// What the answers came to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Setup {
+    pub key: PathBuf,
+    pub cert: PathBuf,
+    pub esp: PathBuf,
+    /// Leave Microsoft signed files alone, `--protect-microsoft`.
+    pub protect_microsoft: bool,
+    /// Leave files the firmware's dbx revokes alone, `--check-dbx`.
+    pub check_dbx: bool,
+    /// Only sign files that changed since the last run, `--state`.
+    pub state: bool,
+    /// Warn before signing that `BitLocker` may ask for its recovery key.
+    pub bitlocker: bool,
+}
+
+impl Setup {
+    /// The command line signing the ESP as set up, quoted for the shell.
+    #[must_use]
+    pub fn command(&self) -> String {
+        let mut command = format!(
+            "sbsevery {} -k {} -c {}",
+            quote(&self.esp),
+            quote(&self.key),
+            quote(&self.cert)
+        );
+        if self.protect_microsoft {
+            command.push_str(" --protect-microsoft");
+        }
+        if self.check_dbx {
+            command.push_str(" --check-dbx");
+        }
+        if self.state {
+            command.push_str(" --state /var/lib/sbsevery/state");
+        }
+        command
+    }
+
+    /// The configuration file as set up.
+    #[must_use]
+    pub fn config(&self) -> String {
+        format!(
+            "# written by sbsevery init\n\n[warnings]\nbitlocker = {}\n",
+            self.bitlocker
+        )
+    }
+}
+
+/// Ask the questions on `output`, read the answers from `input`, and write the configuration
+/// and a hook under `root`.
+///
+/// # Errors
+///
+/// Fails if `input` runs out before every question is answered, a key can't be generated or
+/// a file can't be written.
+pub fn init(root: &Path, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<Setup> {
+    let mut prompt = Prompt { input, output };
+
+    let (key, cert) = keys(root, &mut prompt)?;
+
+    let found = esps(root);
+    let esp = prompt.ask(
+        "ESP to sign",
+        found
+            .first()
+            .map_or("/efi", |esp| esp.to_str().unwrap_or("/efi")),
+    )?;
+    let esp = PathBuf::from(esp);
+    if !within(root, &esp).join("EFI").is_dir() {
+        prompt.say(&format!(
+            "{} has no EFI directory, check it is the ESP",
+            escape::path(&esp)
+        ))?;
+    }
+
+    let protect_microsoft = prompt.confirm(
+        "leave files signed by Microsoft alone, like Windows' boot manager?",
+        true,
+    )?;
+    let check_dbx = !host::in_container()
+        && prompt.confirm("leave files the firmware's dbx revokes alone?", true)?;
+    let state = prompt.confirm("only sign files that changed since the last run?", false)?;
+    let bitlocker = prompt.confirm(
+        "warn before signing that BitLocker may ask for its recovery key?",
+        true,
+    )?;
+
+    let setup = Setup {
+        key,
+        cert,
+        esp,
+        protect_microsoft,
+        check_dbx,
+        state,
+        bitlocker,
+    };
+
+    let config = within(root, Path::new(config::SYSTEM));
+    prompt.write(&config, &setup.config(), false)?;
+
+    match hook(root, &setup.command()) {
+        Some((path, text, executable)) => prompt.write(&path, &text, executable)?,
+        None => prompt.say(&format!(
+            "no package manager hook to add, run this after each kernel update:\n\t{}",
+            setup.command()
+        ))?,
+    }
+
+    Ok(setup)
+}
+
+/// Find a key and cert, or make them.
+fn keys(
+    root: &Path,
+    prompt: &mut Prompt<impl BufRead, impl Write>,
+) -> io::Result<(PathBuf, PathBuf)> {
+    let found = KEYS.iter().find(|(dir, key, cert)| {
+        let dir = within(root, Path::new(dir));
+        dir.join(key).is_file() && dir.join(cert).is_file()
+    });
+
+    if let Some((dir, key, cert)) = found {
+        let dir = Path::new(dir);
+        let key = prompt.ask("key", &dir.join(key).to_string_lossy())?;
+        let cert = prompt.ask("cert", &dir.join(cert).to_string_lossy())?;
+        return Ok((key.into(), cert.into()));
+    }
+
+    let (dir, key, cert) = KEYS[0];
+    let dir = Path::new(dir);
+    if !prompt.confirm(
+        &format!("no key found, generate one in {}?", escape::path(dir)),
+        true,
+    )? {
+        let key = prompt.ask("key", "")?;
+        let cert = prompt.ask("cert", "")?;
+        return Ok((key.into(), cert.into()));
+    }
+
+    let (key, cert) = (dir.join(key), dir.join(cert));
+    generate(&within(root, &key), &within(root, &cert))?;
+    prompt.say(&format!(
+        "generated {}, enroll it in the firmware's db before turning Secure Boot on, with \
+         sbctl or KeyTool say",
+        escape::path(&cert)
+    ))?;
+    Ok((key, cert))
+}
+
+/// Make a key and a self-signed cert for it, good for ten years.
+fn generate(key: &Path, cert: &Path) -> io::Result<()> {
+    if let Some(dir) = key.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let out = Command::new("openssl")
+        .args([
+            "req", "-new", "-x509", "-newkey", "rsa:2048", "-nodes", "-sha256",
+        ])
+        .args(["-days", "3650", "-subj", "/CN=sbsevery DB/"])
+        .arg("-keyout")
+        .arg(key)
+        .arg("-out")
+        .arg(cert)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("couldn't run openssl: {e}")))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(io::Error::other(format!(
+            "openssl {}: {}",
+            out.status,
+            stderr.trim()
+        )));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::set_permissions(key, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+/// ESPs that look mounted under `root`, FAT mounts from `/proc/mounts` first and then the usual
+/// places, that have an `EFI` directory.
+fn esps(root: &Path) -> Vec<PathBuf> {
+    let mounts = if root == Path::new("/") {
+        std::fs::read_to_string("/proc/mounts").unwrap_or_default()
+    } else {
+        // the mounts of this machine say nothing about the tree
+        String::new()
+    };
+    let mounted = mounts.lines().filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        let point = fields.nth(1)?;
+        (fields.next()? == "vfat").then(|| PathBuf::from(point))
+    });
+
+    let mut esps: Vec<PathBuf> = Vec::new();
+    for esp in mounted.chain(ESPS.iter().map(PathBuf::from)) {
+        if within(root, &esp).join("EFI").is_dir() && !esps.contains(&esp) {
+            esps.push(esp);
+        }
+    }
+    esps
+}
+
+/// The hook signing the ESP after kernel installs for the package manager under `root`: where
+/// it goes, what is in it and whether it is run as a program.
+fn hook(root: &Path, command: &str) -> Option<(PathBuf, String, bool)> {
+    if within(root, Path::new("/etc/pacman.d")).is_dir() {
+        let text = format!(
+            "[Trigger]\n\
+             Operation = Install\n\
+             Operation = Upgrade\n\
+             Type = Path\n\
+             Target = usr/lib/modules/*/vmlinuz\n\
+             Target = usr/lib/systemd/boot/efi/*\n\
+             Target = boot/*\n\
+             \n\
+             [Action]\n\
+             Description = Signing the ESP with sbsevery\n\
+             When = PostTransaction\n\
+             Exec = /bin/sh -c \"{}\"\n",
+            command.replace('"', "\\\"")
+        );
+        let path = within(root, Path::new("/etc/pacman.d/hooks/zz-sbsevery.hook"));
+        return Some((path, text, false));
+    }
+
+    // Debian runs these after every kernel install, kernel-install the others
+    if within(root, Path::new("/etc/kernel/postinst.d")).is_dir() {
+        let text = format!("#!/bin/sh\n# written by sbsevery init\nexec {command}\n");
+        let path = within(root, Path::new("/etc/kernel/postinst.d/zz-sbsevery"));
+        return Some((path, text, true));
+    }
+    if within(root, Path::new("/usr/lib/kernel/install.d")).is_dir() {
+        let text = format!(
+            "#!/bin/sh\n# written by sbsevery init\n[ \"$1\" = add ] || exit 0\nexec {command}\n"
+        );
+        let path = within(root, Path::new("/etc/kernel/install.d/95-sbsevery.install"));
+        return Some((path, text, true));
+    }
+
+    None
+}
+
+/// `path` as seen from the machine, under `root`.
+fn within(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// `path` quoted for the shell if it needs to be.
+fn quote(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if path
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-+".contains(c))
+    {
+        path.into_owned()
+    } else {
+        format!("'{}'", path.replace('\'', "'\\''"))
+    }
+}
+
+struct Prompt<'a, R, W> {
+    input: &'a mut R,
+    output: &'a mut W,
+}
+
+impl<R: BufRead, W: Write> Prompt<'_, R, W> {
+    fn say(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.output, "{text}")
+    }
+
+    /// Show `prompt` and read a line, trimmed.
+    fn read(&mut self, prompt: &str) -> io::Result<String> {
+        write!(self.output, "{prompt}: ")?;
+        self.output.flush()?;
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("no answer to {prompt}"),
+            ));
+        }
+        Ok(line.trim().to_string())
+    }
+
+    /// Ask for a line, `default` on an empty one.
+    fn ask(&mut self, question: &str, default: &str) -> io::Result<String> {
+        if default.is_empty() {
+            return self.read(question);
+        }
+        let answer = self.read(&format!("{question} [{default}]"))?;
+        Ok(if answer.is_empty() {
+            default.to_string()
+        } else {
+            answer
+        })
+    }
+
+    /// Ask a yes or no question.
+    fn confirm(&mut self, question: &str, default: bool) -> io::Result<bool> {
+        let hint = if default { "Y/n" } else { "y/N" };
+        loop {
+            let answer = self.read(&format!("{question} [{hint}]"))?;
+            match answer.to_ascii_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => self.say("yes or no?")?,
+            }
+        }
+    }
+
+    /// Write `text` to `path`, asking first if there is something there already.
+    fn write(&mut self, path: &Path, text: &str, executable: bool) -> io::Result<()> {
+        if path.exists()
+            && !self.confirm(
+                &format!("{} exists, replace it?", escape::path(path)),
+                false,
+            )?
+        {
+            return Ok(());
+        }
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, text)?;
+        #[cfg(unix)]
+        if executable {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+        }
+        #[cfg(not(unix))]
+        let _ = executable;
+
+        self.say(&format!("wrote {}", escape::path(path)))
+    }
+}
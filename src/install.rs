@@ -0,0 +1,51 @@
+//! `--install-to`, sign files that live outside the ESP, the systemd-boot or shim a package
+//! ships under `/usr/lib` say, and put the signed result on the ESP in one step. The source is
+//! left as it is, so the next package update doesn't find a file it doesn't know.
+//!
+//! Each file is copied next to its destination, signed there and renamed into place, so the
+//! ESP never holds a half signed or unsigned loader.
+
+use std::path::{Path, PathBuf};
+
+use crate::{escape, Options};
+
+/// Check that `options.install_to` makes sense for the roots.
+///
+/// # Errors
+///
+/// Fails if it is given with `--transactional`, or isn't a directory while the roots take
+/// more than one file.
+pub(crate) fn check(options: &Options) -> Result<(), String> {
+    let Some(dest) = &options.install_to else {
+        return Ok(());
+    };
+    if options.transactional {
+        return Err("--install-to and --transactional can't be combined".into());
+    }
+    let several = options.roots.len() > 1 || options.roots.iter().any(|root| root.is_dir());
+    if several && !dest.is_dir() {
+        return Err(format!(
+            "--install-to {} has to be a directory to install several files into",
+            escape::path(dest)
+        ));
+    }
+    Ok(())
+}
+
+/// Where `file` is installed to, `None` without `options.install_to`. Files found under a
+/// directory keep their path under it, a file given as a root goes into the destination if
+/// that is a directory and becomes it otherwise.
+pub(crate) fn destination(file: &Path, options: &Options) -> Option<PathBuf> {
+    let dest = options.install_to.as_ref()?;
+    let root = options
+        .roots
+        .iter()
+        .filter(|root| file.starts_with(root) && *root != file)
+        .max_by_key(|root| root.components().count());
+
+    Some(match (root, file.file_name()) {
+        (Some(root), _) => dest.join(file.strip_prefix(root).unwrap_or(file)),
+        (None, Some(name)) if dest.is_dir() => dest.join(name),
+        _ => dest.clone(),
+    })
+}
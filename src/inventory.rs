@@ -0,0 +1,348 @@
+//! `sbsevery snapshot` and `sbsevery diff`, record what is on the ESP and who signed it, then
+//! point out what changed since. Windows and firmware updaters like to drop new, unsigned
+//! binaries on the ESP, this is how to notice.
+//!
+//! A snapshot is JSON, optionally with a detached CMS signature next to it in `<file>.sig`
+//! made with the signing key, so the snapshot itself can't be quietly updated to match. It can
+//! also be written as a `CycloneDX` BOM, to go along with the SBOM of the OS image, see
+//! [`Inventory::cyclonedx`].
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{escape, filter::Filter, pe::Layout, search, state, version, Backend, Options};
+
+/// Every file found and what it looked like.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inventory {
+    /// In path order.
+    pub files: Vec<Entry>,
+}
+
+/// One file of an [`Inventory`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Escaped like every path sbsevery prints, see [`escape`].
+    pub path: String,
+    /// SHA-256 of the whole file.
+    pub sha256: String,
+    /// Authenticode SHA-256 of the image, which doesn't change when it is signed again.
+    /// `None` if it isn't a PE image.
+    pub authenticode: Option<String>,
+    /// Subjects of the certs in its signatures, empty if it is unsigned.
+    pub signers: Vec<String>,
+}
+
+/// How a file differs from its snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    /// Not in the snapshot.
+    New,
+    /// In the snapshot, but gone.
+    Removed,
+    /// The image itself changed.
+    Modified,
+    /// The same image, but signed by someone else or not at all any more.
+    Resigned,
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Change::New => "new",
+            Change::Removed => "removed",
+            Change::Modified => "modified",
+            Change::Resigned => "resigned",
+        })
+    }
+}
+
+impl Inventory {
+    /// Search the roots in `options` with the usual filters and record every file found.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the filters can't be set up. Files that can't be read are left out and
+    /// reported on stderr.
+    pub fn take(options: &Options) -> io::Result<Self> {
+        let filter = Filter::new(options, &[])?;
+        let files = search::each(options, &filter, entry)
+            .into_iter()
+            .filter_map(|(file, entry)| match entry {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    eprintln!("error:\t{}: {e}", escape::path(&file));
+                    None
+                }
+            })
+            .collect();
+
+        Ok(Self { files })
+    }
+
+    /// Read a snapshot written by [`Inventory::save`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or isn't a snapshot.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", escape::path(path)),
+            )
+        })
+    }
+
+    /// Write the snapshot to `path` as JSON, loadable with [`Inventory::load`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        std::fs::write(path, text + "\n")
+    }
+
+    /// The snapshot as a `CycloneDX` 1.5 BOM, one `file` component per file with its hashes and
+    /// signers, sbsevery and the tool behind `backend` listed as the tools that made it.
+    #[must_use]
+    pub fn cyclonedx(&self, backend: &Backend) -> Value {
+        let mut tools = vec![json!({
+            "type": "application",
+            "name": "sbsevery",
+            "version": version::VERSION,
+        })];
+        if let Some((tool, flag)) = backend.tool() {
+            let mut component = json!({ "type": "application", "name": tool });
+            if let Value::String(v) = version::tool_version(tool, flag) {
+                component["version"] = Value::from(v);
+            }
+            tools.push(component);
+        }
+
+        let components: Vec<_> = self
+            .files
+            .iter()
+            .map(|entry| {
+                let mut properties = Vec::new();
+                if let Some(authenticode) = &entry.authenticode {
+                    properties
+                        .push(json!({ "name": "sbsevery:authenticode", "value": authenticode }));
+                }
+                for signer in &entry.signers {
+                    properties.push(json!({ "name": "sbsevery:signer", "value": signer }));
+                }
+                json!({
+                    "type": "file",
+                    "bom-ref": entry.path,
+                    "name": entry.path,
+                    "hashes": [{ "alg": "SHA-256", "content": entry.sha256 }],
+                    "properties": properties,
+                })
+            })
+            .collect();
+
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "metadata": {
+                "timestamp": timestamp(SystemTime::now()),
+                "tools": { "components": tools },
+            },
+            "components": components,
+        })
+    }
+
+    /// What changed going from `self` to `now`, in path order.
+    #[must_use]
+    pub fn diff(&self, now: &Inventory) -> Vec<(String, Change)> {
+        let then: BTreeMap<_, _> = self.files.iter().map(|e| (&e.path, e)).collect();
+        let now: BTreeMap<_, _> = now.files.iter().map(|e| (&e.path, e)).collect();
+
+        let mut changes = Vec::new();
+        for (path, old) in &then {
+            match now.get(path) {
+                None => changes.push(((*path).clone(), Change::Removed)),
+                Some(new) if new.sha256 == old.sha256 => {}
+                // signing again with the same cert only changes the signature
+                Some(new) if new.authenticode.is_some() && new.authenticode == old.authenticode => {
+                    if new.signers != old.signers {
+                        changes.push(((*path).clone(), Change::Resigned));
+                    }
+                }
+                Some(_) => changes.push(((*path).clone(), Change::Modified)),
+            }
+        }
+        for path in now.keys().filter(|path| !then.contains_key(*path)) {
+            changes.push(((*path).clone(), Change::New));
+        }
+
+        changes.sort();
+        changes
+    }
+}
+
+/// `t` in UTC as RFC 3339, to the second.
+fn timestamp(t: SystemTime) -> String {
+    let secs = t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // days to a civil date, after Howard Hinnant's days_from_civil inverse
+    let z = days + 719_468;
+    let (era, doe) = (z / 146_097, z % 146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+fn entry(file: &Path) -> io::Result<Entry> {
+    let sha256 = state::hex(&state::sha256_file(file)?);
+    let data = std::fs::read(file)?;
+
+    let (authenticode, signers) = match Layout::parse(&data) {
+        Some(layout) => {
+            let mut signers: Vec<_> = layout.pkcs7(&data).into_iter().flat_map(subjects).collect();
+            signers.sort();
+            signers.dedup();
+            (Some(state::hex(&layout.authenticode(&data))), signers)
+        }
+        None => (None, Vec::new()),
+    };
+
+    Ok(Entry {
+        path: escape::path(file).to_string(),
+        sha256,
+        authenticode,
+        signers,
+    })
+}
+
+/// Subjects of the certs in a PKCS#7 signature, as `openssl pkcs7 -print_certs` lists them.
+/// Empty if openssl can't make sense of it.
+pub(crate) fn subjects(pkcs7: &[u8]) -> Vec<String> {
+    let Ok(mut child) = Command::new("openssl")
+        .args(["pkcs7", "-inform", "DER", "-print_certs", "-noout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return Vec::new();
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(pkcs7);
+    }
+    let Ok(output) = child.wait_with_output() else {
+        return Vec::new();
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.strip_prefix("subject="))
+        .map(|subject| subject.trim().to_string())
+        .collect()
+}
+
+/// Path of the detached signature of the snapshot at `path`.
+#[must_use]
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut sig = path.as_os_str().to_owned();
+    sig.push(".sig");
+    PathBuf::from(sig)
+}
+
+/// Sign the snapshot at `path` with `options.key` and `options.cert`, see [`signature_path`].
+///
+/// # Errors
+///
+/// Fails if openssl can't be run or can't sign.
+pub fn sign(path: &Path, options: &Options) -> io::Result<()> {
+    let mut cmd = Command::new("openssl");
+    cmd.args(["cms", "-sign", "-binary", "-outform", "DER", "-signer"])
+        .arg(&options.cert)
+        .arg("-inkey")
+        .arg(&options.key)
+        .arg("-in")
+        .arg(path)
+        .arg("-out")
+        .arg(signature_path(path))
+        .stdout(Stdio::null());
+    if options.passphrase.is_some() {
+        cmd.args(["-passin", "stdin"]).stdin(Stdio::piped());
+    }
+
+    let mut child = cmd.spawn()?;
+    if let (Some(passphrase), Some(mut stdin)) = (&options.passphrase, child.stdin.take()) {
+        stdin.write_all(passphrase.as_bytes())?;
+        stdin.write_all(b"\n")?;
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "couldn't sign {}: openssl {status}",
+            escape::path(path)
+        )));
+    }
+
+    Ok(())
+}
+
+/// Check the signature of the snapshot at `path` was made with the key of `cert`.
+///
+/// # Errors
+///
+/// Fails if there is no signature, it doesn't verify or openssl can't be run.
+pub fn check_signature(path: &Path, cert: &Path) -> io::Result<()> {
+    let sig = signature_path(path);
+    if !sig.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not signed", escape::path(path)),
+        ));
+    }
+
+    // the cert itself is the trust anchor, whatever issued it
+    let status = Command::new("openssl")
+        .args(["cms", "-verify", "-binary", "-inform", "DER", "-in"])
+        .arg(&sig)
+        .arg("-content")
+        .arg(path)
+        .arg("-CAfile")
+        .arg(cert)
+        .args(["-partial_chain", "-purpose", "any", "-out"])
+        .arg(if cfg!(windows) { "NUL" } else { "/dev/null" })
+        .stderr(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "signature of {} doesn't verify against {}",
+            escape::path(path),
+            escape::path(cert)
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+//! Temporary files and backups, and cleaning up the ones a crash left behind.
+//!
+//! Temporary files are what signers that can't sign in place write to next to the file before
+//! it is renamed over it. They are called `.sbsevery-tmp-PID-N`: short and plain ASCII whatever
+//! the file is called, so they fit on FAT however long its name already is, and never the same
+//! for two workers or two runs at once. The PID says whether the run that made one is still
+//! going, if it isn't the file is stale and removed when a run comes across it.
+//!
+//! With `--backup` every file is copied to `NAME.sbsevery-bak-PID` before signing. The copy is
+//! removed once the signer is done with the file and put back if it failed, so a backup that is
+//! still there after its run is gone means the run died in between, and the file may be half
+//! written. Those are only reported, `--restore-stale` puts them back and `--cleanup` removes
+//! them, both before anything is signed.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{escape, Options};
+
+/// What every temporary file is called, up to the PID.
+pub(crate) const PREFIX: &str = ".sbsevery-tmp-";
+
+/// What backups are called after the name of the file they are of, up to the PID.
+const BACKUP: &str = ".sbsevery-bak-";
+
+/// Path next to `file` to sign it into, unique to this process and call.
+pub(crate) fn temp_path(file: &Path) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    file.with_file_name(format!("{PREFIX}{}-{n}", std::process::id()))
+}
+
+/// Whether `path` is one of our temporary files, with this naming or an older one.
+pub(crate) fn is_temp(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(PREFIX))
+}
+
+/// Whether `path` is a backup made by `--backup`.
+pub(crate) fn is_backup(path: &Path) -> bool {
+    backup_of(path).is_some()
+}
+
+/// The file the backup `path` is of, and the PID of the run that made it.
+fn backup_of(path: &Path) -> Option<(PathBuf, u32)> {
+    let name = path.file_name()?.to_str()?;
+    let (original, pid) = name.rsplit_once(BACKUP)?;
+    let pid = pid.parse().ok()?;
+    (!original.is_empty()).then(|| (path.with_file_name(original), pid))
+}
+
+/// Copy `file` to its backup.
+pub(crate) fn back_up(file: &Path) -> io::Result<PathBuf> {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(format!("{BACKUP}{}", std::process::id()));
+    let backup = file.with_file_name(name);
+
+    std::fs::copy(file, &backup)?;
+    // once put back it stands in for the file, label and all
+    crate::xattr::copy(file, &backup)?;
+    Ok(backup)
+}
+
+/// Done signing the file `backup` is of: throw the backup away if `signed`, put it back
+/// otherwise.
+pub(crate) fn finish(backup: &Path, file: &Path, signed: bool) {
+    let res = if signed {
+        std::fs::remove_file(backup)
+    } else {
+        std::fs::rename(backup, file)
+    };
+    if let Err(e) = res {
+        eprintln!("backup {}: {e}", escape::path(backup));
+    }
+}
+
+/// Whether `path` is a backup left behind by a run that is gone, said so on stderr if it is.
+pub(crate) fn report_backup(path: &Path) {
+    if backup_of(path).is_some_and(|(_, pid)| !alive(pid)) {
+        eprintln!(
+            "stale backup:\t{} (--restore-stale puts it back, --cleanup removes it)",
+            escape::path(path)
+        );
+    }
+}
+
+/// Before signing, remove stale temporary files and restore or remove stale backups under the
+/// roots, as `--restore-stale` and `--cleanup` ask.
+///
+/// # Errors
+///
+/// Fails if a backup can't be restored, the file it is of is in an unknown state then.
+pub(crate) fn tidy(options: &Options) -> io::Result<()> {
+    let mut dirs: Vec<PathBuf> = options
+        .roots
+        .iter()
+        .filter(|root| root.is_dir())
+        .cloned()
+        .collect();
+
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = dir.read_dir() else {
+            // the walk proper reports it
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                if options.recursive {
+                    dirs.push(path);
+                }
+            } else if is_temp(&path) {
+                sweep(&path);
+            } else if let Some((original, pid)) = backup_of(&path).filter(|(_, pid)| !alive(*pid)) {
+                if options.restore_stale {
+                    std::fs::rename(&path, &original).map_err(|e| {
+                        io::Error::new(
+                            e.kind(),
+                            format!("couldn't restore {}: {e}", escape::path(&original)),
+                        )
+                    })?;
+                    eprintln!("restored:\t{} (left by run {pid})", escape::path(&original));
+                } else if options.cleanup {
+                    std::fs::remove_file(&path)?;
+                    eprintln!("removed stale:\t{}", escape::path(&path));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the run that made the temporary file `path` is gone. Names without a PID are from
+/// versions that put the file name there instead, no run of those is still going either.
+fn stale(path: &Path) -> bool {
+    let pid = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix(PREFIX))
+        .and_then(|rest| rest.split_once('-'))
+        .and_then(|(pid, n)| n.parse::<usize>().is_ok().then_some(pid))
+        .and_then(|pid| pid.parse::<u32>().ok());
+
+    match pid {
+        Some(pid) => !alive(pid),
+        None => true,
+    }
+}
+
+#[cfg(unix)]
+fn alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks whether the process exists
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn alive(_: u32) -> bool {
+    // no telling, leave it be
+    true
+}
+
+/// Remove the temporary file `path` if it is stale.
+pub(crate) fn sweep(path: &Path) {
+    if !stale(path) {
+        return;
+    }
+
+    match std::fs::remove_file(path) {
+        Ok(()) => eprintln!("removed stale:\t{}", escape::path(path)),
+        Err(e) => eprintln!("couldn't remove stale {}: {e}", escape::path(path)),
+    }
+}
@@ -0,0 +1,126 @@
+//! `--journal`, write down how far a run got so `sbsevery resume` can pick it up after a crash
+//! or a power cut rather than start over, for long runs over slow media.
+//!
+//! The journal is a text file of lines of a kind and a value separated by a tab, paths and
+//! arguments escaped like everywhere else so a tab or newline in a name can't break it:
+//!
+//! ```text
+//! arg /efi
+//! arg -k
+//! arg /etc/efi-keys/DB.key
+//! root /efi
+//! done /efi/EFI/BOOT/BOOTX64.EFI
+//! ```
+//!
+//! `arg` and `root` lines are written by [`begin`] before the run, a `done` line by the run as
+//! each file is signed, and the file is removed once the run has finished. Resuming runs with
+//! the same arguments and roots, leaving out the files already done.
+
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::escape;
+
+/// What a journal says about the run that wrote it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resume {
+    /// The arguments the run was given, without the program name.
+    pub args: Vec<String>,
+    /// The roots it searched, as they came out of the arguments.
+    pub roots: Vec<PathBuf>,
+    /// Files it signed.
+    pub done: HashSet<PathBuf>,
+}
+
+/// Start a journal at `path` for a run given `args` that searches `roots`, replacing whatever
+/// was there.
+///
+/// # Errors
+///
+/// Fails if the journal can't be written.
+pub fn begin(path: &Path, args: &[String], roots: &[PathBuf]) -> io::Result<()> {
+    let mut out = File::create(path)?;
+    for arg in args {
+        writeln!(out, "arg\t{}", escape::path(arg))?;
+    }
+    for root in roots {
+        writeln!(out, "root\t{}", escape::path(root))?;
+    }
+    out.sync_all()
+}
+
+/// Read the journal at `path`.
+///
+/// # Errors
+///
+/// Fails if there is no journal there or it can't be read. Lines that don't make sense, the
+/// last one cut short by a crash say, are left out.
+pub fn load(path: &Path) -> io::Result<Resume> {
+    let file = File::open(path).map_err(|e| {
+        let e = io::Error::new(e.kind(), format!("{}: {e}", escape::path(path)));
+        if e.kind() == io::ErrorKind::NotFound {
+            io::Error::new(e.kind(), format!("{e}, nothing to resume"))
+        } else {
+            e
+        }
+    })?;
+
+    let mut resume = Resume::default();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let Some((kind, value)) = line.split_once('\t') else {
+            continue;
+        };
+        let Some(value) = escape::unescape(value) else {
+            continue;
+        };
+        match kind {
+            "arg" => resume.args.push(value.to_string_lossy().into_owned()),
+            "root" => resume.roots.push(value.into()),
+            "done" => {
+                resume.done.insert(value.into());
+            }
+            _ => {}
+        }
+    }
+
+    Ok(resume)
+}
+
+/// A journal a run is writing to.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Journal {
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Write down that `file` is signed.
+    pub(crate) fn done(&self, file: &Path) {
+        if let Ok(mut out) = self.file.lock() {
+            // a line lost to a failed write only means signing the file again
+            let _ = writeln!(out, "done\t{}", escape::path(file));
+        }
+    }
+
+    /// The run is over, there is nothing left to resume.
+    pub(crate) fn finish(&self) -> io::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
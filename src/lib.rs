@@ -0,0 +1,953 @@
+#![warn(clippy::all, clippy::pedantic)]
+/*!
+ * # Sbsevery
+ *
+ * Secure boot sign every(thing)
+ *
+ * Recursively sign files for secureboot (helpful when dualbooting Windows with custom SB keys)
+ *
+ * This is the library half of sbsevery, the `sbsevery` binary is a thin command line wrapper
+ * around [`run`].
+ */
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver},
+        Arc, Mutex,
+    },
+    thread::{available_parallelism, spawn, JoinHandle},
+    time::{Duration, Instant},
+};
+
+macro_rules! dprintln {
+    ($b:expr, $($arg:tt)*) => ({
+        if $b {
+            eprintln!($($arg)*);
+        }
+    })
+}
+
+pub mod access;
+#[cfg(target_os = "linux")]
+pub mod agent;
+pub mod allowlist;
+pub mod annotate;
+pub mod audit;
+mod backend;
+mod base64;
+pub mod bench;
+pub mod bootctl;
+mod busy;
+pub mod cancel;
+pub mod cmdline;
+pub mod compat;
+pub mod config;
+#[cfg(target_os = "linux")]
+pub mod dbus;
+pub mod dbx;
+pub mod diagnose;
+pub mod escape;
+pub mod explain;
+pub mod filter;
+pub mod fwupd;
+pub mod glob;
+mod hook;
+pub mod host;
+#[cfg(target_os = "linux")]
+pub mod http;
+mod immutable;
+pub mod init;
+mod install;
+pub mod inventory;
+mod janitor;
+pub mod journal;
+pub mod mirror;
+pub mod mock;
+pub mod observe;
+pub mod offline;
+pub mod pcr;
+pub mod pe;
+pub mod pin;
+pub mod plugin;
+pub mod preset;
+pub mod quarantine;
+pub mod report;
+pub mod rules;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod sandbox;
+pub mod sbat;
+mod search;
+pub mod secret;
+pub mod selftest;
+mod settle;
+mod sign;
+#[cfg(target_os = "linux")]
+mod socket;
+pub mod ssh;
+pub mod state;
+pub mod stats;
+pub mod stream;
+#[cfg(unix)]
+pub mod sudo;
+pub mod tap;
+pub mod throttle;
+pub mod transaction;
+#[cfg(unix)]
+pub mod unprivileged;
+pub mod usage;
+#[cfg(target_os = "linux")]
+pub mod varlink;
+pub mod vault;
+pub mod verify;
+pub mod version;
+#[cfg(target_os = "linux")]
+mod volume;
+#[cfg(target_os = "linux")]
+pub mod watch;
+mod xattr;
+
+pub use backend::Backend;
+pub use filter::{Case, Skip};
+pub use report::{Entry, Failure};
+pub use stats::Stats;
+
+use filter::Filter;
+use secret::Secret;
+use sign::Shared;
+use state::State;
+
+/// Everything a signing run needs to know.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Options {
+    /// Tool doing the signing.
+    pub backend: Backend,
+    /// Private key handed to the signer.
+    pub key: PathBuf,
+    /// Certificate handed to the signer.
+    pub cert: PathBuf,
+    /// Files and directories to sign, directories are searched recursively unless `recursive`
+    /// is off.
+    pub roots: Vec<PathBuf>,
+    /// Descend into the subdirectories of directory roots, instead of only signing the files
+    /// directly in them.
+    pub recursive: bool,
+    /// Print what is being done to stderr.
+    pub verbose: bool,
+    /// Maximum number of signer processes running at once, also used as the number of directory
+    /// walker threads.
+    pub jobs: usize,
+    /// Kill a signer process if it runs longer than this.
+    pub timeout: Option<Duration>,
+    /// Wait up to this long for other processes to close a file they have open for writing
+    /// before signing it, a file still open after that fails.
+    pub busy_wait: Duration,
+    /// Before anything else, wait for nothing under the roots to have changed for this long.
+    pub settle: Option<Duration>,
+    /// Niceness to run signers at, see [`throttle`].
+    pub nice: Option<i32>,
+    /// I/O scheduling class to run signers in.
+    pub ionice: Option<throttle::IoClass>,
+    /// Hand at most this many bytes of files a second to the signers.
+    pub io_limit: Option<u64>,
+    /// Clear the immutable flag of files that have it for signing and set it again after,
+    /// rather than turning them down.
+    pub handle_immutable: bool,
+    /// Sign copies of the files found and put them here rather than sign the files, see
+    /// [`install`]. `state` isn't kept then, the files found stay as they were.
+    pub install_to: Option<PathBuf>,
+    /// Write down each file signed here, see [`journal`].
+    pub journal: Option<PathBuf>,
+    /// Append a record of every use of the key to this log, see [`audit`].
+    pub audit_log: Option<PathBuf>,
+    /// What the key may be used for, see [`usage`].
+    pub usage: usage::Policy,
+    /// Intermediate certs to put in each signature after `cert`, for firmware that only trusts
+    /// the root they lead to. A bundle of PEM certs, or a DER one.
+    pub chain: Option<PathBuf>,
+    /// The only certs and keys that may be signed with, see [`pin`].
+    pub pin: pin::Pin,
+    /// Only sign files whose SHA-256 is in this list, see [`allowlist`].
+    pub allow_hashes: Option<PathBuf>,
+    /// Only sign these files, by their paths with symlinks resolved, and only while they have
+    /// the SHA-256 they had when signing them was approved, see [`http`].
+    pub approved: Option<Arc<HashMap<PathBuf, state::Hash>>>,
+    /// Files a run being resumed already signed, left alone.
+    pub completed: HashSet<PathBuf>,
+    /// Stops the run when cancelled, see [`cancel`].
+    pub cancel: cancel::Cancel,
+    /// Told about every file as the run goes, see [`observe`].
+    pub observers: observe::Observers,
+    /// Remember the hash of every signed file here and skip files that haven't changed since.
+    pub state: Option<PathBuf>,
+    /// Passphrase of an encrypted key. The key is decrypted once and handed to each signer over
+    /// a pipe, so neither the passphrase nor the plain key touch argv or the disk. Linux only,
+    /// with the sbsign and osslsigncode backends.
+    pub passphrase: Option<Arc<Secret>>,
+    /// The key already decrypted, from the agent, see [`agent`]. Used instead of `passphrase`.
+    pub unlocked: Option<Arc<Secret>>,
+    /// Hand the key decrypted with `passphrase` to the agent listening on this socket.
+    pub agent: Option<PathBuf>,
+    /// Walk directories in a child process running as this uid and gid instead of as whoever
+    /// runs sbsevery, see [`unprivileged`]. Unix only.
+    pub walk_as: Option<(u32, u32)>,
+    /// Give signed files back to this uid and gid if the files they were signed from were
+    /// theirs, see [`sudo`]. Unix only.
+    pub caller: Option<(u32, u32)>,
+    /// Confine signer processes to the file being signed, see [`sandbox`].
+    pub sandbox: bool,
+    /// Add a signature to files that are already signed. Otherwise sbsign signing an already
+    /// signed file is taken back and the file counted as skipped, so runs don't pile up
+    /// signatures.
+    pub resign: bool,
+    /// Fail files with something wrong with the image, found before signing or warned about by
+    /// the signer, leaving them unsigned. See [`diagnose::Warning::pe`].
+    pub strict_pe: bool,
+    /// Only sign images for these architectures, all of them if empty.
+    pub arch: Vec<pe::Machine>,
+    /// Set the checksum of every image signed to what it should be, where the signer left it
+    /// wrong. See [`pe::fix_checksum`].
+    pub fix_checksum: bool,
+    /// Warn about signed images this firmware is likely to refuse, see [`compat`].
+    pub compat: Vec<compat::Firmware>,
+    /// Move files that keep failing here, see [`quarantine`].
+    pub quarantine: Option<quarantine::Quarantine>,
+    /// Don't sign images whose `.sbat` section has a component at a lower generation than
+    /// this, see [`sbat`].
+    pub sbat_minimum: BTreeMap<String, u32>,
+    /// Don't sign UKIs whose embedded command line breaks the policy in this file, see
+    /// [`cmdline`].
+    pub cmdline_policy: Option<PathBuf>,
+    /// Never read anything about the machine sbsevery runs on, for image builds in containers
+    /// where that is the build host. See [`host`].
+    pub offline: bool,
+    /// Have the signer record a fixed signing time, so signing the same image twice gives the
+    /// same bytes: `SOURCE_DATE_EPOCH` if it is set, the epoch otherwise. Vault signatures,
+    /// built here, record no time at all. Runs fail on backends that can't give the same bytes:
+    /// sbsign, pesign, signtool and ssh, which always record the current time, plugins not
+    /// providing `deterministic`, and ECDSA keys with osslsigncode or Vault.
+    pub deterministic: bool,
+    /// Copy each file before signing it, and put the copy back if signing fails.
+    pub backup: bool,
+    /// Before signing, remove backups left behind by runs that died.
+    pub cleanup: bool,
+    /// Before signing, put back the files of backups left behind by runs that died.
+    pub restore_stale: bool,
+    /// Sign copies of the files and only rename them over the files once every one has been
+    /// signed, if any fails none of the files change. Takes the place of `backup`.
+    pub transactional: bool,
+    /// Sign the images of these architectures with their own key and cert rather than `key`
+    /// and `cert`, see [`config::Signers`]. An encrypted key given with `passphrase` is only
+    /// used for the rest.
+    pub signers: Vec<(pe::Machine, config::Signer)>,
+    /// Sign the files under these directories with their own key and cert, the innermost
+    /// winning, over `signers` too, see [`config::Directory`].
+    pub directories: Vec<(PathBuf, config::Signer)>,
+    /// Sign and log files in lexicographic path order instead of whatever order they are found
+    /// and finished in. Signing only starts once the search is complete.
+    pub sort: bool,
+    /// Rules deciding what to do with each file before the other filters, see [`rules`].
+    pub rules: Option<PathBuf>,
+    /// Whether to sign the fwupd loader, its capsules are never signed, see [`fwupd`].
+    pub fwupd: fwupd::Policy,
+    /// Skip files smaller than this many bytes.
+    pub min_size: Option<u64>,
+    /// Skip files larger than this many bytes.
+    pub max_size: Option<u64>,
+    /// Warn about files that come out of signing larger than this many bytes, for firmware that
+    /// can't load images past a size and tightly sized ESPs.
+    pub size_limit: Option<u64>,
+    /// Only sign files with one of these extensions, given without the dot.
+    pub extensions: Vec<String>,
+    /// Only sign files matching one of these globs. A pattern with a `/` in it is matched
+    /// against the path relative to the root the file was found under (the whole path if it
+    /// starts with `/`), others against the file name.
+    pub include: Vec<String>,
+    /// Never sign files matching one of these globs, matched like `include`.
+    pub exclude: Vec<String>,
+    /// Whether the three above care about case.
+    pub case: Case,
+    /// Search roots that look like a mistake too, like `/` or `$HOME`. Without this they are
+    /// skipped with a warning, see [`filter::suspicious`].
+    pub any_root: bool,
+    /// Don't fail when nothing at all is left to sign, which is usually a typo'd root or an
+    /// over eager filter.
+    pub allow_empty: bool,
+    /// Hand every file found to the signer, not just ones that look like PE images.
+    pub all_files: bool,
+    /// Leave files Microsoft signed alone, so a dual booted Windows keeps booting with the
+    /// vendor keys on machines that still have them enrolled.
+    pub protect_microsoft: bool,
+    /// Leave files alone that carry a signature with a cert whose subject matches one of these
+    /// patterns, as a whole or one of its parts: `CN=Microsoft*`, `O=Dell*`.
+    pub skip_signed_by: Vec<String>,
+    /// Only sign files that carry no signature at all, whoever made it, for a first run over an
+    /// ESP of several vendors.
+    pub only_unsigned: bool,
+    /// Forbidden signature database to skip revoked images by, an EFI signature list file or
+    /// the efivar [`dbx::SYSTEM`].
+    pub dbx: Option<PathBuf>,
+    /// Shell command run for each file signed, in path order once every file is done and, with
+    /// `transactional`, the transaction too. `{path}` is replaced with the file and `{status}`
+    /// with `signed`, or `rolled-back` for a signed copy thrown away rather than renamed over
+    /// it. A hook failing fails its file.
+    pub post_sign: Option<String>,
+    /// Shell command run once the whole run is done. `{files}`, `{failures}`, `{unchanged}` and
+    /// `{skipped}` are replaced with those counts and `{status}` with `ok`, or `failed` if the
+    /// run didn't succeed, see [`Report::succeeded`].
+    pub post_run: Option<String>,
+    /// Shell command deciding whether each file that passed every other filter is signed, by
+    /// exiting 0. It gets the path as `{path}` and as a line on stdin.
+    pub filter_cmd: Option<String>,
+    /// Directory to load filter, signer and report plugins from, see [`plugin`].
+    pub plugins: Option<PathBuf>,
+    /// TCG event log to predict PCR values from once signing is done, see [`pcr`], usually
+    /// [`pcr::EVENT_LOG`].
+    pub event_log: Option<PathBuf>,
+    /// Signature database to find the cert in for the PCR 7 prediction, [`dbx::DB`] if not
+    /// given.
+    pub db: Option<PathBuf>,
+    /// Warn before signing when Windows is on a root and the machine has a TPM, as `BitLocker`
+    /// will likely want its recovery key afterwards. Turned off in the [`config`].
+    pub warn_bitlocker: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            key: PathBuf::new(),
+            cert: PathBuf::new(),
+            roots: Vec::new(),
+            recursive: true,
+            verbose: false,
+            jobs: available_parallelism().map_or(1, std::num::NonZeroUsize::get),
+            timeout: None,
+            busy_wait: Duration::from_secs(10),
+            settle: None,
+            nice: None,
+            ionice: None,
+            io_limit: None,
+            handle_immutable: false,
+            install_to: None,
+            journal: None,
+            audit_log: None,
+            usage: usage::Policy::default(),
+            chain: None,
+            pin: pin::Pin::default(),
+            allow_hashes: None,
+            approved: None,
+            completed: HashSet::new(),
+            cancel: cancel::Cancel::new(),
+            observers: observe::Observers::default(),
+            state: None,
+            passphrase: None,
+            unlocked: None,
+            agent: None,
+            walk_as: None,
+            caller: None,
+            sandbox: false,
+            resign: false,
+            strict_pe: false,
+            arch: Vec::new(),
+            fix_checksum: false,
+            compat: Vec::new(),
+            quarantine: None,
+            sbat_minimum: BTreeMap::new(),
+            cmdline_policy: None,
+            offline: false,
+            deterministic: false,
+            backup: false,
+            cleanup: false,
+            restore_stale: false,
+            transactional: false,
+            signers: Vec::new(),
+            directories: Vec::new(),
+            sort: false,
+            rules: None,
+            fwupd: fwupd::Policy::default(),
+            min_size: None,
+            max_size: None,
+            size_limit: None,
+            extensions: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            case: Case::default(),
+            any_root: false,
+            allow_empty: false,
+            all_files: false,
+            protect_microsoft: false,
+            skip_signed_by: Vec::new(),
+            only_unsigned: false,
+            dbx: None,
+            post_sign: None,
+            post_run: None,
+            filter_cmd: None,
+            plugins: None,
+            event_log: None,
+            db: None,
+            warn_bitlocker: true,
+        }
+    }
+}
+
+/// Summary of a finished run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Number of files handed to the signer.
+    pub files: usize,
+    /// Number of files the signer failed on.
+    pub failures: usize,
+    /// Files the signer failed on and how, in path order.
+    pub failed: Vec<(PathBuf, Failure)>,
+    /// Number of files left alone because `--state` says they are already signed.
+    pub unchanged: usize,
+    /// Files filtered out before signing or found already signed, in path order.
+    pub skipped: Vec<(PathBuf, Skip)>,
+    /// Directories and files that couldn't be searched and why, in path order.
+    pub errors: Vec<(PathBuf, String)>,
+    /// What the signer warned about for each file signed, in path order.
+    pub warnings: Vec<(PathBuf, diagnose::Warning)>,
+    /// Files whose checksum was wrong after signing and has been fixed, in path order.
+    pub checksums_fixed: Vec<PathBuf>,
+    /// The SBAT generations of each file signed that has any, in path order.
+    pub sbat: Vec<(PathBuf, Vec<sbat::Entry>)>,
+    /// How much each file signed grew, in path order. With `options.transactional` that is the
+    /// signed copy, committed or not.
+    pub sizes: Vec<(PathBuf, stats::Growth)>,
+    /// Files larger than `options.size_limit` once signed, in path order.
+    pub oversized: Vec<PathBuf>,
+    /// Files moved out of the way by `options.quarantine` and where to, in path order.
+    pub quarantined: Vec<(PathBuf, PathBuf)>,
+    /// Sizes and timings.
+    pub stats: Stats,
+    /// Expected PCR values, with `options.event_log`.
+    pub pcrs: Option<pcr::Prediction>,
+    /// Whether nothing was changed after all because a file failed, with
+    /// `options.transactional`.
+    pub rolled_back: bool,
+    /// How committing went on each root, with `options.transactional`.
+    pub transaction: Vec<(PathBuf, transaction::Outcome)>,
+    /// Whether `options.cancel` stopped the run before every file was done.
+    pub cancelled: bool,
+    /// Every file found and what became of it, in path order. Files `options.state` says are
+    /// unchanged are in here too.
+    pub entries: Vec<Entry>,
+    /// The roots of a run given several, empty for one. Paths are then reported relative to
+    /// the root they were found under, with the root named, see [`report::within`].
+    pub roots: Vec<PathBuf>,
+}
+
+impl Report {
+    /// What became of `path`, if it was found.
+    #[must_use]
+    pub fn entry(&self, path: &Path) -> Option<&Entry> {
+        self.entries
+            .binary_search_by(|entry| entry.path.as_path().cmp(path))
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+
+    /// The files signed, in path order.
+    pub fn signed(&self) -> impl Iterator<Item = &Path> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.action == report::Action::Signed)
+            .map(|entry| entry.path.as_path())
+    }
+
+    /// Whether the run did all it was meant to: no file failed, every path could be searched,
+    /// and it was neither rolled back nor cancelled.
+    #[must_use]
+    pub fn succeeded(&self) -> bool {
+        self.failures == 0 && self.errors.is_empty() && !self.rolled_back && !self.cancelled
+    }
+}
+
+/// Search every root and sign every file found, with at most `options.jobs` signers running at
+/// once. This blocks until every file has been processed, or the run is cancelled with
+/// `options.cancel` and the files being signed then are done.
+///
+/// # Errors
+///
+/// Fails if the state file can't be read or written, a sandbox was asked for but can't be set
+/// up, something is needed from the host that can't be had (a [`host::Unavailable`]), the
+/// files found can't be written (an [`access::Denied`]), nothing was found to sign and
+/// `options.allow_empty` isn't set, or the post-run hook or a report plugin fails. Failures
+/// signing individual files only show up in the report.
+pub fn run(options: &Options) -> Result<Report, Box<dyn std::error::Error>> {
+    prepare(options)?;
+
+    let start = Instant::now();
+    let mut shared = shared(options)?;
+    let predictor = match &options.event_log {
+        Some(log) => {
+            let db = options.db.as_deref().unwrap_or(Path::new(dbx::DB));
+            Some(pcr::Predictor::load(log, db)?)
+        }
+        None => None,
+    };
+    let state = shared.state.clone();
+
+    let cpu = stats::child_cpu_time();
+    let (sx, rx) = channel();
+
+    let mut walk = options.clone();
+    let plugins = match &options.plugins {
+        Some(dir) => plugin::discover(dir)?,
+        None => Vec::new(),
+    };
+    let filter = Arc::new(Filter::new(options, &plugins)?.sweeping());
+    if !options.any_root {
+        drop_suspicious(&mut walk.roots, &filter);
+    }
+    warn_bitlocker(options, &walk.roots);
+    shared.resign = filter.resigning();
+    let found = Arc::clone(&filter);
+    // walker output is interleaved across threads, with --sort it is logged below in order
+    let verbose = options.verbose && !options.sort;
+    let searcher = spawn(move || {
+        match walk.walk_as {
+            #[cfg(unix)]
+            Some(user) => unprivileged::searcher(&sx, &walk, &found, verbose, user),
+            _ => search::searcher(&sx, &walk, &found, verbose),
+        }
+        start.elapsed()
+    });
+
+    let (rx, hashing) = if let Some(state) = &state {
+        let (sx, hashed) = channel();
+        (
+            hashed,
+            Some(state::hasher(
+                rx,
+                &sx,
+                state,
+                &filter,
+                options.jobs,
+                verbose,
+                &options.cancel,
+            )),
+        )
+    } else {
+        (rx, None)
+    };
+
+    let rx = queue(rx, options, &filter)?;
+    let (workers, finished) = workers(rx, options, &shared);
+    let mut report = Report {
+        roots: named_roots(options),
+        ..Report::default()
+    };
+    let staged = sign::wait(workers, &finished, &mut report, options, &filter);
+    report.cancelled = options.cancel.is_cancelled();
+    let committed = options
+        .transactional
+        .then(|| transaction::finish(&mut report, staged, options, state.as_deref()));
+
+    report.stats.discovery = searcher.join().unwrap_or_default();
+    if let Some((spent, unchanged)) = hashing.and_then(|t| t.join().ok()) {
+        report.stats.hash += spent;
+        report.unchanged = unchanged;
+    }
+    report.skipped = filter.skipped();
+    refused(&mut report, &filter);
+    entries(&mut report);
+    sign::post_sign(&mut report, options, committed.as_ref());
+    report.errors = filter.errors();
+    if let Some(quarantine) = &options.quarantine {
+        quarantine.apply(&mut report, &options.backend)?;
+    }
+    if let (Some(predictor), Some(digests), false) =
+        (predictor, &shared.digests, report.rolled_back)
+    {
+        let digests = digests.lock().map(|d| d.clone()).unwrap_or_default();
+        report.pcrs = Some(predictor.predict(&digests, &options.cert)?);
+    }
+    report.stats.wall = start.elapsed();
+    report.stats.child_cpu = stats::child_cpu_time().saturating_sub(cpu);
+
+    finish(&shared, report.cancelled)?;
+    options.observers.finished(&report);
+
+    if report.files == 0 && report.unchanged == 0 && !options.allow_empty && !report.cancelled {
+        return Err(nothing_found(&options.roots, &report).into());
+    }
+
+    if let Some(template) = &options.post_run {
+        post_run(template, &report).map_err(|e| format!("post-run hook: {e}"))?;
+    }
+    for sink in plugins.iter().filter(|p| p.provides("report")) {
+        sink.report(&report::json(&report))?;
+    }
+
+    Ok(report)
+}
+
+/// The roots paths are reported relative to, those of `options` if it has several, each said
+/// with the number stderr knows it by.
+fn named_roots(options: &Options) -> Vec<PathBuf> {
+    if options.roots.len() < 2 {
+        return Vec::new();
+    }
+    for (i, root) in options.roots.iter().enumerate() {
+        dprintln!(options.verbose, "root:\t[{}] {}", i + 1, escape::path(root));
+    }
+    options.roots.clone()
+}
+
+/// The files to hand the workers, in path order with `options.sort`, once the first has been
+/// found writable, see [`access`]. Files installed elsewhere are only read, and mock runs write
+/// nothing.
+fn queue(
+    rx: Receiver<PathBuf>,
+    options: &Options,
+    filter: &Filter,
+) -> Result<Receiver<PathBuf>, access::Denied> {
+    let rx = if options.sort {
+        sorted(&rx, filter, options.verbose)
+    } else {
+        rx
+    };
+    if options.install_to.is_some() || matches!(options.backend, Backend::Mock(_)) {
+        return Ok(rx);
+    }
+    let Ok(first) = rx.recv() else {
+        return Ok(rx);
+    };
+    access::check(&first)?;
+
+    let (sx, checked) = channel();
+    // the receiver is still in scope, sending can't fail
+    let _ = sx.send(first);
+    spawn(move || {
+        for file in rx {
+            if sx.send(file).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(checked)
+}
+
+/// Start `options.jobs` workers signing the files coming down `rx`, and hand back what they
+/// send once done with each.
+fn workers(
+    rx: Receiver<PathBuf>,
+    options: &Options,
+    shared: &Shared,
+) -> (Vec<JoinHandle<Stats>>, Receiver<sign::Done>) {
+    let rx = Arc::new(Mutex::new(rx));
+    let (done, finished) = channel();
+    let workers = (0..options.jobs.max(1))
+        .map(|_| {
+            let rx = Arc::clone(&rx);
+            let done = done.clone();
+            let options = options.clone();
+            let shared = shared.clone();
+            spawn(move || sign::worker(&rx, &done, &options, &shared))
+        })
+        .collect();
+
+    // the workers hang up once they are done, which is how waiting knows they are
+    (workers, finished)
+}
+
+/// Check `options` can be done here, and clean up after earlier runs if asked to.
+fn prepare(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    if options.sandbox {
+        check_sandbox()?;
+    }
+    if cfg!(not(unix)) && options.caller.is_some() {
+        return Err("--preserve-caller-ownership is only supported on unix".into());
+    }
+    if cfg!(not(unix)) && options.walk_as.is_some() {
+        return Err("walking as another user is only supported on unix".into());
+    }
+    host::check(options)?;
+    install::check(options)?;
+    if let Some(quiet) = options.settle {
+        settle::wait(options, quiet);
+    }
+    if options.cleanup || options.restore_stale {
+        janitor::tidy(options)?;
+    }
+
+    Ok(())
+}
+
+/// Count the files the filter failed, by `error` rules or checks that couldn't be made, along
+/// with the ones the signer did.
+fn refused(report: &mut Report, filter: &Filter) {
+    let refused = filter.refused();
+    if refused.is_empty() {
+        return;
+    }
+    report.files += refused.len();
+    report.failures += refused.len();
+    for (file, why) in refused {
+        let failure = Failure::Error(why);
+        report.entries.push(Entry {
+            path: file.clone(),
+            action: report::Action::Failed,
+            duration: Duration::ZERO,
+            backend: None,
+            error: Some(failure.clone()),
+        });
+        report.failed.push((file, failure));
+    }
+    report.failed.sort_by(|a, b| a.0.cmp(&b.0));
+}
+
+/// Add the files that never got to a signer to `report.entries`, which has the rest.
+fn entries(report: &mut Report) {
+    let skipped = report
+        .skipped
+        .iter()
+        // the workers have those
+        .filter(|(_, reason)| *reason != Skip::AlreadySigned)
+        .map(|(path, reason)| Entry::skipped(path.clone(), *reason));
+    report.entries.extend(skipped.collect::<Vec<_>>());
+    report.entries.sort_by(|a, b| a.path.cmp(&b.path));
+}
+
+/// Write down what the run leaves behind for the next one, a cancelled run being left to
+/// resume.
+fn finish(shared: &Shared, cancelled: bool) -> std::io::Result<()> {
+    if let Some(state) = &shared.state {
+        state.save()?;
+    }
+    if let (Some(journal), false) = (&shared.journal, cancelled) {
+        journal.finish()?;
+    }
+
+    Ok(())
+}
+
+/// Check the backend of `options` gives the same bytes each time it signs an image, for
+/// `options.deterministic`.
+fn deterministic(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    if !options.backend.deterministic() {
+        return Err(format!(
+            "the {} backend always records the current time, sign deterministically with \
+             osslsigncode",
+            options.backend.name()
+        )
+        .into());
+    }
+    if let Backend::Plugin { name } = &options.backend {
+        if !plugin::deterministic(name, options)? {
+            return Err(format!(
+                "the {name} plugin doesn't provide \"deterministic\", it may not sign the same \
+                 image the same way twice"
+            )
+            .into());
+        }
+    }
+    if matches!(options.backend, Backend::Osslsigncode | Backend::Vault(_))
+        && offline::ecdsa(&options.cert)?
+    {
+        return Err(format!(
+            "{} has an ECDSA key, whose signatures are different every time, sign \
+             deterministically with an RSA key",
+            escape::path(&options.cert)
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Load what the workers share.
+fn shared(options: &Options) -> Result<Shared, Box<dyn std::error::Error>> {
+    let mut shared = Shared::default();
+    if options.deterministic {
+        deterministic(options)?;
+    }
+    if options.chain.is_some() && !options.backend.chains() {
+        return Err(format!(
+            "the {} backend can't be given --chain, give it the intermediate certs its own way",
+            options.backend.name()
+        )
+        .into());
+    }
+    if let Some(path) = &options.state {
+        shared.state = Some(Arc::new(State::load(path)?));
+    }
+    if let Some(rate) = options.io_limit {
+        shared.throttle = Some(Arc::new(throttle::Throttle::new(rate)));
+    }
+    if let Some(path) = &options.journal {
+        shared.journal = Some(Arc::new(journal::Journal::open(path)?));
+    }
+    if let Some(path) = &options.audit_log {
+        shared.audit = Some(Arc::new(audit::AuditLog::open(path)?));
+    }
+    if let Some(path) = &options.allow_hashes {
+        shared.allowed = Some(Arc::new(allowlist::load(path)?));
+    }
+    if !options.usage.is_empty() {
+        shared.usage = Some(Arc::new(usage::Counter::new(options.usage.clone())));
+    }
+    if (options.passphrase.is_some() || options.unlocked.is_some())
+        && (cfg!(not(target_os = "linux")) || !options.backend.pem_key())
+    {
+        return Err(format!(
+            "can't pass a decrypted key to {} here",
+            options.backend.name()
+        )
+        .into());
+    }
+    if let Some(key) = &options.unlocked {
+        shared.key = Some(Arc::clone(key));
+    } else if let Some(passphrase) = &options.passphrase {
+        let key = secret::decrypt_key(&options.key, passphrase)?;
+        #[cfg(target_os = "linux")]
+        if let Some(socket) = &options.agent {
+            if let Err(e) = agent::store(socket, &options.key, &key) {
+                eprintln!("warning: {e}, the passphrase will be asked for again next time");
+            }
+        }
+        shared.key = Some(Arc::new(key));
+    }
+    options.pin.check(options, shared.key.as_deref())?;
+    if let Backend::Vault(vault) = &options.backend {
+        shared.vault_token = Some(Arc::new(vault.token(options.timeout)?));
+    }
+    if options.event_log.is_some() {
+        shared.digests = Some(Arc::default());
+    }
+
+    Ok(shared)
+}
+
+fn warn_bitlocker(options: &Options, roots: &[PathBuf]) {
+    // the TPM would be that of whatever the container runs on
+    if !options.warn_bitlocker || options.offline || host::in_container() {
+        return;
+    }
+    let Some(bootmgfw) = pcr::bitlocker_risk(roots) else {
+        return;
+    };
+
+    eprintln!(
+        "warning: this machine dual boots Windows ({}) and has a TPM.\n\
+         warning: signing changes what measured boot records in PCR 7 (and PCR 4 for any image\n\
+         warning: whose hash changes), which BitLocker seals its key to. Suspend BitLocker in\n\
+         warning: Windows first (manage-bde -protectors -disable C: -RebootCount 1) or have the\n\
+         warning: recovery key at hand, the next Windows boot will ask for it otherwise.\n\
+         warning: set bitlocker = false under [warnings] in {} to stop this warning.",
+        escape::path(&bootmgfw),
+        config::SYSTEM,
+    );
+}
+
+/// Leave out roots that look like a mistake, with a warning.
+fn drop_suspicious(roots: &mut Vec<PathBuf>, filter: &Filter) {
+    roots.retain(|root| {
+        let Some(why) = filter::suspicious(root) else {
+            return true;
+        };
+        eprintln!(
+            "warning: not signing everything under {}, {why} (pass --i-know-what-im-doing if that is really meant)",
+            escape::path(root)
+        );
+        filter.skip(root, Skip::SuspiciousRoot, false);
+        false
+    });
+}
+
+fn post_run(template: &str, report: &Report) -> std::io::Result<()> {
+    let count = |n: usize| OsString::from(n.to_string());
+    let (files, failures) = (count(report.files), count(report.failures));
+    let (unchanged, skipped) = (count(report.unchanged), count(report.skipped.len()));
+    let status = OsStr::new(if report.succeeded() { "ok" } else { "failed" });
+    let vars = [
+        ("files", files.as_os_str()),
+        ("failures", &failures),
+        ("unchanged", &unchanged),
+        ("skipped", &skipped),
+        ("status", status),
+    ];
+
+    sign::check_hook(hook::run(template, &vars))
+}
+
+/// Explain why a run found nothing to sign.
+fn nothing_found(roots: &[PathBuf], report: &Report) -> String {
+    let skipped = &report.skipped;
+    if skipped.is_empty() {
+        let roots: Vec<_> = roots
+            .iter()
+            .map(|root| escape::path(root).to_string())
+            .collect();
+        let errors = match report.errors.len() {
+            0 => String::new(),
+            n => format!(", {n} paths couldn't be searched"),
+        };
+        return format!("no files found under {}{errors}", roots.join(", "));
+    }
+
+    let mut reasons: Vec<(Skip, usize)> = Vec::new();
+    for (_, reason) in skipped {
+        match reasons.iter_mut().find(|(r, _)| r == reason) {
+            Some((_, n)) => *n += 1,
+            None => reasons.push((*reason, 1)),
+        }
+    }
+    reasons.sort();
+    let reasons: Vec<_> = reasons.iter().map(|(r, n)| format!("{n} {r}")).collect();
+
+    format!(
+        "no files left to sign, every file found was filtered out: {}",
+        reasons.join(", ")
+    )
+}
+
+/// Drain the searcher and queue everything it found again in path order, logging what was
+/// skipped in between.
+fn sorted(rx: &Receiver<PathBuf>, filter: &Filter, verbose: bool) -> Receiver<PathBuf> {
+    let mut files: Vec<_> = rx.iter().collect();
+    files.sort();
+    files.dedup();
+
+    let mut skipped = filter.skipped().into_iter().peekable();
+    let (sx, rx) = channel();
+    for file in files {
+        while let Some((path, reason)) = skipped.next_if(|(path, _)| *path < file) {
+            dprintln!(verbose, "skipping:\t{} ({})", escape::path(&path), reason);
+        }
+        dprintln!(verbose, "pushing:\t{}", escape::path(&file));
+        // the receiver is still in scope, sending can't fail
+        let _ = sx.send(file);
+    }
+    for (path, reason) in skipped {
+        dprintln!(verbose, "skipping:\t{} ({})", escape::path(&path), reason);
+    }
+
+    rx
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn check_sandbox() -> std::io::Result<()> {
+    sandbox::check()
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+fn check_sandbox() -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "sandboxing is only supported on x86_64 and aarch64 Linux",
+    ))
+}
@@ -15,154 +15,1317 @@
  *
  * verbosely sign all files
  * ```
- * sbsevery /efi -k /etc/efi-keys/DB.key -c /etc/efi-keys/DB.crt -d
+ * sbsevery /efi -k /etc/efi-keys/DB.key -c /etc/efi-keys/DB.crt -v
  * ```
+ *
+ * everything else, the other backends, the subcommands and the configuration file, is in
+ * README.md
  */
 
 use std::{
     path::{Path, PathBuf},
-    process::ExitStatus,
-    sync::mpsc::{channel, Receiver, Sender},
-    thread::{spawn, JoinHandle},
+    sync::Arc,
+    time::Duration,
 };
 
 use jargon_args::Jargon;
-
-macro_rules! dprintln {
-    ($b:expr, $($arg:tt)*) => ({
-        if $b {
-            eprintln!($($arg)*);
-        }
-    })
-}
+use sbsevery::{
+    config::Config, secret, transaction::Outcome, Backend, Case, Failure, Options, Skip,
+};
 
 fn main() {
     if let Err(e) = main_prog() {
-        eprintln!("{}", e);
+        eprintln!("{e}");
+        if e.is::<sbsevery::host::Unavailable>() {
+            std::process::exit(sbsevery::host::EXIT_UNAVAILABLE);
+        }
+        if e.is::<sbsevery::access::Denied>() {
+            std::process::exit(sbsevery::access::EXIT_NOPERM);
+        }
         std::process::exit(1);
     }
 }
 
 fn main_prog() -> Result<(), Box<dyn std::error::Error>> {
-    let mut jargon = Jargon::from_env();
+    let mut args: Vec<String> = std::env::args().collect();
+
+    #[cfg(unix)]
+    if let Some(i) = args.iter().position(|arg| arg == "--pkexec") {
+        args.remove(i);
+        // SAFETY: geteuid can't fail
+        if unsafe { libc::geteuid() } != 0 {
+            std::process::exit(pkexec(&args[1..])?);
+        }
+    }
+
+    #[cfg(unix)]
+    if args.get(1).map(String::as_str) == Some("__elevated") {
+        args = elevated_args(&args)?;
+    }
+
+    let walking = args.get(1).map(String::as_str) == Some("__walk");
+    let resumed = if args.get(1).map(String::as_str) == Some("resume") {
+        Some(resume(&mut args)?)
+    } else {
+        None
+    };
+
+    let subcommand = match args.get(1).map(String::as_str) {
+        Some(
+            "init" | "bench" | "selftest" | "verify" | "snapshot" | "diff" | "mirror"
+            | "sign-stream" | "bootctl-update" | "explain" | "watch" | "ctl" | "audit-verify"
+            | "digest" | "apply-signatures" | "agent" | "__walk",
+        ) => Some(args.remove(1)),
+        _ => None,
+    };
+    env_args(&mut args, subcommand.as_deref());
+
+    let given = args[1..].to_vec();
+    let mut jargon = Jargon::from_vec(args);
+
+    if jargon.contains(["-V", "--version"]) {
+        version(jargon.contains("--json"));
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    if walking {
+        return walk(jargon);
+    }
+
+    match subcommand.as_deref() {
+        Some("init") => return init(jargon),
+        Some("ctl") => return ctl(jargon),
+        Some("audit-verify") => return audit_verify(jargon),
+        Some("agent") => return agent(jargon),
+        _ => {}
+    }
+
+    let stats = jargon.contains("--stats");
+    let report_to = jargon.option_arg::<String, _>("--report");
+    let verifying = matches!(
+        subcommand.as_deref(),
+        Some("verify" | "snapshot" | "diff" | "mirror" | "explain" | "digest" | "apply-signatures")
+    );
+    let config_path = jargon
+        .option_arg::<String, _>("--config")
+        .map(PathBuf::from);
+    let config = Config::given(config_path.as_deref())?;
+    let mut options = options(&mut jargon, !verifying)?;
+    config.apply(&mut options);
+
+    if subcommand.as_deref() == Some("bench") {
+        let files = jargon.option_arg("--files").unwrap_or(1000);
+        let size = match jargon.option_arg::<String, _>("--size") {
+            Some(size) => parse_size(&size)?,
+            None => 64 * 1024,
+        };
+        let report = sbsevery::bench::bench(&options, files, size)?;
+        eprintln!("{}", sbsevery::stats::Display(&report.stats, report.files));
+        return Ok(());
+    }
+
+    if subcommand.as_deref() == Some("selftest") {
+        sbsevery::selftest::selftest(&options)?;
+        eprintln!("selftest passed");
+        return Ok(());
+    }
+
+    if subcommand.as_deref() == Some("sign-stream") {
+        return sbsevery::stream::sign_stream(
+            &options,
+            &mut std::io::stdin().lock(),
+            &mut std::io::stdout().lock(),
+        );
+    }
+
+    match subcommand.as_deref() {
+        Some("verify") => return verify(jargon, options, report_to.as_deref()),
+        Some("snapshot") => return snapshot(jargon, options),
+        Some("diff") => return diff(jargon, options),
+        Some("mirror") => return mirror(jargon, &options),
+        Some("bootctl-update") => return bootctl_update(jargon, &options),
+        Some("explain") => return explain(jargon, options),
+        Some("watch") => return watch(jargon, options, config_path),
+        Some("digest") => return digest(jargon, options),
+        Some("apply-signatures") => return apply_signatures(jargon, &options),
+        _ => {}
+    }
+
+    sign(
+        jargon,
+        options,
+        &given,
+        resumed,
+        stats,
+        report_to.as_deref(),
+    )
+}
+
+/// `sbsevery __walk`, the unprivileged half of a run searching the roots for the privileged one.
+#[cfg(unix)]
+fn walk(mut jargon: Jargon) -> Result<(), Box<dyn std::error::Error>> {
     let verbose = jargon.contains(["-v", "--verbose"]);
-    let key_path: PathBuf = jargon.result_arg(["-k", "--key"])?;
-    let cert_path: PathBuf = jargon.result_arg(["-c", "--cert"])?;
-    let directories: Vec<PathBuf> = jargon.finish().iter().map(PathBuf::from).collect();
-    let (sx, rx) = channel();
+    let walkers = jargon.option_arg(["-j", "--jobs"]).unwrap_or(1);
+    let recursive = !jargon.contains("--no-recursive");
+    let roots = jargon.finish().iter().map(PathBuf::from).collect();
+    Ok(sbsevery::unprivileged::print(
+        roots, walkers, recursive, verbose,
+    )?)
+}
 
-    spawn(move || searcher(&sx, directories, verbose));
+/// Sign the roots, picking up where the journal left off if `resumed`.
+fn sign(
+    mut jargon: Jargon,
+    mut options: Options,
+    args: &[String],
+    resumed: Option<sbsevery::journal::Resume>,
+    stats: bool,
+    report_to: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let output = output_format(&mut jargon)?;
+    #[cfg(unix)]
+    let mut summary_fd = match jargon.option_arg("--summary-fd") {
+        Some(fd) => Some(sbsevery::report::from_fd(fd)?),
+        None => None,
+    };
+    if let Some(resume) = resumed {
+        // the roots as they were, a list read from stdin isn't there to read again
+        options.roots = resume.roots;
+        options.completed = resume.done;
+    } else {
+        options.roots = roots(jargon)?;
+        if let Some(journal) = &options.journal {
+            sbsevery::journal::begin(journal, args, &options.roots)?;
+        }
+    }
 
-    let mut threads = Vec::new();
-    threader(&rx, &key_path, &cert_path, &mut threads, verbose);
+    let report = sbsevery::run(&options)?;
 
-    let thread_count = threads.len();
-    let mut failures = 0;
-    wait(threads, &mut failures);
+    summary(&report);
+    if let Some(prediction) = &report.pcrs {
+        print_pcrs(prediction, &options);
+    }
+    if stats {
+        eprintln!("{}", sbsevery::stats::Display(&report.stats, report.files));
+    }
+    if let Some(spec) = report_to {
+        sbsevery::report::write(&report, spec)?;
+    }
+    #[cfg(unix)]
+    if let Some(fd) = &mut summary_fd {
+        sbsevery::report::write_to(&report, fd)?;
+    }
+    print_output(
+        output.as_deref(),
+        || sbsevery::tap::run(&report),
+        || sbsevery::annotate::run(&report),
+    );
+
+    if report.succeeded() {
+        Ok(())
+    } else {
+        Err(unfinished(&report).into())
+    }
+}
 
-    eprintln!("ran {} threads with {} failures", thread_count, failures);
+/// What the run left undone, for the error it exits with.
+fn unfinished(report: &sbsevery::Report) -> String {
+    let mut undone = Vec::new();
+    if report.failures > 0 {
+        undone.push(format!("{} files failed", report.failures));
+    }
+    if !report.errors.is_empty() {
+        undone.push(format!(
+            "{} paths couldn't be searched",
+            report.errors.len()
+        ));
+    }
+    if report.rolled_back {
+        undone.push("the run was rolled back".to_string());
+    }
+    if report.cancelled {
+        undone.push("the run was cancelled".to_string());
+    }
+    format!("not everything was signed: {}", undone.join(", "))
+}
 
-    Ok(())
+/// `--output-format`, how to tell about each file on stdout, the summary staying on stderr.
+fn output_format(jargon: &mut Jargon) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let Some(format) = jargon.option_arg::<String, _>("--output-format") else {
+        return Ok(None);
+    };
+    match format.as_str() {
+        "tap" | "github" | "gitlab" => Ok(Some(format)),
+        _ => {
+            Err(format!("unknown output format: '{format}', expected tap, github or gitlab").into())
+        }
+    }
 }
 
-fn wait(
-    threads: Vec<std::thread::JoinHandle<Result<ExitStatus, std::io::Error>>>,
-    failures: &mut i32,
+/// Print what `--output-format` asks for, made from `tap` or the `annotations`.
+fn print_output(
+    format: Option<&str>,
+    tap: impl FnOnce() -> String,
+    annotations: impl FnOnce() -> Vec<sbsevery::annotate::Annotation>,
 ) {
-    for t in threads {
-        if let Ok(res) = t.join() {
-            match res {
-                Ok(status) => {
-                    if !status.success() {
-                        *failures += 1;
+    match format {
+        Some("tap") => print!("{}", tap()),
+        Some("github") => print!("{}", sbsevery::annotate::github(&annotations())),
+        Some("gitlab") => print!("{}", sbsevery::annotate::gitlab(&annotations())),
+        _ => {}
+    }
+}
+
+/// `sbsevery watch`, sign the roots and again whenever they change, until stopped.
+#[cfg(target_os = "linux")]
+fn watch(
+    mut jargon: Jargon,
+    mut options: Options,
+    config: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let watch = sbsevery::watch::Watch {
+        health: jargon
+            .option_arg::<String, _>("--health")
+            .map(PathBuf::from),
+        rescan_every: jargon
+            .option_arg::<String, _>("--rescan-every")
+            .map(|every| parse_duration(&every))
+            .transpose()?,
+        dbus: jargon
+            .option_arg::<String, _>("--dbus")
+            .map(|bus| bus.parse())
+            .transpose()?,
+        varlink: jargon
+            .option_arg::<String, _>("--varlink")
+            .map(PathBuf::from),
+        http: match jargon.option_arg::<String, _>("--http") {
+            Some(listen) => Some(sbsevery::http::Http {
+                listen,
+                tokens: jargon
+                    .option_arg::<String, _>("--http-tokens")
+                    .map(PathBuf::from)
+                    .ok_or("--http needs --http-tokens, the clients that may use it")?,
+                audit: jargon
+                    .option_arg::<String, _>("--http-audit")
+                    .map(PathBuf::from),
+                rate: jargon
+                    .option_arg("--http-rate")
+                    .unwrap_or(sbsevery::http::Http::RATE),
+            }),
+            None => None,
+        },
+        config,
+    };
+    options.roots = roots(jargon)?;
+    cancel_on_signals(&options.cancel);
+    Ok(sbsevery::watch::watch(&options, &watch)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch(_: Jargon, _: Options, _: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    Err("watch needs Linux".into())
+}
+
+/// `sbsevery ctl`, ask the watcher listening on `--socket` to sign paths, say how it is doing
+/// or read its configuration again.
+#[cfg(target_os = "linux")]
+fn ctl(mut jargon: Jargon) -> Result<(), Box<dyn std::error::Error>> {
+    use sbsevery::varlink;
+    use serde_json::json;
+
+    let socket = jargon
+        .option_arg::<String, _>("--socket")
+        .map_or_else(|| PathBuf::from(varlink::SOCKET), PathBuf::from);
+    let verbose = jargon.contains(["-v", "--verbose"]);
+    let args = jargon.finish();
+    let (command, paths) = args
+        .split_first()
+        .ok_or("ctl needs sign, status or reload")?;
+
+    match command.as_str() {
+        "sign" if !paths.is_empty() => {
+            let mut failures = 0;
+            for path in paths {
+                // the watcher has a working directory of its own
+                let path = std::fs::canonicalize(path).map_err(|e| format!("{path}: {e}"))?;
+                let reply =
+                    varlink::call(&socket, "Sign", json!({ "path": path.to_string_lossy() }))?;
+                let report = &reply["report"];
+                for entry in report["entries"].as_array().into_iter().flatten() {
+                    let path = entry["path"].as_str().unwrap_or_default();
+                    let path = sbsevery::escape::text(path);
+                    match (entry["action"].as_str(), entry["error"].as_str()) {
+                        (Some("failed"), error) => {
+                            let error = sbsevery::escape::text(error.unwrap_or("failed"));
+                            eprintln!("failed:\t{path} ({error})");
+                        }
+                        (Some(action), _) if verbose => eprintln!("{action}:\t{path}"),
+                        _ => {}
                     }
                 }
-                Err(e) => eprintln!("{}", e),
+                eprintln!(
+                    "signed {} files with {} failures",
+                    report["files"], report["failures"]
+                );
+                failures += report["failures"].as_u64().unwrap_or(0);
             }
-        } else {
-            eprintln!("Thread join failed");
+            if failures > 0 {
+                return Err(format!("{failures} files failed").into());
+            }
+        }
+        "status" if paths.is_empty() => {
+            let reply = varlink::call(&socket, "Status", json!({}))?;
+            println!("{}", reply["status"]);
+        }
+        "reload" if paths.is_empty() => {
+            varlink::call(&socket, "Reload", json!({}))?;
+        }
+        "sign" => return Err("ctl sign needs paths to sign".into()),
+        _ => {
+            return Err(format!(
+                "unknown ctl command '{command}', expected sign PATH..., status or reload"
+            )
+            .into())
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn ctl(_: Jargon) -> Result<(), Box<dyn std::error::Error>> {
+    Err("ctl needs Linux".into())
+}
+
+/// `sbsevery audit-verify LOG`, check the chain of an `--audit-log`.
+fn audit_verify(jargon: Jargon) -> Result<(), Box<dyn std::error::Error>> {
+    let args = jargon.finish();
+    let [log] = args.as_slice() else {
+        return Err("audit-verify needs the log to check".into());
+    };
+    let (records, last) = sbsevery::audit::verify(Path::new(log))?;
+    eprintln!("{records} records, the chain is intact");
+    println!("{last}");
+    Ok(())
+}
+
+/// `sbsevery agent`, keep decrypted keys for runs given `--agent`, or `--forget` them.
+#[cfg(target_os = "linux")]
+fn agent(mut jargon: Jargon) -> Result<(), Box<dyn std::error::Error>> {
+    use sbsevery::agent;
+
+    let socket = jargon
+        .option_arg::<String, _>("--socket")
+        .map_or_else(|| PathBuf::from(agent::SOCKET), PathBuf::from);
+    if jargon.contains("--forget") {
+        agent::forget(&socket)?;
+        return Ok(());
+    }
+    let ttl = jargon
+        .option_arg::<String, _>("--ttl")
+        .map_or(Ok(Duration::from_mins(10)), |ttl| parse_duration(&ttl))?;
+    jargon.finish();
+    eprintln!(
+        "keeping keys for {}s at {}",
+        ttl.as_secs(),
+        sbsevery::escape::path(&socket)
+    );
+    agent::serve(&socket, ttl)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn agent(_: Jargon) -> Result<(), Box<dyn std::error::Error>> {
+    Err("agent needs Linux".into())
+}
+
+/// Cancel `cancel` on SIGTERM or SIGINT, letting the files being signed finish. A second one
+/// kills as usual.
+#[cfg(unix)]
+fn cancel_on_signals(cancel: &sbsevery::cancel::Cancel) {
+    static CANCEL: std::sync::OnceLock<sbsevery::cancel::Cancel> = std::sync::OnceLock::new();
+
+    extern "C" fn cancelled(signal: libc::c_int) {
+        if let Some(cancel) = CANCEL.get() {
+            cancel.cancel();
         }
+        // SAFETY: signal is async signal safe
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+
+    let _ = CANCEL.set(cancel.clone());
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        // SAFETY: the handler only stores to an atomic and resets itself
+        unsafe { libc::signal(signal, cancelled as *const () as libc::sighandler_t) };
     }
 }
 
-fn threader(
-    rx: &Receiver<PathBuf>,
-    key_path: &Path,
-    cert_path: &Path,
-    threads: &mut Vec<JoinHandle<Result<ExitStatus, std::io::Error>>>,
-    verbose: bool,
-) {
-    while let Ok(file) = rx.recv() {
-        let key_path = key_path.to_path_buf();
-        let cert_path = cert_path.to_path_buf();
-        let t = spawn(move || sign_file(&file, &key_path, &cert_path, verbose));
-        threads.push(t);
-    }
-}
-
-fn sign_file(
-    file: &Path,
-    key: &Path,
-    cert: &Path,
-    verbose: bool,
-) -> Result<ExitStatus, std::io::Error> {
-    dprintln!(verbose, "signing:\t{}", file.display());
-
-    let mut child = std::process::Command::new("sbsign")
-        .arg("--key")
-        .arg(key.as_os_str())
-        .arg("--cert")
-        .arg(cert.as_os_str())
-        .arg("--output")
-        .arg(&file)
-        .arg(&file)
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .spawn()?;
-    child.wait()
-}
-
-fn searcher(sx: &Sender<PathBuf>, directories: Vec<PathBuf>, verbose: bool) {
-    for dir in directories {
-        let err = if dir.is_dir() {
-            push_dir(sx, &dir, verbose)
-        } else {
-            push_file(sx, &dir, verbose)
+/// `sbsevery resume --journal PATH`, turn `args` into those of the run the journal is of, with
+/// any others given to resume after them.
+fn resume(args: &mut Vec<String>) -> Result<sbsevery::journal::Resume, Box<dyn std::error::Error>> {
+    args.remove(1);
+    let i = args
+        .iter()
+        .position(|arg| arg == "--journal")
+        .filter(|i| i + 1 < args.len())
+        .ok_or("resume needs the --journal of the run to resume")?;
+    let resume = sbsevery::journal::load(args[i + 1].as_ref())?;
+
+    let mut extra: Vec<_> = args.drain(1..).collect();
+    extra.drain(i - 1..=i);
+    args.extend(resume.args.iter().cloned());
+    args.extend(extra);
+    Ok(resume)
+}
+
+/// Settings that can come from the environment instead of the command line, the flags they
+/// stand for and whether they are switches. Switches are on for `1`, `true` and `yes`.
+const ENV: &[(&str, &[&str], bool)] = &[
+    ("SBSEVERY_KEY", &["-k", "--key"], false),
+    ("SBSEVERY_CERT", &["-c", "--cert"], false),
+    ("SBSEVERY_JOBS", &["-j", "--jobs"], false),
+    ("SBSEVERY_BACKEND", &["--backend"], false),
+    ("SBSEVERY_TIMEOUT", &["--timeout"], false),
+    ("SBSEVERY_STATE", &["--state"], false),
+    ("SBSEVERY_REPORT", &["--report"], false),
+    ("SBSEVERY_CONFIG", &["--config"], false),
+    ("SBSEVERY_VERBOSE", &["-v", "--verbose"], true),
+    ("SBSEVERY_OFFLINE", &["--offline"], true),
+    ("SBSEVERY_AGENT", &["--agent"], false),
+];
+
+/// Add the settings of [`ENV`] that are set, and not empty, to `args` unless the command line
+/// already has them, so the command line wins over the environment. Only for the commands
+/// taking the settings, the rest would take them for arguments of their own.
+fn env_args(args: &mut Vec<String>, subcommand: Option<&str>) {
+    if matches!(
+        subcommand,
+        Some("init" | "ctl" | "audit-verify" | "agent" | "__walk")
+    ) {
+        return;
+    }
+    for &(var, flags, switch) in ENV {
+        let Some(value) = std::env::var(var).ok().filter(|v| !v.is_empty()) else {
+            continue;
         };
+        if args.iter().any(|arg| flags.contains(&arg.as_str())) {
+            continue;
+        }
 
-        if let Err(e) = err {
-            dprintln!(verbose, "error:\t{}", e);
+        let flag = flags[flags.len() - 1].to_string();
+        if !switch {
+            args.extend([flag, value]);
+        } else if matches!(value.as_str(), "1" | "true" | "yes") {
+            args.push(flag);
         }
     }
 }
 
-fn push_dir(
-    sx: &Sender<PathBuf>,
-    dir: &Path,
-    verbose: bool,
+/// What happened, in numbers.
+fn summary(report: &sbsevery::Report) {
+    eprintln!(
+        "signed {} files with {} failures",
+        report.files, report.failures
+    );
+    // crashes and the OOM killer point at the machine rather than the files, call them out
+    let mut signals = std::collections::BTreeMap::new();
+    for (_, failure) in &report.failed {
+        if let Failure::Signal(signal) = failure {
+            *signals.entry(*signal).or_insert(0) += 1;
+        }
+    }
+    for (signal, n) in signals {
+        eprintln!("{n} of them {}", Failure::Signal(signal));
+    }
+    if !report.errors.is_empty() {
+        eprintln!("couldn't search {} paths", report.errors.len());
+    }
+    if report.unchanged > 0 {
+        eprintln!("skipped {} unchanged files", report.unchanged);
+    }
+    let filtered = report
+        .skipped
+        .iter()
+        .filter(|(_, reason)| *reason != Skip::Unchanged)
+        .count();
+    if filtered > 0 {
+        eprintln!("skipped {filtered} filtered files");
+    }
+    let mut warned: Vec<_> = report
+        .warnings
+        .iter()
+        .filter(|(_, warning)| warning.pe())
+        .map(|(path, _)| path)
+        .collect();
+    warned.dedup();
+    if !warned.is_empty() {
+        eprintln!("{} files have malformed images", warned.len());
+    }
+    if !report.sizes.is_empty() && !report.rolled_back {
+        let growth: i64 = report.sizes.iter().map(|(_, growth)| growth.delta()).sum();
+        eprintln!(
+            "signing grew {} files by {growth} bytes",
+            report.sizes.len()
+        );
+    }
+    if !report.oversized.is_empty() {
+        eprintln!("{} files are over the size limit", report.oversized.len());
+    }
+    if !report.quarantined.is_empty() {
+        eprintln!("quarantined {} files", report.quarantined.len());
+    }
+    if !report.checksums_fixed.is_empty() {
+        eprintln!(
+            "fixed the checksum of {} files",
+            report.checksums_fixed.len()
+        );
+    }
+    if report.rolled_back {
+        eprintln!("rolled back, no file was changed");
+    }
+    // one root went as the run did, with several say how far each got
+    let failed = report
+        .transaction
+        .iter()
+        .any(|(_, outcome)| matches!(outcome, Outcome::Failed(..)));
+    if report.transaction.len() > 1 || failed {
+        for (root, outcome) in &report.transaction {
+            eprintln!("{}: {outcome}", sbsevery::escape::path(root));
+        }
+    }
+}
+
+/// Old and new expected PCR values, for resealing ahead of the next boot.
+fn print_pcrs(prediction: &sbsevery::pcr::Prediction, options: &Options) {
+    use sbsevery::{pcr::Authority, state::hex};
+
+    let (old, new) = prediction.pcr4;
+    if old == new {
+        eprintln!("PCR 4: {} (unchanged)", hex(&old));
+    } else {
+        eprintln!("PCR 4: {} -> {}", hex(&old), hex(&new));
+    }
+
+    let cert = sbsevery::escape::path(&options.cert);
+    match prediction.authority {
+        Authority::Measured => eprintln!(
+            "PCR 7: {} (unchanged, {cert} already authorised an image this boot)",
+            hex(&prediction.pcr7)
+        ),
+        Authority::New(event) => eprintln!(
+            "PCR 7: {} will change, {cert} gets measured as a new authority event {}",
+            hex(&prediction.pcr7),
+            hex(&event)
+        ),
+        Authority::NotInDb => {
+            eprintln!("warning: {cert} is not in db, firmware will refuse the newly signed images");
+        }
+    }
+}
+
+/// `--version`, as JSON with `--json`.
+fn version(json: bool) {
+    if json {
+        println!("{}", sbsevery::version::json());
+    } else {
+        println!(
+            "sbsevery {} ({})",
+            sbsevery::version::VERSION,
+            sbsevery::version::COMMIT
+        );
+    }
+}
+
+/// `sbsevery init`, ask how to sign and write it down, under `--root` if given.
+fn init(mut jargon: Jargon) -> Result<(), Box<dyn std::error::Error>> {
+    let root = jargon
+        .option_arg::<String, _>("--root")
+        .unwrap_or_else(|| String::from("/"));
+    let setup = sbsevery::init::init(
+        root.as_ref(),
+        &mut std::io::stdin().lock(),
+        &mut std::io::stderr(),
+    )?;
+
+    eprintln!("all set, sign the ESP now with:\n\t{}", setup.command());
+    Ok(())
+}
+
+/// `sbsevery verify`, check the roots against `--policy`, or against `-c` alone without one.
+fn verify(
+    mut jargon: Jargon,
+    mut options: Options,
+    report_to: Option<&str>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    dprintln!(verbose, "expanding:\t{}", dir.display());
-    if let Ok(dir) = dir.read_dir() {
-        for entry in dir.flatten() {
-            let entry = entry.path();
-
-            if entry.is_dir() {
-                push_dir(sx, &entry, verbose)?;
-            } else {
-                push_file(sx, &entry, verbose)?;
+    use sbsevery::verify::{Policy, Verdict};
+
+    let policy = match jargon.option_arg::<String, _>("--policy") {
+        Some(path) => Policy::load(path.as_ref())?,
+        None if !options.cert.as_os_str().is_empty() => Policy::single(&options.cert),
+        None => return Err("verify needs a --policy or a cert to check against".into()),
+    };
+    let output = output_format(&mut jargon)?;
+    options.roots = roots(jargon)?;
+
+    let compliance = sbsevery::verify::verify(&options, &policy)?;
+
+    for (path, verdict) in &compliance.checked {
+        let path = sbsevery::escape::path(path);
+        match verdict {
+            Verdict::Compliant if options.verbose => eprintln!("ok:\t{path}"),
+            Verdict::Unmatched if options.verbose => eprintln!("unmatched:\t{path}"),
+            Verdict::Compliant | Verdict::Unmatched => {}
+            Verdict::Violation(missing) => {
+                let missing: Vec<_> = missing
+                    .iter()
+                    .map(|cert| sbsevery::escape::path(cert).to_string())
+                    .collect();
+                eprintln!("FAIL:\t{path} (not signed by {})", missing.join(", "));
+            }
+            Verdict::Error(e) => eprintln!("error:\t{path}: {e}"),
+        }
+    }
+
+    let violations = compliance.count(|v| matches!(v, Verdict::Violation(_) | Verdict::Error(_)));
+    eprintln!(
+        "{} compliant, {violations} non-compliant, {} not covered by the policy",
+        compliance.count(|v| *v == Verdict::Compliant),
+        compliance.count(|v| *v == Verdict::Unmatched),
+    );
+    if !compliance.errors.is_empty() {
+        eprintln!("couldn't search {} paths", compliance.errors.len());
+    }
+
+    if let Some(spec) = report_to {
+        sbsevery::verify::write(&compliance, spec)?;
+    }
+    print_output(
+        output.as_deref(),
+        || sbsevery::tap::verify(&compliance),
+        || sbsevery::annotate::verify(&compliance),
+    );
+    if !compliance.ok() {
+        return Err("policy violated".into());
+    }
+
+    Ok(())
+}
+
+/// `sbsevery snapshot`, record the roots in `--output`, signed if there is a key and cert. With
+/// `--format cyclonedx` it is written as a `CycloneDX` BOM instead.
+fn snapshot(mut jargon: Jargon, mut options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    use sbsevery::inventory::{self, Inventory};
+
+    let output: String = jargon.result_arg(["-o", "--output"])?;
+    let output = PathBuf::from(output);
+    let format = jargon
+        .option_arg::<String, _>("--format")
+        .unwrap_or_else(|| String::from("json"));
+    options.roots = roots(jargon)?;
+
+    let inventory = Inventory::take(&options)?;
+    match format.as_str() {
+        "json" => inventory.save(&output)?,
+        "cyclonedx" => {
+            let bom = inventory.cyclonedx(&options.backend);
+            std::fs::write(&output, format!("{bom:#}\n"))?;
+        }
+        _ => return Err(format!("unknown snapshot format: '{format}'").into()),
+    }
+    let signed = !options.key.as_os_str().is_empty() && !options.cert.as_os_str().is_empty();
+    if signed {
+        inventory::sign(&output, &options)?;
+    }
+
+    eprintln!(
+        "recorded {} files{}",
+        inventory.files.len(),
+        if signed { ", signed" } else { "" }
+    );
+    Ok(())
+}
+
+/// `sbsevery digest`, write down what the images under the roots need signed, to be signed
+/// elsewhere.
+fn digest(mut jargon: Jargon, mut options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    let output: String = jargon.result_arg(["-o", "--out"])?;
+    options.roots = roots(jargon)?;
+
+    let digests = sbsevery::offline::Digests::take(&options)?;
+    digests.save(Path::new(&output))?;
+    eprintln!("wrote the digests of {} files", digests.files.len());
+    Ok(())
+}
+
+/// `sbsevery apply-signatures`, put signatures made elsewhere into the images they are of.
+fn apply_signatures(jargon: Jargon, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let args = jargon.finish();
+    let [signatures] = args.as_slice() else {
+        return Err("apply-signatures needs the signatures to apply".into());
+    };
+    if options.cert.as_os_str().is_empty() {
+        return Err("apply-signatures needs the cert of the key that signed, -c".into());
+    }
+
+    options.pin.check(options, None)?;
+    let digests = sbsevery::offline::Digests::load(Path::new(signatures))?;
+    let mut failures = 0;
+    let applied = sbsevery::offline::apply(&digests, &options.cert, options.chain.as_deref())?;
+    for (path, res) in &applied {
+        match res {
+            Ok(()) if options.verbose => eprintln!("signed:\t{}", sbsevery::escape::path(path)),
+            Ok(()) => {}
+            Err(e) => {
+                eprintln!("failed:\t{} ({e})", sbsevery::escape::path(path));
+                failures += 1;
             }
         }
     }
+    eprintln!(
+        "applied {} signatures with {failures} failures",
+        applied.len()
+    );
+    if failures > 0 {
+        return Err(format!("{failures} files failed").into());
+    }
+    Ok(())
+}
+
+/// `sbsevery diff`, compare the roots against `--snapshot`, whose signature is checked first
+/// if there is a cert.
+fn diff(mut jargon: Jargon, mut options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    use sbsevery::inventory::{self, Inventory};
+
+    let snapshot: String = jargon.result_arg("--snapshot")?;
+    let snapshot = PathBuf::from(snapshot);
+    options.roots = roots(jargon)?;
+
+    if !options.cert.as_os_str().is_empty() {
+        inventory::check_signature(&snapshot, &options.cert)?;
+    }
+    let then = Inventory::load(&snapshot)?;
+    let changes = then.diff(&Inventory::take(&options)?);
+
+    for (path, change) in &changes {
+        println!("{change}:\t{path}");
+    }
+    if !changes.is_empty() {
+        return Err(format!("{} files differ from the snapshot", changes.len()).into());
+    }
+
+    eprintln!("no changes since the snapshot");
+    Ok(())
+}
+
+/// `sbsevery mirror`, copy the signed files under `--from` to `--to`.
+fn mirror(mut jargon: Jargon, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    use sbsevery::mirror::Copy;
+
+    let from: String = jargon.result_arg("--from")?;
+    let to: String = jargon.result_arg("--to")?;
+    let mirrored = sbsevery::mirror::mirror(options, from.as_ref(), to.as_ref())?;
+
+    for (path, copy) in &mirrored.files {
+        let path = sbsevery::escape::path(path);
+        match copy {
+            Copy::Copied | Copy::Unchanged => {}
+            Copy::Unsigned => eprintln!("FAIL:\t{path} (the copy isn't signed by the cert)"),
+            Copy::Error(e) => eprintln!("error:\t{path}: {e}"),
+        }
+    }
+    eprintln!(
+        "copied {} files, {} already there, {} failed",
+        mirrored.count(|c| *c == Copy::Copied),
+        mirrored.count(|c| *c == Copy::Unchanged),
+        mirrored.count(|c| matches!(c, Copy::Unsigned | Copy::Error(_))),
+    );
+    if !mirrored.errors.is_empty() {
+        eprintln!("couldn't search {} paths", mirrored.errors.len());
+    }
+    if !mirrored.ok() {
+        return Err("mirror incomplete".into());
+    }
+
+    Ok(())
+}
+
+/// `sbsevery explain`, what a run would do with each file given and why.
+fn explain(mut jargon: Jargon, mut options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    options.roots = repeated(&mut jargon, "--root")
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    let files = roots(jargon)?;
+    if options.roots.is_empty() {
+        // patterns are relative to the roots, with none given each file is its own
+        options.roots.clone_from(&files);
+    }
+
+    for file in &files {
+        println!("{}", sbsevery::explain::explain(file, &options)?);
+    }
+
+    Ok(())
+}
+
+/// `sbsevery bootctl-update`, sign and install the systemd-boot shipped onto the ESP.
+fn bootctl_update(mut jargon: Jargon, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let source = jargon
+        .option_arg::<String, _>("--source")
+        .map_or_else(|| PathBuf::from(sbsevery::bootctl::SOURCE), PathBuf::from);
+    let esp = match jargon.option_arg::<String, _>("--esp") {
+        Some(esp) => PathBuf::from(esp),
+        None => sbsevery::bootctl::find_esp().ok_or("couldn't find the ESP, pass --esp")?,
+    };
+    let updated = sbsevery::bootctl::update(options, &source, &esp)?;
+
+    for (path, e) in &updated.failed {
+        eprintln!("failed:\t{} ({e})", sbsevery::escape::path(path));
+    }
+    eprintln!(
+        "updated {} loaders, {} already current, {} failed",
+        updated.installed.len(),
+        updated.current.len(),
+        updated.failed.len()
+    );
+    if !updated.failed.is_empty() {
+        return Err("bootctl-update incomplete".into());
+    }
+
+    Ok(())
+}
+
+/// Options shared by every subcommand. Unless `signing`, the key and cert are optional.
+fn options(jargon: &mut Jargon, signing: bool) -> Result<Options, Box<dyn std::error::Error>> {
+    let mut options = Options {
+        verbose: jargon.contains(["-v", "--verbose"]),
+        sort: jargon.contains("--sort"),
+        sandbox: jargon.contains("--sandbox"),
+        resign: jargon.contains("--resign"),
+        strict_pe: jargon.contains("--strict-pe"),
+        fix_checksum: jargon.contains("--fix-checksum"),
+        offline: jargon.contains("--offline"),
+        deterministic: jargon.contains("--deterministic"),
+        backup: jargon.contains("--backup"),
+        cleanup: jargon.contains("--cleanup"),
+        restore_stale: jargon.contains("--restore-stale"),
+        transactional: jargon.contains("--transactional"),
+        any_root: jargon.contains("--i-know-what-im-doing"),
+        allow_empty: jargon.contains("--allow-empty"),
+        all_files: jargon.contains("--all-files"),
+        protect_microsoft: jargon.contains("--protect-microsoft"),
+        only_unsigned: jargon.contains("--only-unsigned"),
+        handle_immutable: jargon.contains("--handle-immutable"),
+        ..Options::default()
+    };
+    // --recursive is the default, accepted so scripts can say so
+    jargon.contains("--recursive");
+    if jargon.contains("--no-recursive") {
+        options.recursive = false;
+    }
+
+    backend(jargon, &mut options, signing)?;
+
+    if let Some(jobs) = jargon.option_arg(["-j", "--jobs"]) {
+        options.jobs = jobs;
+    }
+    options.timeout = jargon
+        .option_arg::<u64, _>("--timeout")
+        .map(Duration::from_secs);
+    options.settle = jargon
+        .option_arg::<u64, _>("--settle")
+        .map(Duration::from_secs);
+    if let Some(dir) = jargon.option_arg::<String, _>("--quarantine") {
+        options.quarantine = Some(sbsevery::quarantine::Quarantine {
+            dir: PathBuf::from(dir),
+            after: jargon
+                .option_arg("--quarantine-after")
+                .unwrap_or(sbsevery::quarantine::AFTER),
+        });
+    }
+    if let Some(checklist) = jargon.option_arg::<String, _>("--compat") {
+        options.compat = sbsevery::compat::checklist(&checklist)?;
+    }
+    options.nice = jargon.option_arg("--nice");
+    if let Some(class) = jargon.option_arg::<String, _>("--ionice") {
+        options.ionice = Some(class.parse()?);
+    }
+    if let Some(rate) = jargon.option_arg::<String, _>("--io-limit") {
+        options.io_limit = Some(parse_size(&rate)?);
+    }
+    options.install_to = jargon
+        .option_arg::<String, _>("--install-to")
+        .map(PathBuf::from);
+    if let Some(secs) = jargon.option_arg::<u64, _>("--busy-wait") {
+        options.busy_wait = Duration::from_secs(secs);
+    }
+    options.state = jargon.option_arg::<String, _>("--state").map(PathBuf::from);
+    options.journal = jargon
+        .option_arg::<String, _>("--journal")
+        .map(PathBuf::from);
+    options.audit_log = jargon
+        .option_arg::<String, _>("--audit-log")
+        .map(PathBuf::from);
+    unlock(jargon, &mut options)?;
+    filters(jargon, &mut options)?;
+    observers(jargon, &mut options)?;
+    options.post_sign = jargon.option_arg("--post-sign");
+    options.post_run = jargon.option_arg("--post-run");
+    options.plugins = jargon
+        .option_arg::<String, _>("--plugins")
+        .map(PathBuf::from);
+    options.event_log = jargon
+        .option_arg::<String, _>("--event-log")
+        .map(PathBuf::from);
+    if jargon.contains("--predict-pcrs") {
+        options.event_log = Some(PathBuf::from(sbsevery::pcr::EVENT_LOG));
+    }
+    options.db = jargon.option_arg::<String, _>("--db").map(PathBuf::from);
+    #[cfg(unix)]
+    if let Some(user) = jargon.option_arg::<String, _>("--walk-as") {
+        options.walk_as = Some(lookup_user(&user)?);
+    }
+    #[cfg(unix)]
+    if jargon.contains("--preserve-caller-ownership") {
+        options.caller = Some(sbsevery::sudo::caller()?);
+    }
+
+    Ok(options)
+}
+
+/// What to tell about the run as it goes.
+fn observers(jargon: &mut Jargon, options: &mut Options) -> std::io::Result<()> {
+    if let Some(path) = jargon.option_arg::<String, _>("--events") {
+        let events = sbsevery::observe::Events::create(Path::new(&path))?;
+        options.observers.add(Arc::new(events));
+    }
+    if jargon.contains("--progress") {
+        options
+            .observers
+            .add(Arc::new(sbsevery::observe::Progress::new()));
+    }
 
     Ok(())
 }
 
-fn push_file(
-    sx: &Sender<PathBuf>,
-    file: &Path,
-    verbose: bool,
+/// The backend of `--backend` with its settings, and the key and cert it signs with.
+fn backend(
+    jargon: &mut Jargon,
+    options: &mut Options,
+    signing: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    dprintln!(verbose, "pushing:\t{}", file.display());
-    sx.send(file.to_path_buf())?;
+    if let Some(backend) = jargon.option_arg::<String, _>("--backend") {
+        options.backend = backend.parse()?;
+    }
+
+    if let Backend::Pesign { token, certificate } = &mut options.backend {
+        if let Some(t) = jargon.option_arg("--pesign-token") {
+            *token = t;
+        }
+        *certificate = jargon.result_arg("--pesign-cert")?;
+    } else if let Backend::Plugin { .. } | Backend::Mock(_) = options.backend {
+        // whatever the plugin needs, if anything
+        options.key = jargon.option_arg(["-k", "--key"]).unwrap_or_default();
+        options.cert = jargon.option_arg(["-c", "--cert"]).unwrap_or_default();
+    } else if let Backend::Vault(vault) = &mut options.backend {
+        vault.path = jargon.result_arg("--vault-path")?;
+        vault.addr = jargon
+            .option_arg("--vault-addr")
+            .or_else(|| std::env::var("VAULT_ADDR").ok())
+            .unwrap_or_default();
+        if let Some(role_id) = jargon.option_arg("--vault-role-id") {
+            vault.approle = Some(sbsevery::vault::AppRole {
+                role_id,
+                secret_id_file: jargon
+                    .option_arg::<String, _>("--vault-secret-id-file")
+                    .map(PathBuf::from)
+                    .ok_or("--vault-role-id needs --vault-secret-id-file")?,
+            });
+        }
+        // the key is in Vault
+        options.cert = jargon.result_arg(["-c", "--cert"])?;
+    } else if let Backend::Ssh(ssh) = &mut options.backend {
+        ssh.remote = jargon.result_arg("--remote")?;
+        ssh.command = jargon.option_arg("--remote-command");
+        ssh.digest = jargon.contains("--remote-digest");
+        // the key is on the remote, and so is the cert unless the signature is built here
+        options.cert = if ssh.digest {
+            if ssh.command.is_none() {
+                return Err("--remote-digest needs --remote-command, what signs there".into());
+            }
+            jargon.result_arg(["-c", "--cert"])?
+        } else {
+            jargon.option_arg(["-c", "--cert"]).unwrap_or_default()
+        };
+    } else if options.backend == Backend::Signtool {
+        options.cert = jargon.result_arg(["-c", "--cert"])?;
+    } else if !signing {
+        options.key = jargon.option_arg(["-k", "--key"]).unwrap_or_default();
+        options.cert = jargon.option_arg(["-c", "--cert"]).unwrap_or_default();
+    } else {
+        options.key = jargon.result_arg(["-k", "--key"])?;
+        options.cert = jargon.result_arg(["-c", "--cert"])?;
+    }
+    options.chain = jargon.option_arg::<String, _>("--chain").map(PathBuf::from);
+    Ok(())
+}
+
+/// The passphrase of the key from `--askpass` or `--passphrase-fd`, unless the agent of
+/// `--agent` has the key decrypted already.
+fn unlock(jargon: &mut Jargon, options: &mut Options) -> Result<(), Box<dyn std::error::Error>> {
+    options.agent = jargon.option_arg::<String, _>("--agent").map(PathBuf::from);
+    let askpass = jargon.option_arg::<String, _>("--askpass");
+    #[cfg(unix)]
+    let fd = jargon.option_arg::<i32, _>("--passphrase-fd");
+    #[cfg(target_os = "linux")]
+    if let Some(socket) = &options.agent {
+        match sbsevery::agent::fetch(socket, &options.key) {
+            Ok(key) => options.unlocked = key.map(Arc::new),
+            Err(e) => eprintln!("warning: {e}"),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if options.agent.is_some() {
+        return Err("--agent needs Linux".into());
+    }
+    if options.unlocked.is_some() {
+        return Ok(());
+    }
+
+    if let Some(cmd) = askpass {
+        options.passphrase = Some(Arc::new(secret::askpass(&cmd, &options.key)?));
+    }
+    #[cfg(unix)]
+    if let Some(fd) = fd {
+        options.passphrase = Some(Arc::new(secret::from_fd(fd)?));
+    }
+    Ok(())
+}
+
+/// Which files to leave alone.
+fn filters(jargon: &mut Jargon, options: &mut Options) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(size) = jargon.option_arg::<String, _>("--min-size") {
+        options.min_size = Some(parse_size(&size)?);
+    }
+    if let Some(size) = jargon.option_arg::<String, _>("--max-size") {
+        options.max_size = Some(parse_size(&size)?);
+    }
+    if let Some(size) = jargon.option_arg::<String, _>("--size-limit") {
+        options.size_limit = Some(parse_size(&size)?);
+    }
+    options.extensions = repeated(jargon, "--ext")
+        .iter()
+        .flat_map(|exts| exts.split(','))
+        .map(|ext| ext.trim_start_matches('.').to_string())
+        .collect();
+    options.arch = repeated(jargon, "--arch")
+        .iter()
+        .flat_map(|arches| arches.split(','))
+        .map(str::parse)
+        .collect::<Result<_, _>>()?;
+    options.include = repeated(jargon, "--include");
+    options.exclude = repeated(jargon, "--exclude");
+    options.rules = jargon.option_arg::<String, _>("--rules").map(PathBuf::from);
+    options.skip_signed_by = repeated(jargon, "--skip-signed-by");
+    for name in repeated(jargon, "--preset") {
+        sbsevery::preset::Preset::get(&name)?.apply(options);
+    }
+    options.filter_cmd = jargon.option_arg("--filter-cmd");
+    options.dbx = jargon.option_arg::<String, _>("--dbx").map(PathBuf::from);
+    options.allow_hashes = jargon
+        .option_arg::<String, _>("--allow-hashes")
+        .map(PathBuf::from);
+    options.cmdline_policy = jargon
+        .option_arg::<String, _>("--cmdline-policy")
+        .map(PathBuf::from);
+    if jargon.contains("--check-dbx") {
+        options.dbx = Some(PathBuf::from(sbsevery::dbx::SYSTEM));
+    }
+    if let Some(policy) = jargon.option_arg::<String, _>("--fwupd") {
+        options.fwupd = policy.parse()?;
+    }
+    if jargon.contains("--case-sensitive") {
+        options.case = Case::Sensitive;
+    }
+    if jargon.contains("--ignore-case") {
+        options.case = Case::Insensitive;
+    }
+
     Ok(())
 }
+
+/// The paths left on the command line, with every `@FILE` replaced by the paths listed in it,
+/// `@-` reading them from stdin.
+fn roots(jargon: Jargon) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut roots = Vec::new();
+    for arg in jargon.finish() {
+        let Some(list) = arg.strip_prefix('@') else {
+            roots.push(PathBuf::from(arg));
+            continue;
+        };
+
+        let data = if list == "-" {
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut std::io::stdin(), &mut data)?;
+            data
+        } else {
+            std::fs::read(list).map_err(|e| format!("{list}: {e}"))?
+        };
+        roots.extend(list_paths(&data));
+    }
+
+    Ok(roots)
+}
+
+/// One path per line, blank lines and lines starting with `#` left out.
+fn list_paths(data: &[u8]) -> impl Iterator<Item = PathBuf> + '_ {
+    data.split(|&b| b == b'\n')
+        .map(<[u8]>::trim_ascii)
+        .filter(|line| !line.is_empty() && !line.starts_with(b"#"))
+        .map(path_from_bytes)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Every value of an option that may be given more than once.
+fn repeated(jargon: &mut Jargon, key: &'static str) -> Vec<String> {
+    std::iter::from_fn(|| jargon.option_arg(key)).collect()
+}
+
+/// Run ourselves again as root through pkexec, see `data/org.sbsevery.policy`. The arguments go
+/// in a file only this user can read rather than argv, so key paths don't show up in `ps`, and
+/// stdin is left for `@-` and `sign-stream`; pkexec closes every other descriptor.
+#[cfg(unix)]
+fn pkexec(args: &[String]) -> Result<i32, Box<dyn std::error::Error>> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let path =
+        std::env::temp_dir().join(format!(".sbsevery-pkexec-{}-{nanos}", std::process::id()));
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .map_err(|e| format!("{}: {e}", sbsevery::escape::path(&path)))?;
+    let status = (|| {
+        for arg in args {
+            file.write_all(arg.as_bytes())?;
+            file.write_all(b"\0")?;
+        }
+        std::process::Command::new("pkexec")
+            .arg(std::env::current_exe()?)
+            .arg("__elevated")
+            .arg(&path)
+            .status()
+    })();
+    let _ = std::fs::remove_file(&path);
+
+    Ok(status?.code().unwrap_or(1))
+}
+
+/// The other end of [`pkexec`], `args` being `__elevated FILE` and the arguments in `FILE`. It
+/// has to be a file of the user who ran pkexec that nobody else can read, or anyone allowed to
+/// run pkexec could have root read any file as arguments.
+#[cfg(unix)]
+fn elevated_args(args: &[String]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    use std::{
+        io::Read,
+        os::unix::fs::{MetadataExt, OpenOptionsExt},
+    };
+
+    let file = Path::new(
+        args.get(2)
+            .ok_or("__elevated needs the file the arguments are in")?,
+    );
+    let uid: u32 = std::env::var("PKEXEC_UID")
+        .ok()
+        .and_then(|uid| uid.parse().ok())
+        .ok_or("__elevated is only for sbsevery --pkexec")?;
+    let mut opened = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(file)
+        .map_err(|e| format!("{}: {e}", sbsevery::escape::path(file)))?;
+    let meta = opened.metadata()?;
+    if !meta.is_file() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err(format!(
+            "{} isn't a private file of uid {uid}, not reading arguments from it",
+            sbsevery::escape::path(file)
+        )
+        .into());
+    }
+    let mut buf = String::new();
+    opened.read_to_string(&mut buf)?;
+
+    Ok(std::iter::once(args[0].clone())
+        .chain(buf.split_terminator('\0').map(String::from))
+        .collect())
+}
+
+/// Uid and primary gid of a user name, or of a literal `uid:gid`.
+#[cfg(unix)]
+fn lookup_user(user: &str) -> Result<(u32, u32), String> {
+    if let Some((uid, gid)) = user.split_once(':') {
+        if let (Ok(uid), Ok(gid)) = (uid.parse(), gid.parse()) {
+            return Ok((uid, gid));
+        }
+    }
+
+    let name = std::ffi::CString::new(user).map_err(|e| e.to_string())?;
+    // SAFETY: nothing else is looking users up yet, getpwnam's static buffer is ours
+    unsafe {
+        let pw = libc::getpwnam(name.as_ptr());
+        if pw.is_null() {
+            return Err(format!("unknown user: '{user}'"));
+        }
+        Ok(((*pw).pw_uid, (*pw).pw_gid))
+    }
+}
+
+/// Parse a number of seconds, or of minutes, hours or days with an m, h or d suffix.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (digits, unit) = match s.chars().last() {
+        Some('s') => (&s[..s.len() - 1], 1),
+        Some('m') => (&s[..s.len() - 1], 60),
+        Some('h') => (&s[..s.len() - 1], 60 * 60),
+        Some('d') => (&s[..s.len() - 1], 24 * 60 * 60),
+        _ => (s, 1),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration: '{s}'"))
+}
+
+/// Parse a byte count with an optional K, M or G (binary) suffix.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, shift) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 10),
+        Some('M') => (&s[..s.len() - 1], 20),
+        Some('G') => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size: '{s}'"))
+}
@@ -0,0 +1,152 @@
+//! `sbsevery mirror`, copy the signed files of one ESP onto another, for mirrored setups that
+//! boot from whichever disk is left.
+//!
+//! Files are found under `--from` as they would be for signing, and copied to the same path
+//! under `--to`. Each copy is checked to be signed by the cert before it takes the place of
+//! what was there, files on the destination that aren't on the source are left alone.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use crate::{config, escape, filter::Filter, janitor, pe, search, Options, Skip};
+
+/// What happened to one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Copy {
+    /// Copied and verified on the destination.
+    Copied,
+    /// The destination already had the same file.
+    Unchanged,
+    /// The copy isn't signed by the cert, the destination was left as it was.
+    Unsigned,
+    /// The file couldn't be copied or verified.
+    Error(String),
+}
+
+/// Outcome of mirroring one root onto another.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mirrored {
+    /// Every file found under the source and what happened to it, in path order.
+    pub files: Vec<(PathBuf, Copy)>,
+    /// Files filtered out, in path order.
+    pub skipped: Vec<(PathBuf, Skip)>,
+    /// Directories and files that couldn't be searched and why, in path order.
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+impl Mirrored {
+    /// Number of files that went this way.
+    #[must_use]
+    pub fn count(&self, copy: fn(&Copy) -> bool) -> usize {
+        self.files.iter().filter(|(_, c)| copy(c)).count()
+    }
+
+    /// Whether every file made it and everything could be searched.
+    #[must_use]
+    pub fn ok(&self) -> bool {
+        self.errors.is_empty()
+            && self
+                .files
+                .iter()
+                .all(|(_, c)| matches!(c, Copy::Copied | Copy::Unchanged))
+    }
+}
+
+/// Copy every file found under `from` to the same path under `to`, checking each copy with
+/// the verifier of `options.backend` against `options.cert`, or the cert its directory or
+/// architecture has in the configuration.
+///
+/// # Errors
+///
+/// Fails if the backend has no verifier, there is no cert to check against or filters can't
+/// be set up. Files that couldn't be mirrored only show up in the result.
+pub fn mirror(
+    options: &Options,
+    from: &Path,
+    to: &Path,
+) -> Result<Mirrored, Box<dyn std::error::Error>> {
+    if !options.backend.verifies() {
+        return Err(format!(
+            "the {} backend has no verifier, mirror with sbsign or osslsigncode",
+            options.backend.name()
+        )
+        .into());
+    }
+    if options.cert.as_os_str().is_empty()
+        && options.signers.is_empty()
+        && options.directories.is_empty()
+    {
+        return Err("mirror needs a cert to check the copies against".into());
+    }
+    if !to.is_dir() {
+        return Err(format!("{} isn't a directory", escape::path(to)).into());
+    }
+
+    let options = Options {
+        roots: vec![from.to_path_buf()],
+        ..options.clone()
+    };
+    let filter = Filter::new(&options, &[])?;
+    let files = search::each(&options, &filter, |file| {
+        // the source root itself can be a file too
+        let relative = file
+            .strip_prefix(from)
+            .ok()
+            .filter(|rel| !rel.as_os_str().is_empty())
+            .or_else(|| file.file_name().map(Path::new))
+            .unwrap_or(file);
+        copy(file, &to.join(relative), &options).unwrap_or_else(|e| Copy::Error(e.to_string()))
+    });
+
+    Ok(Mirrored {
+        files,
+        skipped: filter.skipped(),
+        errors: filter.errors(),
+    })
+}
+
+fn copy(file: &Path, dest: &Path, options: &Options) -> io::Result<Copy> {
+    let data = std::fs::read(file)?;
+    if std::fs::read(dest).is_ok_and(|there| there == data) {
+        return Ok(Copy::Unchanged);
+    }
+
+    if let Some(dir) = dest.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let staged = janitor::temp_path(dest);
+    std::fs::write(&staged, &data)?;
+
+    let cert = config::signer(options, file, || {
+        pe::Layout::parse(&data).map(|layout| layout.machine)
+    })
+    .map_or(&options.cert, |signer| &signer.cert);
+    let signed = verified(&staged, cert, options);
+    match signed {
+        Ok(true) => {
+            std::fs::File::open(&staged)?.sync_all()?;
+            std::fs::rename(&staged, dest)?;
+            dprintln!(options.verbose, "copied:\t{}", escape::path(dest));
+            Ok(Copy::Copied)
+        }
+        res => {
+            let _ = std::fs::remove_file(&staged);
+            res.map(|_| Copy::Unsigned)
+        }
+    }
+}
+
+fn verified(file: &Path, cert: &Path, options: &Options) -> io::Result<bool> {
+    let Some(mut cmd) = options.backend.verify_command(file, cert) else {
+        return Err(io::Error::other("no verifier"));
+    };
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    cmd.stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .map_err(|e| io::Error::new(e.kind(), format!("couldn't run {program}: {e}")))
+}
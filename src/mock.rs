@@ -0,0 +1,50 @@
+//! `--backend mock`, a signer that signs nothing. Every file that would have been handed to a
+//! signer is written down instead and left as it is, so hooks, reports and scripts built
+//! around a run can be tried against a tree of fixtures without keys or sbsigntools.
+//!
+//! Files already signed are treated as sbsign treats them, left alone without `--resign`, and
+//! images too broken to sign fail. `--state` and `--journal` aren't written to, nothing having
+//! been signed after all. From the command line each file is said on stderr as `would sign:`
+//! and the path, in the library [`MockSigner::signed`] has them.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// The files a mock run would have signed, shared by all its clones.
+#[derive(Debug, Clone, Default)]
+pub struct MockSigner {
+    signed: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+/// Two of them are the same if they write down to the same list.
+impl PartialEq for MockSigner {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.signed, &other.signed)
+    }
+}
+
+impl Eq for MockSigner {}
+
+impl MockSigner {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every file signed so far, in path order.
+    #[must_use]
+    pub fn signed(&self) -> Vec<PathBuf> {
+        let mut signed = self.signed.lock().map(|s| s.clone()).unwrap_or_default();
+        signed.sort();
+        signed
+    }
+
+    /// Write down that `file` would have been signed.
+    pub(crate) fn record(&self, file: &Path) {
+        if let Ok(mut signed) = self.signed.lock() {
+            signed.push(file.to_path_buf());
+        }
+    }
+}
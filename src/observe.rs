@@ -0,0 +1,214 @@
+//! Following a run as it goes, for programs that embed sbsevery and want to show progress of
+//! their own. Add an [`Observer`] to [`Options::observers`](crate::Options::observers) and it is
+//! told about every file as it is found and as it is done with.
+//!
+//! `--events` and `--progress` are observers too, [`Events`] and [`Progress`].
+
+use std::{
+    fmt,
+    io::{self, IsTerminal, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde_json::json;
+
+use crate::{escape, Entry, Report, Skip};
+
+/// Something to tell about a run as it goes. Calls come from the run's threads, any number at
+/// once, and hold the run up for as long as they take.
+pub trait Observer: Send + Sync {
+    /// `file` was found, before the filters had their say.
+    fn on_discovered(&self, _file: &Path) {}
+    /// A file was signed.
+    fn on_signed(&self, _entry: &Entry) {}
+    /// Signing a file failed, or an `error` rule failed it.
+    fn on_failed(&self, _entry: &Entry) {}
+    /// `file` was left alone for `reason`.
+    fn on_skipped(&self, _file: &Path, _reason: Skip) {}
+    /// The run is over and this is how it went.
+    fn on_finished(&self, _report: &Report) {}
+}
+
+/// The observers of a run.
+#[derive(Clone, Default)]
+pub struct Observers(Vec<Arc<dyn Observer>>);
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+impl Observers {
+    /// Tell `observer` about the run too.
+    pub fn add(&mut self, observer: Arc<dyn Observer>) {
+        self.0.push(observer);
+    }
+
+    pub(crate) fn discovered(&self, file: &Path) {
+        self.0.iter().for_each(|o| o.on_discovered(file));
+    }
+
+    pub(crate) fn signed(&self, entry: &Entry) {
+        self.0.iter().for_each(|o| o.on_signed(entry));
+    }
+
+    pub(crate) fn failed(&self, entry: &Entry) {
+        self.0.iter().for_each(|o| o.on_failed(entry));
+    }
+
+    pub(crate) fn skipped(&self, file: &Path, reason: Skip) {
+        self.0.iter().for_each(|o| o.on_skipped(file, reason));
+    }
+
+    pub(crate) fn finished(&self, report: &Report) {
+        self.0.iter().for_each(|o| o.on_finished(report));
+    }
+}
+
+/// `--events`, a JSON object a line for everything that happens, written as it does.
+///
+/// ```text
+/// {"event":"discovered","path":"/efi/EFI/BOOT/BOOTX64.EFI"}
+/// {"event":"signed","path":"/efi/EFI/BOOT/BOOTX64.EFI","duration":0.021,"backend":"sbsign"}
+/// {"event":"finished","files":1,"failures":0}
+/// ```
+pub struct Events(Mutex<Box<dyn Write + Send>>);
+
+impl Events {
+    /// Write the events to `out`.
+    #[must_use]
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self(Mutex::new(out))
+    }
+
+    /// Write the events to the file at `path`, `/dev/stdout` say.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be created.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = std::fs::File::create(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+        Ok(Self::new(Box::new(io::LineWriter::new(file))))
+    }
+
+    fn write(&self, event: &serde_json::Value) {
+        if let Ok(mut out) = self.0.lock() {
+            // a reader that went away doesn't stop the run
+            let _ = writeln!(out, "{event}");
+        }
+    }
+
+    fn entry(&self, event: &str, entry: &Entry) {
+        let mut json = json!({
+            "event": event,
+            "path": escape::path(&entry.path).to_string(),
+            "duration": entry.duration.as_secs_f64(),
+            "backend": entry.backend.as_ref().map(crate::Backend::name),
+        });
+        if let Some(error) = &entry.error {
+            json["error"] = error.to_string().into();
+        }
+        self.write(&json);
+    }
+}
+
+impl Observer for Events {
+    fn on_discovered(&self, file: &Path) {
+        self.write(&json!({ "event": "discovered", "path": escape::path(file).to_string() }));
+    }
+
+    fn on_signed(&self, entry: &Entry) {
+        self.entry("signed", entry);
+    }
+
+    fn on_failed(&self, entry: &Entry) {
+        self.entry("failed", entry);
+    }
+
+    fn on_skipped(&self, file: &Path, reason: Skip) {
+        self.write(&json!({
+            "event": "skipped",
+            "path": escape::path(file).to_string(),
+            "reason": reason.to_string(),
+        }));
+    }
+
+    fn on_finished(&self, report: &Report) {
+        self.write(&json!({
+            "event": "finished",
+            "files": report.files,
+            "failures": report.failures,
+        }));
+    }
+}
+
+/// `--progress`, a line on stderr counting the files done against those found, redrawn as
+/// each is done. Nothing is drawn if stderr isn't a terminal.
+#[derive(Debug, Default)]
+pub struct Progress {
+    found: AtomicUsize,
+    signed: AtomicUsize,
+    failed: AtomicUsize,
+    skipped: AtomicUsize,
+    /// Held while drawing so lines don't mix.
+    draw: Mutex<()>,
+}
+
+impl Progress {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn draw(&self, end: &str) {
+        if !io::stderr().is_terminal() {
+            return;
+        }
+        let Ok(_drawing) = self.draw.lock() else {
+            return;
+        };
+        let (signed, failed, skipped) = (
+            self.signed.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+        );
+        let found = self.found.load(Ordering::Relaxed);
+        // \x1b[K clears what is left of a longer line before
+        eprint!(
+            "\r{}/{found} files, {signed} signed, {failed} failed, {skipped} skipped\x1b[K{end}",
+            signed + failed + skipped
+        );
+    }
+}
+
+impl Observer for Progress {
+    fn on_discovered(&self, _: &Path) {
+        self.found.fetch_add(1, Ordering::Relaxed);
+        self.draw("");
+    }
+
+    fn on_signed(&self, _: &Entry) {
+        self.signed.fetch_add(1, Ordering::Relaxed);
+        self.draw("");
+    }
+
+    fn on_failed(&self, _: &Entry) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        self.draw("");
+    }
+
+    fn on_skipped(&self, _: &Path, _: Skip) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+        self.draw("");
+    }
+
+    fn on_finished(&self, _: &Report) {
+        self.draw("\n");
+    }
+}
@@ -0,0 +1,545 @@
+//! `sbsevery digest` and `sbsevery apply-signatures`, signing with a key that never leaves an
+//! air-gapped machine. The online host works out what each image's signature has to sign, the
+//! signing machine signs it, and the signatures are put into the images back on the host:
+//!
+//! ```text
+//! sbsevery digest /efi --out digests.json
+//! # on the signing machine, with the key or an HSM
+//! sbsevery apply-signatures signatures.json -c /etc/efi-keys/DB.crt
+//! ```
+//!
+//! `digests.json` has an entry for each image, its Authenticode hash and `to_sign`, the signed
+//! attributes of the Authenticode signature in hex. `signatures.json` is the same with
+//! `signature` added to each entry, the signature of `to_sign` with SHA-256, in hex, as
+//! `openssl dgst -sha256 -sign` makes it; an HSM signing digests signs `to_sign_sha256`:
+//!
+//! ```text
+//! jq -c '.files[]' digests.json | while read -r file; do
+//!     sig=$(jq -r .to_sign <<<"$file" | xxd -r -p | openssl dgst -sha256 -sign DB.key | xxd -p | tr -d '\n')
+//!     jq -c --arg sig "$sig" '.signature = $sig' <<<"$file"
+//! done | jq -s '{files: .}' > signatures.json
+//! ```
+//!
+//! Applying checks each image still has the hash it had and each signature verifies against the
+//! cert before it builds the PKCS#7 signature out of them and the cert and adds it to the image,
+//! after any it has already. RSA and ECDSA keys both work.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+use crate::{
+    escape,
+    filter::Filter,
+    janitor::temp_path,
+    pe::Layout,
+    search,
+    state::{hex, unhex},
+    Options,
+};
+
+/// What the images found need signed, as `sbsevery digest` writes it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Digests {
+    pub files: Vec<Digest>,
+}
+
+/// What an image needs signed, and the signature once it has been.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Digest {
+    /// Escaped as everywhere else, see [`crate::escape`].
+    pub path: String,
+    /// The Authenticode hash of the image, as it will be once signed.
+    pub authenticode_sha256: String,
+    /// The DER of the signed attributes, which is what is signed, in hex.
+    pub to_sign: String,
+    /// The SHA-256 of `to_sign`.
+    pub to_sign_sha256: String,
+    /// The signature of `to_sign`, in hex.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl Digests {
+    /// What every image under the roots of `options` that passes the filters needs signed.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the filters can't be loaded. Images that can't be read are printed and left
+    /// out.
+    pub fn take(options: &Options) -> io::Result<Self> {
+        let filter = Filter::new(options, &[])?;
+        let files = search::each(options, &filter, digest)
+            .into_iter()
+            .filter_map(|(file, digest)| match digest {
+                Ok(digest) => Some(digest),
+                Err(e) => {
+                    eprintln!("error:\t{}: {e}", escape::path(&file));
+                    None
+                }
+            })
+            .collect();
+        Ok(Self { files })
+    }
+
+    /// Read a file written by [`Digests::save`], signatures added or not.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or isn't one.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+        serde_json::from_str(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", escape::path(path)),
+            )
+        })
+    }
+
+    /// Write them to `path` as JSON.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        std::fs::write(path, text + "\n")
+    }
+}
+
+/// Put the signature of each entry of `digests` into its image, signed by the key of `cert`,
+/// the intermediate certs of `chain` after it. Returns each image and what came of it, entries
+/// without a signature are left out.
+///
+/// # Errors
+///
+/// Fails if the cert or chain can't be read.
+pub fn apply(
+    digests: &Digests,
+    cert: &Path,
+    chain: Option<&Path>,
+) -> io::Result<Vec<(PathBuf, io::Result<()>)>> {
+    let signer = Signer::load(cert, chain)?;
+
+    Ok(digests
+        .files
+        .iter()
+        .filter_map(|entry| {
+            let signature = entry.signature.as_deref()?;
+            let path = escape::unescape(&entry.path)
+                .map_or_else(|| PathBuf::from(&entry.path), PathBuf::from);
+            let res = apply_one(&path, entry, signature, &signer, cert);
+            Some((path, res))
+        })
+        .collect())
+}
+
+fn invalid(why: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, why.into())
+}
+
+/// The image at `file` and its layout, an unsigned one padded to where its signatures will
+/// start, as that is part of what they sign.
+fn read_image(file: &Path) -> io::Result<(Vec<u8>, Layout)> {
+    let mut data = std::fs::read(file)?;
+    let layout = Layout::parse(&data).ok_or_else(|| invalid("not a PE image"))?;
+    match &layout.cert_table {
+        Some(table) if table.end != data.len() => {
+            return Err(invalid(
+                "its signatures aren't at the end, where another would go",
+            ))
+        }
+        Some(_) => {}
+        // padding that is part of the hash
+        None => data.resize(data.len().next_multiple_of(8), 0),
+    }
+    Ok((data, layout))
+}
+
+fn digest(file: &Path) -> io::Result<Digest> {
+    let (data, layout) = read_image(file)?;
+    let authenticode = layout.authenticode(&data);
+    let (to_sign, _) = signed_attributes(&authenticode);
+    Ok(Digest {
+        path: escape::path(file).to_string(),
+        authenticode_sha256: hex(&authenticode),
+        to_sign_sha256: hex(&Sha256::digest(&to_sign)),
+        to_sign: hex(&to_sign),
+        signature: None,
+    })
+}
+
+fn apply_one(
+    file: &Path,
+    entry: &Digest,
+    signature: &str,
+    signer: &Signer,
+    cert: &Path,
+) -> io::Result<()> {
+    let signature = unhex_any(signature).ok_or_else(|| invalid("the signature isn't hex"))?;
+    let (mut data, layout) = read_image(file)?;
+    let authenticode = layout.authenticode(&data);
+    if unhex(&entry.authenticode_sha256) != Some(authenticode) {
+        return Err(invalid(
+            "it changed since its digest was taken, take it again",
+        ));
+    }
+    let (to_sign, spc) = signed_attributes(&authenticode);
+    if !verifies(&to_sign, &signature, cert)? {
+        return Err(invalid(format!(
+            "the signature doesn't verify against {}",
+            escape::path(cert)
+        )));
+    }
+
+    let pkcs7 = signer.pkcs7(&spc, &to_sign, &signature);
+    if layout.pkcs7(&data).contains(&pkcs7.as_slice()) {
+        // applied already
+        return Ok(());
+    }
+    attach(&mut data, &layout, &pkcs7)?;
+    write(file, file, &data)
+}
+
+/// Sign the image at `file` into `output`, having `sign` sign the signed attributes, with the
+/// key of `cert`, the intermediate certs of `chain` after it, for signers that only sign, see
+/// [`crate::vault`].
+pub(crate) fn sign_image(
+    file: &Path,
+    output: &Path,
+    cert: &Path,
+    chain: Option<&Path>,
+    sign: impl FnOnce(&[u8]) -> io::Result<Vec<u8>>,
+) -> io::Result<()> {
+    let signer = Signer::load(cert, chain)?;
+    let (mut data, layout) = read_image(file)?;
+    let (to_sign, spc) = signed_attributes(&layout.authenticode(&data));
+    let signature = sign(&to_sign)?;
+    if !verifies(&to_sign, &signature, cert)? {
+        return Err(invalid(format!(
+            "the signature doesn't verify against {}, is it the cert of the key?",
+            escape::path(cert)
+        )));
+    }
+    attach(
+        &mut data,
+        &layout,
+        &signer.pkcs7(&spc, &to_sign, &signature),
+    )?;
+    write(file, output, &data)
+}
+
+/// Whether the key of `cert` is an ECDSA one, whose signatures are different every time.
+pub(crate) fn ecdsa(cert: &Path) -> io::Result<bool> {
+    Ok(Signer::load(cert, None)?.ecdsa)
+}
+
+/// Add the signature `pkcs7` to the image `data`, after any it has already.
+fn attach(data: &mut Vec<u8>, layout: &Layout, pkcs7: &[u8]) -> io::Result<()> {
+    // WIN_CERTIFICATE: length, revision 2.0, PKCS#7 signed data, then the signature padded
+    let mut cert_entry = Vec::with_capacity(pkcs7.len() + 16);
+    cert_entry.extend_from_slice(&to_u32(pkcs7.len() + 8)?.to_le_bytes());
+    cert_entry.extend_from_slice(&0x0200_u16.to_le_bytes());
+    cert_entry.extend_from_slice(&0x0002_u16.to_le_bytes());
+    cert_entry.extend_from_slice(pkcs7);
+    cert_entry.resize(cert_entry.len().next_multiple_of(8), 0);
+
+    let table = match &layout.cert_table {
+        Some(table) => {
+            data.resize(data.len().next_multiple_of(8), 0);
+            table.start
+        }
+        None => data.len(),
+    };
+    data.extend_from_slice(&cert_entry);
+    let dir = layout.cert_dir.start;
+    data[dir..dir + 4].copy_from_slice(&to_u32(table)?.to_le_bytes());
+    let size = to_u32(data.len() - table)?;
+    data[dir + 4..dir + 8].copy_from_slice(&size.to_le_bytes());
+    let checksum = layout.compute_checksum(data);
+    data[layout.checksum.clone()].copy_from_slice(&checksum.to_le_bytes());
+    Ok(())
+}
+
+/// Write the image `data` signed from `file` to `output`, by way of a temporary file.
+fn write(file: &Path, output: &Path, data: &[u8]) -> io::Result<()> {
+    let temp = temp_path(output);
+    let written = std::fs::write(&temp, data)
+        .and_then(|()| std::fs::set_permissions(&temp, std::fs::metadata(file)?.permissions()))
+        .and_then(|()| std::fs::File::open(&temp)?.sync_all())
+        .and_then(|()| {
+            crate::sign::keep_xattrs(file, &temp);
+            std::fs::rename(&temp, output)
+        });
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written
+}
+
+fn to_u32(n: usize) -> io::Result<u32> {
+    u32::try_from(n).map_err(|_| invalid("too large for a PE image"))
+}
+
+/// Hex of any case, with or without whitespace or colons between the bytes.
+fn unhex_any(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b':')
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Whether `signature` is that of `data` by the key of `cert`, asking openssl.
+fn verifies(data: &[u8], signature: &[u8], cert: &Path) -> io::Result<bool> {
+    let dir = std::env::temp_dir();
+    let key = temp_path(&dir.join("key"));
+    let sig = temp_path(&dir.join("sig"));
+    let res = (|| {
+        let output = Command::new("openssl")
+            .args(["x509", "-pubkey", "-noout", "-in"])
+            .arg(cert)
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "couldn't read {}: openssl {}",
+                escape::path(cert),
+                output.status
+            )));
+        }
+        std::fs::write(&key, output.stdout)?;
+        std::fs::write(&sig, signature)?;
+
+        let mut child = Command::new("openssl")
+            .args(["dgst", "-sha256", "-verify"])
+            .arg(&key)
+            .arg("-signature")
+            .arg(&sig)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            io::Write::write_all(&mut stdin, data)?;
+        }
+        Ok(child.wait()?.success())
+    })();
+    let _ = std::fs::remove_file(&key);
+    let _ = std::fs::remove_file(&sig);
+    res
+}
+
+const SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+const CONTENT_TYPE: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x03];
+const MESSAGE_DIGEST: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x04];
+const RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const SPC_INDIRECT_DATA: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x01, 0x04];
+const SPC_SP_OPUS_INFO: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x01, 0x0c];
+const SPC_PE_IMAGE_DATA: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x01, 0x0f];
+
+/// The signed attributes of a signature of an image with `authenticode` as its hash, as they
+/// are signed, and the `SpcIndirectDataContent` they are the attributes of.
+fn signed_attributes(authenticode: &[u8; 32]) -> (Vec<u8>, Vec<u8>) {
+    // SpcPeImageData, no flags and the file name everyone gives
+    let obsolete: Vec<u8> = "<<<Obsolete>>>"
+        .encode_utf16()
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    let image = seq(&[
+        &[0x03, 0x01, 0x00],
+        &tlv(0xa0, &tlv(0xa2, &tlv(0x80, &obsolete))),
+    ]);
+    let spc = seq(&[
+        &seq(&[&oid(SPC_PE_IMAGE_DATA), &image]),
+        &seq(&[&algorithm(SHA256, true), &tlv(0x04, authenticode)]),
+    ]);
+
+    // Authenticode hashes the content of the SpcIndirectDataContent, without its tag
+    let content = read(&spc).map_or(&spc[..], |spc| spc.content);
+    let attribute = |kind: &[u8], value: &[u8]| seq(&[&oid(kind), &tlv(0x31, value)]);
+    let mut attributes = [
+        attribute(CONTENT_TYPE, &oid(SPC_INDIRECT_DATA)),
+        attribute(MESSAGE_DIGEST, &tlv(0x04, &Sha256::digest(content))),
+        attribute(SPC_SP_OPUS_INFO, &seq(&[])),
+    ];
+    // a DER SET OF is sorted
+    attributes.sort();
+    (tlv(0x31, &attributes.concat()), spc)
+}
+
+/// Where the signer's cert says it is from, to name it in a signature.
+struct Signer {
+    cert: Vec<u8>,
+    /// The intermediate certs that go in with it, as DER.
+    chain: Vec<Vec<u8>>,
+    issuer: Vec<u8>,
+    serial: Vec<u8>,
+    ecdsa: bool,
+}
+
+impl Signer {
+    fn load(cert: &Path, chain: Option<&Path>) -> io::Result<Self> {
+        let mut signer = Self::parse(&crate::pcr::der(cert)?)
+            .ok_or_else(|| invalid(format!("{} isn't an X.509 cert", escape::path(cert))))?;
+        if let Some(chain) = chain {
+            signer.chain = crate::pcr::ders(chain)?;
+        }
+        Ok(signer)
+    }
+
+    fn parse(cert: &[u8]) -> Option<Self> {
+        let certificate = read(cert).filter(|e| e.tag == 0x30)?;
+        let tbs = read(certificate.content).filter(|e| e.tag == 0x30)?;
+        let mut rest = tbs.content;
+        let mut next = || {
+            let element = read(rest)?;
+            rest = element.rest;
+            Some(element)
+        };
+        let mut serial = next()?;
+        // the version is left out of v1 certs
+        if serial.tag == 0xa0 {
+            serial = next()?;
+        }
+        if serial.tag != 0x02 {
+            return None;
+        }
+        let _signature = next()?;
+        let issuer = next()?;
+        let _validity = next()?;
+        let _subject = next()?;
+        let key = next()?;
+        let algorithm = read(key.content)?;
+        let kind = read(algorithm.content)?;
+
+        Some(Self {
+            cert: cert.to_vec(),
+            chain: Vec::new(),
+            issuer: issuer.all.to_vec(),
+            serial: serial.all.to_vec(),
+            ecdsa: kind.content == EC_PUBLIC_KEY,
+        })
+    }
+
+    /// The Authenticode signature, PKCS#7 `SignedData` of `spc` with `signature` of its
+    /// `attributes`.
+    fn pkcs7(&self, spc: &[u8], attributes: &[u8], signature: &[u8]) -> Vec<u8> {
+        // signed as a SET, carried as [0]
+        let attributes = read(attributes).map_or(attributes, |set| set.content);
+        let signature_algorithm = if self.ecdsa {
+            algorithm(ECDSA_SHA256, false)
+        } else {
+            algorithm(RSA, true)
+        };
+        let signer_info = seq(&[
+            &[0x02, 0x01, 0x01],
+            &seq(&[&self.issuer, &self.serial]),
+            &algorithm(SHA256, true),
+            &tlv(0xa0, attributes),
+            &signature_algorithm,
+            &tlv(0x04, signature),
+        ]);
+        // the signer's cert first, then whatever leads from it to the root
+        let signed_data = seq(&[
+            &[0x02, 0x01, 0x01],
+            &tlv(0x31, &algorithm(SHA256, true)),
+            &seq(&[&oid(SPC_INDIRECT_DATA), &tlv(0xa0, spc)]),
+            &tlv(
+                0xa0,
+                &[std::slice::from_ref(&self.cert), &self.chain]
+                    .concat()
+                    .concat(),
+            ),
+            &tlv(0x31, &signer_info),
+        ]);
+        seq(&[&oid(SIGNED_DATA), &tlv(0xa0, &signed_data)])
+    }
+}
+
+/// A DER element, its tag, length and `content`.
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len().to_be_bytes();
+    match content.len() {
+        // short form
+        0..0x80 => out.push(len[len.len() - 1]),
+        n => {
+            let bytes = &len[(n.leading_zeros() / 8) as usize..];
+            out.push(0x80 | u8::try_from(bytes.len()).unwrap_or(0));
+            out.extend_from_slice(bytes);
+        }
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn seq(parts: &[&[u8]]) -> Vec<u8> {
+    tlv(0x30, &parts.concat())
+}
+
+fn oid(encoded: &[u8]) -> Vec<u8> {
+    tlv(0x06, encoded)
+}
+
+/// An `AlgorithmIdentifier`, with NULL parameters or none.
+fn algorithm(kind: &[u8], null: bool) -> Vec<u8> {
+    if null {
+        seq(&[&oid(kind), &[0x05, 0x00]])
+    } else {
+        seq(&[&oid(kind)])
+    }
+}
+
+/// A DER element read off the front of some bytes.
+struct Element<'a> {
+    tag: u8,
+    /// Tag, length and content.
+    all: &'a [u8],
+    content: &'a [u8],
+    /// What follows it.
+    rest: &'a [u8],
+}
+
+/// The DER element at the start of `data`.
+fn read(data: &[u8]) -> Option<Element<'_>> {
+    let tag = *data.first()?;
+    let first = *data.get(1)?;
+    let (len, header) = if first < 0x80 {
+        (usize::from(first), 2)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > std::mem::size_of::<usize>() {
+            return None;
+        }
+        let len = data
+            .get(2..2 + count)?
+            .iter()
+            .fold(0, |len, &b| len << 8 | usize::from(b));
+        (len, 2 + count)
+    };
+    let end = header.checked_add(len)?;
+    let all = data.get(..end)?;
+    Some(Element {
+        tag,
+        all,
+        content: &all[header..],
+        rest: &data[end..],
+    })
+}
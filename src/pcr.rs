@@ -0,0 +1,336 @@
+//! `--predict-pcrs`, work out what measured boot will see once the new binaries are booted, so
+//! secrets sealed to PCRs can be resealed before the reboot rather than found locked after it.
+//!
+//! The firmware's TCG event log is replayed as it is, giving the values the PCRs should have
+//! now, and again with every signed image's old Authenticode hash in PCR 4 replaced by its new
+//! one. PCR 7 doesn't depend on the images but on which db entry authorised them, so for it
+//! only the event the signing cert will be measured as is worked out, and whether the log
+//! already has it.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{dbx, escape, pe::Image, state::Hash};
+
+/// Where Linux exposes the firmware's event log.
+pub const EVENT_LOG: &str = "/sys/kernel/security/tpm0/binary_bios_measurements";
+
+const EV_NO_ACTION: u32 = 0x03;
+const EV_EFI_BOOT_SERVICES_APPLICATION: u32 = 0x8000_0003;
+const EV_EFI_VARIABLE_AUTHORITY: u32 = 0x8000_00e0;
+const TPM_ALG_SHA256: u16 = 0x000b;
+
+/// `EFI_IMAGE_SECURITY_DATABASE_GUID`, as it is laid out on disk.
+const IMAGE_SECURITY_DATABASE: [u8; 16] = [
+    0xcb, 0xb2, 0x19, 0xd7, 0x3a, 0x3d, 0x96, 0x45, 0xa3, 0xbc, 0xda, 0xd0, 0x0e, 0x67, 0x65, 0x6f,
+];
+
+/// One measurement of the event log, in its SHA-256 bank.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Event {
+    pcr: u32,
+    kind: u32,
+    digest: Hash,
+    data: Vec<u8>,
+}
+
+/// What will happen to PCR 7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authority {
+    /// The signing cert already authorised something this boot, PCR 7 stays as it is.
+    Measured,
+    /// The signing cert will be measured as this new event, so PCR 7 will change.
+    New(Hash),
+    /// The signing cert isn't in db, firmware will refuse the new binaries.
+    NotInDb,
+}
+
+/// Expected PCR values before and after booting the newly signed binaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prediction {
+    /// PCR 4 as the log has it now, and once the new binaries are booted.
+    pub pcr4: (Hash, Hash),
+    /// PCR 7 as the log has it now.
+    pub pcr7: Hash,
+    /// How the signing cert changes PCR 7.
+    pub authority: Authority,
+}
+
+/// A crypto agile TCG event log and the db the signing cert is looked up in.
+#[derive(Debug)]
+pub(crate) struct Predictor {
+    events: Vec<Event>,
+    db: PathBuf,
+}
+
+impl Predictor {
+    /// Read the event log at `log`, ahead of signing so a missing TPM is found out before
+    /// anything changes.
+    pub(crate) fn load(log: &Path, db: &Path) -> io::Result<Self> {
+        let data = std::fs::read(log).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("couldn't read event log {}: {e}", escape::path(log)),
+            )
+        })?;
+        let events = parse(&data).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is not a crypto agile event log with a SHA-256 bank",
+                    escape::path(log)
+                ),
+            )
+        })?;
+
+        Ok(Self {
+            events,
+            db: db.to_path_buf(),
+        })
+    }
+
+    /// Predict the PCRs given the `(old, new)` Authenticode hashes of every image signed and
+    /// the signing `cert`.
+    pub(crate) fn predict(&self, images: &[(Hash, Hash)], cert: &Path) -> io::Result<Prediction> {
+        let pcr4 = (
+            replay(&self.events, 4, |_| None),
+            replay(&self.events, 4, |event| {
+                if event.kind != EV_EFI_BOOT_SERVICES_APPLICATION {
+                    return None;
+                }
+                images
+                    .iter()
+                    .find(|(old, _)| *old == event.digest)
+                    .map(|(_, new)| *new)
+            }),
+        );
+        let pcr7 = replay(&self.events, 7, |_| None);
+
+        let der = der(cert)?;
+        let entry = dbx::entries(&self.db, &dbx::CERT_X509)?
+            .into_iter()
+            .find(|entry| entry.get(16..) == Some(&der[..]));
+        let authority = match entry {
+            Some(entry) => {
+                let data = variable_data(&entry);
+                let digest: Hash = Sha256::digest(&data).into();
+                let measured = self.events.iter().any(|event| {
+                    event.pcr == 7
+                        && event.kind == EV_EFI_VARIABLE_AUTHORITY
+                        && (event.digest == digest || event.data == data)
+                });
+                if measured {
+                    Authority::Measured
+                } else {
+                    Authority::New(digest)
+                }
+            }
+            None => Authority::NotInDb,
+        };
+
+        Ok(Prediction {
+            pcr4,
+            pcr7,
+            authority,
+        })
+    }
+}
+
+/// Path of the Windows boot manager on the ESP.
+const BOOTMGFW: &[&str] = &["EFI", "Microsoft", "Boot", "bootmgfw.efi"];
+
+/// The Windows boot manager under one of `roots`, if there is one and the machine has a TPM,
+/// in which case `BitLocker` most likely seals its key to PCR 7 and signing sets off recovery.
+pub(crate) fn bitlocker_risk(roots: &[PathBuf]) -> Option<PathBuf> {
+    if !has_tpm() {
+        return None;
+    }
+
+    // whichever of its parents the root is, FAT doesn't care about case
+    roots
+        .iter()
+        .find_map(|root| (0..BOOTMGFW.len()).find_map(|skip| find_nocase(root, &BOOTMGFW[skip..])))
+}
+
+#[cfg(target_os = "linux")]
+fn has_tpm() -> bool {
+    Path::new("/sys/class/tpm/tpm0").exists()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn has_tpm() -> bool {
+    false
+}
+
+fn find_nocase(dir: &Path, components: &[&str]) -> Option<PathBuf> {
+    let Some((first, rest)) = components.split_first() else {
+        return dir.is_file().then(|| dir.to_path_buf());
+    };
+
+    dir.read_dir().ok()?.flatten().find_map(|entry| {
+        entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.eq_ignore_ascii_case(first))
+            .then(|| find_nocase(&entry.path(), rest))
+            .flatten()
+    })
+}
+
+/// Authenticode hash of the image at `file`, `None` if it isn't one or can't be read.
+pub(crate) fn image_digest(file: &Path) -> Option<Hash> {
+    Image::open(file).ok()??.authenticode().ok()
+}
+
+/// Extend `pcr` from zero with every event for it, `substitute` may hand back a different
+/// digest for an event.
+fn replay(events: &[Event], pcr: u32, substitute: impl Fn(&Event) -> Option<Hash>) -> Hash {
+    events
+        .iter()
+        .filter(|event| event.pcr == pcr && event.kind != EV_NO_ACTION)
+        .fold([0; 32], |value, event| {
+            let digest = substitute(event).unwrap_or(event.digest);
+            let mut hasher = Sha256::new();
+            hasher.update(value);
+            hasher.update(digest);
+            hasher.finalize().into()
+        })
+}
+
+/// The events of a crypto agile log: a `TCG_PCR_EVENT` holding the spec ID event with the
+/// digest sizes, then `TCG_PCR_EVENT2`s with a digest for each bank.
+fn parse(data: &[u8]) -> Option<Vec<Event>> {
+    let le16 = |at: usize| Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?));
+    let le32 = |at: usize| Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?));
+
+    // pcr, type, SHA-1 digest, size, then the spec ID event
+    let spec = 32;
+    let spec_size = le32(28)? as usize;
+    if data.get(spec..spec + 16)? != b"Spec ID Event03\0" {
+        return None;
+    }
+    let algorithms = le32(spec + 24)? as usize;
+    let mut sizes = Vec::with_capacity(algorithms);
+    for i in 0..algorithms {
+        sizes.push((
+            le16(spec + 28 + i * 4)?,
+            usize::from(le16(spec + 30 + i * 4)?),
+        ));
+    }
+    if !sizes.iter().any(|&(alg, _)| alg == TPM_ALG_SHA256) {
+        return None;
+    }
+
+    let mut events = Vec::new();
+    let mut at = spec + spec_size;
+    while at < data.len() {
+        let pcr = le32(at)?;
+        let kind = le32(at + 4)?;
+        let count = le32(at + 8)? as usize;
+        at += 12;
+
+        let mut digest = None;
+        for _ in 0..count {
+            let alg = le16(at)?;
+            let &(_, size) = sizes.iter().find(|&&(a, _)| a == alg)?;
+            if alg == TPM_ALG_SHA256 {
+                digest = data.get(at + 2..at + 2 + size)?.try_into().ok();
+            }
+            at += 2 + size;
+        }
+
+        let size = le32(at)? as usize;
+        let event = data.get(at + 4..at + 4 + size)?.to_vec();
+        at += 4 + size;
+
+        events.push(Event {
+            pcr,
+            kind,
+            digest: digest?,
+            data: event,
+        });
+    }
+
+    Some(events)
+}
+
+/// The `UEFI_VARIABLE_DATA` firmware measures when a db `entry` authorises an image.
+fn variable_data(entry: &[u8]) -> Vec<u8> {
+    let name: Vec<u8> = "db".encode_utf16().flat_map(u16::to_le_bytes).collect();
+
+    let mut data = Vec::with_capacity(32 + name.len() + entry.len());
+    data.extend_from_slice(&IMAGE_SECURITY_DATABASE);
+    data.extend_from_slice(&2u64.to_le_bytes());
+    data.extend_from_slice(&(entry.len() as u64).to_le_bytes());
+    data.extend_from_slice(&name);
+    data.extend_from_slice(entry);
+    data
+}
+
+/// Every cert in `file` as DER, a bundle of PEM certs or a DER one, for `--chain`.
+pub(crate) fn ders(file: &Path) -> io::Result<Vec<Vec<u8>>> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let data = std::fs::read(file)?;
+    if !data.starts_with(b"-----BEGIN") {
+        return Ok(vec![data]);
+    }
+    let text = String::from_utf8_lossy(&data);
+    let mut certs = Vec::new();
+    let mut rest = &text[..];
+    while let Some(start) = rest.find(BEGIN) {
+        let body = &rest[start + BEGIN.len()..];
+        let end = body.find(END).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: a cert is cut off", escape::path(file)),
+            )
+        })?;
+        let base64: String = body[..end].split_whitespace().collect();
+        certs.push(crate::base64::decode(&base64).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: a cert isn't base64", escape::path(file)),
+            )
+        })?);
+        rest = &body[end + END.len()..];
+    }
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: no certs", escape::path(file)),
+        ));
+    }
+    Ok(certs)
+}
+
+/// `cert` as DER, converted by openssl if it is PEM.
+pub(crate) fn der(cert: &Path) -> io::Result<Vec<u8>> {
+    let data = std::fs::read(cert)?;
+    if !data.starts_with(b"-----BEGIN") {
+        return Ok(data);
+    }
+
+    let output = Command::new("openssl")
+        .arg("x509")
+        .arg("-in")
+        .arg(cert)
+        .args(["-outform", "DER"])
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "couldn't read {}: openssl {}",
+            escape::path(cert),
+            output.status
+        )));
+    }
+
+    Ok(output.stdout)
+}
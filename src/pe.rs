@@ -0,0 +1,787 @@
+//! Just enough PE/COFF to produce test images, to find the parts of an image that matter
+//! for signing and to spot images firmware is likely to refuse.
+//!
+//! Whatever is on an ESP gets parsed, so none of this trusts the headers it reads: offsets and
+//! sizes that point past the end of the data, or overflow on the way there, make an image
+//! malformed rather than panic. [`is_pe`], [`machine_of`] and [`Layout::parse`] work on bytes
+//! alone and can be fed anything.
+//!
+//! Images on disk are looked at through [`Image`], which reads the headers and section table
+//! and then only what is asked for, streaming the rest through the checksum and hashes, so a
+//! UKI of a few hundred megabytes is never in memory whole.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+use sha2::{Digest, Sha256};
+
+/// `IMAGE_FILE_MACHINE_*` values for the architectures UEFI firmware runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Machine {
+    /// x86
+    Ia32,
+    /// `x86_64`
+    X64,
+    /// 64-bit ARM
+    Aa64,
+}
+
+impl Machine {
+    /// Value of the COFF header machine field.
+    #[must_use]
+    pub fn value(self) -> u16 {
+        match self {
+            Machine::Ia32 => 0x014c,
+            Machine::X64 => 0x8664,
+            Machine::Aa64 => 0xaa64,
+        }
+    }
+
+    /// The machine with COFF header machine field `value`, if it is one of these.
+    #[must_use]
+    pub fn from_value(value: u16) -> Option<Self> {
+        [Machine::Ia32, Machine::X64, Machine::Aa64]
+            .into_iter()
+            .find(|m| m.value() == value)
+    }
+
+    fn is_64(self) -> bool {
+        !matches!(self, Machine::Ia32)
+    }
+
+    /// `return EFI_SUCCESS`
+    fn entry(self) -> &'static [u8] {
+        match self {
+            // xor eax, eax; ret
+            Machine::Ia32 | Machine::X64 => &[0x31, 0xc0, 0xc3],
+            // mov x0, #0; ret
+            Machine::Aa64 => &[0x00, 0x00, 0x80, 0xd2, 0xc0, 0x03, 0x5f, 0xd6],
+        }
+    }
+}
+
+impl fmt::Display for Machine {
+    /// The name UEFI gives it, as in `BOOTX64.EFI`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Machine::Ia32 => "ia32",
+            Machine::X64 => "x64",
+            Machine::Aa64 => "aa64",
+        })
+    }
+}
+
+impl std::str::FromStr for Machine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ia32" => Ok(Machine::Ia32),
+            "x64" => Ok(Machine::X64),
+            "aa64" => Ok(Machine::Aa64),
+            _ => Err(format!(
+                "unknown architecture: '{s}', expected x64, ia32 or aa64"
+            )),
+        }
+    }
+}
+
+/// `IMAGE_FILE_MACHINE_*` values of every architecture there is UEFI firmware for, the ones
+/// [`Machine`] knows plus 32-bit ARM, RISC-V and `LoongArch`.
+const UEFI_MACHINES: &[u16] = &[0x014c, 0x8664, 0xaa64, 0x01c2, 0x5032, 0x5064, 0x6264];
+
+/// Something about an image firmware may object to, found by [`Layout::problems`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Problem {
+    /// The optional header checksum is set but wrong. Most firmware ignores it, some doesn't.
+    Checksum,
+    /// The alignments in the optional header are out of range, or a section doesn't start
+    /// where they say it should.
+    Misaligned,
+    /// Data after the last section that isn't the certificate table, which a signature covers
+    /// but the loader never maps.
+    Overlay,
+    /// Built for an architecture no UEFI firmware runs.
+    Machine,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Problem::Checksum => "bad-checksum",
+            Problem::Misaligned => "misaligned",
+            Problem::Overlay => "overlay",
+            Problem::Machine => "unknown-machine",
+        })
+    }
+}
+
+const FILE_ALIGNMENT: usize = 0x200;
+const SECTION_ALIGNMENT: usize = 0x1000;
+
+/// Build a minimal EFI application for `machine` that does nothing but return success, padded
+/// with zeros to at least `size` bytes. The result is well formed enough for sbsign and
+/// firmware to accept.
+#[must_use]
+pub fn dummy(machine: Machine, size: usize) -> Vec<u8> {
+    let raw = align(size.saturating_sub(FILE_ALIGNMENT).max(1), FILE_ALIGNMENT);
+    let image_size = SECTION_ALIGNMENT + align(raw, SECTION_ALIGNMENT);
+    let mut buf = Vec::with_capacity(FILE_ALIGNMENT + raw);
+
+    // DOS header, only the magic and the offset of the PE header matter
+    buf.extend_from_slice(b"MZ");
+    buf.resize(0x3c, 0);
+    put32(&mut buf, 0x40);
+
+    buf.extend_from_slice(b"PE\0\0");
+
+    // COFF header
+    let optional_size: u16 = if machine.is_64() { 240 } else { 224 };
+    let characteristics: u16 = if machine.is_64() { 0x0022 } else { 0x0102 };
+    put16(&mut buf, machine.value());
+    put16(&mut buf, 1); // sections
+    put32(&mut buf, 0); // timestamp
+    put32(&mut buf, 0); // symbol table
+    put32(&mut buf, 0); // symbols
+    put16(&mut buf, optional_size);
+    put16(&mut buf, characteristics);
+
+    // optional header
+    put16(&mut buf, if machine.is_64() { 0x020b } else { 0x010b });
+    buf.extend_from_slice(&[0, 0]); // linker version
+    put32(&mut buf, to_u32(raw)); // code
+    put32(&mut buf, 0); // initialized data
+    put32(&mut buf, 0); // uninitialized data
+    put32(&mut buf, to_u32(SECTION_ALIGNMENT)); // entry point
+    put32(&mut buf, to_u32(SECTION_ALIGNMENT)); // base of code
+    if machine.is_64() {
+        put64(&mut buf, 0x1_4000_0000);
+    } else {
+        put32(&mut buf, 0); // base of data
+        put32(&mut buf, 0x40_0000);
+    }
+    put32(&mut buf, to_u32(SECTION_ALIGNMENT));
+    put32(&mut buf, to_u32(FILE_ALIGNMENT));
+    buf.extend_from_slice(&[0; 16]); // os, image and subsystem versions, win32 version
+    put32(&mut buf, to_u32(image_size));
+    put32(&mut buf, to_u32(FILE_ALIGNMENT)); // headers
+    put32(&mut buf, 0); // checksum
+    put16(&mut buf, 10); // EFI application
+    put16(&mut buf, 0); // dll characteristics
+    for _ in 0..4 {
+        // stack and heap reserve and commit
+        if machine.is_64() {
+            put64(&mut buf, 0);
+        } else {
+            put32(&mut buf, 0);
+        }
+    }
+    put32(&mut buf, 0); // loader flags
+    put32(&mut buf, 16); // data directories
+    buf.extend_from_slice(&[0; 16 * 8]);
+
+    // the one section
+    buf.extend_from_slice(b".text\0\0\0");
+    put32(&mut buf, to_u32(raw)); // virtual size
+    put32(&mut buf, to_u32(SECTION_ALIGNMENT));
+    put32(&mut buf, to_u32(raw));
+    put32(&mut buf, to_u32(FILE_ALIGNMENT));
+    buf.extend_from_slice(&[0; 12]); // relocations and line numbers
+    put32(&mut buf, 0x6000_0020); // code, execute, read
+
+    buf.resize(FILE_ALIGNMENT, 0);
+    buf.extend_from_slice(machine.entry());
+    buf.resize(FILE_ALIGNMENT + raw, 0);
+    buf
+}
+
+/// Whether `data` starts like a PE image, an `MZ` header pointing at a `PE\0\0` signature.
+#[must_use]
+pub fn is_pe(data: &[u8]) -> bool {
+    signature_offset(data)
+        .and_then(|at| data.get(at..))
+        .is_some_and(|pe| pe.starts_with(b"PE\0\0"))
+}
+
+/// COFF header machine field of the image starting `data`, `None` if it doesn't start like
+/// one.
+#[must_use]
+pub fn machine_of(data: &[u8]) -> Option<u16> {
+    coff_machine(data.get(signature_offset(data)?..)?)
+}
+
+/// Where the DOS header at the start of `data` says the PE signature is, `None` if there is
+/// no `MZ` header.
+fn signature_offset(data: &[u8]) -> Option<usize> {
+    if data.get(..2)? != b"MZ" {
+        return None;
+    }
+    usize::try_from(get32(data, 0x3c)?).ok()
+}
+
+/// The machine field after the PE signature `pe` starts with.
+fn coff_machine(pe: &[u8]) -> Option<u16> {
+    pe.starts_with(b"PE\0\0").then(|| get16(pe, 4)).flatten()
+}
+
+/// The first `len` bytes of the file at `path` from where its DOS header says the PE signature
+/// is, `None` if it hasn't got a DOS header or that many bytes.
+fn read_signature(path: &Path, len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    let mut dos = [0; 0x40];
+    if file.read_exact(&mut dos).is_err() {
+        return Ok(None);
+    }
+    let Some(offset) = signature_offset(&dos) else {
+        return Ok(None);
+    };
+
+    let mut pe = vec![0; len];
+    file.seek(SeekFrom::Start(offset as u64))?;
+    Ok(file.read_exact(&mut pe).is_ok().then_some(pe))
+}
+
+/// Whether `path` starts like a PE image, see [`is_pe`]. Reads only the few bytes needed.
+///
+/// # Errors
+///
+/// Fails if the file can't be read.
+pub fn sniff(path: &Path) -> io::Result<bool> {
+    Ok(read_signature(path, 4)?.is_some_and(|pe| pe == b"PE\0\0"))
+}
+
+/// COFF header machine field of the image at `path`, `None` if it isn't one. Reads only the
+/// few bytes needed, like [`sniff`].
+///
+/// # Errors
+///
+/// Fails if the file can't be read.
+pub fn machine(path: &Path) -> io::Result<Option<u16>> {
+    Ok(read_signature(path, 6)?.and_then(|pe| coff_machine(&pe)))
+}
+
+/// Set the optional header checksum of the image at `path` to what it should be, it being left
+/// out of the Authenticode hash this doesn't invalidate signatures. Whether it was wrong, a
+/// file that isn't an image is left alone.
+///
+/// # Errors
+///
+/// Fails if the file can't be read or written.
+pub fn fix_checksum(path: &Path) -> io::Result<bool> {
+    use std::io::Write;
+
+    let Some(mut image) = Image::open(path)? else {
+        return Ok(false);
+    };
+    let checksum = image.compute_checksum()?;
+    let at = image.layout.checksum.start;
+    if get32(&image.head, at) == Some(checksum) {
+        return Ok(false);
+    }
+    drop(image);
+
+    let mut file = File::options().write(true).open(path)?;
+    file.seek(SeekFrom::Start(at as u64))?;
+    file.write_all(&checksum.to_le_bytes())?;
+    file.sync_data()?;
+    Ok(true)
+}
+
+/// Where the interesting parts of a PE image are, as byte ranges into the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// COFF header machine field, see [`Machine::value`].
+    pub machine: u16,
+    /// Optional header checksum field.
+    pub checksum: Range<usize>,
+    /// Optional header `FileAlignment` and `SectionAlignment`.
+    pub alignment: (u32, u32),
+    /// The section table, 40 bytes per section.
+    pub section_table: Range<usize>,
+    /// Security entry of the data directory, pointing at the certificate table.
+    pub cert_dir: Range<usize>,
+    /// Everything up to and including the section table.
+    pub headers: Range<usize>,
+    /// Raw data of each section, in file order.
+    pub sections: Vec<Range<usize>>,
+    /// Certificate table holding the signatures, if the image is signed.
+    pub cert_table: Option<Range<usize>>,
+}
+
+impl Layout {
+    /// Find the parts of `data`, `None` if it isn't a well formed PE image. Anything can be
+    /// given, the offsets in the headers are checked before they are followed.
+    #[must_use]
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        Self::parse_within(data, data.len())
+    }
+
+    /// Find the parts of an image `len` bytes long from `data`, the start of it up to the end
+    /// of the section table at least.
+    fn parse_within(data: &[u8], len: usize) -> Option<Self> {
+        let pe = signature_offset(data)?;
+        if data.get(pe..pe.checked_add(4)?)? != b"PE\0\0" {
+            return None;
+        }
+
+        let coff = pe + 4;
+        let machine = get16(data, coff)?;
+        let sections = usize::from(get16(data, coff.checked_add(2)?)?);
+        let optional = coff.checked_add(20)?;
+        let optional_size = usize::from(get16(data, coff.checked_add(16)?)?);
+
+        let dirs = match get16(data, optional)? {
+            0x010b => optional.checked_add(96)?,
+            0x020b => optional.checked_add(112)?,
+            _ => return None,
+        };
+        let rvas = get32(data, dirs - 4)?;
+        let cert_dir = dirs.checked_add(4 * 8)?..dirs.checked_add(5 * 8)?;
+        let header_size = usize::try_from(get32(data, optional + 60)?).ok()?;
+
+        // at most 65535 sections of 40 bytes, the end can't overflow once the start didn't
+        let table = optional.checked_add(optional_size)?;
+        let table_end = table.checked_add(sections * 40)?;
+        if cert_dir.end > table || table_end > data.len() {
+            return None;
+        }
+        let mut raw = Vec::with_capacity(sections);
+        for entry in data[table..table_end].chunks_exact(40) {
+            let size = usize::try_from(get32(entry, 16)?).ok()?;
+            let start = usize::try_from(get32(entry, 20)?).ok()?;
+            if size > 0 {
+                raw.push(start..start.checked_add(size)?);
+            }
+        }
+        raw.sort_by_key(|r| r.start);
+
+        let cert_table = if rvas > 4 {
+            let start = usize::try_from(get32(data, cert_dir.start)?).ok()?;
+            let size = usize::try_from(get32(data, cert_dir.start + 4)?).ok()?;
+            (size > 0).then_some(start..start.checked_add(size)?)
+        } else {
+            None
+        };
+
+        let layout = Self {
+            machine,
+            checksum: optional + 64..optional + 68,
+            alignment: (get32(data, optional + 36)?, get32(data, optional + 32)?),
+            section_table: table..table_end,
+            cert_dir,
+            headers: 0..header_size.max(table_end),
+            sections: raw,
+            cert_table,
+        };
+
+        let ends = layout
+            .sections
+            .iter()
+            .chain(&layout.cert_table)
+            .map(|r| r.end);
+        (layout.headers.end <= len && ends.max().unwrap_or(0) <= len).then_some(layout)
+    }
+
+    /// The raw certificate table, the `WIN_CERTIFICATE`s with the signatures in them.
+    #[must_use]
+    pub fn signatures<'a>(&self, data: &'a [u8]) -> Option<&'a [u8]> {
+        data.get(self.cert_table.clone()?)
+    }
+
+    /// The PKCS#7 `SignedData` of each Authenticode signature in the certificate table.
+    #[must_use]
+    pub fn pkcs7<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        self.signatures(data).map(signed_data).unwrap_or_default()
+    }
+
+    /// Raw data of the section called `name`, the first if there are several.
+    #[must_use]
+    pub fn section<'a>(&self, data: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+        data.get(self.section_range(data, name)?)
+    }
+
+    /// Where the data of the section called `name` is, from the section table in `head`: as
+    /// much of its raw data as its virtual size says it has, the rest being padding.
+    fn section_range(&self, head: &[u8], name: &[u8]) -> Option<Range<usize>> {
+        let table = head.get(self.section_table.clone())?;
+        table.chunks_exact(40).find_map(|entry| {
+            let end = entry[..8].iter().position(|&b| b == 0).unwrap_or(8);
+            if &entry[..end] != name {
+                return None;
+            }
+            let virtual_size = usize::try_from(get32(entry, 8)?).ok()?;
+            let raw = usize::try_from(get32(entry, 16)?).ok()?;
+            let start = usize::try_from(get32(entry, 20)?).ok()?;
+            // some linkers leave the virtual size of sections they write at 0
+            let size = if virtual_size == 0 {
+                raw
+            } else {
+                virtual_size.min(raw)
+            };
+            Some(start..start.checked_add(size)?)
+        })
+    }
+
+    /// What checksum the optional header should have for `data`: the 16-bit ones' complement
+    /// sum of the file with the checksum field left out, plus the file length.
+    #[must_use]
+    pub fn compute_checksum(&self, data: &[u8]) -> u32 {
+        add_words(0, data, 0, &self.checksum).wrapping_add(to_u32(data.len()))
+    }
+
+    /// Everything about `data` firmware may object to, in the order of [`Problem`].
+    #[must_use]
+    pub fn problems(&self, data: &[u8]) -> Vec<Problem> {
+        self.problems_with(data, data.len(), || self.compute_checksum(data))
+    }
+
+    /// Everything about an image `len` bytes long starting with `head`, up to the end of its
+    /// section table at least, firmware may object to. `checksum` works out what the checksum
+    /// should be, only if one is set.
+    fn problems_with(
+        &self,
+        head: &[u8],
+        len: usize,
+        checksum: impl FnOnce() -> u32,
+    ) -> Vec<Problem> {
+        let mut problems = Vec::new();
+
+        let stored = get32(head, self.checksum.start).unwrap_or(0);
+        if stored != 0 && stored != checksum() {
+            problems.push(Problem::Checksum);
+        }
+
+        let (file, section) = self.alignment;
+        let misaligned = !file.is_power_of_two()
+            || !(0x200..=0x1_0000).contains(&file)
+            || section < file
+            || !section.is_power_of_two()
+            || head
+                .get(self.section_table.clone())
+                .unwrap_or_default()
+                .chunks_exact(40)
+                .any(|entry| {
+                    let address = get32(entry, 12).unwrap_or(0);
+                    let (size, start) =
+                        (get32(entry, 16).unwrap_or(0), get32(entry, 20).unwrap_or(0));
+                    !address.is_multiple_of(section) || (size > 0 && !start.is_multiple_of(file))
+                });
+        if misaligned {
+            problems.push(Problem::Misaligned);
+        }
+
+        // the last section is padded up to the file alignment at most
+        let end = self
+            .sections
+            .iter()
+            .map(|r| r.end)
+            .max()
+            .unwrap_or(self.headers.end);
+        let end = if file.is_power_of_two() {
+            align(end, file as usize)
+        } else {
+            end
+        };
+        let tail = self.cert_table.as_ref().map_or(len, |t| t.start);
+        if tail > end {
+            problems.push(Problem::Overlay);
+        }
+
+        if !UEFI_MACHINES.contains(&self.machine) {
+            problems.push(Problem::Machine);
+        }
+
+        problems
+    }
+
+    /// Authenticode SHA-256 of the image, the hash firmware looks up in db and dbx and the one
+    /// a signature signs: everything but the checksum, the security directory entry and the
+    /// certificate table.
+    #[must_use]
+    pub fn authenticode(&self, data: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        for range in self.hashed(data.len()) {
+            hasher.update(&data[range]);
+        }
+        hasher.finalize().into()
+    }
+
+    /// What the Authenticode hash of an image `len` bytes long hashes, in order.
+    fn hashed(&self, len: usize) -> Vec<Range<usize>> {
+        let mut hashed = vec![
+            0..self.checksum.start,
+            self.checksum.end..self.cert_dir.start,
+            self.cert_dir.end..self.headers.end,
+        ];
+
+        let mut done = self.headers.end;
+        for section in &self.sections {
+            hashed.push(section.clone());
+            done = done.max(section.end);
+        }
+
+        // whatever trails the last section, short of the signatures
+        let end = self.cert_table.as_ref().map_or(len, |t| t.start);
+        if done < end {
+            hashed.push(done..end);
+        }
+        hashed
+    }
+}
+
+/// The PKCS#7 `SignedData` of each Authenticode signature in the certificate table `table`.
+#[must_use]
+pub fn signed_data(table: &[u8]) -> Vec<&[u8]> {
+    const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 0x0002;
+
+    let mut found = Vec::new();
+    let mut at = 0;
+    // WIN_CERTIFICATEs, each 8 byte aligned: length including the header, revision, type
+    while let (Some(len), Some(kind)) = (get32(table, at), get16(table, at + 6)) {
+        // a length past the end of the table ends it, as does one that overflows
+        let Some(end) = usize::try_from(len)
+            .ok()
+            .and_then(|len| at.checked_add(len.max(8)))
+        else {
+            break;
+        };
+        let Some(cert) = table.get(at + 8..end) else {
+            break;
+        };
+        if kind == WIN_CERT_TYPE_PKCS_SIGNED_DATA {
+            found.push(cert);
+        }
+        at = align(end, 8);
+    }
+
+    found
+}
+
+/// How much of the start of a file [`Image::open`] reads at most looking for the end of the
+/// section table, far more than any real image needs.
+pub const MAX_HEAD: usize = 4 << 20;
+
+/// The most of a section [`Image::section`] reads, far more than a command line or SBAT data
+/// ever is.
+pub const MAX_SECTION: usize = 1 << 20;
+
+/// How much of an image is read at a time when it is streamed through, even so the checksum
+/// words don't straddle two reads.
+const CHUNK: usize = 64 << 10;
+
+/// An image on disk, read only as far as it is looked at: the headers and section table when
+/// it is opened, a section or the certificate table when asked for, and everything else only
+/// streamed through [`Image::compute_checksum`] and [`Image::authenticode`].
+#[derive(Debug)]
+pub struct Image {
+    file: File,
+    len: usize,
+    /// The start of the file, up to the end of the section table at least.
+    head: Vec<u8>,
+    pub layout: Layout,
+}
+
+impl Image {
+    /// Open the image at `path`, `None` if it isn't a well formed one, see [`Layout::parse`],
+    /// or its section table ends past [`MAX_HEAD`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read.
+    pub fn open(path: &Path) -> io::Result<Option<Self>> {
+        let mut file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len()).unwrap_or(usize::MAX);
+        let most = len.min(MAX_HEAD);
+
+        let mut head = Vec::new();
+        let mut want = 4096;
+        loop {
+            let have = head.len();
+            head.resize(want.min(most), 0);
+            file.read_exact(&mut head[have..])?;
+            if let Some(layout) = Layout::parse_within(&head, len) {
+                return Ok(Some(Self {
+                    file,
+                    len,
+                    head,
+                    layout,
+                }));
+            }
+            // reading further only helps if the PE signature is past what was read
+            let hopeless = signature_offset(&head).is_none_or(|pe| {
+                head.get(pe..pe.saturating_add(4))
+                    .is_some_and(|sig| sig != b"PE\0\0")
+            });
+            if hopeless || head.len() == most {
+                return Ok(None);
+            }
+            want = head.len() * 16;
+        }
+    }
+
+    /// How long the image is.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    /// The raw certificate table, `None` if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read.
+    pub fn signatures(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self.layout.cert_table.clone() {
+            Some(table) => self.read(table).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Data of the section called `name`, the first if there are several, as far as its
+    /// virtual size goes, `None` if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read, the section goes past its end, or it is longer than
+    /// [`MAX_SECTION`], which no section looked at by name is.
+    pub fn section(&mut self, name: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let Some(range) = self.layout.section_range(&self.head, name) else {
+            return Ok(None);
+        };
+        let shown = String::from_utf8_lossy(name);
+        if range.len() > MAX_SECTION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{shown} is over {MAX_SECTION} bytes long"),
+            ));
+        }
+        if range.end > self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{shown} goes past the end of the image"),
+            ));
+        }
+        self.read(range).map(Some)
+    }
+
+    /// See [`Layout::compute_checksum`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read.
+    pub fn compute_checksum(&mut self) -> io::Result<u32> {
+        let skip = self.layout.checksum.clone();
+        let mut sum = 0;
+        let mut at = 0;
+        self.stream(0..self.len, |chunk| {
+            sum = add_words(sum, chunk, at, &skip);
+            at += chunk.len();
+        })?;
+        Ok(sum.wrapping_add(to_u32(self.len)))
+    }
+
+    /// See [`Layout::problems`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read.
+    pub fn problems(&mut self) -> io::Result<Vec<Problem>> {
+        let stored = get32(&self.head, self.layout.checksum.start).unwrap_or(0);
+        let computed = if stored == 0 {
+            0
+        } else {
+            self.compute_checksum()?
+        };
+        Ok(self.layout.problems_with(&self.head, self.len, || computed))
+    }
+
+    /// See [`Layout::authenticode`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read.
+    pub fn authenticode(&mut self) -> io::Result<[u8; 32]> {
+        let mut hasher = Sha256::new();
+        for range in self.layout.hashed(self.len) {
+            self.stream(range, |chunk| hasher.update(chunk))?;
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    fn read(&mut self, range: Range<usize>) -> io::Result<Vec<u8>> {
+        if let Some(bytes) = self.head.get(range.clone()) {
+            return Ok(bytes.to_vec());
+        }
+        let mut bytes = vec![0; range.len()];
+        self.file.seek(SeekFrom::Start(range.start as u64))?;
+        self.file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Feed `range` to `f` [`CHUNK`] bytes at a time, the last maybe shorter.
+    fn stream(&mut self, range: Range<usize>, mut f: impl FnMut(&[u8])) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(range.start as u64))?;
+        let mut buf = vec![0; CHUNK.min(range.len())];
+        let mut left = range.len();
+        while left > 0 {
+            let chunk = &mut buf[..CHUNK.min(left)];
+            self.file.read_exact(chunk)?;
+            f(chunk);
+            left -= chunk.len();
+        }
+        Ok(())
+    }
+}
+
+/// Add the little endian 16-bit words of `data`, found `at` into the file, to the ones'
+/// complement `sum`, leaving out those in `skip`. `at` is even.
+fn add_words(mut sum: u32, data: &[u8], at: usize, skip: &Range<usize>) -> u32 {
+    for (i, word) in data.chunks(2).enumerate() {
+        if skip.contains(&(at + i * 2)) {
+            continue;
+        }
+        sum += u32::from(u16::from_le_bytes([
+            word[0],
+            word.get(1).copied().unwrap_or(0),
+        ]));
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum
+}
+
+fn get16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(at..at.checked_add(2)?)?.try_into().ok()?,
+    ))
+}
+
+fn get32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(at..at.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+fn align(n: usize, to: usize) -> usize {
+    n.div_ceil(to) * to
+}
+
+fn to_u32(n: usize) -> u32 {
+    u32::try_from(n).unwrap_or(u32::MAX)
+}
+
+fn put16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
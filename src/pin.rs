@@ -0,0 +1,149 @@
+//! The `[pin]` table of the configuration, the certs and keys a machine signs with, so a run
+//! handed the test cert or the wrong key stops before it signs anything:
+//!
+//! ```toml
+//! [pin]
+//! # openssl x509 -in DB.crt -outform DER | sha256sum
+//! cert_sha256 = ["5d1e0f3a…"]
+//! # openssl pkey -in DB.key -pubout -outform DER | sha256sum
+//! key_sha256 = ["9b2ec0ff…"]
+//! ```
+//!
+//! Every cert a run signs with, `--cert` and those of `[signers]` and `[[directory]]`, has to be
+//! one of `cert_sha256`, and every key one of `key_sha256`, a key being known by its public
+//! half. Fingerprints are hex, colons and case as `openssl x509 -fingerprint -sha256` has them
+//! are fine. Keys the backend doesn't read as files, in an HSM, Vault or behind pesignd, aren't
+//! checked; an encrypted key is checked once decrypted, with `--askpass` or `--passphrase-fd`.
+//!
+//! The pins of [`crate::config::SYSTEM`] hold even for runs given another file with `--config`,
+//! see [`Pin::and`].
+
+use std::{
+    io::{Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{escape, secret::Secret, state::hex, Options};
+
+/// The certs and keys that may be signed with, anything if not set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pin {
+    /// SHA-256 of each cert as DER.
+    pub cert_sha256: Option<Vec<String>>,
+    /// SHA-256 of the public key of each key, as DER.
+    pub key_sha256: Option<Vec<String>>,
+}
+
+impl Pin {
+    /// The pins of keeping to both `self` and `other`, only what both allow.
+    #[must_use]
+    pub fn and(self, other: Self) -> Self {
+        let both = |a: Option<Vec<String>>, b: Option<Vec<String>>| match (a, b) {
+            (Some(a), Some(b)) => Some(
+                a.into_iter()
+                    .filter(|pin| b.iter().any(|other| normal(other) == normal(pin)))
+                    .collect(),
+            ),
+            (a, b) => a.or(b),
+        };
+        Self {
+            cert_sha256: both(self.cert_sha256, other.cert_sha256),
+            key_sha256: both(self.key_sha256, other.key_sha256),
+        }
+    }
+
+    /// Check the certs and keys of `options` are pinned, `key` being the key decrypted.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the first that isn't, or can't be read.
+    pub fn check(&self, options: &Options, key: Option<&Secret>) -> Result<(), String> {
+        let others = || options.signers.iter().map(|(_, signer)| signer);
+        let others = others().chain(options.directories.iter().map(|(_, signer)| signer));
+
+        if let Some(pins) = &self.cert_sha256 {
+            let certs = [options.cert.as_path()]
+                .into_iter()
+                .chain(others.clone().map(|signer| signer.cert.as_path()))
+                .filter(|cert| !cert.as_os_str().is_empty());
+            for cert in certs {
+                let der =
+                    crate::pcr::der(cert).map_err(|e| format!("{}: {e}", escape::path(cert)))?;
+                pinned(pins, &hex(&Sha256::digest(der)), "cert", cert)?;
+            }
+        }
+
+        if let Some(pins) = &self.key_sha256 {
+            if options.backend.pem_key() && !options.key.as_os_str().is_empty() {
+                let public = public_key(&options.key, key)?;
+                pinned(pins, &public, "key", &options.key)?;
+            }
+            for signer in others {
+                pinned(pins, &public_key(&signer.key, None)?, "key", &signer.key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn pinned(pins: &[String], fingerprint: &str, what: &str, path: &Path) -> Result<(), String> {
+    if pins.iter().any(|pin| normal(pin) == fingerprint) {
+        return Ok(());
+    }
+    Err(format!(
+        "{} isn't a pinned {what}, its SHA-256 is {fingerprint}, refusing to sign with it",
+        escape::path(path)
+    ))
+}
+
+/// A fingerprint in lowercase hex without colons or spaces.
+fn normal(pin: &str) -> String {
+    pin.chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// The SHA-256 of the public half of `key`, in hex, from `decrypted` if it was.
+fn public_key(key: &Path, decrypted: Option<&Secret>) -> Result<String, String> {
+    let mut cmd = Command::new("openssl");
+    cmd.args(["pkey", "-pubout", "-outform", "DER", "-passin", "pass:"]);
+    if decrypted.is_none() {
+        cmd.arg("-in").arg(key);
+    }
+    let failed = |why: String| {
+        format!(
+            "couldn't read the public key of {} to check it is pinned: {why}",
+            escape::path(key)
+        )
+    };
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| failed(e.to_string()))?;
+    if let (Some(mut stdin), Some(key)) = (child.stdin.take(), decrypted) {
+        stdin
+            .write_all(key.as_bytes())
+            .map_err(|e| failed(e.to_string()))?;
+    }
+    let mut der = Vec::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout
+            .read_to_end(&mut der)
+            .map_err(|e| failed(e.to_string()))?;
+    }
+    let status = child.wait().map_err(|e| failed(e.to_string()))?;
+    if !status.success() || der.is_empty() {
+        return Err(failed(format!(
+            "openssl {status}, an encrypted key needs --askpass or --passphrase-fd"
+        )));
+    }
+    Ok(hex(&Sha256::digest(der)))
+}
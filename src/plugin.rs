@@ -0,0 +1,195 @@
+//! `--plugins`, filters, signers and report sinks living in external executables.
+//!
+//! Every executable in the plugin directory is a plugin. Each is run with one argument saying
+//! what is wanted, gets a JSON object on stdin and answers with one on stdout:
+//!
+//! - `describe`, stdin `{}`, answers `{"name": "...", "provides": ["filter", "signer", "report"]}`
+//!   with whichever of the three it implements. Run once per plugin when a run starts. A signer
+//!   that gives the same bytes for the same image and signing time also provides
+//!   `"deterministic"`, `--deterministic` refuses any other.
+//! - `filter`, stdin `{"path": "..."}`, answers `{"sign": true}` or `{"sign": false}` for every
+//!   file that passed the built in filters.
+//! - `sign`, stdin `{"file": "...", "output": "...", "key": "...", "cert": "..."}`, signs `file`
+//!   into `output` and exits 0, with `--backend plugin:NAME`. Nothing is read from stdout. With
+//!   `--deterministic` there is also `"signing_time"`, seconds since the epoch to record as the
+//!   signing time rather than the current one, if any is recorded at all, and with `--chain`
+//!   `"chain"`, intermediate certs to put in the signature after the cert.
+//! - `report`, stdin the report as `--report json` writes it, once a run is done. Nothing is
+//!   read from stdout.
+//!
+//! Paths are escaped as [`crate::escape`] describes, which leaves all but names with
+//! backslashes, control characters or invalid UTF-8 alone.
+
+use std::{
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde_json::{json, Value};
+
+use crate::{escape, Options};
+
+/// A plugin found in the plugin directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plugin {
+    /// Name it gave itself.
+    pub name: String,
+    pub path: PathBuf,
+    /// Which of `filter`, `signer` and `report` it implements.
+    pub provides: Vec<String>,
+}
+
+impl Plugin {
+    #[must_use]
+    pub fn provides(&self, what: &str) -> bool {
+        self.provides.iter().any(|p| p == what)
+    }
+
+    /// Ask the plugin whether `file` should be signed.
+    pub(crate) fn filter(&self, file: &Path) -> io::Result<bool> {
+        let answer = call(
+            &self.path,
+            "filter",
+            &json!({ "path": escape::path(file).to_string() }),
+        )?;
+        Ok(answer.get("sign").and_then(Value::as_bool).unwrap_or(false))
+    }
+
+    /// Hand the JSON report to the plugin.
+    pub(crate) fn report(&self, report: &Value) -> io::Result<()> {
+        call(&self.path, "report", report).map(drop)
+    }
+}
+
+/// Describe every executable in `dir`.
+///
+/// # Errors
+///
+/// Fails if `dir` can't be read or a plugin in it doesn't describe itself.
+pub fn discover(dir: &Path) -> io::Result<Vec<Plugin>> {
+    let mut plugins = Vec::new();
+
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if !is_executable(&path) {
+            continue;
+        }
+        plugins.push(describe(path)?);
+    }
+
+    plugins.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(plugins)
+}
+
+/// Ask the plugin at `path` what it is.
+fn describe(path: PathBuf) -> io::Result<Plugin> {
+    let description = call(&path, "describe", &json!({}))?;
+    let name = description
+        .get("name")
+        .and_then(Value::as_str)
+        .map_or_else(|| file_name(&path), String::from);
+    let provides = description
+        .get("provides")
+        .and_then(Value::as_array)
+        .map(|p| {
+            p.iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(Plugin {
+        name,
+        path,
+        provides,
+    })
+}
+
+/// Whether the signer plugin `name` from `options.plugins` says it signs deterministically.
+pub(crate) fn deterministic(name: &str, options: &Options) -> io::Result<bool> {
+    let dir = options.plugins.clone().unwrap_or_default();
+    Ok(describe(dir.join(name))?.provides("deterministic"))
+}
+
+/// Command running the signer plugin `name` from `options.plugins`.
+pub(crate) fn sign_command(name: &str, options: &Options) -> Command {
+    let dir = options.plugins.clone().unwrap_or_default();
+    let mut cmd = Command::new(dir.join(name));
+    cmd.arg("sign");
+    cmd
+}
+
+/// What a signer plugin gets on stdin.
+pub(crate) fn sign_request(file: &Path, output: &Path, key: &Path, options: &Options) -> Vec<u8> {
+    let mut request = json!({
+        "file": escape::path(file).to_string(),
+        "output": escape::path(output).to_string(),
+        "key": escape::path(key).to_string(),
+        "cert": escape::path(&options.cert).to_string(),
+    });
+    if let Some(chain) = &options.chain {
+        request["chain"] = Value::from(escape::path(chain).to_string());
+    }
+    if options.deterministic {
+        request["signing_time"] = Value::from(crate::backend::signing_time());
+    }
+    request.to_string().into_bytes()
+}
+
+/// Run `plugin what` with `request` on stdin and parse whatever it prints, nothing at all being
+/// an empty object.
+fn call(plugin: &Path, what: &str, request: &Value) -> io::Result<Value> {
+    let mut child = Command::new(plugin)
+        .arg(what)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(request.to_string().as_bytes())?;
+    }
+    let mut out = Vec::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_end(&mut out)?;
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "plugin {} {what}: {status}",
+            escape::path(plugin)
+        )));
+    }
+
+    if out.iter().all(u8::is_ascii_whitespace) {
+        return Ok(json!({}));
+    }
+    serde_json::from_slice(&out).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("plugin {} {what}: {e}", escape::path(plugin)),
+        )
+    })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
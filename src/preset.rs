@@ -0,0 +1,72 @@
+//! `--preset`, filters for the ESP of a distribution as it usually lays it out, so the boot chain
+//! gets signed and nothing else without working out the globs. The roots are the ESP itself,
+//! the patterns are relative to it.
+//!
+//! Each preset is a TOML file in `data/presets`, built into the binary:
+//!
+//! ```toml
+//! description = "Arch Linux with systemd-boot"
+//! include = ["EFI/systemd/systemd-boot*.efi", "EFI/Linux/*.efi"]
+//! exclude = ["EFI/Microsoft/**"]
+//! ```
+
+use serde::Deserialize;
+
+use crate::Options;
+
+/// Every preset, by name.
+const PRESETS: &[(&str, &str)] = &[
+    (
+        "arch-systemd-boot",
+        include_str!("../data/presets/arch-systemd-boot.toml"),
+    ),
+    (
+        "debian-shim",
+        include_str!("../data/presets/debian-shim.toml"),
+    ),
+    (
+        "fedora-grub",
+        include_str!("../data/presets/fedora-grub.toml"),
+    ),
+];
+
+/// The files of a distribution's ESP worth signing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    pub description: String,
+    /// Patterns like `--include` takes.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Patterns like `--exclude` takes.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl Preset {
+    /// The preset called `name`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if there is none, listing the ones there are.
+    pub fn get(name: &str) -> Result<Self, String> {
+        let (_, text) = PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .ok_or_else(|| format!("unknown preset: '{name}', expected one of {}", names()))?;
+        toml::from_str(text).map_err(|e| format!("preset {name}: {e}"))
+    }
+
+    /// Add the filters to `options`, after those already there.
+    pub fn apply(&self, options: &mut Options) {
+        options.include.extend(self.include.iter().cloned());
+        options.exclude.extend(self.exclude.iter().cloned());
+    }
+}
+
+/// The name of every preset, comma separated.
+#[must_use]
+pub fn names() -> String {
+    let names: Vec<_> = PRESETS.iter().map(|(name, _)| *name).collect();
+    names.join(", ")
+}
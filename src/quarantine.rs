@@ -0,0 +1,166 @@
+//! `--quarantine`, moving images that keep failing out of the boot path, so a truncated or
+//! otherwise broken binary left on the ESP doesn't end up in a boot menu that won't boot it:
+//!
+//! ```text
+//! sbsevery /efi -k /etc/efi-keys/DB.key -c /etc/efi-keys/DB.crt --quarantine /var/lib/sbsevery/quarantine
+//! ```
+//!
+//! A file that failed `--quarantine-after` runs in a row, three unless set, is moved under the
+//! quarantine directory at its absolute path, `/efi/EFI/Linux/a.efi` to
+//! `/var/lib/sbsevery/quarantine/efi/EFI/Linux/a.efi`, and listed in the report. Put the
+//! directory off the ESP, moving a file across filesystems copies it.
+//!
+//! A failure only counts against the file if it is the file's fault: the image is malformed,
+//! failed `--strict-pe`, or failed to sign in a run that signed others. A run where nothing
+//! could be signed, a wrong key or a signer that isn't there, counts against no file, nor do
+//! files `error` rules refused. A file signed, unchanged or skipped starts over. The counts are
+//! kept in `failures` in the quarantine directory, as `<count>  <path>` lines.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    escape,
+    report::{shown, Action, Failure},
+    Backend, Report,
+};
+
+/// How many runs in a row a file fails before it is moved unless told otherwise.
+pub const AFTER: u32 = 3;
+
+/// Where failing files go, and after how many failures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quarantine {
+    pub dir: PathBuf,
+    /// Runs in a row a file has to fail in, at least one.
+    pub after: u32,
+}
+
+impl Quarantine {
+    /// Count the failures of the run in `report` and move the files that failed too often,
+    /// adding them to `report.quarantined`. A file that can't be moved is warned about and
+    /// tried again next run. A mock run, signed with `backend`, counts nothing.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the counts can't be read or written.
+    pub(crate) fn apply(&self, report: &mut Report, backend: &Backend) -> io::Result<()> {
+        if matches!(backend, Backend::Mock(_)) {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.dir).map_err(|e| context(&self.dir, &e))?;
+        let record = self.dir.join("failures");
+        let mut counts = load(&record).map_err(|e| context(&record, &e))?;
+
+        let working = report.signed().next().is_some();
+        let mut failing = Vec::new();
+        for entry in &report.entries {
+            match (&entry.action, &entry.error) {
+                (Action::Failed, Some(failure))
+                    if entry.backend.is_some()
+                        && (working || about_image(failure, &entry.path)) =>
+                {
+                    let count = counts.entry(entry.path.clone()).or_default();
+                    *count += 1;
+                    if *count >= self.after.max(1) {
+                        failing.push(entry.path.clone());
+                    }
+                }
+                (Action::Failed, _) => {}
+                _ => {
+                    counts.remove(&entry.path);
+                }
+            }
+        }
+
+        for file in failing {
+            match self.take(&file) {
+                Ok(dest) => {
+                    eprintln!(
+                        "quarantined:\t{} ({})",
+                        shown(&report.roots, &file),
+                        escape::path(&dest)
+                    );
+                    counts.remove(&file);
+                    report.quarantined.push((file, dest));
+                }
+                Err(e) => eprintln!(
+                    "warning:\tcouldn't quarantine {}: {e}",
+                    shown(&report.roots, &file)
+                ),
+            }
+        }
+
+        save(&record, &counts).map_err(|e| context(&record, &e))
+    }
+
+    /// Move `file` under the quarantine directory, returning where it went.
+    fn take(&self, file: &Path) -> io::Result<PathBuf> {
+        let absolute = std::path::absolute(file)?;
+        let relative: PathBuf = absolute
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        let dest = self.dir.join(relative);
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        match std::fs::rename(file, &dest) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                std::fs::copy(file, &dest)?;
+                File::open(&dest)?.sync_all()?;
+                std::fs::remove_file(file)?;
+            }
+            res => res?,
+        }
+        Ok(dest)
+    }
+}
+
+fn context(path: &Path, e: &io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("quarantine {}: {e}", escape::path(path)))
+}
+
+/// Whether `failure` says something is wrong with the image at `path` itself, whatever the
+/// rest of the run did.
+fn about_image(failure: &Failure, path: &Path) -> bool {
+    matches!(failure, Failure::Strict(_))
+        || crate::pe::Image::open(path).is_ok_and(|image| image.is_none())
+}
+
+fn load(path: &Path) -> io::Result<BTreeMap<PathBuf, u32>> {
+    let mut counts = BTreeMap::new();
+    match File::open(path) {
+        Ok(file) => {
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if let Some((count, file)) = line.split_once("  ") {
+                    if let Ok(count) = count.parse() {
+                        counts.insert(PathBuf::from(file), count);
+                    }
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(counts)
+}
+
+fn save(path: &Path, counts: &BTreeMap<PathBuf, u32>) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    for (file, count) in counts {
+        // as with the state file, a name with a newline in it isn't counted
+        let Some(name) = file.to_str().filter(|name| !name.contains('\n')) else {
+            continue;
+        };
+        writeln!(out, "{count}  {name}")?;
+    }
+    out.into_inner()?.sync_all()?;
+    std::fs::rename(tmp, path)
+}
@@ -0,0 +1,447 @@
+//! `--report`, write the outcome of a run somewhere a script can read it.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    diagnose::Warning,
+    escape,
+    pcr::{Authority, Prediction},
+    state::hex,
+    transaction::Outcome,
+    Backend, Report, Skip,
+};
+
+/// What was done with a file found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Signed, or with `options.transactional` signed and staged, see [`Report::rolled_back`].
+    Signed,
+    /// Handed to the signer, which failed on it.
+    Failed,
+    /// Left alone, by a filter or for being signed already.
+    Skipped(Skip),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Signed => f.write_str("signed"),
+            Action::Failed => f.write_str("failed"),
+            Action::Skipped(reason) => write!(f, "skipped ({reason})"),
+        }
+    }
+}
+
+/// One file found in a run and what became of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub action: Action,
+    /// How long signing it took, waiting for a writer to be done with it included. Zero for
+    /// files that weren't handed to a signer.
+    pub duration: Duration,
+    /// The backend it was handed to, which with `[[signer]]` sections needn't be the run's.
+    pub backend: Option<Backend>,
+    pub error: Option<Failure>,
+}
+
+impl Entry {
+    /// A file left alone for `reason` without getting near a signer.
+    pub(crate) fn skipped(path: PathBuf, reason: Skip) -> Self {
+        Self {
+            path,
+            action: Action::Skipped(reason),
+            duration: Duration::ZERO,
+            backend: None,
+            error: None,
+        }
+    }
+
+    fn json(&self, report: &Report) -> Value {
+        let (action, reason) = match self.action {
+            Action::Signed => ("signed", None),
+            Action::Failed => ("failed", None),
+            Action::Skipped(reason) => ("skipped", Some(reason.to_string())),
+        };
+        let entry = json!({
+            "action": action,
+            "reason": reason,
+            "duration": self.duration.as_secs_f64(),
+            "backend": self.backend.as_ref().map(Backend::name),
+            "error": self.error.as_ref().map(ToString::to_string),
+        });
+        placed(report, &self.path, entry)
+    }
+}
+
+/// The root `path` was found under, by its place in `roots`, and the path relative to it.
+/// `None` unless there are several roots, paths are reported as they are then.
+#[must_use]
+pub fn within<'a>(roots: &[PathBuf], path: &'a Path) -> Option<(usize, &'a Path)> {
+    if roots.len() < 2 {
+        return None;
+    }
+    let (i, root) = roots
+        .iter()
+        .enumerate()
+        .filter(|(_, root)| path.starts_with(root))
+        .max_by_key(|(_, root)| root.components().count())?;
+    let relative = path.strip_prefix(root).ok()?;
+    // a root that is a file is the file
+    match (relative.as_os_str().is_empty(), path.file_name()) {
+        (true, Some(name)) => Some((i, Path::new(name))),
+        _ => Some((i, relative)),
+    }
+}
+
+/// `path` as stderr has it, `[2] EFI/Linux/a.efi` for one under the second of several roots.
+pub(crate) fn shown(roots: &[PathBuf], path: &Path) -> String {
+    match within(roots, path) {
+        Some((i, relative)) => format!("[{}] {}", i + 1, escape::path(relative)),
+        None => escape::path(path).to_string(),
+    }
+}
+
+/// `entry` with `path` in it, relative to its root and with the root as `root` if the run had
+/// several, see [`Report::roots`].
+fn placed(report: &Report, path: &Path, mut entry: Value) -> Value {
+    match within(&report.roots, path) {
+        Some((root, relative)) => {
+            entry["path"] = Value::from(escape::path(relative).to_string());
+            entry["root"] = Value::from(escape::path(&report.roots[root]).to_string());
+        }
+        None => entry["path"] = Value::from(escape::path(path).to_string()),
+    }
+    entry
+}
+
+/// How signing a file went wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// The signer exited with this code.
+    Exit(i32),
+    /// The signer was killed by this signal, a crash or the OOM killer more likely than a
+    /// problem with the file.
+    Signal(i32),
+    /// The signer couldn't be run, timed out, or something after signing failed.
+    Error(String),
+    /// The signer warned about the image and `--strict-pe` was given, the file was left as it
+    /// was.
+    Strict(Warning),
+}
+
+impl Failure {
+    /// The failure `status` stands for, `None` if it is a success.
+    #[must_use]
+    pub fn from_status(status: ExitStatus) -> Option<Self> {
+        if status.success() {
+            return None;
+        }
+
+        #[cfg(unix)]
+        if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+            return Some(Failure::Signal(signal));
+        }
+
+        Some(Failure::Exit(status.code().unwrap_or(-1)))
+    }
+
+    fn json(&self) -> Value {
+        match self {
+            Failure::Exit(code) => json!({ "exit": code }),
+            Failure::Signal(signal) => json!({ "signal": signal, "name": signal_name(*signal) }),
+            Failure::Error(e) => json!({ "error": e }),
+            Failure::Strict(warning) => json!({ "warning": warning.to_string() }),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Exit(code) => write!(f, "exit status {code}"),
+            Failure::Signal(signal) => match signal_name(*signal) {
+                Some(name) => write!(f, "killed by signal {signal} ({name})"),
+                None => write!(f, "killed by signal {signal}"),
+            },
+            Failure::Error(e) => f.write_str(e),
+            Failure::Strict(warning) => write!(f, "{warning} with --strict-pe"),
+        }
+    }
+}
+
+/// Name of the signals a signer is likely to die of.
+#[must_use]
+pub fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        15 => "SIGTERM",
+        _ => return None,
+    })
+}
+
+/// The report as JSON, with every failed and skipped file and search error and why, and the
+/// signer's warnings.
+#[must_use]
+pub fn json(report: &Report) -> Value {
+    let failed: Vec<_> = report
+        .failed
+        .iter()
+        .map(|(path, failure)| placed(report, path, failure.json()))
+        .collect();
+    let skipped: Vec<_> = report
+        .skipped
+        .iter()
+        .map(|(path, reason)| placed(report, path, json!({ "reason": reason.to_string() })))
+        .collect();
+
+    let errors: Vec<_> = report
+        .errors
+        .iter()
+        .map(|(path, e)| placed(report, path, json!({ "error": e })))
+        .collect();
+    let warnings: Vec<_> = report
+        .warnings
+        .iter()
+        .map(|(path, warning)| placed(report, path, json!({ "warning": warning.to_string() })))
+        .collect();
+    let sbat: Vec<_> = report
+        .sbat
+        .iter()
+        .map(|(path, entries)| {
+            let generations: serde_json::Map<_, _> = entries
+                .iter()
+                .map(|e| (e.component.clone(), Value::from(e.generation)))
+                .collect();
+            placed(report, path, json!({ "generations": generations }))
+        })
+        .collect();
+    let transaction: Vec<_> = report
+        .transaction
+        .iter()
+        .map(|(root, outcome)| {
+            let mut entry = match outcome {
+                Outcome::Committed(n) => json!({ "committed": n }),
+                Outcome::RolledBack(n) => json!({ "rolled_back": n }),
+                Outcome::Failed(n, e) => json!({ "committed": n, "error": e }),
+            };
+            entry["root"] = Value::from(escape::path(root).to_string());
+            entry
+        })
+        .collect();
+    let sizes: Vec<_> = report
+        .sizes
+        .iter()
+        .map(|(path, growth)| {
+            let growth = json!({
+                "before": growth.before,
+                "after": growth.after,
+                "delta": growth.delta(),
+            });
+            placed(report, path, growth)
+        })
+        .collect();
+    let shown = |paths: &[PathBuf]| -> Vec<_> {
+        paths
+            .iter()
+            .map(|path| shown(&report.roots, path))
+            .collect()
+    };
+    let entries: Vec<_> = report
+        .entries
+        .iter()
+        .map(|entry| entry.json(report))
+        .collect();
+
+    let mut json = json!({
+        "files": report.files,
+        "failures": report.failures,
+        "unchanged": report.unchanged,
+        "failed": failed,
+        "skipped": skipped,
+        "errors": errors,
+        "warnings": warnings,
+        "checksums_fixed": shown(&report.checksums_fixed),
+        "sbat": sbat,
+        "sizes": sizes,
+        "growth": report.sizes.iter().map(|(_, growth)| growth.delta()).sum::<i64>(),
+        "oversized": shown(&report.oversized),
+        "quarantined": quarantined(report),
+        "rolled_back": report.rolled_back,
+        "cancelled": report.cancelled,
+        "transaction": transaction,
+        "entries": entries,
+    });
+    if let Some(prediction) = &report.pcrs {
+        json["pcrs"] = pcrs(prediction);
+    }
+    if !report.roots.is_empty() {
+        let roots: Vec<_> = report
+            .roots
+            .iter()
+            .map(|root| escape::path(root).to_string())
+            .collect();
+        json["roots"] = Value::from(roots);
+    }
+    json
+}
+
+/// The files moved by `--quarantine` and where to.
+fn quarantined(report: &Report) -> Vec<Value> {
+    report
+        .quarantined
+        .iter()
+        .map(|(path, to)| placed(report, path, json!({ "to": escape::path(to).to_string() })))
+        .collect()
+}
+
+impl serde::Serialize for Report {
+    /// As [`json`] has it.
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        json(self).serialize(serializer)
+    }
+}
+
+fn pcrs(prediction: &Prediction) -> Value {
+    let authority = match prediction.authority {
+        Authority::Measured => json!("measured"),
+        Authority::New(event) => json!({ "new": hex(&event) }),
+        Authority::NotInDb => json!("not-in-db"),
+    };
+
+    json!({
+        "4": { "old": hex(&prediction.pcr4.0), "new": hex(&prediction.pcr4.1) },
+        "7": { "old": hex(&prediction.pcr7), "authority": authority },
+    })
+}
+
+/// Write `report` to `spec`, given as `FORMAT=PATH`. `-` as the path is stdout. The formats
+/// are `json` and `csv`, see [`csv`].
+///
+/// # Errors
+///
+/// Fails on an unknown format or if the report can't be written.
+pub fn write(report: &Report, spec: &str) -> io::Result<()> {
+    emit(spec, |format| match format {
+        "json" => Some(json(report).to_string()),
+        "csv" => Some(csv(report)),
+        _ => None,
+    })
+}
+
+/// The entries of the report as CSV, a line for each file under a header:
+///
+/// ```text
+/// path,action,signer,exit_code,duration_ms
+/// /efi/EFI/BOOT/BOOTX64.EFI,signed,sbsign,0,21
+/// /efi/EFI/BOOT/fbx64.efi,failed,sbsign,1,18
+/// /efi/EFI/BOOT/notes.txt,skipped (not-pe),,,0
+/// ```
+///
+/// The exit code is that of the signer, empty for files it never saw and for signers that
+/// didn't exit on their own.
+#[must_use]
+pub fn csv(report: &Report) -> String {
+    let mut csv = String::from("path,action,signer,exit_code,duration_ms");
+    for entry in &report.entries {
+        let code = match (&entry.action, &entry.error) {
+            (Action::Signed, _) => "0".to_string(),
+            (_, Some(Failure::Exit(code))) => code.to_string(),
+            _ => String::new(),
+        };
+        csv.push('\n');
+        csv.push_str(
+            &[
+                field(&shown(&report.roots, &entry.path)),
+                field(&entry.action.to_string()),
+                entry
+                    .backend
+                    .as_ref()
+                    .map(Backend::name)
+                    .unwrap_or_default()
+                    .to_string(),
+                code,
+                entry.duration.as_millis().to_string(),
+            ]
+            .join(","),
+        );
+    }
+    csv
+}
+
+/// `s` as a CSV field, quoted if it has to be. Newlines there are none of, paths being escaped.
+fn field(s: &str) -> String {
+    if s.contains([',', '"']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Write `report` as a line of JSON to `out`, as the `json` format of [`write`] has it.
+///
+/// # Errors
+///
+/// Fails if `out` can't be written.
+pub fn write_to(report: &Report, out: &mut impl io::Write) -> io::Result<()> {
+    writeln!(out, "{}", json(report))?;
+    out.flush()
+}
+
+/// Take over the already open file descriptor `fd`, for `--summary-fd`: a wrapper script reads
+/// the report from its end of a pipe while stdout and stderr are left for people. The report is
+/// written to it with [`write_to`], and it is closed when dropped.
+///
+/// # Errors
+///
+/// Fails if `fd` isn't open.
+#[cfg(unix)]
+pub fn from_fd(fd: i32) -> io::Result<std::fs::File> {
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: F_GETFD only looks the descriptor up
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        let e = io::Error::last_os_error();
+        return Err(io::Error::new(
+            e.kind(),
+            format!("file descriptor {fd}: {e}"),
+        ));
+    }
+    // SAFETY: it is open, and the caller handed it over to be consumed
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
+
+/// Write what `render` makes of the format of `spec` to its path, see [`write`]. `None` from it
+/// is a format it doesn't know.
+pub(crate) fn emit(spec: &str, render: impl FnOnce(&str) -> Option<String>) -> io::Result<()> {
+    let (format, path) = spec.split_once('=').unwrap_or((spec, "-"));
+    let Some(text) = render(format) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown report format: '{format}'"),
+        ));
+    };
+
+    if path == "-" {
+        println!("{text}");
+        Ok(())
+    } else {
+        std::fs::write(Path::new(path), text + "\n")
+    }
+}
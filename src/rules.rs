@@ -0,0 +1,278 @@
+//! `--rules`, a policy deciding file by file what to do, for fleets where a few flags don't
+//! cover it. A rules file has one rule a line, an action and the conditions that all have to
+//! hold for it to apply, and the first rule that applies to a file decides:
+//!
+//! ```text
+//! # Windows keeps the signatures Microsoft gave it
+//! skip    path EFI/Microsoft/**
+//! skip    signer CN=Microsoft*
+//! # nothing 32-bit is supposed to be here
+//! error   arch ia32
+//! resign  path EFI/Linux/*.efi
+//! sign    unsigned size <64M
+//! skip
+//! ```
+//!
+//! The actions are `sign`, `resign` (sign even if signed already), `skip` and `error`, which
+//! fails the file and with it the run. The conditions are `path GLOB`, matched like `--include`
+//! patterns, `signer PATTERN`, matched like `--skip-signed-by`, `arch` with one of the names
+//! `--arch` takes, `signed`, `unsigned` and `size` with `<` or `>` and a size like `--min-size`
+//! takes. Quote a glob or pattern with spaces in it.
+//!
+//! Rules come after sbsevery's own files and fwupd's capsules are set aside and before every
+//! other filter, which still applies to the files a rule lets through. A file no rule applies
+//! to is signed, end with a bare `skip` to have the rules list everything that is.
+
+use std::{cell::OnceCell, fmt, io, path::Path, str::FromStr};
+
+use crate::{escape, filter, glob, pe, Options};
+
+/// What a rule has done with the files it applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Let it through to the other filters and sign it.
+    Sign,
+    /// Sign it even if it is signed already, as with `--resign`.
+    Resign,
+    /// Leave it alone.
+    Skip,
+    /// Fail it, it isn't supposed to be there.
+    Error,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Sign => "sign",
+            Action::Resign => "resign",
+            Action::Skip => "skip",
+            Action::Error => "error",
+        })
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sign" => Ok(Action::Sign),
+            "resign" => Ok(Action::Resign),
+            "skip" => Ok(Action::Skip),
+            "error" => Ok(Action::Error),
+            _ => Err(format!(
+                "unknown action: '{s}', expected sign, resign, skip or error"
+            )),
+        }
+    }
+}
+
+/// Something about a file a rule can ask.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    Path(String),
+    Signer(String),
+    Arch(pe::Machine),
+    Signed(bool),
+    Smaller(u64),
+    Larger(u64),
+}
+
+/// One line of a rules file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    /// Where it is in the file, from 1.
+    pub line: usize,
+    pub action: Action,
+    conditions: Vec<Condition>,
+    /// The line as written, to say which rule it was.
+    text: String,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule {} ({})", self.line, self.text)
+    }
+}
+
+/// The rules of a rules file, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rules {
+    pub rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Read the rules in the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or a line doesn't make sense, saying which.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+        text.parse().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", escape::path(path)),
+            )
+        })
+    }
+
+    /// The first rule that applies to `file`, found under one of `options.roots`.
+    #[must_use]
+    pub fn decide(&self, file: &Path, options: &Options) -> Option<&Rule> {
+        let facts = Facts {
+            file,
+            options,
+            image: OnceCell::new(),
+        };
+        self.rules
+            .iter()
+            .find(|rule| rule.conditions.iter().all(|c| facts.hold(c)))
+    }
+}
+
+impl FromStr for Rules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+        for (n, line) in s.lines().enumerate() {
+            let text = line.split_once('#').map_or(line, |(rule, _)| rule).trim();
+            if text.is_empty() {
+                continue;
+            }
+            let rule = parse(text).map_err(|e| format!("line {}: {e}", n + 1))?;
+            rules.push(Rule {
+                line: n + 1,
+                text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+                ..rule
+            });
+        }
+        Ok(Self { rules })
+    }
+}
+
+/// One rule, without where it came from.
+fn parse(text: &str) -> Result<Rule, String> {
+    let words = words(text)?;
+    let mut words = words.into_iter();
+    let action = words.next().unwrap_or_default().parse()?;
+
+    let mut conditions = Vec::new();
+    while let Some(word) = words.next() {
+        let mut arg = || words.next().ok_or_else(|| format!("{word} needs a value"));
+        conditions.push(match word.as_str() {
+            "path" => Condition::Path(arg()?),
+            "signer" => Condition::Signer(arg()?),
+            "arch" => Condition::Arch(arg()?.parse()?),
+            "signed" => Condition::Signed(true),
+            "unsigned" => Condition::Signed(false),
+            "size" => {
+                let size = arg()?;
+                if let Some(n) = size.strip_prefix('<') {
+                    Condition::Smaller(parse_size(n)?)
+                } else if let Some(n) = size.strip_prefix('>') {
+                    Condition::Larger(parse_size(n)?)
+                } else {
+                    return Err(format!("size needs < or >, not '{size}'"));
+                }
+            }
+            _ => return Err(format!("unknown condition: '{word}'")),
+        });
+    }
+
+    Ok(Rule {
+        line: 0,
+        action,
+        conditions,
+        text: String::new(),
+    })
+}
+
+/// The words of a line, split on whitespace outside quotes.
+fn words(text: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        if c == '\'' || c == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some(q) if q == c => break,
+                    Some(ch) => word.push(ch),
+                    None => return Err(format!("unterminated {c}")),
+                }
+            }
+        } else {
+            while let Some(ch) = chars.next_if(|ch| !ch.is_whitespace()) {
+                word.push(ch);
+            }
+        }
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// A number of bytes with an optional `K`, `M` or `G`.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, shift) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 10),
+        Some('M') => (&s[..s.len() - 1], 20),
+        Some('G') => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size: '{s}'"))
+}
+
+/// What is known about the file the rules are asked about, its headers and certificate table
+/// read once if a condition needs them.
+struct Facts<'a> {
+    file: &'a Path,
+    options: &'a Options,
+    image: OnceCell<Option<(pe::Layout, Vec<u8>)>>,
+}
+
+impl Facts<'_> {
+    /// The layout of the image and its certificate table, `None` if it isn't one.
+    fn image(&self) -> Option<&(pe::Layout, Vec<u8>)> {
+        self.image
+            .get_or_init(|| {
+                let mut image = pe::Image::open(self.file).ok()??;
+                let table = image.signatures().ok()?.unwrap_or_default();
+                Some((image.layout, table))
+            })
+            .as_ref()
+    }
+
+    fn hold(&self, condition: &Condition) -> bool {
+        let size = || self.file.metadata().map_or(0, |m| m.len());
+        match condition {
+            Condition::Path(pattern) => {
+                let fold = filter::folds(self.file, self.options.case);
+                glob::matches_file(pattern, self.file, &self.options.roots, fold)
+            }
+            Condition::Signer(pattern) => self.image().is_some_and(|(_, table)| {
+                filter::signed_by(&pe::signed_data(table), std::slice::from_ref(pattern)).is_some()
+            }),
+            Condition::Arch(machine) => self
+                .image()
+                .is_some_and(|(layout, _)| layout.machine == machine.value()),
+            Condition::Signed(signed) => self
+                .image()
+                .is_some_and(|(_, table)| pe::signed_data(table).is_empty() != *signed),
+            Condition::Smaller(limit) => size() < *limit,
+            Condition::Larger(limit) => size() > *limit,
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! `--sandbox`, confine signer processes with Landlock and a seccomp filter.
+//!
+//! A signer may read the system directories it needs to run, the key and the cert, and may only
+//! write inside the directory of the file it is signing. On top of that a seccomp filter refuses
+//! syscalls no signer has any business making (loading modules, kexec, mounting, ptrace, ...).
+//! Both are installed in the child between fork and exec, sbsevery itself is not confined.
+
+use std::{
+    ffi::CString,
+    io,
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::Path,
+    process::Command,
+};
+
+use crate::Options;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+const FS_EXECUTE: u64 = 1 << 0;
+const FS_WRITE_FILE: u64 = 1 << 1;
+const FS_READ_FILE: u64 = 1 << 2;
+const FS_READ_DIR: u64 = 1 << 3;
+const FS_REMOVE_FILE: u64 = 1 << 5;
+const FS_MAKE_REG: u64 = 1 << 8;
+/// Every filesystem right of Landlock ABI 1, the ones not granted below are denied everywhere.
+const FS_ALL_V1: u64 = (1 << 13) - 1;
+
+const READ_EXEC: u64 = FS_EXECUTE | FS_READ_FILE | FS_READ_DIR;
+const READ: u64 = FS_READ_FILE | FS_READ_DIR;
+const WRITE_DIR: u64 = FS_READ_FILE | FS_READ_DIR | FS_WRITE_FILE | FS_MAKE_REG | FS_REMOVE_FILE;
+
+/// Where signers and the libraries they link against live.
+const SYSTEM: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib64",
+    "/nix/store",
+    "/opt",
+];
+/// Read only bits of the system a signer may want, openssl config, the loader cache and so on.
+const SYSTEM_READ: &[&str] = &["/etc", "/dev/urandom", "/dev/null", "/proc/self"];
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_RET_K: u16 = 0x06;
+
+/// Syscalls answered with `EPERM`.
+const DENIED: &[libc::c_long] = &[
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_setns,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_reboot,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_open_by_handle_at,
+];
+
+/// Fail early if the running kernel has no Landlock, rather than for every file.
+///
+/// # Errors
+///
+/// Fails with the kernel's error if Landlock is unsupported or disabled.
+pub fn check() -> io::Result<()> {
+    // SAFETY: asking for the ABI version takes no pointers
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+
+    if abi < 1 {
+        let e = io::Error::last_os_error();
+        return Err(io::Error::new(
+            e.kind(),
+            format!("landlock unavailable, can't sandbox: {e}"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Confine the process `cmd` will start, `file` being the one it signs.
+pub(crate) fn apply(cmd: &mut Command, file: &Path, options: &Options) {
+    let mut rules: Vec<(CString, u64)> = Vec::new();
+    let mut rule = |path: &Path, access: u64| {
+        let Ok(meta) = path.metadata() else { return };
+        // directory rights on a file make the whole rule invalid
+        let access = if meta.is_dir() {
+            access
+        } else {
+            access & (FS_EXECUTE | FS_WRITE_FILE | FS_READ_FILE)
+        };
+
+        if let Ok(path) = CString::new(path.as_os_str().as_bytes()) {
+            rules.push((path, access));
+        }
+    };
+
+    for dir in SYSTEM {
+        rule(Path::new(dir), READ_EXEC);
+    }
+    for path in SYSTEM_READ {
+        rule(Path::new(path), READ);
+    }
+    // signers found somewhere odd on $PATH still have to be able to run
+    if let Some(paths) = std::env::var_os("PATH") {
+        for dir in std::env::split_paths(&paths) {
+            rule(&dir, READ_EXEC);
+        }
+    }
+    rule(&options.key, FS_READ_FILE);
+    rule(&options.cert, FS_READ_FILE);
+    if let Some(dir) = file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        rule(dir, WRITE_DIR);
+    } else {
+        rule(Path::new("."), WRITE_DIR);
+    }
+
+    let mut filter = seccomp_filter();
+
+    // SAFETY: only raw syscalls on memory prepared above happen between fork and exec
+    unsafe {
+        cmd.pre_exec(move || {
+            restrict(&rules)?;
+
+            let prog = libc::sock_fprog {
+                len: u16::try_from(filter.len()).unwrap_or(u16::MAX),
+                filter: filter.as_mut_ptr(),
+            };
+            if libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                std::ptr::addr_of!(prog),
+            ) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+}
+
+/// Runs in the forked child, so no allocating.
+unsafe fn restrict(rules: &[(CString, u64)]) -> io::Result<()> {
+    let attr = RulesetAttr {
+        handled_access_fs: FS_ALL_V1,
+    };
+    let ruleset = libc::syscall(
+        libc::SYS_landlock_create_ruleset,
+        std::ptr::addr_of!(attr),
+        std::mem::size_of::<RulesetAttr>(),
+        0u32,
+    );
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let ruleset = i32::try_from(ruleset).unwrap_or(-1);
+
+    for (path, access) in rules {
+        let fd = libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC);
+        if fd < 0 {
+            continue;
+        }
+
+        let beneath = PathBeneathAttr {
+            allowed_access: *access,
+            parent_fd: fd,
+        };
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset,
+            LANDLOCK_RULE_PATH_BENEATH,
+            std::ptr::addr_of!(beneath),
+            0u32,
+        );
+        libc::close(fd);
+    }
+
+    if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+        || libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0u32) != 0
+    {
+        let e = io::Error::last_os_error();
+        libc::close(ruleset);
+        return Err(e);
+    }
+
+    libc::close(ruleset);
+    Ok(())
+}
+
+fn seccomp_filter() -> Vec<libc::sock_filter> {
+    let op = |code, jt, jf, k| libc::sock_filter { code, jt, jf, k };
+
+    let mut filter = vec![
+        // seccomp_data.arch, anything but the native ABI is killed outright
+        op(BPF_LD_W_ABS, 0, 0, 4),
+        op(BPF_JEQ_K, 1, 0, AUDIT_ARCH),
+        op(BPF_RET_K, 0, 0, libc::SECCOMP_RET_KILL_PROCESS),
+        // seccomp_data.nr
+        op(BPF_LD_W_ABS, 0, 0, 0),
+    ];
+
+    for nr in DENIED {
+        filter.push(op(BPF_JEQ_K, 0, 1, u32::try_from(*nr).unwrap_or(u32::MAX)));
+        filter.push(op(
+            BPF_RET_K,
+            0,
+            0,
+            libc::SECCOMP_RET_ERRNO | u32::try_from(libc::EPERM).unwrap_or_default(),
+        ));
+    }
+
+    filter.push(op(BPF_RET_K, 0, 0, libc::SECCOMP_RET_ALLOW));
+    filter
+}
@@ -0,0 +1,79 @@
+//! SBAT, the `.sbat` section shim and GRUB carry to say which generation of each of their
+//! components they are. Revoking a vulnerable build is done by raising the generation firmware
+//! wants, so signing an old one with a fresh key undoes the revocation for anyone trusting it.
+//!
+//! The section is CSV, one component per line:
+//!
+//! ```text
+//! sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md
+//! shim,4,UEFI shim,shim,15.8,https://github.com/rhboot/shim
+//! ```
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::pe::Layout;
+
+/// One line of an `.sbat` section, the rest of its fields describe the build for people.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Entry {
+    pub component: String,
+    pub generation: u32,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // it comes from the image, which may be anyone's
+        write!(
+            f,
+            "{} {}",
+            crate::escape::text(&self.component),
+            self.generation
+        )
+    }
+}
+
+/// The entries of the `.sbat` section of the image `data`, in section order. Empty if it
+/// isn't an image or has no such section.
+#[must_use]
+pub fn entries(data: &[u8]) -> Vec<Entry> {
+    Layout::parse(data)
+        .and_then(|layout| layout.section(data, b".sbat"))
+        .map(parse)
+        .unwrap_or_default()
+}
+
+/// The entries of an `.sbat` section, lines that don't start with a component and a
+/// generation are left out.
+#[must_use]
+pub fn parse(section: &[u8]) -> Vec<Entry> {
+    // the section is padded with NULs up to the file alignment
+    let end = section
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(section.len());
+    String::from_utf8_lossy(&section[..end])
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(',');
+            let component = fields.next()?.trim();
+            let generation = fields.next()?.trim().parse().ok()?;
+            (!component.is_empty()).then(|| Entry {
+                component: component.to_string(),
+                generation,
+            })
+        })
+        .collect()
+}
+
+/// The first of `entries` with a lower generation than `minimum` asks of its component, and
+/// that minimum.
+#[must_use]
+pub fn below<'a>(
+    entries: &'a [Entry],
+    minimum: &BTreeMap<String, u32>,
+) -> Option<(&'a Entry, u32)> {
+    entries.iter().find_map(|entry| {
+        let min = *minimum.get(&entry.component)?;
+        (entry.generation < min).then_some((entry, min))
+    })
+}
@@ -0,0 +1,252 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Sender},
+        Condvar, Mutex,
+    },
+    thread::scope,
+};
+
+use crate::{escape, filter::Filter, Options};
+
+/// Directories waiting to be expanded, shared between the walker threads.
+#[derive(Default)]
+struct Queue {
+    state: Mutex<QueueState>,
+    cv: Condvar,
+    /// Directories expanded so far, by device and inode, so one reached again through a
+    /// symlink, a link to `..` say, isn't searched again and round forever.
+    seen: Mutex<HashSet<(u64, u64)>>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    dirs: Vec<PathBuf>,
+    /// Walkers currently expanding a directory, they may still push more work.
+    busy: usize,
+}
+
+impl Queue {
+    fn push(&self, dir: PathBuf) {
+        if let Ok(mut state) = self.state.lock() {
+            state.dirs.push(dir);
+            self.cv.notify_one();
+        }
+    }
+
+    /// Block until there is a directory to expand, `None` once every walker is idle and the
+    /// queue is empty.
+    fn pop(&self) -> Option<PathBuf> {
+        let mut state = self.state.lock().ok()?;
+        loop {
+            if let Some(dir) = state.dirs.pop() {
+                state.busy += 1;
+                return Some(dir);
+            }
+
+            if state.busy == 0 {
+                self.cv.notify_all();
+                return None;
+            }
+
+            state = self.cv.wait(state).ok()?;
+        }
+    }
+
+    /// Whether `dir` is expanded for the first time, it is taken to be if there is no telling.
+    fn first_visit(&self, dir: &Path) -> bool {
+        let Some(id) = identity(dir) else {
+            return true;
+        };
+        self.seen.lock().is_ok_and(|mut seen| seen.insert(id))
+    }
+
+    fn done(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.busy -= 1;
+            if state.busy == 0 && state.dirs.is_empty() {
+                self.cv.notify_all();
+            }
+        }
+    }
+}
+
+/// Walk every root in `options` with `options.jobs` threads, sending each file that passes the
+/// filters down `sx`, the rest are recorded in `filter`.
+pub(crate) fn searcher(sx: &Sender<PathBuf>, options: &Options, filter: &Filter, verbose: bool) {
+    let queue = Queue::default();
+    let walk = Walk {
+        queue: &queue,
+        sx,
+        options,
+        filter,
+        verbose,
+    };
+
+    for dir in &options.roots {
+        if dir.is_dir() {
+            queue.push(dir.clone());
+        } else if let Err(e) = push_file(&walk, dir) {
+            dprintln!(verbose, "error:\t{}", e);
+        }
+    }
+
+    scope(|s| {
+        for _ in 0..options.jobs.max(1) {
+            let walk = walk.clone();
+            s.spawn(move || walker(&walk));
+        }
+    });
+}
+
+/// Search like [`searcher`] and run `f` on every file found, `options.jobs` files at a time.
+/// Returns the results in path order.
+pub(crate) fn each<T: Send>(
+    options: &Options,
+    filter: &Filter,
+    f: impl Fn(&Path) -> T + Sync,
+) -> Vec<(PathBuf, T)> {
+    let (sx, rx) = channel();
+    let rx = Mutex::new(rx);
+    let done = Mutex::new(Vec::new());
+
+    scope(|s| {
+        s.spawn(move || searcher(&sx, options, filter, options.verbose));
+        for _ in 0..options.jobs.max(1) {
+            s.spawn(|| loop {
+                let file = match rx.lock() {
+                    Ok(rx) => rx.recv(),
+                    Err(_) => break,
+                };
+                let Ok(file) = file else { break };
+
+                let res = f(&file);
+                if let Ok(mut done) = done.lock() {
+                    done.push((file, res));
+                }
+            });
+        }
+    });
+
+    let mut done = done.into_inner().unwrap_or_default();
+    done.sort_by(|a, b| a.0.cmp(&b.0));
+    done
+}
+
+/// What every walker thread shares.
+#[derive(Clone)]
+struct Walk<'a> {
+    queue: &'a Queue,
+    sx: &'a Sender<PathBuf>,
+    options: &'a Options,
+    filter: &'a Filter,
+    verbose: bool,
+}
+
+fn walker(walk: &Walk) {
+    let (queue, verbose) = (walk.queue, walk.verbose);
+    while let Some(dir) = queue.pop() {
+        let err = push_dir(walk, &dir);
+        queue.done();
+
+        if let Err(e) = err {
+            dprintln!(verbose, "error:\t{}", e);
+        }
+    }
+}
+
+fn push_dir(walk: &Walk, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if walk.options.cancel.is_cancelled() {
+        return Ok(());
+    }
+    if !walk.queue.first_visit(dir) {
+        dprintln!(walk.verbose, "searched already:\t{}", escape::path(dir));
+        return Ok(());
+    }
+    dprintln!(walk.verbose, "expanding:\t{}", escape::path(dir));
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(e) => {
+            walk.filter.error(dir, &e);
+            return Ok(());
+        }
+    };
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                walk.filter.error(dir, &e);
+                continue;
+            }
+        };
+        let path = entry.path();
+
+        if too_long(&path) {
+            // the kernel would refuse it anyway, say so here rather than leave it to a
+            // confusing failure in the signer
+            let e = io::Error::new(io::ErrorKind::InvalidFilename, "path too long");
+            walk.filter.error(&path, &e);
+            continue;
+        }
+
+        // no stat per entry, except for symlinks, which are followed
+        let is_dir = match entry.file_type() {
+            Ok(t) if t.is_symlink() => path.is_dir(),
+            Ok(t) => t.is_dir(),
+            Err(e) => {
+                walk.filter.error(&path, &e);
+                continue;
+            }
+        };
+
+        if is_dir {
+            if walk.options.recursive {
+                walk.queue.push(path);
+            } else {
+                dprintln!(walk.verbose, "not descending:\t{}", escape::path(&path));
+            }
+        } else {
+            push_file(walk, &path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The device and inode of the directory `dir`, symlinks followed.
+#[cfg(unix)]
+fn identity(dir: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = std::fs::metadata(dir).ok()?;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Whether `path` is longer than the system lets a path be.
+#[cfg(unix)]
+fn too_long(path: &Path) -> bool {
+    path.as_os_str().len() >= usize::try_from(libc::PATH_MAX).unwrap_or(usize::MAX)
+}
+
+#[cfg(not(unix))]
+fn too_long(_: &Path) -> bool {
+    false
+}
+
+fn push_file(walk: &Walk, file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if walk.options.cancel.is_cancelled() || !walk.filter.pass(file, walk.options, walk.verbose) {
+        return Ok(());
+    }
+
+    dprintln!(walk.verbose, "pushing:\t{}", escape::path(file));
+    walk.sx.send(file.to_path_buf())?;
+    Ok(())
+}
@@ -0,0 +1,184 @@
+//! Passphrases and decrypted keys. These never go through argv or the environment: the
+//! passphrase comes from a file descriptor or an askpass program, the key is decrypted once by
+//! `openssl` over pipes and handed to every signer through a pipe of its own.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Bytes that are wiped when dropped.
+pub struct Secret(Vec<u8>);
+
+impl Secret {
+    #[must_use]
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub(crate) fn as_mut_bytes(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        for b in &mut self.0 {
+            // SAFETY: b is a valid &mut u8, volatile so the wipe isn't optimised away
+            unsafe { std::ptr::write_volatile(b, 0) };
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+/// Ask `cmd` for the passphrase of `key` the way ssh-askpass and pinentry wrappers expect: the
+/// prompt is the only argument, the passphrase is the first line of stdout.
+///
+/// # Errors
+///
+/// Fails if the program can't be run or exits unsuccessfully.
+pub fn askpass(cmd: &str, key: &Path) -> io::Result<Secret> {
+    let mut child = Command::new(cmd)
+        .arg(format!(
+            "Enter passphrase for {}:",
+            crate::escape::path(key)
+        ))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // big enough up front that reading never reallocates and leaves copies behind
+    let mut line = Vec::with_capacity(4096);
+    if let Some(stdout) = child.stdout.take() {
+        BufReader::new(stdout).read_until(b'\n', &mut line)?;
+    }
+    let line = Secret::new(line);
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{cmd} {status}")));
+    }
+
+    Ok(trim(&line))
+}
+
+/// Read the passphrase from an already open file descriptor, up to the first newline.
+///
+/// # Errors
+///
+/// Fails if `fd` can't be read.
+#[cfg(unix)]
+pub fn from_fd(fd: i32) -> io::Result<Secret> {
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: the caller handed us the descriptor to consume
+    let file = unsafe { File::from_raw_fd(fd) };
+    let mut line = Vec::with_capacity(4096);
+    BufReader::new(file).read_until(b'\n', &mut line)?;
+    let line = Secret::new(line);
+
+    Ok(trim(&line))
+}
+
+fn trim(line: &Secret) -> Secret {
+    let bytes = line.as_bytes();
+    let end = bytes
+        .iter()
+        .rposition(|&b| b != b'\n' && b != b'\r')
+        .map_or(0, |i| i + 1);
+    Secret::new(bytes[..end].to_vec())
+}
+
+/// Decrypt `key` into memory with `openssl pkey`, the passphrase goes in on stdin.
+///
+/// # Errors
+///
+/// Fails if openssl can't be run or can't decrypt the key.
+pub fn decrypt_key(key: &Path, passphrase: &Secret) -> io::Result<Secret> {
+    let mut child = Command::new("openssl")
+        .arg("pkey")
+        .arg("-in")
+        .arg(key)
+        .arg("-passin")
+        .arg("stdin")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(passphrase.as_bytes())?;
+        stdin.write_all(b"\n")?;
+    }
+
+    let mut pem = Vec::with_capacity(16 * 1024);
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_end(&mut pem)?;
+    }
+    let pem = Secret::new(pem);
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "couldn't decrypt {}: openssl {status}",
+            crate::escape::path(key)
+        )));
+    }
+
+    Ok(pem)
+}
+
+/// Descriptor the decrypted key shows up as in a signer, passed as `/dev/fd/3`.
+#[cfg(target_os = "linux")]
+pub(crate) const KEY_FD: i32 = 3;
+
+/// Arrange for `cmd` to find the key on [`KEY_FD`]. Returns both ends of the pipe: the read
+/// end has to stay open until the process is spawned, the key is then written into the write
+/// end.
+#[cfg(target_os = "linux")]
+pub(crate) fn pass_key(cmd: &mut Command) -> io::Result<(File, File)> {
+    use std::os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::process::CommandExt,
+    };
+
+    let mut fds = [0; 2];
+    // SAFETY: pipe2 writes two descriptors into fds
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both descriptors were just created and are ours
+    let (read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    let fd = read.as_raw_fd();
+    // SAFETY: dup2 is async signal safe, the copy doesn't inherit close-on-exec
+    unsafe {
+        cmd.pre_exec(move || {
+            let ok = if fd == KEY_FD {
+                libc::fcntl(fd, libc::F_SETFD, 0) == 0
+            } else {
+                libc::dup2(fd, KEY_FD) >= 0
+            };
+            if ok {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+
+    Ok((read, write))
+}
@@ -0,0 +1,87 @@
+//! `sbsevery selftest`, sign a throwaway image with the real key, cert and backend and check
+//! the result, before anything on the ESP is touched.
+
+use std::{fs, path::Path, process::Stdio};
+
+use crate::{
+    pe::{self, Layout, Machine},
+    Options,
+};
+
+/// Architecture of the firmware this most likely runs on.
+const NATIVE: Machine = if cfg!(target_arch = "aarch64") {
+    Machine::Aa64
+} else if cfg!(target_arch = "x86") {
+    Machine::Ia32
+} else {
+    Machine::X64
+};
+
+/// Generate a dummy image in a temporary directory, sign it as `options` would sign real files
+/// and verify the signature, with the backend's own verifier where there is one. The directory
+/// is removed again either way.
+///
+/// # Errors
+///
+/// Fails with what went wrong if the image couldn't be signed or doesn't verify.
+pub fn selftest(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("sbsevery-selftest-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let file = dir.join("selftest.efi");
+
+    let res = fs::write(&file, pe::dummy(NATIVE, 4096))
+        .map_err(Into::into)
+        .and_then(|()| check(&file, options));
+
+    fs::remove_dir_all(&dir)?;
+    res
+}
+
+fn check(file: &Path, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    // only what it takes to sign, filters and state are for real runs
+    let options = Options {
+        backend: options.backend.clone(),
+        key: options.key.clone(),
+        cert: options.cert.clone(),
+        roots: vec![file.to_path_buf()],
+        verbose: options.verbose,
+        jobs: 1,
+        timeout: options.timeout,
+        passphrase: options.passphrase.clone(),
+        sandbox: options.sandbox,
+        ..Options::default()
+    };
+    let name = options.backend.name();
+
+    let report = crate::run(&options)?;
+    if report.failures > 0 || report.files != 1 {
+        return Err(format!("{name} failed to sign a test image").into());
+    }
+    eprintln!("selftest: signed a test image with {name}");
+
+    let data = fs::read(file)?;
+    if Layout::parse(&data).is_none_or(|layout| layout.cert_table.is_none()) {
+        return Err(format!("{name} succeeded but left the test image unsigned").into());
+    }
+
+    if let Some(mut cmd) = options.backend.verify_command(file, &options.cert) {
+        let program = cmd.get_program().to_string_lossy().into_owned();
+        let status = cmd
+            .stdout(Stdio::null())
+            .stderr(if options.verbose {
+                Stdio::inherit()
+            } else {
+                Stdio::null()
+            })
+            .status()
+            .map_err(|e| format!("couldn't run {program}: {e}"))?;
+        if !status.success() {
+            return Err(format!("{program} rejected the signed test image: {status}").into());
+        }
+        eprintln!("selftest: {program} accepts the signature");
+    } else {
+        eprintln!("selftest: test image carries a signature");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! `--settle`, wait for the roots to stop changing before signing, for hooks the package
+//! manager runs halfway through installing a kernel and its initrds.
+//!
+//! The roots are looked at again every so often, and once the name, size and modification
+//! time of every file under them have stayed the same for long enough the run goes ahead. A
+//! tree that never settles doesn't hold it up forever, after ten times as long it goes ahead
+//! anyway.
+
+use std::{
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::Options;
+
+/// How often to look again, at most.
+const POLL: Duration = Duration::from_millis(500);
+
+/// Everything about a file that changes while it is written.
+type Seen = Vec<(PathBuf, u64, Option<SystemTime>)>;
+
+/// Block until nothing under the roots has changed for `quiet`.
+pub(crate) fn wait(options: &Options, quiet: Duration) {
+    let start = Instant::now();
+    let poll = POLL.min(quiet);
+    let mut seen = look(options);
+    let mut since = Instant::now();
+
+    while since.elapsed() < quiet {
+        if start.elapsed() >= quiet * 10 {
+            eprintln!("the roots are still changing, signing anyway");
+            return;
+        }
+        sleep(poll);
+
+        let now = look(options);
+        if now != seen {
+            dprintln!(
+                options.verbose,
+                "settling:\tthe roots changed, waiting again"
+            );
+            seen = now;
+            since = Instant::now();
+        }
+    }
+}
+
+fn look(options: &Options) -> Seen {
+    let mut seen = Vec::new();
+    let mut paths = options.roots.clone();
+
+    while let Some(path) = paths.pop() {
+        // roots are followed like the walk follows them, links below them aren't
+        let top = options.roots.contains(&path);
+        let meta = if top {
+            std::fs::metadata(&path)
+        } else {
+            std::fs::symlink_metadata(&path)
+        };
+        let Ok(meta) = meta else {
+            continue;
+        };
+        if meta.is_dir() {
+            if top || options.recursive {
+                if let Ok(entries) = path.read_dir() {
+                    paths.extend(entries.flatten().map(|e| e.path()));
+                }
+            }
+        } else {
+            seen.push((path, meta.len(), meta.modified().ok()));
+        }
+    }
+
+    seen.sort();
+    seen
+}
@@ -0,0 +1,981 @@
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+    process::{Child, ExitStatus, Stdio},
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    audit::{AuditLog, Operation},
+    busy, config,
+    diagnose::{self, Warning},
+    escape,
+    filter::Filter,
+    hook, immutable, install,
+    janitor::{self, temp_path},
+    journal::Journal,
+    mock::MockSigner,
+    pcr,
+    pe::{self, Problem},
+    report::{shown, Action, Entry},
+    sbat,
+    secret::Secret,
+    state::{sha256_file, Hash, State},
+    stats::Growth,
+    throttle::{self, Throttle},
+    transaction::Staged,
+    usage, xattr, Backend, Failure, Options, Report, Skip, Stats,
+};
+
+type Outcome = Result<ExitStatus, io::Error>;
+
+/// How a signer run went, and what it warned about on the way.
+#[derive(Debug)]
+pub(crate) struct Signed {
+    /// `None` if the signer wasn't run, validation already having turned the file down.
+    status: Option<ExitStatus>,
+    warnings: Vec<Warning>,
+    /// Whether the checksum was wrong once signed and has been fixed, with `--fix-checksum`.
+    checksum_fixed: bool,
+    /// The `.sbat` section of the image as it was given to the signer.
+    sbat: Vec<sbat::Entry>,
+    /// Where the signed copy waits to be renamed over the file, with `--transactional`.
+    staged: Option<PathBuf>,
+    /// How much the file grew, if it was changed.
+    growth: Option<Growth>,
+}
+
+impl Signed {
+    /// Whether the file was already signed and so left alone, without `--resign`.
+    fn skipped(&self, options: &Options) -> bool {
+        !options.resign && self.warnings.contains(&Warning::AlreadySigned)
+    }
+
+    /// The warning the file was left alone for with `--strict-pe`.
+    fn rejected(&self, options: &Options) -> Option<Warning> {
+        if !options.strict_pe {
+            return None;
+        }
+        self.warnings.iter().copied().find(|w| w.pe())
+    }
+
+    /// Whether the file now carries a new signature.
+    fn changed(&self, options: &Options) -> bool {
+        self.status.is_some_and(|s| s.success())
+            && !self.skipped(options)
+            && self.rejected(options).is_none()
+    }
+}
+
+/// Authenticode hash of an image before and after signing.
+type Digests = (Hash, Hash);
+
+/// A file a worker is done with, how long it took and the backend it was signed with.
+pub(crate) type Done = (PathBuf, io::Result<Signed>, Duration, Backend);
+
+/// Take in the files the workers are done with as they come down `done`, until every worker
+/// is finished, and add them to `report`. With `options.sort` they are taken in once all are
+/// done, in path order.
+pub(crate) fn wait(
+    workers: Vec<JoinHandle<Stats>>,
+    done: &Receiver<Done>,
+    report: &mut Report,
+    options: &Options,
+    filter: &Filter,
+) -> Vec<Staged> {
+    let mut staged = Vec::new();
+    if options.sort {
+        let mut results: Vec<_> = done.iter().collect();
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        for done in results {
+            account(done, report, options, filter, &mut staged);
+        }
+    } else {
+        for file in done {
+            account(file, report, options, filter, &mut staged);
+        }
+    }
+
+    for t in workers {
+        if let Ok(stats) = t.join() {
+            report.stats.add(&stats);
+        } else {
+            eprintln!("Thread join failed");
+        }
+    }
+
+    // workers finish in whatever order the scheduler likes, report in path order instead
+    report.failed.sort_by(|a, b| a.0.cmp(&b.0));
+    report.warnings.sort_by(|a, b| a.0.cmp(&b.0));
+    report.sbat.sort_by(|a, b| a.0.cmp(&b.0));
+    report.sizes.sort_by(|a, b| a.0.cmp(&b.0));
+    report.oversized.sort();
+    report.checksums_fixed.sort();
+    staged
+}
+
+/// Add a file a worker is done with to `report`, and `staged` if it waits to be committed.
+fn account(
+    (file, res, duration, backend): Done,
+    report: &mut Report,
+    options: &Options,
+    filter: &Filter,
+    staged: &mut Vec<Staged>,
+) {
+    let mut entry = Entry {
+        path: file.clone(),
+        action: Action::Signed,
+        duration,
+        backend: Some(backend),
+        error: None,
+    };
+    dprintln!(
+        options.verbose && options.sort,
+        "signing:\t{}",
+        shown(&options.roots, &file)
+    );
+    if let Ok(signed) = &res {
+        if let Some(copy) = &signed.staged {
+            staged.push((copy.clone(), file.clone()));
+        }
+        if signed.skipped(options) {
+            filter.skip(&file, Skip::AlreadySigned, options.verbose);
+            entry.action = Action::Skipped(Skip::AlreadySigned);
+            report.entries.push(entry);
+            return;
+        }
+        for &warning in &signed.warnings {
+            dprintln!(
+                options.verbose || warning.pe() || matches!(warning, Warning::Compat(_)),
+                "warning:\t{} ({warning})",
+                shown(&options.roots, &file)
+            );
+            report.warnings.push((file.clone(), warning));
+        }
+        if !signed.sbat.is_empty() {
+            let generations: Vec<_> = signed.sbat.iter().map(ToString::to_string).collect();
+            dprintln!(
+                options.verbose,
+                "sbat:\t{} ({})",
+                shown(&options.roots, &file),
+                generations.join(", ")
+            );
+            report.sbat.push((file.clone(), signed.sbat.clone()));
+        }
+        if let Some(growth) = signed.growth {
+            dprintln!(
+                options.verbose,
+                "size:\t{} ({growth})",
+                shown(&options.roots, &file)
+            );
+            if let Some(limit) = options.size_limit.filter(|&limit| growth.after > limit) {
+                eprintln!(
+                    "warning:\t{} ({} bytes signed, over the size limit of {limit})",
+                    shown(&options.roots, &file),
+                    growth.after
+                );
+                report.oversized.push(file.clone());
+            }
+            report.sizes.push((file.clone(), growth));
+        }
+        if signed.checksum_fixed {
+            dprintln!(
+                options.verbose,
+                "checksum:\t{} (fixed)",
+                shown(&options.roots, &file)
+            );
+            report.checksums_fixed.push(file.clone());
+        }
+    }
+
+    report.files += 1;
+    let failure = match res {
+        Ok(signed) => signed
+            .status
+            .and_then(Failure::from_status)
+            .or_else(|| signed.rejected(options).map(Failure::Strict)),
+        Err(e) => Some(Failure::Error(e.to_string())),
+    };
+
+    if let Some(failure) = failure {
+        eprintln!(
+            "failed:\t{} ({})",
+            shown(&options.roots, &file),
+            escape::text(&failure.to_string())
+        );
+        report.failures += 1;
+        entry.action = Action::Failed;
+        entry.error = Some(failure.clone());
+        report.failed.push((file, failure));
+    }
+    if entry.action == Action::Signed {
+        options.observers.signed(&entry);
+    } else {
+        options.observers.failed(&entry);
+    }
+    report.entries.push(entry);
+}
+
+/// Run the `--post-sign` hook on every file `report` has signed, in path order, failing those it
+/// fails on. With `--transactional`, files not in `committed` were rolled back and it is told so.
+pub(crate) fn post_sign(
+    report: &mut Report,
+    options: &Options,
+    committed: Option<&HashSet<PathBuf>>,
+) {
+    let Some(template) = &options.post_sign else {
+        return;
+    };
+    for entry in &mut report.entries {
+        if entry.action != Action::Signed {
+            continue;
+        }
+        let status = match committed {
+            Some(committed) if !committed.contains(&entry.path) => "rolled-back",
+            _ => "signed",
+        };
+        let vars = [
+            ("path", entry.path.as_os_str()),
+            ("status", OsStr::new(status)),
+        ];
+        if let Err(e) = check_hook(hook::run(template, &vars)) {
+            let failure = Failure::Error(format!("post-sign hook: {e}"));
+            eprintln!(
+                "failed:\t{} ({})",
+                shown(&options.roots, &entry.path),
+                escape::text(&failure.to_string())
+            );
+            report.failures += 1;
+            entry.action = Action::Failed;
+            entry.error = Some(failure.clone());
+            report.failed.push((entry.path.clone(), failure));
+        }
+    }
+}
+
+/// Turn a hook exiting unsuccessfully into an error too.
+pub(crate) fn check_hook(status: Outcome) -> io::Result<()> {
+    let status = status?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(status.to_string()))
+    }
+}
+
+/// What the workers share besides the options: the state file, the decrypted key, and the
+/// logs and counters every worker writes to.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Shared {
+    pub(crate) state: Option<Arc<State>>,
+    /// The key, decrypted, when `--askpass` or `--passphrase-fd` was given.
+    pub(crate) key: Option<Arc<Secret>>,
+    /// Authenticode hashes of every image signed, before and after, for `--predict-pcrs`.
+    pub(crate) digests: Option<Arc<Mutex<Vec<Digests>>>>,
+    pub(crate) journal: Option<Arc<Journal>>,
+    pub(crate) audit: Option<Arc<AuditLog>>,
+    pub(crate) usage: Option<Arc<usage::Counter>>,
+    /// The hashes of `--allow-hashes`, sorted.
+    pub(crate) allowed: Option<Arc<Vec<Hash>>>,
+    pub(crate) throttle: Option<Arc<Throttle>>,
+    /// Files to sign even if they are signed already, as `resign` rules say.
+    pub(crate) resign: Arc<Mutex<HashSet<PathBuf>>>,
+    /// The token of the Vault login, with `--backend vault`.
+    pub(crate) vault_token: Option<Arc<Secret>>,
+}
+
+/// Pull files off the shared queue until the searcher hangs up, signing one at a time.
+pub(crate) fn worker(
+    rx: &Arc<Mutex<Receiver<PathBuf>>>,
+    done: &Sender<Done>,
+    options: &Options,
+    shared: &Shared,
+) -> Stats {
+    let mut timings = Stats::default();
+
+    loop {
+        // hold the lock only for the recv so other workers can pick up files while we sign
+        let file = match rx.lock() {
+            Ok(rx) => rx.recv(),
+            Err(_) => break,
+        };
+        let Ok(file) = file else { break };
+        // the rest stay as they are, unsigned
+        if options.cancel.is_cancelled() {
+            break;
+        }
+
+        let picked = pick(&file, options, shared);
+        let (options, shared) = match &picked {
+            Some((options, shared)) => (options, shared),
+            None => (options, shared),
+        };
+
+        // with --sort this is logged once everything is done so the order is stable
+        dprintln!(
+            options.verbose && !options.sort,
+            "signing:\t{}",
+            shown(&options.roots, &file)
+        );
+
+        let began = Instant::now();
+        if let Err(e) = busy::settle(&file, options.busy_wait, options.verbose) {
+            // nothing is listening any more once the run is over
+            let _ = done.send((file, Err(e), began.elapsed(), options.backend.clone()));
+            continue;
+        }
+
+        let dest = install::destination(&file, options);
+        let hash_before = match permit(&file, dest.as_deref(), options, shared) {
+            Ok(hash) => hash,
+            Err(e) => {
+                let _ = done.send((file, Err(e), began.elapsed(), options.backend.clone()));
+                continue;
+            }
+        };
+
+        let meta = std::fs::metadata(&file).ok();
+        let size = meta.as_ref().map_or(0, std::fs::Metadata::len);
+        #[cfg(unix)]
+        let uid = meta.map(|m| std::os::unix::fs::MetadataExt::uid(&m));
+        if let Some(throttle) = &shared.throttle {
+            throttle.take(size);
+        }
+
+        let before = shared
+            .digests
+            .as_ref()
+            .and_then(|_| pcr::image_digest(&file));
+        let mut res = match (&options.backend, &dest) {
+            (Backend::Mock(signer), _) => mock(&file, signer, options),
+            (_, Some(dest)) => install(&file, dest, options, shared, &mut timings),
+            (_, None) => sign_one(&file, options, shared, &mut timings),
+        };
+        if let Ok(signed) = &mut res {
+            let path = signed
+                .staged
+                .as_deref()
+                .or(dest.as_deref())
+                .unwrap_or(&file);
+            if let (true, Ok(meta)) = (signed.changed(options), std::fs::metadata(path)) {
+                signed.growth = Some(Growth {
+                    before: size,
+                    after: meta.len(),
+                });
+            }
+            #[cfg(unix)]
+            if let (true, Some(caller)) = (signed.changed(options), options.caller) {
+                crate::sudo::give_back(path, uid, caller);
+            }
+        }
+        let signed = match &res {
+            Ok(signed) => signed
+                .staged
+                .as_deref()
+                .or(dest.as_deref())
+                .unwrap_or(&file),
+            Err(_) => &file,
+        };
+        if let (Some(digests), Some(before)) = (&shared.digests, before) {
+            if res.as_ref().is_ok_and(|s| s.changed(options)) {
+                if let (Some(after), Ok(mut digests)) = (pcr::image_digest(signed), digests.lock())
+                {
+                    digests.push((before, after));
+                }
+            }
+        }
+
+        if let Some(audit) = &shared.audit {
+            if let Err(e) = record(audit, &file, signed, hash_before, &res, options) {
+                res = Err(e);
+            }
+        }
+
+        if res.as_ref().is_ok_and(|s| s.changed(options)) {
+            remember(&file, dest.is_some(), options, shared, &mut timings);
+        }
+
+        let _ = done.send((file, res, began.elapsed(), options.backend.clone()));
+    }
+
+    timings
+}
+
+/// Check `file` may be signed, by `--allow-hashes` and what was approved, and written signed to
+/// `dest`, the file itself unless it is installed elsewhere, by the usage policy. Return its
+/// hash if it had to be worked out.
+fn permit(
+    file: &Path,
+    dest: Option<&Path>,
+    options: &Options,
+    shared: &Shared,
+) -> io::Result<Option<Hash>> {
+    let hash = match &shared.allowed {
+        Some(allowed) => {
+            let hash = sha256_file(file)?;
+            if allowed.binary_search(&hash).is_err() {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "policy violation, its SHA-256 {} isn't in the allowlist",
+                        crate::state::hex(&hash)
+                    ),
+                ));
+            }
+            Some(hash)
+        }
+        None => shared.audit.as_ref().and_then(|_| sha256_file(file).ok()),
+    };
+    let hash = match (&options.approved, hash) {
+        (Some(approved), hash) => {
+            let hash = hash.map_or_else(|| sha256_file(file), Ok)?;
+            if approved.get(&file.canonicalize()?) != Some(&hash) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "policy violation, it isn't as it was when signing it was approved",
+                ));
+            }
+            Some(hash)
+        }
+        (None, hash) => hash,
+    };
+    if let Some(usage) = &shared.usage {
+        usage.permit(dest.unwrap_or(file))?;
+    }
+    Ok(hash)
+}
+
+/// The options and shared state to sign `file` with when they aren't those of the run: the key
+/// and cert of its directory or architecture, or `--resign` for a `resign` rule.
+fn pick(file: &Path, options: &Options, shared: &Shared) -> Option<(Options, Shared)> {
+    let resign = shared
+        .resign
+        .lock()
+        .is_ok_and(|resign| resign.contains(file));
+    let signer = config::signer(options, file, || pe::machine(file).ok().flatten());
+    if signer.is_none() && !resign {
+        return None;
+    }
+
+    let mut picked = (options.clone(), shared.clone());
+    if let Some(signer) = signer {
+        // the decrypted key is that of --key, the others are handed over as they are
+        picked.0.key.clone_from(&signer.key);
+        picked.0.cert.clone_from(&signer.cert);
+        picked.1.key = None;
+    }
+    picked.0.resign |= resign;
+    Some(picked)
+}
+
+/// Sign `file` the way `options` asks: into a copy, after backing it up or as it is.
+fn sign_one(
+    file: &Path,
+    options: &Options,
+    shared: &Shared,
+    timings: &mut Stats,
+) -> io::Result<Signed> {
+    // a staged file has the flag cleared when it is committed
+    let immutable = immutable::check(file, options.handle_immutable)?;
+    let unlocked = if immutable && !options.transactional {
+        immutable::clear(file)?
+    } else {
+        None
+    };
+
+    let res = if options.transactional {
+        stage(file, options, shared, timings)
+    } else if options.backup {
+        janitor::back_up(file).and_then(|backup| {
+            let res = sign_file(file, options, shared, timings);
+            let signed = res
+                .as_ref()
+                .is_ok_and(|s| s.status.is_some_and(|s| s.success()));
+            janitor::finish(&backup, file, signed);
+            res
+        })
+    } else {
+        sign_file(file, options, shared, timings)
+    };
+
+    if let Some(unlocked) = unlocked {
+        unlocked.restore();
+    }
+    res
+}
+
+fn sign_file(
+    file: &Path,
+    options: &Options,
+    shared: &Shared,
+    timings: &mut Stats,
+) -> io::Result<Signed> {
+    let Inspection {
+        mut problems,
+        sbat,
+        signed,
+    } = inspect(file, options);
+    if options.strict_pe && !problems.is_empty() {
+        return Ok(Signed {
+            status: None,
+            warnings: problems,
+            checksum_fixed: false,
+            sbat,
+            staged: None,
+            growth: None,
+        });
+    }
+
+    // a signer that only warns once it has signed signs a copy, thrown away if the warning
+    // means the file should be left alone
+    let guard = options.backend.warns_signed() && (!options.resign || options.strict_pe);
+    let output = if options.backend.in_place() && !guard {
+        file.to_path_buf()
+    } else {
+        temp_path(file)
+    };
+
+    timings.bytes += std::fs::metadata(file).map_or(0, |m| m.len());
+
+    let (status, stderr) = if options.backend.in_process() {
+        if signed == Some(true) {
+            // as sbsign would have said
+            problems.push(Warning::AlreadySigned);
+        }
+        let start = Instant::now();
+        if options.resign || !problems.contains(&Warning::AlreadySigned) {
+            crate::offline::sign_image(
+                file,
+                &output,
+                &options.cert,
+                options.chain.as_deref(),
+                |data| sign_data(data, options, shared),
+            )?;
+        }
+        timings.sign += start.elapsed();
+        (ExitStatus::default(), Vec::new())
+    } else {
+        run(file, &output, options, shared, timings).inspect_err(|_| {
+            if output != file {
+                let _ = std::fs::remove_file(&output);
+            }
+        })?
+    };
+    if options.backend.to_stdout() && status.success() && !carries_signature(&output)? {
+        let _ = std::fs::remove_file(&output);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "what came back isn't a signed image",
+        ));
+    }
+    let mut warnings = problems;
+    warnings.extend(diagnose::classify(&stderr));
+    warnings.sort();
+    warnings.dedup();
+    let mut signed = Signed {
+        status: Some(status),
+        warnings,
+        checksum_fixed: false,
+        sbat,
+        staged: None,
+        growth: None,
+    };
+
+    if output != file {
+        if signed.changed(options) {
+            keep_xattrs(file, &output);
+            std::fs::rename(&output, file)?;
+        } else {
+            let _ = std::fs::remove_file(&output);
+        }
+    }
+    if options.fix_checksum && signed.changed(options) {
+        signed.checksum_fixed = pe::fix_checksum(file)?;
+    }
+    if !options.compat.is_empty() && signed.changed(options) {
+        if let Some(mut image) = pe::Image::open(file)? {
+            let quirks = crate::compat::check_image(&mut image, &options.compat)?;
+            signed
+                .warnings
+                .extend(quirks.into_iter().map(Warning::Compat));
+        }
+    }
+
+    Ok(signed)
+}
+
+/// Whether the image at `file` has a signature.
+fn carries_signature(file: &Path) -> io::Result<bool> {
+    Ok(match pe::Image::open(file)? {
+        Some(mut image) => signed(&mut image),
+        None => false,
+    })
+}
+
+/// Whether `image` has a signature, one whose certificate table can't be read hasn't.
+fn signed(image: &mut pe::Image) -> bool {
+    image
+        .signatures()
+        .ok()
+        .flatten()
+        .is_some_and(|table| !pe::signed_data(&table).is_empty())
+}
+
+/// Have the backend of `options` sign `data`, for the backends that only sign what a signature
+/// signs.
+fn sign_data(data: &[u8], options: &Options, shared: &Shared) -> io::Result<Vec<u8>> {
+    match &options.backend {
+        Backend::Vault(vault) => {
+            let token = shared
+                .vault_token
+                .as_deref()
+                .ok_or_else(|| io::Error::other("not logged in to Vault"))?;
+            vault.sign(token, data, options.timeout)
+        }
+        Backend::Ssh(ssh) => ssh.sign(data),
+        backend => Err(io::Error::other(format!(
+            "{} signs images, not data",
+            backend.name()
+        ))),
+    }
+}
+
+/// Run the signer of `options` on `file`, returning how it exited and what it said on stderr.
+fn run(
+    file: &Path,
+    output: &Path,
+    options: &Options,
+    shared: &Shared,
+    timings: &mut Stats,
+) -> io::Result<(ExitStatus, Vec<u8>)> {
+    #[cfg(target_os = "linux")]
+    let key_path = if shared.key.is_some() {
+        PathBuf::from(format!("/dev/fd/{}", crate::secret::KEY_FD))
+    } else {
+        options.key.clone()
+    };
+    #[cfg(not(target_os = "linux"))]
+    let key_path = options.key.clone();
+
+    let mut cmd = options.backend.command(file, output, &key_path, options);
+    throttle::apply(&mut cmd, options);
+    #[cfg(target_os = "linux")]
+    let key_pipe = match &shared.key {
+        Some(_) => Some(crate::secret::pass_key(&mut cmd)?),
+        None => None,
+    };
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    if options.sandbox {
+        crate::sandbox::apply(&mut cmd, file, options);
+    }
+
+    let request = options.backend.request(file, output, &key_path, options);
+    if request.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+
+    let start = Instant::now();
+    let stdout = if options.backend.to_stdout() {
+        Stdio::from(std::fs::File::create(output)?)
+    } else {
+        Stdio::null()
+    };
+    let spawned = cmd.stdout(stdout).stderr(Stdio::piped()).spawn();
+    timings.spawn += start.elapsed();
+    let mut child = spawned?;
+
+    // read in the background, a chatty signer could fill the pipe before it exits
+    let stderr = child.stderr.take().map(|mut pipe| {
+        std::thread::spawn(move || {
+            use std::io::Read;
+
+            let mut buf = Vec::new();
+            let _ = pipe.read_to_end(&mut buf);
+            buf
+        })
+    });
+
+    if let (Some(request), Some(mut stdin)) = (request, child.stdin.take()) {
+        use std::io::Write;
+
+        if let Err(e) = stdin.write_all(&request) {
+            drop(stdin);
+            return Err(stopped_reading(child, stderr, &e));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    if let (Some((read, mut write)), Some(key)) = (key_pipe, &shared.key) {
+        use std::io::Write;
+
+        drop(read);
+        // a key is a few kilobytes, well under what a pipe buffers, so this can't block
+        let _ = write.write_all(key.as_bytes());
+    }
+
+    let start = Instant::now();
+    let status = if let Some(timeout) = options.timeout {
+        wait_timeout(child, timeout, file)
+    } else {
+        child.wait()
+    };
+    timings.sign += start.elapsed();
+
+    let status = status?;
+    let stderr = stderr.and_then(|t| t.join().ok()).unwrap_or_default();
+    Ok((status, stderr))
+}
+
+/// What to say of a signer that stopped reading its input before taking all of it, and its
+/// reason for that, once it is stopped and waited on.
+fn stopped_reading(
+    mut child: Child,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+    e: &io::Error,
+) -> io::Error {
+    // one that exited already keeps the status it exited with
+    let _ = child.kill();
+    let status = match child.wait() {
+        Ok(status) => status.to_string(),
+        Err(e) => format!("couldn't wait for it: {e}"),
+    };
+    let stderr = stderr.and_then(|t| t.join().ok()).unwrap_or_default();
+    let stderr = String::from_utf8_lossy(&stderr);
+    let mut why = format!("the signer stopped reading its input ({e}), {status}");
+    if !stderr.trim().is_empty() {
+        why = format!("{why}: {}", stderr.trim());
+    }
+    io::Error::new(e.kind(), why)
+}
+
+/// Write down in the audit log that the key was used on `file`, signed into `signed` or not as
+/// `res` says. Files the signer wasn't run on aren't a use of the key.
+fn record(
+    audit: &AuditLog,
+    file: &Path,
+    signed: &Path,
+    before: Option<Hash>,
+    res: &io::Result<Signed>,
+    options: &Options,
+) -> io::Result<()> {
+    if matches!(options.backend, Backend::Mock(_)) {
+        return Ok(());
+    }
+    let changed = match res {
+        Ok(s) if s.skipped(options) || s.status.is_none() => return Ok(()),
+        Ok(s) => s.changed(options),
+        Err(_) => false,
+    };
+    audit.record(&Operation {
+        path: file,
+        signed: changed,
+        before,
+        after: changed.then(|| sha256_file(signed).ok()).flatten(),
+        cert: &options.cert,
+        backend: options.backend.name(),
+    })
+}
+
+/// Write `file` down as signed in the state and journal, as far as it is there to stay.
+fn remember(file: &Path, installed: bool, options: &Options, shared: &Shared, timings: &mut Stats) {
+    // nothing was signed after all
+    if matches!(options.backend, Backend::Mock(_)) {
+        return;
+    }
+
+    // a staged file is recorded once it is committed, an installed one not at all
+    if let Some(state) = shared
+        .state
+        .as_ref()
+        .filter(|_| !options.transactional && !installed)
+    {
+        let start = Instant::now();
+        if let Ok(hash) = sha256_file(file) {
+            state.record(file, hash);
+        }
+        timings.hash += start.elapsed();
+    }
+
+    // staged files aren't anywhere yet, resuming does them again
+    if let Some(journal) = shared.journal.as_ref().filter(|_| !options.transactional) {
+        journal.done(file);
+    }
+}
+
+/// Write down that `file` would be signed, with what signing it would have warned about. An
+/// image no signer could make sense of fails, as it would with one.
+fn mock(file: &Path, mock: &MockSigner, options: &Options) -> io::Result<Signed> {
+    let Inspection {
+        problems: mut warnings,
+        sbat,
+        signed,
+    } = inspect(file, options);
+    let signed =
+        signed.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed PE image"))?;
+    if signed {
+        warnings.push(Warning::AlreadySigned);
+        warnings.sort();
+    }
+    let signed = Signed {
+        status: Some(ExitStatus::default()),
+        warnings,
+        checksum_fixed: false,
+        sbat,
+        staged: None,
+        growth: None,
+    };
+    if signed.changed(options) {
+        eprintln!("would sign:\t{}", escape::path(file));
+        mock.record(file);
+    }
+    Ok(signed)
+}
+
+/// Give the signed copy `output` of `file` its extended attributes, a file mislabeled for
+/// `SELinux` is signed still but deserves a warning.
+pub(crate) fn keep_xattrs(file: &Path, output: &Path) {
+    if let Err(e) = xattr::copy(file, output) {
+        eprintln!(
+            "warning:\t{} (couldn't keep extended attribute {e})",
+            escape::path(file)
+        );
+    }
+}
+
+/// Sign a copy of `file` next to `dest` and rename it over that, for `--install-to`.
+fn install(
+    file: &Path,
+    dest: &Path,
+    options: &Options,
+    shared: &Shared,
+    timings: &mut Stats,
+) -> io::Result<Signed> {
+    if let Some(dir) = dest.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let copy = temp_path(dest);
+    std::fs::copy(file, &copy)?;
+    // the label is that of where the file goes, not where it came from
+    if dest.exists() {
+        keep_xattrs(dest, &copy);
+    }
+
+    let signed = sign_file(&copy, options, shared, timings).and_then(|signed| {
+        if !signed.changed(options) {
+            return Ok(signed);
+        }
+        let immutable = dest.exists() && immutable::check(dest, options.handle_immutable)?;
+        let unlocked = if immutable {
+            immutable::clear(dest)?
+        } else {
+            None
+        };
+        let renamed = std::fs::rename(&copy, dest);
+        if let Some(unlocked) = unlocked {
+            unlocked.restore();
+        }
+        renamed?;
+        dprintln!(options.verbose, "installed:\t{}", escape::path(dest));
+        Ok(signed)
+    });
+
+    if copy.exists() {
+        let _ = std::fs::remove_file(&copy);
+    }
+    signed
+}
+
+/// Sign a copy of `file` next to it for `--transactional`, to be renamed over it once every
+/// file is signed.
+fn stage(
+    file: &Path,
+    options: &Options,
+    shared: &Shared,
+    timings: &mut Stats,
+) -> io::Result<Signed> {
+    let copy = temp_path(file);
+    std::fs::copy(file, &copy)?;
+    keep_xattrs(file, &copy);
+
+    match sign_file(&copy, options, shared, timings) {
+        Ok(mut signed) if signed.changed(options) => {
+            signed.staged = Some(copy);
+            Ok(signed)
+        }
+        res => {
+            let _ = std::fs::remove_file(&copy);
+            res
+        }
+    }
+}
+
+/// What [`inspect`] found out about an image before signing it.
+#[derive(Default)]
+struct Inspection {
+    problems: Vec<Warning>,
+    sbat: Vec<sbat::Entry>,
+    /// Whether it is signed already, `None` if it isn't a well formed image.
+    signed: Option<bool>,
+}
+
+/// What is wrong with the image at `file` before signing, its SBAT generations and whether it
+/// is signed, from one pass over it.
+fn inspect(file: &Path, options: &Options) -> Inspection {
+    // unreadable files are left to fail in the signer
+    let Ok(Some(mut image)) = pe::Image::open(file) else {
+        return Inspection::default();
+    };
+    let mut problems: Vec<_> = image
+        .problems()
+        .unwrap_or_default()
+        .into_iter()
+        .map(Warning::Image)
+        .collect();
+    if options.fix_checksum {
+        // it will be right by the time anything boots it
+        problems.retain(|w| *w != Warning::Image(Problem::Checksum));
+    }
+    let sbat = image
+        .section(b".sbat")
+        .ok()
+        .flatten()
+        .map(|section| sbat::parse(&section))
+        .unwrap_or_default();
+
+    Inspection {
+        problems,
+        sbat,
+        signed: Some(signed(&mut image)),
+    }
+}
+
+fn wait_timeout(mut child: Child, timeout: Duration, file: &Path) -> Outcome {
+    let start = Instant::now();
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out signing {}", escape::path(&file)),
+            ));
+        }
+
+        sleep(Duration::from_millis(10));
+    }
+}
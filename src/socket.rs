@@ -0,0 +1,44 @@
+//! Unix sockets only their owner may connect to from the moment anyone can find them. A socket
+//! bound where it is meant to be and then chmodded is open to everyone the umask lets in until
+//! the chmod, so it is bound in a directory only the owner may enter, chmodded there and only
+//! then linked into place.
+
+use std::{
+    fs::{DirBuilder, Permissions},
+    io,
+    os::unix::{
+        fs::{DirBuilderExt, PermissionsExt},
+        net::UnixListener,
+    },
+    path::Path,
+};
+
+/// Listen on a new socket at `path` only its owner may connect to. Fails if there is anything
+/// at `path` already.
+pub(crate) fn bind(path: &Path) -> io::Result<UnixListener> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name for a socket"))?;
+    let mut private = name.to_os_string();
+    private.push(format!(".{}.tmp", std::process::id()));
+    let private = dir.join(private);
+    let socket = private.join("socket");
+    // left behind by a process of the same pid that didn't get to clean up
+    let _ = std::fs::remove_file(&socket);
+    let _ = std::fs::remove_dir(&private);
+
+    DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = UnixListener::bind(&socket).and_then(|listener| {
+        std::fs::set_permissions(&socket, Permissions::from_mode(0o600))?;
+        // a link rather than a rename, which would replace whatever is at path
+        std::fs::hard_link(&socket, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&socket);
+    let _ = std::fs::remove_dir(&private);
+    bound
+}
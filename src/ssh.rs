@@ -0,0 +1,95 @@
+//! `--backend ssh`, signing on another machine over SSH, with the keys and trust SSH already
+//! has rather than a signing service of its own:
+//!
+//! ```text
+//! sbsevery /efi --backend ssh --remote signer@keybox
+//! ```
+//!
+//! Each image goes to the remote on stdin and comes back signed on stdout, from `sbsevery
+//! sign-stream` run there, see [`crate::stream`]. The remote's `authorized_keys` gives it the
+//! key and cert, and pins what the SSH key may run whatever is asked:
+//!
+//! ```text
+//! command="sbsevery sign-stream -k /etc/efi-keys/DB.key -c /etc/efi-keys/DB.crt",restrict ssh-ed25519 AAAA… build@host
+//! ```
+//!
+//! or `--remote-command` runs something else that does the same. A remote with `allow` in its
+//! `[usage]` table needs `stream = true` there as well, see [`crate::stream`].
+//!
+//! With `--remote-digest` only what the signature signs goes over, and `-c` is the cert of the
+//! remote key. sbsevery builds the signature as [`crate::offline`] does and the remote command
+//! signs stdin with SHA-256 onto stdout, as `openssl dgst -sha256 -sign /etc/efi-keys/DB.key`
+//! does; a signature that doesn't verify against the cert never goes into an image.
+//!
+//! ssh runs with `BatchMode`, never asking for a password, a key or an agent has to do.
+
+use std::{
+    io,
+    process::{Command, Stdio},
+};
+
+use crate::Options;
+
+/// A machine to sign on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ssh {
+    /// Where to sign, `user@host` or a `Host` of `~/.ssh/config`.
+    pub remote: String,
+    /// What to run there, `sbsevery sign-stream` unless set.
+    pub command: Option<String>,
+    /// Send only what the signature signs rather than the image.
+    pub digest: bool,
+}
+
+impl Ssh {
+    /// ssh running `command` on the remote.
+    fn ssh(&self, command: &str) -> Command {
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-T", "-o", "BatchMode=yes", "--"])
+            .arg(&self.remote)
+            .arg(command);
+        cmd
+    }
+
+    /// The command signing an image piped to it, for [`crate::Backend::command`].
+    pub(crate) fn command(&self, options: &Options) -> Command {
+        let command = match &self.command {
+            Some(command) => command.clone(),
+            None if options.resign => "sbsevery sign-stream --resign".to_string(),
+            None => "sbsevery sign-stream".to_string(),
+        };
+        self.ssh(&command)
+    }
+
+    /// Have the remote sign `data`, returning the signature.
+    pub(crate) fn sign(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let command = self.command.as_deref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--remote-digest needs --remote-command, what signs there",
+            )
+        })?;
+        let mut child = self
+            .ssh(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("can't run ssh: {e}")))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            io::Write::write_all(&mut stdin, data)?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() || output.stdout.is_empty() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(format!(
+                "{} couldn't sign: ssh {}{}{}",
+                self.remote,
+                output.status,
+                if stderr.trim().is_empty() { "" } else { ", " },
+                stderr.trim()
+            )));
+        }
+        Ok(output.stdout)
+    }
+}
@@ -0,0 +1,231 @@
+//! `--state`, remember what every file hashed to after it was signed so unchanged files are
+//! skipped on the next run.
+//!
+//! Files are known by the UUID of their filesystem and their path within it where both can be
+//! had, so an ESP mounted at `/efi` by the kernel hook and at `/boot/efi` for a run by hand
+//! shares one state file. Files on filesystems without a UUID,
+//! and entries written before, are known by their path.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{spawn, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    cancel::Cancel,
+    filter::{Filter, Skip},
+};
+
+/// Read this much of a file at a time when hashing, UKIs with an initrd inside can be hundreds
+/// of megabytes.
+const CHUNK: usize = 1 << 20;
+
+pub(crate) type Hash = [u8; 32];
+
+/// SHA-256 of a file, read in chunks rather than all at once.
+///
+/// # Errors
+///
+/// Fails if the file can't be read.
+pub fn sha256_file(path: &Path) -> io::Result<Hash> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK];
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher.finalize().into())
+}
+
+/// Lowercase hex, as `sha256sum` prints it.
+#[must_use]
+pub fn hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+pub(crate) fn unhex(s: &str) -> Option<Hash> {
+    let mut hash = [0; 32];
+    if s.len() != 64 {
+        return None;
+    }
+
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(hash)
+}
+
+/// Hashes of signed files, as loaded from and saved to the state file.
+#[derive(Debug)]
+pub(crate) struct State {
+    path: PathBuf,
+    hashes: Mutex<HashMap<PathBuf, Hash>>,
+    #[cfg(target_os = "linux")]
+    volumes: crate::volume::Volumes,
+}
+
+impl State {
+    /// Load the state file, a missing file is an empty state.
+    ///
+    /// The format is the same as `sha256sum` output, one `<hash>  <path>` line per file, the
+    /// path being `UUID=<uuid>/<path>` for one known by its filesystem.
+    pub(crate) fn load(path: &Path) -> io::Result<Self> {
+        let mut hashes = HashMap::new();
+
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if let Some((hash, file)) = line.split_once("  ") {
+                        if let Some(hash) = unhex(hash) {
+                            hashes.insert(PathBuf::from(file), hash);
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            hashes: Mutex::new(hashes),
+            #[cfg(target_os = "linux")]
+            volumes: crate::volume::Volumes::load(),
+        })
+    }
+
+    /// What `file` is known by, its path where its filesystem isn't.
+    fn key(&self, file: &Path) -> PathBuf {
+        #[cfg(target_os = "linux")]
+        if let Some(key) = self.volumes.locate(file) {
+            return key;
+        }
+        file.to_path_buf()
+    }
+
+    pub(crate) fn unchanged(&self, file: &Path, hash: &Hash) -> bool {
+        let key = self.key(file);
+        self.hashes
+            .lock()
+            .is_ok_and(|hashes| hashes.get(&key).or_else(|| hashes.get(file)) == Some(hash))
+    }
+
+    pub(crate) fn record(&self, file: &Path, hash: Hash) {
+        let key = self.key(file);
+        if let Ok(mut hashes) = self.hashes.lock() {
+            // an entry from before files were known by their filesystem
+            if key != file {
+                hashes.remove(file);
+            }
+            hashes.insert(key, hash);
+        }
+    }
+
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let hashes = self
+            .hashes
+            .lock()
+            .map_err(|_| io::Error::other("state poisoned"))?;
+
+        let mut entries: Vec<_> = hashes.iter().collect();
+        entries.sort();
+
+        let tmp = self.path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        for (file, hash) in entries {
+            // the format is line based, a file with a newline in its name is simply signed
+            // again next time
+            let Some(name) = file.to_str().filter(|name| !name.contains('\n')) else {
+                continue;
+            };
+            writeln!(out, "{}  {name}", hex(hash))?;
+        }
+        out.into_inner()?.sync_all()?;
+
+        std::fs::rename(tmp, &self.path)
+    }
+}
+
+/// Hash files coming from the searcher on `jobs` threads of their own, passing on only those
+/// that changed since they were last signed. Returns how long was spent hashing and how many
+/// files were skipped. Once `cancel` is cancelled the rest are let go unhashed.
+pub(crate) fn hasher(
+    rx: Receiver<PathBuf>,
+    sx: &Sender<PathBuf>,
+    state: &Arc<State>,
+    filter: &Arc<Filter>,
+    jobs: usize,
+    verbose: bool,
+    cancel: &Cancel,
+) -> JoinHandle<(Duration, usize)> {
+    let rx = Arc::new(Mutex::new(rx));
+    let threads: Vec<_> = (0..jobs.max(1))
+        .map(|_| {
+            let rx = Arc::clone(&rx);
+            let sx = sx.clone();
+            let state = Arc::clone(state);
+            let filter = Arc::clone(filter);
+            let cancel = cancel.clone();
+            spawn(move || {
+                let mut spent = Duration::ZERO;
+                let mut skipped = 0;
+
+                loop {
+                    let file = match rx.lock() {
+                        Ok(rx) => rx.recv(),
+                        Err(_) => break,
+                    };
+                    let Ok(file) = file else { break };
+                    if cancel.is_cancelled() {
+                        break;
+                    }
+
+                    let start = Instant::now();
+                    let hash = sha256_file(&file);
+                    spent += start.elapsed();
+
+                    if hash.is_ok_and(|hash| state.unchanged(&file, &hash)) {
+                        filter.skip(&file, Skip::Unchanged, verbose);
+                        skipped += 1;
+                    } else if sx.send(file).is_err() {
+                        break;
+                    }
+                }
+
+                (spent, skipped)
+            })
+        })
+        .collect();
+
+    spawn(move || {
+        threads
+            .into_iter()
+            .filter_map(|t| t.join().ok())
+            .fold((Duration::ZERO, 0), |(d, n), (spent, skipped)| {
+                (d + spent, n + skipped)
+            })
+    })
+}
@@ -0,0 +1,110 @@
+use std::{fmt, time::Duration};
+
+/// Where the time of a run went.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Total size of the files handed to the signer, before signing.
+    pub bytes: u64,
+    /// Wall clock time of the whole run.
+    pub wall: Duration,
+    /// Wall clock time until the search finished.
+    pub discovery: Duration,
+    /// Time spent hashing files for `--state`, summed over every thread.
+    pub hash: Duration,
+    /// Time spent starting signer processes, summed over every worker.
+    pub spawn: Duration,
+    /// Time spent waiting on signer processes, summed over every worker.
+    pub sign: Duration,
+    /// User and system CPU time used by every signer process.
+    pub child_cpu: Duration,
+}
+
+impl Stats {
+    pub(crate) fn add(&mut self, other: &Stats) {
+        self.bytes += other.bytes;
+        self.hash += other.hash;
+        self.spawn += other.spawn;
+        self.sign += other.sign;
+    }
+}
+
+/// How much signing grew a file, the signature being appended to it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Growth {
+    /// Size before signing.
+    pub before: u64,
+    /// Size after.
+    pub after: u64,
+}
+
+impl Growth {
+    /// Bytes the file grew by, negative if it shrank, dropping an old signature say.
+    #[must_use]
+    pub fn delta(&self) -> i64 {
+        i64::try_from(self.after).unwrap_or(i64::MAX)
+            - i64::try_from(self.before).unwrap_or(i64::MAX)
+    }
+}
+
+impl fmt::Display for Growth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} bytes, {:+}",
+            self.before,
+            self.after,
+            self.delta()
+        )
+    }
+}
+
+/// Printed by `--stats`, the file count lives in the report so it is passed alongside.
+pub struct Display<'a>(pub &'a Stats, pub usize);
+
+impl fmt::Display for Display<'_> {
+    #[allow(clippy::cast_precision_loss)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Display(stats, files) = self;
+        let secs = stats.wall.as_secs_f64().max(f64::EPSILON);
+
+        writeln!(f, "files:\t\t{files}")?;
+        writeln!(f, "bytes:\t\t{}", stats.bytes)?;
+        writeln!(f, "wall time:\t{:.3}s", stats.wall.as_secs_f64())?;
+        writeln!(f, "child cpu:\t{:.3}s", stats.child_cpu.as_secs_f64())?;
+        writeln!(f, "discovery:\t{:.3}s", stats.discovery.as_secs_f64())?;
+        writeln!(f, "hash:\t\t{:.3}s", stats.hash.as_secs_f64())?;
+        writeln!(f, "spawn:\t\t{:.3}s", stats.spawn.as_secs_f64())?;
+        writeln!(f, "sign:\t\t{:.3}s", stats.sign.as_secs_f64())?;
+        write!(
+            f,
+            "throughput:\t{:.1} files/s, {:.1} MiB/s",
+            *files as f64 / secs,
+            stats.bytes as f64 / secs / 1024.0 / 1024.0
+        )
+    }
+}
+
+/// CPU time of every child that has been waited on so far.
+#[cfg(unix)]
+pub(crate) fn child_cpu_time() -> Duration {
+    fn to_duration(t: libc::timeval) -> Duration {
+        Duration::from_secs(u64::try_from(t.tv_sec).unwrap_or_default())
+            + Duration::from_micros(u64::try_from(t.tv_usec).unwrap_or_default())
+    }
+
+    // SAFETY: getrusage only writes into the struct we hand it
+    let usage = unsafe {
+        let mut usage = std::mem::zeroed::<libc::rusage>();
+        if libc::getrusage(libc::RUSAGE_CHILDREN, std::ptr::addr_of_mut!(usage)) != 0 {
+            return Duration::ZERO;
+        }
+        usage
+    };
+
+    to_duration(usage.ru_utime) + to_duration(usage.ru_stime)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn child_cpu_time() -> Duration {
+    Duration::ZERO
+}
@@ -0,0 +1,96 @@
+//! `sbsevery sign-stream`, sign an image read from stdin and write it to stdout, for build
+//! pipelines that have it in a pipe rather than a file.
+//!
+//! Every signer wants a file, so the image goes into a private directory for as long as it
+//! takes to sign it, on tmpfs where there is one so it never reaches a disk.
+//!
+//! That directory is never under the `allow` of a `[usage]` table, and the image ends up on
+//! stdout, nowhere it could name. With `allow` set, images are only signed if `stream = true`
+//! says so too, and `allow` is then left out of the run; `max_per_run` and `max_per_day` count
+//! each image as usual, see [`crate::usage`].
+
+use std::{
+    fs,
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use crate::{escape, usage, Options};
+
+/// Read an image from `input`, sign it as `options` would a file found under a root, and write
+/// the result to `output`. Filters and validation apply as usual, an image they turn down is
+/// an error rather than passed through unsigned.
+///
+/// # Errors
+///
+/// Fails if the image can't be read, signed or written.
+pub fn sign_stream(
+    options: &Options,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !options.usage.allow.is_empty() && !options.usage.stream {
+        return Err("the usage policy doesn't allow sign-stream, see stream in [usage]".into());
+    }
+    let dir = private_dir()?;
+    let file = dir.join("stream.efi");
+
+    let res = (|| {
+        let mut image = Vec::new();
+        input.read_to_end(&mut image)?;
+        fs::write(&file, image)?;
+        sign(&file, options)?;
+        output.write_all(&fs::read(&file)?)?;
+        output.flush()?;
+        Ok(())
+    })();
+
+    fs::remove_dir_all(&dir)?;
+    res
+}
+
+fn sign(file: &std::path::Path, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    // the file is the whole run, there is nothing to walk, remember or predict
+    let options = Options {
+        roots: vec![file.to_path_buf()],
+        jobs: 1,
+        state: None,
+        event_log: None,
+        warn_bitlocker: false,
+        allow_empty: true,
+        // checked by sign_stream, the file is never anywhere it names
+        usage: usage::Policy {
+            allow: Vec::new(),
+            ..options.usage.clone()
+        },
+        ..options.clone()
+    };
+
+    let report = crate::run(&options)?;
+    if let Some((_, failure)) = report.failed.first() {
+        return Err(format!("couldn't sign the image: {failure}").into());
+    }
+    if let Some((_, reason)) = report.skipped.first() {
+        return Err(format!("refusing to sign the image ({reason})").into());
+    }
+    if let Some((path, e)) = report.errors.first() {
+        return Err(format!("{}: {e}", escape::path(path)).into());
+    }
+    Ok(())
+}
+
+/// A new directory only the current user can get into, on tmpfs if possible.
+fn private_dir() -> std::io::Result<PathBuf> {
+    let base = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_dir())
+        .or_else(|| Some(PathBuf::from("/dev/shm")).filter(|dir| dir.is_dir()))
+        .unwrap_or_else(std::env::temp_dir);
+    let dir = base.join(format!("sbsevery-stream-{}", std::process::id()));
+
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir)?;
+    Ok(dir)
+}
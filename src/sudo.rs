@@ -0,0 +1,46 @@
+//! `--preserve-caller-ownership`, for runs under sudo signing a staging tree in someone's home
+//! directory that an image builder running as them picks up afterwards:
+//!
+//! ```text
+//! sudo sbsevery ~/build/esp -k /etc/efi-keys/DB.key -c /etc/efi-keys/DB.crt --preserve-caller-ownership
+//! ```
+//!
+//! A signer writes the signed image to a new file, which then belongs to root. With this it is
+//! handed back to `SUDO_UID` and `SUDO_GID`, if the file it was signed from belonged to
+//! `SUDO_UID`; files of anyone else, root's on the ESP say, stay root's. Unix only.
+
+use std::path::Path;
+
+use crate::escape;
+
+/// The uid and gid of whoever ran sudo, from `SUDO_UID` and `SUDO_GID`.
+///
+/// # Errors
+///
+/// Fails if either isn't set or isn't a number, not running under sudo.
+pub fn caller() -> Result<(u32, u32), String> {
+    let id = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| {
+                format!("--preserve-caller-ownership is for running under sudo, {name} isn't set")
+            })
+    };
+    Ok((id("SUDO_UID")?, id("SUDO_GID")?))
+}
+
+/// Give the signed `path` to `owner`, if `uid`, who the file it was signed from belonged to,
+/// is its uid. A file that can't be given back is warned about.
+pub(crate) fn give_back(path: &Path, uid: Option<u32>, owner: (u32, u32)) {
+    if uid != Some(owner.0) {
+        return;
+    }
+    if let Err(e) = std::os::unix::fs::chown(path, Some(owner.0), Some(owner.1)) {
+        eprintln!(
+            "warning:\t{} (couldn't give it back to uid {}: {e})",
+            escape::path(path),
+            owner.0
+        );
+    }
+}
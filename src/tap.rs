@@ -0,0 +1,120 @@
+//! `--output-format tap`, the outcome of a run or of `sbsevery verify` as a
+//! [TAP](https://testanything.org) stream on stdout, a test point for each file, for `prove`
+//! and the CI harnesses that read it.
+//!
+//! ```text
+//! TAP version 13
+//! 1..3
+//! ok 1 - /efi/EFI/BOOT/BOOTX64.EFI
+//! not ok 2 - /efi/EFI/BOOT/fbx64.efi
+//! # exit status 1
+//! ok 3 - /efi/EFI/BOOT/notes.txt # SKIP not-pe
+//! ```
+//!
+//! Files left alone are skipped test points, files that couldn't be signed or aren't signed as
+//! the policy says fail, and so do paths that couldn't be searched.
+
+use std::fmt::Write;
+
+use crate::{
+    escape,
+    report::Action,
+    verify::{Compliance, Verdict},
+    Report,
+};
+
+/// A test point for each file of `report`, then one for each path that couldn't be searched.
+#[must_use]
+pub fn run(report: &Report) -> String {
+    let mut tap = Tap::default();
+    for entry in &report.entries {
+        let path = escape::path(&entry.path).to_string();
+        match entry.action {
+            Action::Signed => tap.ok(&path),
+            Action::Failed => {
+                let why = entry.error.as_ref().map(ToString::to_string);
+                tap.not_ok(&path, why.as_deref().unwrap_or("failed"));
+            }
+            Action::Skipped(reason) => tap.skip(&path, &reason.to_string()),
+        }
+    }
+    tap.errors(&report.errors);
+    tap.finish()
+}
+
+/// A test point for each file `sbsevery verify` checked, then the ones it skipped, then one for
+/// each path that couldn't be searched.
+#[must_use]
+pub fn verify(compliance: &Compliance) -> String {
+    let mut tap = Tap::default();
+    for (path, verdict) in &compliance.checked {
+        let path = escape::path(path).to_string();
+        match verdict {
+            Verdict::Compliant => tap.ok(&path),
+            Verdict::Unmatched => tap.skip(&path, "not covered by the policy"),
+            Verdict::Violation(missing) => {
+                let missing: Vec<_> = missing
+                    .iter()
+                    .map(|cert| escape::path(cert).to_string())
+                    .collect();
+                tap.not_ok(&path, &format!("not signed by {}", missing.join(", ")));
+            }
+            Verdict::Error(e) => tap.not_ok(&path, e),
+        }
+    }
+    for (path, reason) in &compliance.skipped {
+        tap.skip(&escape::path(path).to_string(), &reason.to_string());
+    }
+    tap.errors(&compliance.errors);
+    tap.finish()
+}
+
+/// The test points written so far.
+#[derive(Default)]
+struct Tap {
+    points: String,
+    count: usize,
+}
+
+impl Tap {
+    fn ok(&mut self, path: &str) {
+        self.count += 1;
+        let _ = writeln!(self.points, "ok {} - {}", self.count, description(path));
+    }
+
+    fn not_ok(&mut self, path: &str, why: &str) {
+        self.count += 1;
+        let _ = writeln!(self.points, "not ok {} - {}", self.count, description(path));
+        for line in why.lines() {
+            let _ = writeln!(self.points, "# {line}");
+        }
+    }
+
+    fn skip(&mut self, path: &str, reason: &str) {
+        self.count += 1;
+        let _ = writeln!(
+            self.points,
+            "ok {} - {} # SKIP {reason}",
+            self.count,
+            description(path)
+        );
+    }
+
+    fn errors(&mut self, errors: &[(std::path::PathBuf, String)]) {
+        for (path, e) in errors {
+            self.not_ok(
+                &escape::path(path).to_string(),
+                &format!("couldn't search: {e}"),
+            );
+        }
+    }
+
+    fn finish(self) -> String {
+        format!("TAP version 13\n1..{}\n{}", self.count, self.points)
+    }
+}
+
+/// `path` as the description of a test point, where a `#` would start a directive.
+fn description(path: &str) -> String {
+    path.replace('\\', "\\\\").replace('#', "\\#")
+}
@@ -0,0 +1,145 @@
+//! `--nice`, `--ionice` and `--io-limit`, keep a big batch signed in the background from
+//! getting in the way of whoever is using the machine, on a slow eMMC ESP most of all.
+//!
+//! The first two are applied to every signer process, the last one spreads the files handed to
+//! signers out so that no more than so many bytes a second of them are signed.
+
+use std::{
+    fmt,
+    process::Command,
+    str::FromStr,
+    sync::Mutex,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::Options;
+
+/// An I/O scheduling class and priority, as `ionice` takes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    /// Served before anything else, priority 0 to 7, 0 first.
+    Realtime(u8),
+    /// The default, priority 0 to 7, 0 first.
+    BestEffort(u8),
+    /// Only served when nothing else wants the disk.
+    Idle,
+}
+
+impl IoClass {
+    /// The value `ioprio_set` takes.
+    fn value(self) -> i32 {
+        match self {
+            IoClass::Realtime(level) => 1 << 13 | i32::from(level),
+            IoClass::BestEffort(level) => 2 << 13 | i32::from(level),
+            IoClass::Idle => 3 << 13,
+        }
+    }
+}
+
+impl fmt::Display for IoClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoClass::Realtime(level) => write!(f, "realtime:{level}"),
+            IoClass::BestEffort(level) => write!(f, "best-effort:{level}"),
+            IoClass::Idle => f.write_str("idle"),
+        }
+    }
+}
+
+impl FromStr for IoClass {
+    type Err = String;
+
+    /// `idle`, or `best-effort` or `realtime` with an optional `:LEVEL`, 4 if left out. The
+    /// numbers `ionice -c` takes, 1 to 3, name the classes too.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (class, level) = match s.split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (s, None),
+        };
+        let level = match level.map(str::parse::<u8>) {
+            None => 4,
+            Some(Ok(level)) if level <= 7 => level,
+            Some(_) => return Err(format!("invalid I/O priority: '{s}', expected 0 to 7")),
+        };
+
+        match class {
+            "realtime" | "1" => Ok(IoClass::Realtime(level)),
+            "best-effort" | "2" => Ok(IoClass::BestEffort(level)),
+            "idle" | "3" if level == 4 => Ok(IoClass::Idle),
+            _ => Err(format!(
+                "invalid I/O class: '{s}', expected idle, best-effort[:LEVEL] or realtime[:LEVEL]"
+            )),
+        }
+    }
+}
+
+/// Run `cmd` at the CPU and I/O priority `options` asks for.
+#[cfg(unix)]
+pub(crate) fn apply(cmd: &mut Command, options: &Options) {
+    use std::os::unix::process::CommandExt;
+
+    let (nice, ionice) = (options.nice, options.ionice);
+    if nice.is_none() && ionice.is_none() {
+        return;
+    }
+
+    // SAFETY: setpriority and ioprio_set are plain syscalls, fine between fork and exec
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(class) = ionice {
+                // IOPRIO_WHO_PROCESS, this process
+                if libc::syscall(libc::SYS_ioprio_set, 1, 0, class.value()) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn apply(_: &mut Command, _: &Options) {}
+
+/// Spreads work out so that no more than `rate` bytes a second of it get done, shared by the
+/// workers.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    rate: u64,
+    /// When the first byte was let through, and how many have been since.
+    taken: Mutex<(Instant, u64)>,
+}
+
+impl Throttle {
+    pub(crate) fn new(rate: u64) -> Self {
+        Self {
+            rate: rate.max(1),
+            taken: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Block until `bytes` more fit in the rate.
+    pub(crate) fn take(&self, bytes: u64) {
+        let due = {
+            let Ok(mut taken) = self.taken.lock() else {
+                return;
+            };
+            let (start, before) = *taken;
+            taken.1 += bytes;
+            // the bytes before these are due by now, these after them
+            let micros = u128::from(before) * 1_000_000 / u128::from(self.rate);
+            start + Duration::from_micros(u64::try_from(micros).unwrap_or(u64::MAX))
+        };
+
+        let now = Instant::now();
+        if due > now {
+            sleep(due - now);
+        }
+    }
+}
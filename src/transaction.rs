@@ -0,0 +1,154 @@
+//! `--transactional`, sign every file into a copy next to it and only rename the copies over
+//! the files once all of them are signed, so a failure can't leave a boot chain half signed.
+//!
+//! With several roots, the ESPs on both disks of a mirrored boot say, this is a two-phase
+//! commit across all of them. Every copy is first synced to disk on every root, and if one
+//! can't be nothing is renamed anywhere. Only then are the copies renamed root by root. A
+//! rename can still fail halfway, a disk going away say, and [`Outcome`] tells for each root
+//! how far it got.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    fs::File,
+    path::PathBuf,
+};
+
+use crate::{escape, immutable, state::sha256_file, Options, Report, State};
+
+/// A signed copy and the file it is to be renamed over.
+pub(crate) type Staged = (PathBuf, PathBuf);
+
+/// How a transactional run ended on one root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// This many files were renamed into place.
+    Committed(usize),
+    /// This many signed copies were thrown away and none of the files changed.
+    RolledBack(usize),
+    /// This many files were renamed into place before renaming the next failed, and the rest
+    /// were thrown away.
+    Failed(usize, String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Committed(n) => write!(f, "committed {n} files"),
+            Outcome::RolledBack(n) => write!(f, "rolled back {n} files"),
+            Outcome::Failed(n, e) => write!(f, "failed after committing {n} files: {e}"),
+        }
+    }
+}
+
+/// Rename every staged copy over its file if no file failed and they can all be synced, throw
+/// them all away otherwise. How it went on each root goes in `report`, the files renamed into
+/// place are returned.
+pub(crate) fn finish(
+    report: &mut Report,
+    staged: Vec<Staged>,
+    options: &Options,
+    state: Option<&State>,
+) -> HashSet<PathBuf> {
+    let roots = &options.roots;
+    let verbose = options.verbose;
+    let mut by_root: BTreeMap<usize, Vec<Staged>> = (0..roots.len()).map(|i| (i, vec![])).collect();
+    for (copy, file) in staged {
+        // the innermost root the file was found under, roots can nest
+        let root = roots
+            .iter()
+            .enumerate()
+            .filter(|(_, root)| file.starts_with(root))
+            .max_by_key(|(_, root)| root.components().count())
+            .map_or(0, |(i, _)| i);
+        by_root.entry(root).or_default().push((copy, file));
+    }
+
+    let commit = report.failures == 0 && !report.cancelled && prepare(by_root.values().flatten());
+    let mut outcomes = Vec::new();
+    let mut failed = false;
+    let mut committed = HashSet::new();
+
+    for (i, staged) in by_root {
+        let root = roots.get(i).cloned().unwrap_or_default();
+        let outcome = if commit && !failed {
+            rename(staged, state, options, &mut committed)
+        } else {
+            Outcome::RolledBack(throw_away(staged))
+        };
+        failed |= matches!(outcome, Outcome::Failed(..));
+        dprintln!(verbose, "transaction:\t{} ({outcome})", escape::path(&root));
+        outcomes.push((root, outcome));
+    }
+
+    report.rolled_back = outcomes
+        .iter()
+        .all(|(_, outcome)| matches!(outcome, Outcome::RolledBack(_)));
+    report.transaction = outcomes;
+    committed
+}
+
+/// The first phase, get every copy onto its disk. Whether all of them made it.
+fn prepare<'a>(staged: impl Iterator<Item = &'a Staged>) -> bool {
+    for (copy, file) in staged {
+        if let Err(e) = File::open(copy).and_then(|f| f.sync_all()) {
+            eprintln!(
+                "couldn't sync the signed copy of {}, rolling back: {e}",
+                escape::path(file)
+            );
+            return false;
+        }
+    }
+    true
+}
+
+/// The second phase on one root, rename its copies over their files.
+fn rename(
+    staged: Vec<Staged>,
+    state: Option<&State>,
+    options: &Options,
+    committed: &mut HashSet<PathBuf>,
+) -> Outcome {
+    let mut staged = staged.into_iter();
+    let mut done = 0;
+
+    while let Some((copy, file)) = staged.next() {
+        let unlocked = if options.handle_immutable {
+            immutable::clear(&file)
+        } else {
+            Ok(None)
+        };
+        let renamed = unlocked.and_then(|unlocked| {
+            let renamed = std::fs::rename(&copy, &file);
+            if let Some(unlocked) = unlocked {
+                unlocked.restore();
+            }
+            renamed
+        });
+        if let Err(e) = renamed {
+            let _ = std::fs::remove_file(&copy);
+            throw_away(staged.collect());
+            return Outcome::Failed(done, format!("{}: {e}", escape::path(&file)));
+        }
+        dprintln!(options.verbose, "committed:\t{}", escape::path(&file));
+        if let (Some(state), Ok(hash)) = (state, sha256_file(&file)) {
+            state.record(&file, hash);
+        }
+        // and the rename onto the disk too, FAT has no journal to replay it from
+        if let Some(dir) = file.parent().and_then(|dir| File::open(dir).ok()) {
+            let _ = dir.sync_all();
+        }
+        done += 1;
+        committed.insert(file);
+    }
+
+    Outcome::Committed(done)
+}
+
+fn throw_away(staged: Vec<Staged>) -> usize {
+    let n = staged.len();
+    for (copy, _) in staged {
+        let _ = std::fs::remove_file(copy);
+    }
+    n
+}
@@ -0,0 +1,126 @@
+//! `--walk-as`, do the directory walk as an unprivileged user.
+
+use std::{
+    ffi::OsStr,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{
+        mpsc::{channel, Sender},
+        Arc,
+    },
+    thread::spawn,
+};
+
+use crate::{filter::Filter, search, Options};
+
+/// Walk every root and write what was found to stdout as NUL terminated records: `f` and a path
+/// for every file, then `e` and a path followed by the error for everything that couldn't be
+/// searched. This is the far end of [`searcher`], run as `sbsevery __walk`.
+///
+/// # Errors
+///
+/// Fails if stdout can't be written to.
+pub fn print(
+    directories: Vec<PathBuf>,
+    walkers: usize,
+    recursive: bool,
+    verbose: bool,
+) -> io::Result<()> {
+    let (sx, rx) = channel();
+    // filtering is left to the privileged end, it has the options, and so is reporting errors
+    let options = Options {
+        roots: directories,
+        jobs: walkers,
+        recursive,
+        all_files: true,
+        ..Options::default()
+    };
+    let filter = Arc::new(Filter::quiet());
+    let errors = Arc::clone(&filter);
+    let searcher = spawn(move || search::searcher(&sx, &options, &filter, verbose));
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for file in rx {
+        out.write_all(b"f")?;
+        out.write_all(file.as_os_str().as_bytes())?;
+        out.write_all(b"\0")?;
+    }
+
+    if searcher.join().is_err() {
+        eprintln!("Thread join failed");
+    }
+    for (path, e) in errors.errors() {
+        out.write_all(b"e")?;
+        out.write_all(path.as_os_str().as_bytes())?;
+        out.write_all(b"\0")?;
+        out.write_all(e.as_bytes())?;
+        out.write_all(b"\0")?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Like the normal search, but the walk happens in a copy of the running executable (which has
+/// to be sbsevery) started as `uid`/`gid`, so a bug tripped over by hostile directory contents
+/// runs without root. Only the signing itself is left to the privileged process.
+pub(crate) fn searcher(
+    sx: &Sender<PathBuf>,
+    options: &Options,
+    filter: &Filter,
+    verbose: bool,
+    (uid, gid): (u32, u32),
+) {
+    let run = || -> io::Result<()> {
+        let mut cmd = Command::new(std::env::current_exe()?);
+        cmd.arg("__walk").arg("-j").arg(options.jobs.to_string());
+        if verbose {
+            cmd.arg("-v");
+        }
+        if !options.recursive {
+            cmd.arg("--no-recursive");
+        }
+        let mut child = cmd
+            .args(&options.roots)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .uid(uid)
+            .gid(gid)
+            .spawn()?;
+
+        if let Some(stdout) = child.stdout.take() {
+            let mut records = BufReader::new(stdout).split(b'\0');
+            while let Some(record) = records.next() {
+                let record = record?;
+                let path = PathBuf::from(OsStr::from_bytes(record.get(1..).unwrap_or_default()));
+
+                if record.first() == Some(&b'e') {
+                    let e = records.next().transpose()?.unwrap_or_default();
+                    let e = io::Error::other(String::from_utf8_lossy(&e).into_owned());
+                    filter.error(&path, &e);
+                    continue;
+                }
+                // the child is read to the end either way so it isn't left blocked writing
+                if options.cancel.is_cancelled() || !filter.pass(&path, options, verbose) {
+                    continue;
+                }
+                if sx.send(path).is_err() {
+                    break;
+                }
+            }
+        }
+
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("unprivileged walker {status}")))
+        }
+    };
+
+    if let Err(e) = run() {
+        eprintln!("error:\t{e}");
+    }
+}
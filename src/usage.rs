@@ -0,0 +1,240 @@
+//! The `[usage]` table of the configuration, limits on what the key is ever used for, whatever a
+//! run is told on the command line. A hook tricked into signing files it shouldn't can then
+//! only sign so many, and only where the key is meant to be used:
+//!
+//! ```toml
+//! [usage]
+//! # files anywhere else are never signed
+//! allow = ["/efi/EFI/Linux", "/efi/EFI/BOOT", "/efi/EFI/systemd"]
+//! max_per_run = 20
+//! max_per_day = 100
+//! ```
+//!
+//! `sbsevery sign-stream` writes what it signs nowhere `allow` could name, so where `allow` is
+//! set it only signs with `stream = true` as well, see [`crate::stream`]. The limits hold for it
+//! all the same.
+//!
+//! Files are compared by where the signed file is written, `..` and symlinks resolved: the file
+//! itself, or where `--install-to` puts it, the file it was signed from is only read. The count
+//! for the day, a UTC one, is kept in `counts`, `/var/lib/sbsevery/usage` unless it names
+//! another file, shared by every run. Each time the signer is run on a file counts, whether it
+//! signs it or not.
+//!
+//! The usage limits of [`crate::config::SYSTEM`] hold even for runs given another file with
+//! `--config`, the two together, see [`Policy::and`], and the day is then counted where the
+//! system file says whatever the other does.
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+
+use crate::{audit::Lock, escape};
+
+/// Where the count for the day is kept by default.
+pub const COUNTS: &str = "/var/lib/sbsevery/usage";
+
+/// What the key may be used for.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Directories the files signed must be under, anywhere if empty.
+    #[serde(default)]
+    pub allow: Vec<PathBuf>,
+    /// Whether `sbsevery sign-stream` signs despite `allow`, the images it signs go to stdout.
+    #[serde(default)]
+    pub stream: bool,
+    /// Most files the signer is run on in one run.
+    pub max_per_run: Option<u64>,
+    /// Most files the signer is run on in a day, by all runs.
+    pub max_per_day: Option<u64>,
+    /// Where the count for the day is kept, [`COUNTS`] if not set.
+    pub counts: Option<PathBuf>,
+}
+
+impl Policy {
+    /// Whether there is anything to enforce.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.max_per_run.is_none() && self.max_per_day.is_none()
+    }
+
+    /// The policy of keeping to both `self` and `other`: the lower of each limit, and only the
+    /// directories both allow, and `sign-stream` only if each allowing any directories at all
+    /// allows it. Where `self` limits anything the day is counted where it counts
+    /// it, [`COUNTS`] if it doesn't say, never where `other` does: a fresh file would start the
+    /// count over.
+    #[must_use]
+    pub fn and(self, other: Self) -> Self {
+        let min = |a: Option<u64>, b: Option<u64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let allow = if self.allow.is_empty() || other.allow.is_empty() {
+            [self.allow.as_slice(), other.allow.as_slice()].concat()
+        } else {
+            // a directory both allow is one under a directory of the other
+            let under = |dir: &PathBuf, dirs: &[PathBuf]| dirs.iter().any(|d| dir.starts_with(d));
+            let mut allow: Vec<_> = self
+                .allow
+                .iter()
+                .filter(|dir| under(dir, &other.allow))
+                .chain(other.allow.iter().filter(|dir| under(dir, &self.allow)))
+                .cloned()
+                .collect();
+            allow.sort();
+            allow.dedup();
+            if allow.is_empty() {
+                // nothing at all, rather than anything
+                allow.push(PathBuf::new());
+            }
+            allow
+        };
+        Self {
+            allow,
+            stream: (self.allow.is_empty() || self.stream)
+                && (other.allow.is_empty() || other.stream),
+            max_per_run: min(self.max_per_run, other.max_per_run),
+            max_per_day: min(self.max_per_day, other.max_per_day),
+            counts: if self.max_per_run.is_some() || self.max_per_day.is_some() {
+                self.counts
+            } else {
+                self.counts.or(other.counts)
+            },
+        }
+    }
+}
+
+/// Enforces a [`Policy`] over a run, counting the files signed.
+#[derive(Debug)]
+pub(crate) struct Counter {
+    policy: Policy,
+    run: Mutex<u64>,
+}
+
+impl Counter {
+    pub(crate) fn new(policy: Policy) -> Self {
+        Self {
+            policy,
+            run: Mutex::new(0),
+        }
+    }
+
+    /// Count running the signer to write `file`, or fail if the policy doesn't allow it.
+    pub(crate) fn permit(&self, file: &Path) -> io::Result<()> {
+        let denied = |why: String| io::Error::new(io::ErrorKind::PermissionDenied, why);
+        if !self.policy.allow.is_empty() {
+            let real = resolve(file)?;
+            let allowed = self.policy.allow.iter().any(|dir| {
+                !dir.as_os_str().is_empty()
+                    && real.starts_with(std::fs::canonicalize(dir).unwrap_or_else(|_| dir.clone()))
+            });
+            if !allowed {
+                return Err(denied(format!(
+                    "the usage policy doesn't allow signing {}",
+                    escape::path(&real)
+                )));
+            }
+        }
+
+        let mut run = self.run.lock().map_err(|_| io::ErrorKind::Other)?;
+        if let Some(max) = self.policy.max_per_run.filter(|max| *run >= *max) {
+            return Err(denied(format!(
+                "the usage policy allows signing {max} files a run"
+            )));
+        }
+        if let Some(max) = self.policy.max_per_day {
+            let path = self.policy.counts.as_deref().unwrap_or(Path::new(COUNTS));
+            let counted = count_day(path, max)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))?;
+            if !counted {
+                return Err(denied(format!(
+                    "the usage policy allows signing {max} files a day, try again tomorrow"
+                )));
+            }
+        }
+        *run += 1;
+        Ok(())
+    }
+}
+
+/// Where `file` really is, or will be once written: the directories it is in that are there
+/// resolved, and the rest of it as it is.
+fn resolve(file: &Path) -> io::Result<PathBuf> {
+    let mut missing = Vec::new();
+    let mut there = file;
+    loop {
+        match std::fs::canonicalize(there) {
+            Ok(mut real) => {
+                real.extend(missing.iter().rev());
+                return Ok(real);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        match (there.parent(), there.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                there = if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+            }
+            // a .. after something that isn't there, there is no telling where that is
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("can't tell where {} is", escape::path(file)),
+                ))
+            }
+        }
+    }
+}
+
+/// Add one to the count for today in the file at `path`, unless it is at `max` already.
+fn count_day(path: &Path, max: u64) -> io::Result<bool> {
+    let today = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86400);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut open = OpenOptions::new();
+    open.read(true).write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut open, 0o600);
+    let mut file = open.open(path)?;
+    let _lock = Lock::new(&file)?;
+
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    // a day and its count, "20375 12"
+    let count = match text.split_whitespace().collect::<Vec<_>>()[..] {
+        [day, count] if day.parse() == Ok(today) => count
+            .parse::<u64>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        [_, _] | [] => 0,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "isn't a usage count",
+            ))
+        }
+    };
+    if count >= max {
+        return Ok(false);
+    }
+
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    writeln!(file, "{today} {}", count + 1)?;
+    file.sync_data()?;
+    Ok(true)
+}
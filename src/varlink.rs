@@ -0,0 +1,331 @@
+//! `sbsevery watch --varlink PATH`, the watcher as a [varlink](https://varlink.org) service on a
+//! unix socket, for `varlinkctl` and scripts:
+//!
+//! ```text
+//! varlinkctl call /run/sbsevery/org.sbsevery.Manager org.sbsevery.Manager.Sign '{"path":"/efi/EFI/Linux/new.efi"}'
+//! ```
+//!
+//! It is the D-Bus service of [`crate::dbus`] over varlink: `Sign`, `Verify` and `Status` return
+//! the report of the run, of `sbsevery verify` and what the `--health` file says, `Reload` reads
+//! the configuration file again, and
+//! `Completions`, called with `more`, is a reply for each pass and `Sign` finishing from then on.
+//! [`INTERFACE`] describes them, `varlinkctl introspect` shows it.
+//!
+//! The socket is only for root, anyone who can connect can have files signed. `sbsevery ctl`
+//! talks to it with [`call`].
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::{fs::FileTypeExt, net::UnixStream},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    escape,
+    watch::{Completed, Request, Requests},
+};
+
+/// Where the socket usually is, and where `sbsevery ctl` looks for it.
+pub const SOCKET: &str = "/run/sbsevery/org.sbsevery.Manager";
+
+/// The interface of the service, in the varlink interface definition language.
+pub const INTERFACE: &str = "\
+# Sign and check boot files with a running sbsevery watch.
+interface org.sbsevery.Manager
+
+# A pass over the roots or a Sign having finished.
+type Completion (
+  paths: []string,
+  ok: bool,
+  signed: int,
+  failed: int
+)
+
+# Signs a file or directory under the roots now, as a run with it as the root would.
+method Sign(path: string) -> (report: object)
+
+# Checks a file or directory under the roots is signed by the cert.
+method Verify(path: string) -> (compliance: object)
+
+# How the watcher is doing.
+method Status() -> (status: object)
+
+# Reads the configuration file again, for the keys and certs it names.
+method Reload() -> ()
+
+# Every pass and Sign finishing from now on, call with more.
+method Completions() -> (completion: Completion)
+
+# The request couldn't be done.
+error Failed(message: string)
+";
+
+/// The interface every varlink service has, to be asked about itself.
+const SERVICE: &str = "\
+# The Varlink Service Interface is provided by every varlink service. It
+# describes the service and the interfaces it implements.
+interface org.varlink.service
+
+# Get a list of all the interfaces a service provides and information
+# about the implementation.
+method GetInfo() -> (
+  vendor: string,
+  product: string,
+  version: string,
+  url: string,
+  interfaces: []string
+)
+
+# Get the description of an interface that is implemented by this service.
+method GetInterfaceDescription(interface: string) -> (description: string)
+
+# The requested interface was not found.
+error InterfaceNotFound (interface: string)
+
+# The requested method was not found
+error MethodNotFound (method: string)
+
+# The interface defines the requested method, but the service does not
+# implement it.
+error MethodNotImplemented (method: string)
+
+# One of the passed parameters is invalid.
+error InvalidParameter (parameter: string)
+
+# Client is denied access
+error PermissionDenied ()
+
+# Method is expected to be called with 'more' set to true, but wasn't
+error ExpectedMore ()
+";
+
+/// The longest call taken, anything longer isn't one of ours.
+const MAX_CALL: u64 = 1 << 20;
+
+/// Listen on `path` and answer calls with `requests`, on threads of their own for as long as
+/// the process runs. A socket left at `path` by a watcher before is replaced.
+///
+/// # Errors
+///
+/// Fails if the socket can't be made.
+pub fn serve(path: &Path, requests: Requests) -> io::Result<()> {
+    let context = |e: io::Error| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path)));
+    if path
+        .symlink_metadata()
+        .is_ok_and(|meta| meta.file_type().is_socket())
+    {
+        std::fs::remove_file(path).map_err(context)?;
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(context)?;
+    }
+    let listener = crate::socket::bind(path).map_err(context)?;
+
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let requests = requests.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = connection(stream, &requests) {
+                            eprintln!("varlink: {e}");
+                        }
+                    });
+                }
+                Err(e) => {
+                    eprintln!("{}: {e}", escape::path(&path));
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answer the calls of one client until it hangs up.
+fn connection(stream: UnixStream, requests: &Requests) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    loop {
+        let mut call = Vec::new();
+        if reader.by_ref().take(MAX_CALL).read_until(0, &mut call)? == 0 {
+            return Ok(());
+        }
+        if call.pop() != Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "call too long or cut off",
+            ));
+        }
+        let call: Value = serde_json::from_slice(&call)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let method = call["method"].as_str().unwrap_or_default();
+        let parameters = &call["parameters"];
+
+        if method == "org.sbsevery.Manager.Completions" {
+            if call["more"] != true {
+                send(
+                    &mut writer,
+                    &error("org.varlink.service.ExpectedMore", json!({})),
+                )?;
+                continue;
+            }
+            for completed in requests.completions() {
+                let mut reply = json!({ "parameters": { "completion": completion(&completed) } });
+                reply["continues"] = true.into();
+                if send(&mut writer, &reply).is_err() {
+                    // hung up
+                    return Ok(());
+                }
+            }
+            return Ok(());
+        }
+
+        let reply = answer(method, parameters, requests);
+        if call["oneway"] != true {
+            send(&mut writer, &reply)?;
+        }
+    }
+}
+
+/// The reply to a call of `method` with `parameters`.
+fn answer(method: &str, parameters: &Value, requests: &Requests) -> Value {
+    let request = match method {
+        "org.varlink.service.GetInfo" => {
+            return json!({ "parameters": {
+                "vendor": "sbsevery",
+                "product": "sbsevery",
+                "version": crate::version::VERSION,
+                "url": "https://github.com/caverym/sbsevery",
+                "interfaces": ["org.varlink.service", "org.sbsevery.Manager"],
+            }});
+        }
+        "org.varlink.service.GetInterfaceDescription" => {
+            return match parameters["interface"].as_str() {
+                Some("org.varlink.service") => json!({ "parameters": { "description": SERVICE } }),
+                Some("org.sbsevery.Manager") => {
+                    json!({ "parameters": { "description": INTERFACE } })
+                }
+                Some(interface) => error(
+                    "org.varlink.service.InterfaceNotFound",
+                    json!({ "interface": interface }),
+                ),
+                None => invalid("interface"),
+            };
+        }
+        "org.sbsevery.Manager.Sign" | "org.sbsevery.Manager.Verify" => {
+            let Some(path) = parameters["path"].as_str() else {
+                return invalid("path");
+            };
+            if method.ends_with("Sign") {
+                Request::Sign(PathBuf::from(path))
+            } else {
+                Request::Verify(PathBuf::from(path))
+            }
+        }
+        "org.sbsevery.Manager.Status" => Request::Status,
+        "org.sbsevery.Manager.Reload" => Request::Reload,
+        _ if method.starts_with("org.sbsevery.Manager.")
+            || method.starts_with("org.varlink.service.") =>
+        {
+            return error(
+                "org.varlink.service.MethodNotFound",
+                json!({ "method": method }),
+            );
+        }
+        _ => {
+            let interface = method
+                .rsplit_once('.')
+                .map_or(method, |(interface, _)| interface);
+            return error(
+                "org.varlink.service.InterfaceNotFound",
+                json!({ "interface": interface }),
+            );
+        }
+    };
+
+    let name = match &request {
+        Request::Sign(_) | Request::SignApproved(..) => "report",
+        Request::Verify(_) => "compliance",
+        Request::Status => "status",
+        Request::Reload => "",
+    };
+    match requests.call(request) {
+        Ok(_) if name.is_empty() => json!({ "parameters": {} }),
+        Ok(answer) => json!({ "parameters": { name: answer } }),
+        Err(e) => error("org.sbsevery.Manager.Failed", json!({ "message": e })),
+    }
+}
+
+/// Call `method` of `org.sbsevery.Manager` with `parameters` on the watcher listening on
+/// `socket`, and return the parameters of the reply.
+///
+/// # Errors
+///
+/// Fails if there is no watcher on the socket, or with the error it replied.
+pub fn call(socket: &Path, method: &str, parameters: Value) -> Result<Value, String> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| {
+        format!(
+            "can't reach the watcher at {}: {e}, is sbsevery watch --varlink running?",
+            escape::path(socket)
+        )
+    })?;
+    let lost = |e: io::Error| format!("lost the watcher: {e}");
+    let mut call = json!({ "method": format!("org.sbsevery.Manager.{method}") });
+    call["parameters"] = parameters;
+    send(&mut stream, &call).map_err(lost)?;
+
+    let mut reply = Vec::new();
+    BufReader::new(stream)
+        .read_until(0, &mut reply)
+        .map_err(lost)?;
+    if reply.pop() != Some(0) {
+        return Err("the watcher hung up".to_string());
+    }
+    let mut reply: Value = serde_json::from_slice(&reply).map_err(|e| e.to_string())?;
+    match reply["error"].as_str() {
+        Some("org.sbsevery.Manager.Failed") => Err(reply["parameters"]["message"]
+            .as_str()
+            .unwrap_or("failed")
+            .to_string()),
+        Some(error) => Err(format!("{error} {}", reply["parameters"])),
+        None => Ok(reply["parameters"].take()),
+    }
+}
+
+fn error(name: &str, parameters: Value) -> Value {
+    let mut reply = json!({ "error": name });
+    reply["parameters"] = parameters;
+    reply
+}
+
+fn invalid(parameter: &str) -> Value {
+    error(
+        "org.varlink.service.InvalidParameter",
+        json!({ "parameter": parameter }),
+    )
+}
+
+fn completion(completed: &Completed) -> Value {
+    let paths: Vec<_> = completed
+        .paths
+        .iter()
+        .map(|path| escape::path(path).to_string())
+        .collect();
+    json!({
+        "paths": paths,
+        "ok": completed.ok,
+        "signed": completed.signed,
+        "failed": completed.failed,
+    })
+}
+
+fn send(writer: &mut UnixStream, reply: &Value) -> io::Result<()> {
+    let mut message = serde_json::to_vec(reply)?;
+    message.push(0);
+    writer.write_all(&message)
+}
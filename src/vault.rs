@@ -0,0 +1,216 @@
+//! `--backend vault`, signing with a key that never leaves `HashiCorp` Vault's transit engine:
+//!
+//! ```text
+//! sbsevery /efi --backend vault --vault-path transit/sign/efi-db -c /etc/efi-keys/DB.crt
+//! ```
+//!
+//! sbsevery builds the Authenticode signature itself, as [`crate::offline`] does, and has Vault
+//! sign what goes into it, at `--vault-path`, the path of `/v1/<mount>/sign/<key>` the key signs
+//! at. Vault hands out no cert, `-c` is the cert of the transit key and every signature is
+//! checked against it before it goes into an image. RSA keys sign PKCS#1 v1.5, ECDSA keys sign
+//! as usual.
+//!
+//! Vault is at `--vault-addr`, `VAULT_ADDR` or `https://127.0.0.1:8200`, in the namespace of
+//! `VAULT_NAMESPACE` if set, its cert checked with `VAULT_CACERT` if set. sbsevery logs in once a
+//! run: with the `AppRole` of `--vault-role-id`, its secret id read from
+//! `--vault-secret-id-file`, or else with the token of `VAULT_TOKEN` or of the `~/.vault-token`
+//! `vault login` leaves behind. It talks to Vault with `curl`, handed the token on stdin, never
+//! on argv.
+
+use std::{
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+use crate::{base64, escape, secret::Secret};
+
+/// Where Vault is unless told otherwise, as the vault CLI has it.
+pub const ADDR: &str = "https://127.0.0.1:8200";
+
+/// A transit key in Vault.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Vault {
+    /// Where Vault is, `https://vault.example.com:8200`.
+    pub addr: String,
+    /// The path the key signs at, `transit/sign/efi-db`.
+    pub path: String,
+    /// Log in with this `AppRole` rather than with a token.
+    pub approle: Option<AppRole>,
+}
+
+/// An `AppRole` to log in to Vault with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppRole {
+    pub role_id: String,
+    /// A file holding the secret id, which doesn't belong on the command line.
+    pub secret_id_file: PathBuf,
+}
+
+impl Vault {
+    /// A token to sign with, from logging in with the `AppRole`, `VAULT_TOKEN` or
+    /// `~/.vault-token`.
+    pub(crate) fn token(&self, timeout: Option<Duration>) -> io::Result<Secret> {
+        if let Some(approle) = &self.approle {
+            let secret_id = std::fs::read_to_string(&approle.secret_id_file).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("{}: {e}", escape::path(&approle.secret_id_file)),
+                )
+            })?;
+            let body = json!({ "role_id": approle.role_id, "secret_id": secret_id.trim() });
+            let reply = self.call("auth/approle/login", None, &body, timeout)?;
+            return reply["auth"]["client_token"]
+                .as_str()
+                .map(|token| Secret::new(token.as_bytes().to_vec()))
+                .ok_or_else(|| io::Error::other("Vault's login gave no token"));
+        }
+
+        if let Some(token) = std::env::var_os("VAULT_TOKEN").filter(|t| !t.is_empty()) {
+            return Ok(Secret::new(token.into_encoded_bytes()));
+        }
+        let file = std::env::var_os("HOME").map(|home| Path::new(&home).join(".vault-token"));
+        match file.map(std::fs::read) {
+            Some(Ok(token)) => Ok(Secret::new(token.trim_ascii().to_vec())),
+            _ => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no Vault token, set VAULT_TOKEN, vault login or give --vault-role-id",
+            )),
+        }
+    }
+
+    /// Have the key sign `data`, returning the signature as DER.
+    pub(crate) fn sign(
+        &self,
+        token: &Secret,
+        data: &[u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<Vec<u8>> {
+        let body = json!({
+            "input": base64::encode(data),
+            "hash_algorithm": "sha2-256",
+            "signature_algorithm": "pkcs1v15",
+            "marshaling_algorithm": "asn1",
+        });
+        let reply = self.call(self.path.trim_matches('/'), Some(token), &body, timeout)?;
+        // vault:v1:MEUCIQ…
+        reply["data"]["signature"]
+            .as_str()
+            .and_then(|signature| signature.rsplit(':').next())
+            .and_then(base64::decode)
+            .ok_or_else(|| io::Error::other("Vault's reply has no signature"))
+    }
+
+    /// POST `body` to `path` of the API and return the reply.
+    fn call(
+        &self,
+        path: &str,
+        token: Option<&Secret>,
+        body: &Value,
+        timeout: Option<Duration>,
+    ) -> io::Result<Value> {
+        let addr = if self.addr.is_empty() {
+            ADDR
+        } else {
+            self.addr.trim_end_matches('/')
+        };
+        // curl's config file, so the token and the body don't show up in ps
+        let mut config = Vec::with_capacity(4096);
+        let mut set = |name: &str, value: &[u8]| {
+            config.extend_from_slice(name.as_bytes());
+            config.extend_from_slice(b" = \"");
+            for &b in value {
+                if b == b'"' || b == b'\\' {
+                    config.push(b'\\');
+                }
+                config.push(b);
+            }
+            config.extend_from_slice(b"\"\n");
+        };
+        set("url", format!("{addr}/v1/{path}").as_bytes());
+        set("header", b"Content-Type: application/json");
+        if let Some(token) = token {
+            set("header", &[b"X-Vault-Token: ", token.as_bytes()].concat());
+        }
+        if let Some(namespace) = std::env::var("VAULT_NAMESPACE")
+            .ok()
+            .filter(|n| !n.is_empty())
+        {
+            set(
+                "header",
+                format!("X-Vault-Namespace: {namespace}").as_bytes(),
+            );
+        }
+        if let Some(ca) = std::env::var_os("VAULT_CACERT").filter(|ca| !ca.is_empty()) {
+            set("cacert", ca.as_encoded_bytes());
+        }
+        if let Some(timeout) = timeout {
+            set("max-time", timeout.as_secs().to_string().as_bytes());
+        }
+        set("data", body.to_string().as_bytes());
+        set("write-out", b"\\n%{http_code}");
+        let config = Secret::new(config);
+
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--config", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("can't run curl: {e}")))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Err(e) = stdin.write_all(config.as_bytes()) {
+                drop(stdin);
+                // one that exited already keeps the status it exited with
+                let _ = child.kill();
+                let output = child.wait_with_output()?;
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!(
+                        "can't reach Vault at {addr}: curl stopped reading its config ({e}), {}: {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ),
+                ));
+            }
+        }
+        let mut reply = Vec::with_capacity(16 * 1024);
+        if let Some(mut stdout) = child.stdout.take() {
+            stdout.read_to_end(&mut reply)?;
+        }
+        let reply = Secret::new(reply);
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "can't reach Vault at {addr}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        let reply = reply.as_bytes();
+        let (body, status) = match reply.iter().rposition(|&b| b == b'\n') {
+            Some(at) => (&reply[..at], &reply[at + 1..]),
+            None => (&[][..], reply),
+        };
+        let body: Value = serde_json::from_slice(body).unwrap_or_default();
+        if !status.starts_with(b"2") {
+            let errors = body["errors"]
+                .as_array()
+                .map_or_else(String::new, |errors| {
+                    let errors: Vec<_> = errors.iter().filter_map(Value::as_str).collect();
+                    errors.join(", ")
+                });
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "Vault said {} to {path}: {errors}",
+                    String::from_utf8_lossy(status)
+                ),
+            ));
+        }
+        Ok(body)
+    }
+}
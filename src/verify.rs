@@ -0,0 +1,242 @@
+//! `sbsevery verify`, check that every file under the roots is signed by the certificates a
+//! policy asks for, without changing anything.
+//!
+//! A policy is a TOML file of rules, each matching files by a glob and listing the certs that
+//! must all have signed them:
+//!
+//! ```toml
+//! [[rule]]
+//! glob = "vmlinuz*"
+//! certs = ["/etc/efi-keys/kernel.crt"]
+//!
+//! [[rule]]
+//! glob = "*.efi"
+//! certs = ["/etc/efi-keys/DB.crt"]
+//! ```
+//!
+//! Globs are matched like `--include`, against the path relative to the root if they have a
+//! `/` in them and the file name otherwise. The first rule matching a file applies, files
+//! matching none are reported as not covered. Relative cert paths are relative to the policy file.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    escape,
+    filter::{self, Filter},
+    glob, report, search, Options, Skip,
+};
+
+/// Which certs have to have signed which files.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Tried in order, the first match wins.
+    #[serde(rename = "rule", default)]
+    pub rules: Vec<Rule>,
+}
+
+/// One entry of a [`Policy`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Files this rule applies to.
+    pub glob: String,
+    /// Certs every one of which must have signed a matching file.
+    pub certs: Vec<PathBuf>,
+}
+
+impl Policy {
+    /// Read a policy from a TOML file.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or isn't a valid policy.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let mut policy: Policy = toml::from_str(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", escape::path(path)),
+            )
+        })?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for rule in &mut policy.rules {
+            for cert in &mut rule.certs {
+                *cert = dir.join(&*cert);
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// The policy of every file being signed by `cert`.
+    #[must_use]
+    pub fn single(cert: &Path) -> Self {
+        Self {
+            rules: vec![Rule {
+                glob: String::from("*"),
+                certs: vec![cert.to_path_buf()],
+            }],
+        }
+    }
+
+    /// The first rule matching `file`, found under one of `roots`.
+    fn rule(&self, file: &Path, roots: &[PathBuf], fold: bool) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .find(|rule| glob::matches_file(&rule.glob, file, roots, fold))
+    }
+}
+
+/// How a file measured up to the policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Signed by every cert its rule asks for.
+    Compliant,
+    /// Not signed by these certs of its rule.
+    Violation(Vec<PathBuf>),
+    /// No rule matches the file.
+    Unmatched,
+    /// The verifier couldn't be run.
+    Error(String),
+}
+
+/// Outcome of checking a tree against a policy.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Compliance {
+    /// Every file checked and how it went, in path order.
+    pub checked: Vec<(PathBuf, Verdict)>,
+    /// Files filtered out before checking, in path order.
+    pub skipped: Vec<(PathBuf, Skip)>,
+    /// Directories and files that couldn't be searched and why, in path order.
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+impl Compliance {
+    /// Number of files with this kind of verdict.
+    #[must_use]
+    pub fn count(&self, verdict: fn(&Verdict) -> bool) -> usize {
+        self.checked.iter().filter(|(_, v)| verdict(v)).count()
+    }
+
+    /// Whether every file covered by the policy complies and everything could be checked.
+    #[must_use]
+    pub fn ok(&self) -> bool {
+        self.errors.is_empty()
+            && self
+                .checked
+                .iter()
+                .all(|(_, v)| matches!(v, Verdict::Compliant | Verdict::Unmatched))
+    }
+}
+
+/// Search the roots in `options` with the usual filters and check every file found against
+/// `policy` with the verifier of `options.backend`, `options.jobs` at a time. Nothing is
+/// written.
+///
+/// # Errors
+///
+/// Fails if the backend has no verifier or filters can't be set up. Files that don't comply
+/// only show up in the result.
+pub fn verify(
+    options: &Options,
+    policy: &Policy,
+) -> Result<Compliance, Box<dyn std::error::Error>> {
+    if !options.backend.verifies() {
+        return Err(format!(
+            "the {} backend has no verifier, verify with sbsign or osslsigncode",
+            options.backend.name()
+        )
+        .into());
+    }
+
+    let filter = Filter::new(options, &[])?;
+    let checked = search::each(options, &filter, |file| check(file, options, policy));
+
+    Ok(Compliance {
+        checked,
+        skipped: filter.skipped(),
+        errors: filter.errors(),
+    })
+}
+
+fn check(file: &Path, options: &Options, policy: &Policy) -> Verdict {
+    let Some(rule) = policy.rule(file, &options.roots, filter::folds(file, options.case)) else {
+        return Verdict::Unmatched;
+    };
+
+    let mut missing = Vec::new();
+    for cert in &rule.certs {
+        let Some(mut cmd) = options.backend.verify_command(file, cert) else {
+            return Verdict::Error(String::from("no verifier"));
+        };
+        let program = cmd.get_program().to_string_lossy().into_owned();
+        match cmd.stdout(Stdio::null()).stderr(Stdio::null()).status() {
+            Ok(status) if status.success() => {}
+            Ok(_) => missing.push(cert.clone()),
+            Err(e) => return Verdict::Error(format!("couldn't run {program}: {e}")),
+        }
+    }
+
+    if missing.is_empty() {
+        Verdict::Compliant
+    } else {
+        Verdict::Violation(missing)
+    }
+}
+
+/// The compliance report as JSON, every file checked with its verdict.
+#[must_use]
+pub fn json(compliance: &Compliance) -> Value {
+    let files: Vec<_> = compliance
+        .checked
+        .iter()
+        .map(|(path, verdict)| {
+            let path = escape::path(path).to_string();
+            match verdict {
+                Verdict::Compliant => json!({ "path": path, "verdict": "compliant" }),
+                Verdict::Violation(missing) => {
+                    let missing: Vec<_> = missing
+                        .iter()
+                        .map(|cert| escape::path(cert).to_string())
+                        .collect();
+                    json!({ "path": path, "verdict": "violation", "missing": missing })
+                }
+                Verdict::Unmatched => json!({ "path": path, "verdict": "unmatched" }),
+                Verdict::Error(e) => json!({ "path": path, "verdict": "error", "error": e }),
+            }
+        })
+        .collect();
+    let errors: Vec<_> = compliance
+        .errors
+        .iter()
+        .map(|(path, e)| json!({ "path": escape::path(path).to_string(), "error": e }))
+        .collect();
+
+    json!({
+        "compliant": compliance.count(|v| *v == Verdict::Compliant),
+        "violations": compliance.count(|v| matches!(v, Verdict::Violation(_))),
+        "unmatched": compliance.count(|v| *v == Verdict::Unmatched),
+        "files": files,
+        "errors": errors,
+    })
+}
+
+/// Write `compliance` to `spec`, given as `FORMAT=PATH` like [`report::write`].
+///
+/// # Errors
+///
+/// Fails on an unknown format or if the report can't be written.
+pub fn write(compliance: &Compliance, spec: &str) -> io::Result<()> {
+    report::emit(spec, |format| {
+        (format == "json").then(|| json(compliance).to_string())
+    })
+}
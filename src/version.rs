@@ -0,0 +1,68 @@
+//! `--version`, and with `--json` what this build can do, for tooling that wants to check before
+//! relying on a feature.
+
+use std::process::{Command, Stdio};
+
+use serde_json::{json, Value};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Commit sbsevery was built from, `unknown` outside a git checkout.
+pub const COMMIT: &str = env!("SBSEVERY_GIT_COMMIT");
+
+/// Backends this build knows, by their `--backend` names.
+pub const BACKENDS: &[&str] = &[
+    "sbsign",
+    "pesign",
+    "osslsigncode",
+    "signtool",
+    "plugin:NAME",
+];
+
+/// Version, commit, backends, which optional features are compiled in and which signing tools
+/// are installed, with their versions.
+#[must_use]
+pub fn json() -> Value {
+    let sandbox = cfg!(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ));
+
+    json!({
+        "version": VERSION,
+        "commit": COMMIT,
+        "backends": BACKENDS,
+        "features": {
+            "sandbox": sandbox,
+            "walk-as": cfg!(unix),
+            "encrypted-keys": cfg!(target_os = "linux"),
+            "plugins": true,
+            "native-signer": false,
+            "pkcs11": false,
+            "tpm": false,
+            "daemon": cfg!(target_os = "linux"),
+            "watch": cfg!(target_os = "linux"),
+        },
+        "tools": {
+            "sbsign": tool_version("sbsign", "--version"),
+            "pesign-client": tool_version("pesign-client", "--version"),
+            "osslsigncode": tool_version("osslsigncode", "--version"),
+        },
+    })
+}
+
+/// First line a tool prints when asked for its version, `null` if it isn't installed.
+pub(crate) fn tool_version(tool: &str, flag: &str) -> Value {
+    let Ok(out) = Command::new(tool)
+        .arg(flag)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+    else {
+        return Value::Null;
+    };
+
+    String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .next()
+        .map_or(Value::Null, |line| Value::from(line.trim()))
+}
@@ -0,0 +1,98 @@
+//! Which filesystem a file is on, by UUID, and where in it, so the state file knows a file by
+//! the same name wherever the filesystem happens to be mounted: `/efi/EFI/Linux/a.efi` and
+//! `/boot/efi/EFI/Linux/a.efi` are both `UUID=1234-ABCD/EFI/Linux/a.efi`. Linux only.
+
+use std::{
+    collections::HashMap,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+/// A mount, from `/proc/self/mountinfo`.
+#[derive(Debug)]
+struct Mount {
+    dev: u64,
+    /// What of the filesystem is mounted, `/` but for bind mounts.
+    root: PathBuf,
+    point: PathBuf,
+}
+
+/// The mounts of this machine and the UUIDs of their filesystems, as they were when loaded.
+#[derive(Debug, Default)]
+pub(crate) struct Volumes {
+    mounts: Vec<Mount>,
+    uuids: HashMap<u64, String>,
+}
+
+impl Volumes {
+    pub(crate) fn load() -> Self {
+        let mounts = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+        // 36 35 259:1 / /efi rw,relatime shared:2 - vfat /dev/nvme0n1p1 rw,…
+        let mounts = mounts
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<_> = line.split(' ').collect();
+                let (major, minor) = fields.get(2)?.split_once(':')?;
+                Some(Mount {
+                    dev: libc::makedev(major.parse().ok()?, minor.parse().ok()?),
+                    root: PathBuf::from(unescape(fields.get(3)?)),
+                    point: PathBuf::from(unescape(fields.get(4)?)),
+                })
+            })
+            .collect();
+
+        let uuids = std::fs::read_dir("/dev/disk/by-uuid")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|link| {
+                let dev = std::fs::metadata(link.path()).ok()?.rdev();
+                Some((dev, link.file_name().into_string().ok()?))
+            })
+            .collect();
+
+        Self { mounts, uuids }
+    }
+
+    /// `file` as `UUID=<uuid>/<path within the filesystem>`, `None` if its filesystem has no
+    /// UUID or it can't be found.
+    pub(crate) fn locate(&self, file: &Path) -> Option<PathBuf> {
+        let file = std::fs::canonicalize(file).ok()?;
+        let dev = std::fs::metadata(&file).ok()?.dev();
+        let uuid = self.uuids.get(&dev)?;
+        // the innermost mount of the filesystem the file is under
+        let (mount, within) = self
+            .mounts
+            .iter()
+            .filter(|mount| mount.dev == dev)
+            .filter_map(|mount| Some((mount, file.strip_prefix(&mount.point).ok()?)))
+            .max_by_key(|(mount, _)| mount.point.as_os_str().len())?;
+        let mut key = PathBuf::from(format!("UUID={uuid}"));
+        key.push(mount.root.strip_prefix("/").unwrap_or(&mount.root));
+        key.push(within);
+        Some(key)
+    }
+}
+
+/// Undo the octal escapes `mountinfo` has for spaces and such in paths.
+fn unescape(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = bytes
+            .get(i + 1..i + 4)
+            .and_then(|code| u8::from_str_radix(std::str::from_utf8(code).ok()?, 8).ok());
+        match (bytes[i], code) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
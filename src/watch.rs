@@ -0,0 +1,753 @@
+//! `sbsevery watch`, sign the roots and then stay around, signing them again whenever something
+//! under them changes: a kernel install dropping a new UKI, a bootloader update. Linux only,
+//! changes are noticed with inotify.
+//!
+//! Changes come in bursts, a pass starts once nothing has changed for half a second. Files written
+//! elsewhere and renamed into place, as installers do, count once they have their name; whole
+//! directories moved under the roots are watched from then on, under their new name if they were
+//! watched before. Should the kernel drop changes, its queue overflowing, everything is looked at
+//! again.
+//!
+//! Not every change makes it to inotify, on network filesystems say, so with
+//! [`Watch::rescan_every`] a pass also runs when there has been none for that long, give or take
+//! a tenth of it so a fleet of machines started together doesn't sign all at once.
+//!
+//! Every pass is a whole run over the roots, with `--state` only the files that changed since the
+//! last one are signed again. An empty root is nothing to fail on here, the files may well come
+//! later.
+//!
+//! Run as a systemd service with `Type=notify` it says when it is watching, and with
+//! `WatchdogSec=` it pings the watchdog: every file a pass is done with counts as a sign of life
+//! and so does waiting, so a watcher stuck on a hung signer is restarted. `--health` keeps a file
+//! saying how it is doing for anything else keeping an eye on it, rewritten at least every
+//! [`HEALTH_EVERY`]:
+//!
+//! ```text
+//! {"pid":4242,"status":"watching","updated":1760443200,"passes":3,"last_pass":1760443100,
+//!  "last_result":{"files":1,"failures":0,"unchanged":40,"cancelled":false,"ok":true}}
+//! ```
+//!
+//! With `--dbus` it is on D-Bus too, see [`crate::dbus`], with `--varlink` on varlink, see
+//! [`crate::varlink`], and with `--http` an HTTP API, see [`crate::http`]: these services send
+//! it [`Request`]s through [`Requests`], served between
+//! passes, and hear of each pass [`Completed`].
+//!
+//! Cancelling [`Options::cancel`] stops it, after the pass going on if there is one.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CString,
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+use crate::{escape, observe::Observer, report::Action, state::Hash, Entry, Options, Report, Skip};
+
+/// How often the `--health` file is rewritten while waiting, for a stale one to mean trouble.
+pub const HEALTH_EVERY: Duration = Duration::from_secs(30);
+
+/// How to watch, on top of the [`Options`] of each pass.
+#[derive(Debug, Clone, Default)]
+pub struct Watch {
+    /// Keep a file here saying how the watcher is doing, see the module docs.
+    pub health: Option<PathBuf>,
+    /// Run a pass when there hasn't been one for this long, changes or not.
+    pub rescan_every: Option<Duration>,
+    /// Serve `org.sbsevery.Manager` on this bus.
+    pub dbus: Option<crate::dbus::Bus>,
+    /// Serve `org.sbsevery.Manager` over varlink on a socket here.
+    pub varlink: Option<PathBuf>,
+    /// Serve the HTTP API.
+    pub http: Option<crate::http::Http>,
+    /// The configuration file to read again on [`Request::Reload`],
+    /// [`config::SYSTEM`](crate::config::SYSTEM) if there is none.
+    pub config: Option<PathBuf>,
+}
+
+/// Something asked of a running watcher by a client of one of its services.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// Sign this file or directory now, as a run with it as the root would. It has to be under
+    /// one of the roots.
+    Sign(PathBuf),
+    /// Sign as [`Request::Sign`] does, but only the files approved for it, each as it was when
+    /// it was approved: by its path with symlinks resolved, and its SHA-256. Any other file, or
+    /// one changed since, fails rather than being signed.
+    SignApproved(PathBuf, Arc<HashMap<PathBuf, Hash>>),
+    /// Check this file or directory under the roots is signed by the cert.
+    Verify(PathBuf),
+    /// How the watcher is doing, what the `--health` file says.
+    Status,
+    /// Read the configuration file again, for the keys and certs it names, and pass with it
+    /// from now on.
+    Reload,
+}
+
+/// A pass or a [`Request::Sign`] having finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completed {
+    /// The roots of the pass, or the path of the request.
+    pub paths: Vec<PathBuf>,
+    pub ok: bool,
+    pub signed: usize,
+    pub failed: usize,
+}
+
+/// What the watcher answers a [`Request`] with, JSON, or why it couldn't.
+pub type Answer = Result<Value, String>;
+
+/// How the services of a watcher get at it: send it [`Request`]s and hear of what it did.
+#[derive(Debug, Clone)]
+pub struct Requests {
+    sender: mpsc::Sender<(Request, mpsc::Sender<Answer>)>,
+    /// Readable when there is a request, for the watcher to stop waiting.
+    wake: Arc<UnixDatagram>,
+    listeners: Arc<Mutex<Vec<mpsc::Sender<Completed>>>>,
+}
+
+impl Requests {
+    /// Ask `request` of the watcher and wait for the answer, which may be until the pass going
+    /// on is done.
+    ///
+    /// # Errors
+    ///
+    /// Fails with why the request failed, or if the watcher stopped.
+    pub fn call(&self, request: Request) -> Answer {
+        let stopped = || "the watcher stopped".to_string();
+        let (reply, answer) = mpsc::channel();
+        self.sender.send((request, reply)).map_err(|_| stopped())?;
+        let _ = self.wake.send(&[0]);
+        answer.recv().map_err(|_| stopped())?
+    }
+
+    /// Every pass and [`Request::Sign`] finishing from now on.
+    #[must_use]
+    pub fn completions(&self) -> mpsc::Receiver<Completed> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.push(sender);
+        }
+        receiver
+    }
+
+    fn completed(&self, completed: &Completed) {
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.retain(|listener| listener.send(completed.clone()).is_ok());
+        }
+    }
+}
+
+/// Sign the roots of `options`, and again whenever something changes under them, until
+/// [`Options::cancel`] is cancelled.
+///
+/// # Errors
+///
+/// Fails if the roots can't be watched or the health file can't be written. A pass that fails
+/// doesn't stop the watcher, it is said on stderr and in the health file.
+pub fn watch(options: &Options, watch: &Watch) -> io::Result<()> {
+    let (wake, woken) = UnixDatagram::pair()?;
+    woken.set_nonblocking(true)?;
+    let mut inotify = Inotify::new(&options.roots, options.recursive, woken)?;
+    let (sender, inbox) = mpsc::channel();
+    let requests = Requests {
+        sender,
+        wake: Arc::new(wake),
+        listeners: Arc::default(),
+    };
+    if let Some(bus) = watch.dbus {
+        crate::dbus::serve(bus, requests.clone())?;
+    }
+    if let Some(path) = &watch.varlink {
+        crate::varlink::serve(path, requests.clone())?;
+    }
+    if let Some(http) = &watch.http {
+        crate::http::serve(http, requests.clone())?;
+    }
+
+    let watchdog = watchdog();
+    let mut options = options.clone();
+    options.allow_empty = true;
+    if let Some(every) = watchdog {
+        options.observers.add(Arc::new(Watchdog::new(every)));
+    }
+
+    let mut health = Health::new(watch.health.clone());
+    let tick = watchdog.map_or(HEALTH_EVERY, |every| every.min(HEALTH_EVERY));
+    let mut ready = false;
+    while !options.cancel.is_cancelled() {
+        health.status = "signing";
+        health.write()?;
+        notify("STATUS=signing");
+        let started = SystemTime::now();
+        let result = crate::run(&options);
+        let touched = touched(result.as_ref().ok(), started);
+        health.finished(&result);
+        requests.completed(&completed(options.roots.clone(), result.as_ref().ok()));
+        match &result {
+            Ok(report) => dprintln!(
+                options.verbose,
+                "pass done: {} signed, {} failed",
+                report.files - report.failures,
+                report.failures
+            ),
+            Err(e) => eprintln!("pass failed: {e}"),
+        }
+
+        health.status = "watching";
+        health.write()?;
+        if !ready {
+            notify("READY=1");
+            ready = true;
+        }
+        notify(&format!("STATUS=watching, {}", health.summary()));
+
+        // what the pass did itself isn't a change to sign for
+        let mut changed = inotify.changes(Duration::ZERO)?;
+        changed.retain(|path| !touched.contains(path));
+        serve(&inbox, &requests, watch, &mut options, &health);
+        let rescan = watch
+            .rescan_every
+            .map(|every| Instant::now() + jittered(every));
+        while changed.is_empty() && !options.cancel.is_cancelled() {
+            let wait = rescan.map_or(tick, |at| {
+                tick.min(at.saturating_duration_since(Instant::now()))
+            });
+            changed = inotify.changes(wait)?;
+            serve(&inbox, &requests, watch, &mut options, &health);
+            notify("WATCHDOG=1");
+            health.write()?;
+            if rescan.is_some_and(|at| Instant::now() >= at) {
+                dprintln!(options.verbose, "rescanning");
+                break;
+            }
+        }
+        for path in &changed {
+            dprintln!(options.verbose, "changed:\t{}", escape::path(path));
+        }
+    }
+
+    notify("STOPPING=1");
+    health.status = "stopped";
+    health.write()
+}
+
+/// Answer the requests there are.
+fn serve(
+    inbox: &mpsc::Receiver<(Request, mpsc::Sender<Answer>)>,
+    requests: &Requests,
+    watch: &Watch,
+    options: &mut Options,
+    health: &Health,
+) {
+    while let Ok((request, reply)) = inbox.try_recv() {
+        dprintln!(options.verbose, "request:\t{request:?}");
+        let answer = match request {
+            Request::Sign(path) => sign(&path, options).map(|report| {
+                requests.completed(&completed(vec![path], Some(&report)));
+                crate::report::json(&report)
+            }),
+            Request::SignApproved(path, approved) => {
+                let mut options = options.clone();
+                options.approved = Some(approved);
+                sign(&path, &options).map(|report| {
+                    requests.completed(&completed(vec![path], Some(&report)));
+                    crate::report::json(&report)
+                })
+            }
+            Request::Verify(path) => verify(&path, options),
+            Request::Status => Ok(health.json()),
+            Request::Reload => reload(watch.config.as_deref(), options),
+        };
+        let _ = reply.send(answer);
+    }
+}
+
+fn reload(config: Option<&Path>, options: &mut Options) -> Answer {
+    crate::config::Config::given(config)
+        .map_err(|e| e.to_string())?
+        .apply(options);
+    Ok(json!({}))
+}
+
+fn sign(path: &Path, options: &Options) -> Result<Report, String> {
+    let mut options = options.clone();
+    options.roots = vec![under_roots(path, &options.roots)?];
+    crate::run(&options).map_err(|e| e.to_string())
+}
+
+fn verify(path: &Path, options: &Options) -> Answer {
+    let mut options = options.clone();
+    options.roots = vec![under_roots(path, &options.roots)?];
+    let policy = crate::verify::Policy::single(&options.cert);
+    crate::verify::verify(&options, &policy)
+        .map(|compliance| crate::verify::json(&compliance))
+        .map_err(|e| e.to_string())
+}
+
+/// `path` with symlinks resolved, if that is under one of `roots`. What is asked of the watcher
+/// stays in what it was told to watch.
+fn under_roots(path: &Path, roots: &[PathBuf]) -> Result<PathBuf, String> {
+    let real = path
+        .canonicalize()
+        .map_err(|e| format!("{}: {e}", escape::path(path)))?;
+    let under = roots
+        .iter()
+        .any(|root| root.canonicalize().is_ok_and(|root| real.starts_with(root)));
+    if under {
+        Ok(real)
+    } else {
+        Err(format!("{} isn't under the roots", escape::path(path)))
+    }
+}
+
+fn completed(paths: Vec<PathBuf>, report: Option<&Report>) -> Completed {
+    Completed {
+        paths,
+        ok: report.is_some_and(Report::succeeded),
+        signed: report.map_or(0, |report| report.files - report.failures),
+        failed: report.map_or(0, |report| report.failures),
+    }
+}
+
+/// `every` and up to a tenth of it more, a different amount each time and in each process.
+fn jittered(every: Duration) -> Duration {
+    use std::hash::{BuildHasher, RandomState};
+
+    let tenth = u64::try_from((every / 10).as_millis()).unwrap_or(u64::MAX);
+    let random = RandomState::new().hash_one(Instant::now());
+    every + Duration::from_millis(random % tenth.saturating_add(1))
+}
+
+/// The files `report` of a pass started at `started` signed or looked at, as long as they
+/// haven't been written since. Their events are the pass's own.
+fn touched(report: Option<&Report>, started: SystemTime) -> HashSet<PathBuf> {
+    let Some(report) = report else {
+        return HashSet::new();
+    };
+    let done = SystemTime::now();
+    report
+        .entries
+        .iter()
+        .filter(|entry| {
+            matches!(
+                entry.action,
+                Action::Signed | Action::Failed | Action::Skipped(Skip::AlreadySigned)
+            )
+        })
+        .map(|entry| entry.path.clone())
+        .filter(|path| {
+            path.metadata()
+                .and_then(|meta| meta.modified())
+                .is_ok_and(|modified| modified >= started && modified <= done)
+        })
+        .collect()
+}
+
+/// The inotify watches on the directories under the roots.
+struct Inotify {
+    fd: OwnedFd,
+    /// Directory of each watch descriptor.
+    dirs: HashMap<i32, PathBuf>,
+    roots: Vec<PathBuf>,
+    recursive: bool,
+    /// Directories moved away, by the cookie of the move, until they turn up again under
+    /// another name or it is clear they left the roots.
+    moved: HashMap<u32, PathBuf>,
+    /// Readable when a request came in.
+    woken: UnixDatagram,
+}
+
+/// What happens to a directory that may be a change: a file written and closed or moved in, a
+/// directory made, moved in or moved away.
+const MASK: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_CREATE;
+
+/// How long nothing may change for a burst of changes to be over, a package manager unpacking
+/// files one after the other say.
+const QUIET: Duration = Duration::from_millis(500);
+
+/// How long a burst of changes is waited out at most, a pass starts then whatever goes on.
+const QUIET_AT_MOST: Duration = Duration::from_secs(10);
+
+impl Inotify {
+    fn new(roots: &[PathBuf], recursive: bool, woken: UnixDatagram) -> io::Result<Self> {
+        // SAFETY: no pointers involved
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut inotify = Self {
+            // SAFETY: it was just opened and nothing else owns it
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            dirs: HashMap::new(),
+            roots: roots.to_vec(),
+            recursive,
+            moved: HashMap::new(),
+            woken,
+        };
+        inotify.watch_roots();
+        Ok(inotify)
+    }
+
+    /// Watch the directory roots, and the directory of file roots. Directories already watched
+    /// keep their watch.
+    fn watch_roots(&mut self) {
+        for root in self.roots.clone() {
+            if root.is_dir() {
+                self.add(&root);
+            } else if let Some(parent) = root.parent() {
+                self.add_dir(if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                });
+            }
+        }
+    }
+
+    /// Watch `dir`, and the directories under it if recursive.
+    fn add(&mut self, dir: &Path) {
+        self.add_dir(dir);
+        if !self.recursive {
+            return;
+        }
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            // symlinks aren't followed, as in a run
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                self.add(&entry.path());
+            }
+        }
+    }
+
+    /// Watch `dir` alone. One that can't be watched is said on stderr and left out.
+    fn add_dir(&mut self, dir: &Path) {
+        let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+            return;
+        };
+        // SAFETY: path is NUL terminated and outlives the call
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), MASK) };
+        if wd < 0 {
+            let e = io::Error::last_os_error();
+            eprintln!("can't watch {}: {e}", escape::path(dir));
+            return;
+        }
+        self.dirs.insert(wd, dir.to_path_buf());
+    }
+
+    /// Wait up to `timeout` for something to change, then until nothing has for [`QUIET`], and
+    /// return everything that did. With the roots themselves if changes were lost, everything
+    /// under them may have changed then.
+    fn changes(&mut self, timeout: Duration) -> io::Result<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+        if !self.wait(timeout)? {
+            return Ok(changed);
+        }
+        let burst = Instant::now();
+        loop {
+            self.read(&mut changed)?;
+            if burst.elapsed() >= QUIET_AT_MOST || !self.wait(QUIET)? {
+                break;
+            }
+        }
+        self.moved_out();
+
+        // gone again by now, a temporary file renamed into place say, is nothing to sign
+        changed.retain(|path| path.symlink_metadata().is_ok());
+        changed.sort();
+        changed.dedup();
+        Ok(changed)
+    }
+
+    /// Wait up to `timeout` for events, whether there are any. A request coming in ends the
+    /// wait early.
+    fn wait(&self, timeout: Duration) -> io::Result<bool> {
+        let mut poll = [self.fd.as_raw_fd(), self.woken.as_raw_fd()].map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        let ms = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        // SAFETY: two pollfds, which outlive the call
+        if unsafe { libc::poll(poll.as_mut_ptr(), 2, ms) } < 0 {
+            let e = io::Error::last_os_error();
+            // a signal, likely the one cancelling
+            return if e.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(e)
+            };
+        }
+        if poll[1].revents & libc::POLLIN != 0 {
+            let mut buf = [0; 16];
+            while self.woken.recv(&mut buf).is_ok() {}
+        }
+        Ok(poll[0].revents & libc::POLLIN != 0)
+    }
+
+    /// Read the events there are, adding the paths that changed to `changed`.
+    fn read(&mut self, changed: &mut Vec<PathBuf>) -> io::Result<()> {
+        let mut buf = vec![0_u8; 64 * 1024];
+        loop {
+            // SAFETY: buf is writable for its length
+            let len =
+                unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            let Ok(len) = usize::try_from(len) else {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted {
+                    return Ok(());
+                }
+                return Err(e);
+            };
+            for (wd, mask, cookie, name) in events(&buf[..len]) {
+                self.event(wd, mask, cookie, name, changed);
+            }
+        }
+    }
+
+    fn event(&mut self, wd: i32, mask: u32, cookie: u32, name: &[u8], changed: &mut Vec<PathBuf>) {
+        if mask & libc::IN_Q_OVERFLOW != 0 {
+            // events were dropped, what they were about is anyone's guess
+            eprintln!("missed changes, rescanning");
+            self.watch_roots();
+            changed.extend(self.roots.iter().cloned());
+            return;
+        }
+        if mask & libc::IN_IGNORED != 0 {
+            self.dirs.remove(&wd);
+            return;
+        }
+        let Some(dir) = self.dirs.get(&wd) else {
+            return;
+        };
+        let path = dir.join(std::ffi::OsStr::from_bytes(name));
+
+        if mask & libc::IN_ISDIR == 0 {
+            if mask & (libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO) != 0 {
+                changed.push(path);
+            }
+        } else if mask & libc::IN_MOVED_FROM != 0 {
+            self.moved.insert(cookie, path);
+        } else if mask & libc::IN_MOVED_TO != 0 {
+            // from elsewhere under the roots its watches come along, only their names change
+            match self.moved.remove(&cookie) {
+                Some(from) => self.renamed(&from, &path),
+                None if self.recursive => self.add(&path),
+                None => {}
+            }
+            changed.push(path);
+        } else if mask & libc::IN_CREATE != 0 {
+            if self.recursive {
+                self.add(&path);
+            }
+            changed.push(path);
+        }
+    }
+
+    /// Directory `from` and everything under it is at `to` now.
+    fn renamed(&mut self, from: &Path, to: &Path) {
+        for dir in self.dirs.values_mut() {
+            if let Ok(rest) = dir.strip_prefix(from) {
+                *dir = to.join(rest);
+            }
+        }
+    }
+
+    /// The directories moved away that didn't turn up under the roots again left them, stop
+    /// watching them.
+    fn moved_out(&mut self) {
+        for (_, from) in self.moved.drain() {
+            for (&wd, dir) in &self.dirs {
+                if dir.starts_with(&from) {
+                    // SAFETY: no pointers involved
+                    unsafe { libc::inotify_rm_watch(self.fd.as_raw_fd(), wd) };
+                }
+            }
+            // IN_IGNORED follows, but they may be moved back in before
+            self.dirs.retain(|_, dir| !dir.starts_with(&from));
+        }
+    }
+}
+
+/// The watch descriptor, mask, cookie and name of each `inotify_event` in `buf`.
+fn events(mut buf: &[u8]) -> impl Iterator<Item = (i32, u32, u32, &[u8])> {
+    // wd, mask, cookie, len, then len bytes of NUL padded name
+    const HEADER: usize = 16;
+
+    std::iter::from_fn(move || {
+        let header = buf.get(..HEADER)?;
+        let field = |at: usize| [header[at], header[at + 1], header[at + 2], header[at + 3]];
+        let wd = i32::from_ne_bytes(field(0));
+        let mask = u32::from_ne_bytes(field(4));
+        let cookie = u32::from_ne_bytes(field(8));
+        let len = u32::from_ne_bytes(field(12)) as usize;
+        let name = buf.get(HEADER..HEADER + len)?;
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(len)];
+        buf = &buf[HEADER + len..];
+        Some((wd, mask, cookie, name))
+    })
+}
+
+/// How the watcher is doing, for `--health`.
+struct Health {
+    path: Option<PathBuf>,
+    status: &'static str,
+    passes: usize,
+    last_pass: Option<u64>,
+    last_result: Value,
+}
+
+impl Health {
+    fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            status: "starting",
+            passes: 0,
+            last_pass: None,
+            last_result: Value::Null,
+        }
+    }
+
+    /// Take down how a pass went.
+    fn finished(&mut self, result: &Result<Report, Box<dyn std::error::Error>>) {
+        self.passes += 1;
+        self.last_pass = Some(now());
+        self.last_result = match result {
+            Ok(report) => json!({
+                "files": report.files,
+                "failures": report.failures,
+                "unchanged": report.unchanged,
+                "cancelled": report.cancelled,
+                "ok": report.succeeded(),
+            }),
+            Err(e) => json!({ "error": e.to_string(), "ok": false }),
+        };
+    }
+
+    /// The last pass in a few words, for systemd's `STATUS=`.
+    fn summary(&self) -> String {
+        match (&self.last_result["files"], &self.last_result["failures"]) {
+            (Value::Number(files), Value::Number(failures)) => {
+                format!("last pass: {files} files, {failures} failed")
+            }
+            _ => "last pass failed".to_string(),
+        }
+    }
+
+    fn json(&self) -> Value {
+        json!({
+            "pid": std::process::id(),
+            "status": self.status,
+            "updated": now(),
+            "passes": self.passes,
+            "last_pass": self.last_pass,
+            "last_result": self.last_result,
+        })
+    }
+
+    /// Rewrite the file, in one go so nothing reading it sees half of it.
+    fn write(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = self.json();
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, format!("{json}\n"))
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", escape::path(path))))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Tell systemd `state`, as `sd_notify(3)` does, if it is listening.
+fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let Ok(sender) = UnixDatagram::unbound() else {
+        return;
+    };
+    let socket = socket.as_bytes();
+    // a leading @ is an abstract socket, a leading NUL on the wire
+    let addr = match socket.strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(std::ffi::OsStr::from_bytes(socket)),
+    };
+    if let Ok(addr) = addr {
+        let _ = sender.send_to_addr(state.as_bytes(), &addr);
+    }
+}
+
+/// How often to ping systemd's watchdog, half of `WatchdogSec=` as `sd_watchdog_enabled(3)`
+/// advises, `None` if it isn't on for this process.
+fn watchdog() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Pings the watchdog as the files of a pass are done with, at most once every `every`.
+struct Watchdog {
+    every: Duration,
+    last: Mutex<Instant>,
+}
+
+impl Watchdog {
+    fn new(every: Duration) -> Self {
+        Self {
+            every,
+            last: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn ping(&self) {
+        let Ok(mut last) = self.last.lock() else {
+            return;
+        };
+        if last.elapsed() >= self.every {
+            notify("WATCHDOG=1");
+            *last = Instant::now();
+        }
+    }
+}
+
+impl Observer for Watchdog {
+    fn on_discovered(&self, _: &Path) {
+        self.ping();
+    }
+
+    fn on_signed(&self, _: &Entry) {
+        self.ping();
+    }
+
+    fn on_failed(&self, _: &Entry) {
+        self.ping();
+    }
+
+    fn on_skipped(&self, _: &Path, _: Skip) {
+        self.ping();
+    }
+}
@@ -0,0 +1,100 @@
+//! Extended attributes of files signed into a copy and renamed over the original, which would
+//! otherwise leave a new file behind without them. The `SELinux` label is one, `security.selinux`,
+//! and a boot file left with the label of wherever the copy was made gets denied under an
+//! enforcing policy.
+//!
+//! Only the `security.` and `user.` namespaces are copied. `trusted.` needs `CAP_SYS_ADMIN`
+//! and `system.` holds ACLs, which describe the directory as much as the file.
+
+use std::{io, path::Path};
+
+/// Namespaces copied over.
+#[cfg(target_os = "linux")]
+const NAMESPACES: [&[u8]; 2] = [b"security.", b"user."];
+
+/// Copy the extended attributes of `from` onto `to`. A filesystem without them, FAT without
+/// a `context=` mount say, isn't an error.
+///
+/// # Errors
+///
+/// Fails if an attribute can't be read or set, naming it.
+#[cfg(target_os = "linux")]
+pub(crate) fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let cstr = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let (from, to) = (cstr(from)?, cstr(to)?);
+
+    let names = match read(|buf, len| {
+        // SAFETY: the path is NUL terminated and buf holds len bytes
+        unsafe { libc::listxattr(from.as_ptr(), buf, len) }
+    }) {
+        Err(e) if unsupported(&e) => return Ok(()),
+        names => names?,
+    };
+
+    for name in names.split(|&b| b == 0) {
+        if !NAMESPACES.iter().any(|ns| name.starts_with(ns)) {
+            continue;
+        }
+        let shown = String::from_utf8_lossy(name).into_owned();
+        let failed = |e: io::Error| io::Error::new(e.kind(), format!("{shown}: {e}"));
+        let name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let value = read(|buf, len| {
+            // SAFETY: as above, and the name is NUL terminated too
+            unsafe { libc::getxattr(from.as_ptr(), name.as_ptr(), buf.cast(), len) }
+        })
+        .map_err(failed)?;
+        // SAFETY: paths and name are NUL terminated and value holds its length in bytes
+        let res = unsafe {
+            libc::setxattr(
+                to.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if res != 0 {
+            let e = io::Error::last_os_error();
+            // nowhere to put it, the filesystem labels every file itself
+            if !unsupported(&e) {
+                return Err(failed(e));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn copy(_: &Path, _: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Read what `call` fills in, asking it for the size first. It can grow in between.
+#[cfg(target_os = "linux")]
+fn read(call: impl Fn(*mut libc::c_char, usize) -> libc::ssize_t) -> io::Result<Vec<u8>> {
+    loop {
+        let len = usize::try_from(call(std::ptr::null_mut(), 0))
+            .map_err(|_| io::Error::last_os_error())?;
+        let mut buf = vec![0u8; len];
+        match usize::try_from(call(buf.as_mut_ptr().cast(), len)) {
+            Ok(read) => {
+                buf.truncate(read);
+                return Ok(buf);
+            }
+            Err(_) if io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE) => {}
+            Err(_) => return Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn unsupported(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EOPNOTSUPP)
+}
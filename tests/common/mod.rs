@@ -0,0 +1,39 @@
+//! Shared by the integration tests.
+
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Once,
+};
+
+/// A fresh directory to work in, unique to `name` and this process.
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sbsevery-test-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Put a stand-in for sbsign first on `PATH`, it appends `SIG` to the file it is given in
+/// place.
+pub fn fake_sbsign() {
+    static ONCE: Once = Once::new();
+
+    ONCE.call_once(|| {
+        let bin = scratch("bin");
+        let signer = bin.join("sbsign");
+        fs::write(&signer, "#!/bin/sh\nprintf SIG >> \"$6\"\n").unwrap();
+        fs::set_permissions(&signer, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let mut paths = vec![bin];
+        paths.extend(std::env::split_paths(&path));
+        std::env::set_var("PATH", std::env::join_paths(paths).unwrap());
+    });
+}
+
+/// Whether the fake sbsign signed `file`.
+pub fn signed(file: &Path) -> bool {
+    fs::read(file).unwrap().ends_with(b"SIG")
+}
@@ -0,0 +1,93 @@
+//! Trees nested thousands of directories deep, down to and past the longest path the system
+//! allows.
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    ffi::CString,
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use sbsevery::{
+    pe::{self, Machine},
+    Options,
+};
+
+fn options(root: PathBuf) -> Options {
+    Options {
+        key: PathBuf::from("DB.key"),
+        cert: PathBuf::from("DB.crt"),
+        roots: vec![root],
+        ..Options::default()
+    }
+}
+
+/// Nest `depth` directories called `name` under `root` and put an image at the bottom, going
+/// through descriptors so it works however long the path gets.
+fn nest(root: &Path, name: &str, depth: usize) {
+    let name = CString::new(name).unwrap();
+    let file = CString::new("deep.efi").unwrap();
+    let image = pe::dummy(Machine::X64, 4096);
+
+    // SAFETY: only descriptors opened here are used and closed
+    unsafe {
+        let root = CString::new(root.as_os_str().as_bytes()).unwrap();
+        let mut fd = libc::open(root.as_ptr(), libc::O_DIRECTORY | libc::O_RDONLY);
+        assert!(fd >= 0);
+
+        for _ in 0..depth {
+            assert_eq!(libc::mkdirat(fd, name.as_ptr(), 0o755), 0);
+            let next = libc::openat(fd, name.as_ptr(), libc::O_DIRECTORY | libc::O_RDONLY);
+            assert!(next >= 0);
+            libc::close(fd);
+            fd = next;
+        }
+
+        let out = libc::openat(fd, file.as_ptr(), libc::O_CREAT | libc::O_WRONLY, 0o644);
+        assert!(out >= 0);
+        let written = libc::write(out, image.as_ptr().cast(), image.len());
+        assert_eq!(usize::try_from(written).unwrap(), image.len());
+        libc::close(out);
+        libc::close(fd);
+    }
+}
+
+#[test]
+fn signs_thousands_of_levels_deep() {
+    common::fake_sbsign();
+    let dir = common::scratch("deep");
+
+    // 1500 levels, about 3000 bytes of path, still under PATH_MAX
+    nest(&dir, "d", 1500);
+    let report = sbsevery::run(&options(dir.clone())).unwrap();
+
+    assert_eq!((report.files, report.failures), (1, 0));
+    assert!(report.errors.is_empty());
+    let file = (0..1500).fold(dir.clone(), |path, _| path.join("d"));
+    assert!(common::signed(&file.join("deep.efi")));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reports_paths_past_path_max() {
+    common::fake_sbsign();
+    let dir = common::scratch("too-long");
+    fs::write(dir.join("top.efi"), pe::dummy(Machine::X64, 4096)).unwrap();
+
+    // 2000 levels of 3 bytes each is well past PATH_MAX
+    nest(&dir, "dd", 2000);
+    let report = sbsevery::run(&options(dir.clone())).unwrap();
+
+    assert_eq!((report.files, report.failures), (1, 0));
+    assert!(common::signed(&dir.join("top.efi")));
+    assert_eq!(report.errors.len(), 1);
+    let (path, e) = &report.errors[0];
+    assert!(path.as_os_str().len() >= 4000, "{e}");
+    assert!(path.starts_with(&dir));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,148 @@
+//! Names that aren't UTF-8, or have control or invisible characters in them, have to reach the
+//! signer intact and show up in reports escaped rather than mangled, one line each.
+#![cfg(unix)]
+
+mod common;
+
+use std::{ffi::OsStr, fs, os::unix::ffi::OsStrExt};
+
+use sbsevery::{
+    escape,
+    pe::{self, Machine},
+    Options,
+};
+
+#[test]
+fn escapes_invalid_utf8() {
+    let name = OsStr::from_bytes(b"/efi/\xffboot\xc3.efi");
+    assert_eq!(escape::path(name).to_string(), "/efi/\\xffboot\\xc3.efi");
+}
+
+#[test]
+fn escapes_control_characters() {
+    let name = OsStr::new("a\tb\nc\r\x1b[31m\\x41\u{85}");
+    assert_eq!(
+        escape::path(name).to_string(),
+        "a\\tb\\nc\\r\\u{1b}[31m\\\\x41\\u{85}"
+    );
+}
+
+#[test]
+fn leaves_ordinary_paths_alone() {
+    let name = OsStr::new("/efi/EFI/Linux/linux-6.1 ünïcode.efi");
+    assert_eq!(escape::path(name).to_string(), name.to_str().unwrap());
+}
+
+#[test]
+fn unescape_round_trips() {
+    let names: &[&[u8]] = &[
+        b"plain",
+        b"\xff\xfe",
+        b"back\\slash\\x41",
+        b"new\nline\ttab\x7f",
+        "caf\u{e9}\u{9b}".as_bytes(),
+        b"half \xe2\x82 of a euro",
+    ];
+
+    for name in names {
+        let escaped = escape::path(OsStr::from_bytes(name)).to_string();
+        let back = escape::unescape(&escaped).unwrap();
+        assert_eq!(back.as_bytes(), *name, "{escaped}");
+    }
+}
+
+#[test]
+fn signs_non_utf8_names() {
+    common::fake_sbsign();
+    let dir = common::scratch("non-utf8");
+
+    let esp = dir.join("esp");
+    fs::create_dir(&esp).unwrap();
+    let file = esp.join(OsStr::from_bytes(b"boot\xff\n.efi"));
+    fs::write(&file, pe::dummy(Machine::X64, 4096)).unwrap();
+
+    let options = Options {
+        key: dir.join("DB.key"),
+        cert: dir.join("DB.crt"),
+        roots: vec![esp],
+        ..Options::default()
+    };
+    let report = sbsevery::run(&options).unwrap();
+
+    assert_eq!((report.files, report.failures), (1, 0));
+    assert!(common::signed(&file));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn escapes_invisible_characters() {
+    // reads as "exe.efi" in a terminal that honours the override
+    let name = OsStr::new("a\u{202e}ife.exe\u{200b}\u{2028}\u{feff}");
+    assert_eq!(
+        escape::path(name).to_string(),
+        "a\\u{202e}ife.exe\\u{200b}\\u{2028}\\u{feff}"
+    );
+    let back = escape::unescape(&escape::path(name).to_string()).unwrap();
+    assert_eq!(back, name);
+}
+
+#[test]
+fn escapes_text_but_not_backslashes() {
+    let message = "can't sign /efi/a\\x41.efi:\nsbsign: bad\u{1b}[2J\u{2066}";
+    assert_eq!(
+        escape::text(message).to_string(),
+        "can't sign /efi/a\\x41.efi:\\nsbsign: bad\\u{1b}[2J\\u{2066}"
+    );
+}
+
+#[test]
+fn widths_by_columns() {
+    assert_eq!(escape::width("linux.efi"), 9);
+    assert_eq!(escape::width("日本語.efi"), 10);
+    assert_eq!(escape::width("e\u{301}.efi"), 5);
+    assert_eq!(escape::width("🔑"), 2);
+    assert_eq!(escape::pad("日本", 6), "日本  ");
+    assert_eq!(escape::pad("toolong", 4), "toolong");
+}
+
+#[test]
+fn reports_adversarial_names_on_one_line() {
+    common::fake_sbsign();
+    let dir = common::scratch("adversarial");
+
+    let esp = dir.join("esp");
+    fs::create_dir(&esp).unwrap();
+    let names = [
+        "new\nline.efi",
+        "over\u{202e}ide.efi",
+        "sep\u{2029}arator.efi",
+        "wide 日本語.efi",
+    ];
+    for name in names {
+        fs::write(esp.join(name), pe::dummy(Machine::X64, 4096)).unwrap();
+    }
+
+    let options = Options {
+        key: dir.join("DB.key"),
+        cert: dir.join("DB.crt"),
+        roots: vec![esp],
+        ..Options::default()
+    };
+    let report = sbsevery::run(&options).unwrap();
+    assert_eq!((report.files, report.failures), (4, 0));
+
+    let csv = sbsevery::report::csv(&report);
+    assert_eq!(csv.lines().count(), 1 + names.len(), "{csv}");
+    let json = sbsevery::report::json(&report).to_string();
+    for escaped in [
+        "new\\\\nline",
+        "over\\\\u{202e}ide",
+        "sep\\\\u{2029}arator",
+        "wide 日本語",
+    ] {
+        assert!(json.contains(escaped), "{escaped} in {json}");
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
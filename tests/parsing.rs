@@ -0,0 +1,242 @@
+//! The parsers that see whatever is on an ESP, fed broken, truncated and made up input. None of
+//! it may panic or take long, and what can't be made sense of comes back as nothing.
+#![cfg(unix)]
+
+use std::{fs, path::Path};
+
+use sbsevery::{
+    cmdline, dbx, diagnose, glob,
+    pe::{self, Layout, Machine},
+    sbat,
+};
+
+fn fixture(name: &str) -> Vec<u8> {
+    fs::read(Path::new("tests/fixtures").join(name)).unwrap()
+}
+
+/// Everything that parses an image, on `data`.
+fn parse_all(data: &[u8]) {
+    let _ = pe::is_pe(data);
+    let _ = pe::machine_of(data);
+    let _ = sbat::entries(data);
+    let _ = cmdline::embedded(data);
+    let _ = diagnose::validate(data);
+
+    if let Some(layout) = Layout::parse(data) {
+        for range in layout.sections.iter().chain(&layout.cert_table) {
+            assert!(range.end <= data.len(), "{range:?} past {}", data.len());
+        }
+        assert!(layout.headers.end <= data.len());
+        for pkcs7 in layout.pkcs7(data) {
+            assert!(pkcs7.len() <= data.len());
+        }
+        let _ = layout.section(data, b".text");
+        let _ = layout.compute_checksum(data);
+        let _ = layout.problems(data);
+        let _ = layout.authenticode(data);
+    }
+}
+
+/// Deterministic bytes, the same on every run.
+fn noise(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state.to_le_bytes()[0]
+        })
+        .collect()
+}
+
+#[test]
+fn truncated_images() {
+    for name in ["x64.efi", "ia32.efi", "signed.efi", "corrupted.efi"] {
+        let data = fixture(name);
+        for len in 0..data.len() {
+            parse_all(&data[..len]);
+        }
+    }
+
+    // cut anywhere in the headers, the x64 image isn't one any more
+    let x64 = fixture("x64.efi");
+    for len in 0..0x200 {
+        assert!(Layout::parse(&x64[..len]).is_none(), "{len}");
+    }
+    assert!(!pe::is_pe(&x64[..0x43]));
+    assert!(pe::is_pe(&x64[..0x44]));
+    assert_eq!(pe::machine_of(&x64[..0x45]), None);
+    assert_eq!(pe::machine_of(&x64[..0x46]), Some(Machine::X64.value()));
+}
+
+#[test]
+fn mutated_headers() {
+    for name in ["x64.efi", "ia32.efi", "signed.efi"] {
+        let original = fixture(name);
+        // every byte of the headers, to each extreme
+        for at in 0..0x200 {
+            for value in [0x00, 0x7f, 0x80, 0xff] {
+                let mut data = original.clone();
+                data[at] = value;
+                parse_all(&data);
+            }
+        }
+    }
+}
+
+#[test]
+fn hostile_offsets() {
+    let x64 = fixture("x64.efi");
+    let signed = fixture("signed.efi");
+    // e_lfanew, section count, optional header size, then of the one section its size and
+    // offset, then the size of and offset to the certificate table
+    let fields: &[(usize, usize)] = &[
+        (0x3c, 4),
+        (0x46, 2),
+        (0x54, 2),
+        (0x148 + 16, 4),
+        (0x148 + 20, 4),
+        (0xe8, 4),
+        (0xec, 4),
+    ];
+
+    for original in [&x64, &signed] {
+        for &(at, len) in fields {
+            for value in [
+                u32::MAX,
+                u32::MAX - 3,
+                0x7fff_ffff,
+                0x8000_0000,
+                0xffff,
+                u32::try_from(original.len()).unwrap(),
+                u32::try_from(original.len()).unwrap() + 1,
+                1,
+                0,
+            ] {
+                let mut data = original.clone();
+                data[at..at + len].copy_from_slice(&value.to_le_bytes()[..len]);
+                parse_all(&data);
+            }
+        }
+    }
+
+    // a certificate table whose first entry claims more than there is
+    let layout = Layout::parse(&signed).unwrap();
+    let table = layout.cert_table.clone().unwrap();
+    for len in [u32::MAX, u32::MAX - 7, 0, 7] {
+        let mut data = signed.clone();
+        data[table.start..table.start + 4].copy_from_slice(&len.to_le_bytes());
+        let layout = Layout::parse(&data).unwrap();
+        assert!(layout.pkcs7(&data).len() <= 1, "{len}");
+    }
+}
+
+#[test]
+fn random_bytes() {
+    for seed in 0..500 {
+        let mut data = noise(seed, 0x400);
+        parse_all(&data);
+
+        // starting like an image gets further in
+        data[..2].copy_from_slice(b"MZ");
+        data[0x3c..0x40].copy_from_slice(&0x40_u32.to_le_bytes());
+        data[0x40..0x44].copy_from_slice(b"PE\0\0");
+        parse_all(&data);
+        data[0x58..0x5a].copy_from_slice(&0x020b_u16.to_le_bytes());
+        parse_all(&data);
+    }
+}
+
+#[test]
+fn file_and_byte_sniffing_agree() {
+    let dir = std::env::temp_dir().join(format!("sbsevery-test-parsing-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let x64 = fixture("x64.efi");
+    let file = dir.join("image.efi");
+    let mut inputs: Vec<Vec<u8>> = (0..0x50).map(|len| x64[..len].to_vec()).collect();
+    inputs.extend([fixture("corrupted.efi"), fixture("aa64.efi"), noise(7, 64)]);
+    let mut far = x64.clone();
+    far[0x3c..0x40].copy_from_slice(&u32::MAX.to_le_bytes());
+    inputs.push(far);
+
+    for data in inputs {
+        fs::write(&file, &data).unwrap();
+        assert_eq!(
+            pe::sniff(&file).unwrap(),
+            pe::is_pe(&data),
+            "{}",
+            data.len()
+        );
+        assert_eq!(pe::machine(&file).unwrap(), pe::machine_of(&data));
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn signature_lists() {
+    // one list holding one SHA-256 entry: type, sizes, no header, owner and hash
+    let mut list = dbx::CERT_SHA256.to_vec();
+    list.extend(76_u32.to_le_bytes());
+    list.extend(0_u32.to_le_bytes());
+    list.extend(48_u32.to_le_bytes());
+    list.extend([0x11; 16]);
+    list.extend([0x22; 32]);
+    assert_eq!(dbx::parse(&list, &dbx::CERT_SHA256).unwrap().len(), 1);
+    assert_eq!(dbx::parse(&list, &dbx::CERT_X509).unwrap().len(), 0);
+
+    for len in 0..list.len() {
+        let _ = dbx::parse(&list[..len], &dbx::CERT_SHA256);
+    }
+    for (at, value) in [(16, u32::MAX), (20, u32::MAX), (20, u32::MAX - 27), (24, 0)] {
+        let mut data = list.clone();
+        data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        assert_eq!(dbx::parse(&data, &dbx::CERT_SHA256), None, "{at} {value}");
+    }
+    for seed in 0..200 {
+        let _ = dbx::parse(&noise(seed, 256), &dbx::CERT_SHA256);
+    }
+}
+
+#[test]
+fn glob_on_hostile_input() {
+    // every ** a choice of where to resume, this used to take exponential time
+    let deep: Vec<String> = (0..300).map(|i| format!("d{i}")).collect();
+    let deep = deep.join("/");
+    let pattern = format!("{}nothing", "**/d*/".repeat(12));
+    assert!(!glob::matches_file(&pattern, Path::new(&deep), &[], false));
+    assert!(glob::matches_file(
+        "**/d1/**/d299",
+        Path::new(&deep),
+        &[],
+        false
+    ));
+    assert!(!glob::matches(&"*a".repeat(100), &"a".repeat(99)));
+
+    for pattern in [
+        "[", "[!", "[]", "[z-a]", "[a-", "[]-]", "[^]]", "\\", "[[]", "*[",
+    ] {
+        for text in ["", "a", "[", "]", "-", "z", "\u{10ffff}"] {
+            let _ = glob::matches(pattern, text);
+        }
+    }
+    assert!(!glob::matches("[z-a]", "m"));
+    assert!(glob::matches("[", "["));
+
+    let alphabet = b"*?[]!^-/a.";
+    for seed in 0..300 {
+        let bytes = noise(seed, 24);
+        let pattern: String = bytes[..12]
+            .iter()
+            .map(|b| char::from(alphabet[usize::from(*b) % alphabet.len()]))
+            .collect();
+        let text: String = bytes[12..]
+            .iter()
+            .map(|b| char::from(alphabet[usize::from(*b) % alphabet.len()]))
+            .collect();
+        let _ = glob::matches(&pattern, &text);
+        let _ = glob::matches_file(&pattern, Path::new(&text), &[], true);
+    }
+}
@@ -0,0 +1,251 @@
+//! Whole runs over a tree of the images in `tests/fixtures`, checking what the report says
+//! became of each.
+//!
+//! The fixtures are tiny on purpose, a kilobyte or so each:
+//!
+//! - `x64.efi`, `ia32.efi` and `aa64.efi` are what [`pe::dummy`] makes for each machine
+//! - `signed.efi` is the x64 one with a certificate table holding a PKCS#7 signature by a
+//!   throwaway `O=sbsevery, CN=sbsevery test fixture` cert
+//! - `corrupted.efi` is the x64 one cut off in the middle of its optional header
+#![cfg(unix)]
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use sbsevery::{
+    mock::MockSigner,
+    pe::{self, Machine},
+    report::Action,
+    usage, Backend, Options, Report, Skip,
+};
+
+const FIXTURES: &[&str] = &[
+    "aa64.efi",
+    "corrupted.efi",
+    "ia32.efi",
+    "signed.efi",
+    "x64.efi",
+];
+
+/// A scratch tree holding every fixture, under `EFI/BOOT`, and a file that isn't an image.
+fn tree(name: &str) -> PathBuf {
+    let dir = common::scratch(name);
+    let boot = dir.join("EFI/BOOT");
+    fs::create_dir_all(&boot).unwrap();
+    for fixture in FIXTURES {
+        let from = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(fixture);
+        fs::copy(from, boot.join(fixture)).unwrap();
+    }
+    fs::write(boot.join("notes.txt"), "not an image\n").unwrap();
+    dir
+}
+
+fn options(root: &Path, backend: Backend) -> Options {
+    Options {
+        backend,
+        key: PathBuf::from("DB.key"),
+        cert: PathBuf::from("DB.crt"),
+        roots: vec![root.to_path_buf()],
+        ..Options::default()
+    }
+}
+
+/// What became of the file called `name` in the tree of `report`.
+fn action(report: &Report, root: &Path, name: &str) -> Action {
+    report
+        .entry(&root.join("EFI/BOOT").join(name))
+        .unwrap_or_else(|| panic!("{name} isn't in the report"))
+        .action
+}
+
+#[test]
+fn fixtures_are_what_they_say() {
+    let fixture = |name| fs::read(Path::new("tests/fixtures").join(name)).unwrap();
+
+    for (name, machine) in [
+        ("x64.efi", Machine::X64),
+        ("ia32.efi", Machine::Ia32),
+        ("aa64.efi", Machine::Aa64),
+    ] {
+        let data = fixture(name);
+        assert_eq!(data, pe::dummy(machine, 1024), "{name}");
+        let layout = pe::Layout::parse(&data).unwrap();
+        assert_eq!(layout.machine, machine.value());
+        assert!(layout.pkcs7(&data).is_empty());
+    }
+
+    let signed = fixture("signed.efi");
+    let layout = pe::Layout::parse(&signed).unwrap();
+    assert_eq!(layout.pkcs7(&signed).len(), 1);
+
+    let corrupted = fixture("corrupted.efi");
+    assert!(pe::Layout::parse(&corrupted).is_none());
+}
+
+#[test]
+fn mock_run_reports_every_file() {
+    let dir = tree("mock");
+    let mock = MockSigner::new();
+    let report = sbsevery::run(&options(&dir, Backend::Mock(mock.clone()))).unwrap();
+
+    for name in ["x64.efi", "ia32.efi", "aa64.efi"] {
+        assert_eq!(action(&report, &dir, name), Action::Signed, "{name}");
+    }
+    assert_eq!(
+        action(&report, &dir, "signed.efi"),
+        Action::Skipped(Skip::AlreadySigned)
+    );
+    assert_eq!(action(&report, &dir, "corrupted.efi"), Action::Failed);
+    assert_eq!(
+        action(&report, &dir, "notes.txt"),
+        Action::Skipped(Skip::NotPe)
+    );
+    assert_eq!(report.entries.len(), FIXTURES.len() + 1);
+    assert_eq!((report.files, report.failures), (4, 1));
+    assert!(!report.succeeded());
+
+    let boot = dir.join("EFI/BOOT");
+    assert_eq!(
+        mock.signed(),
+        ["aa64.efi", "ia32.efi", "x64.efi"].map(|name| boot.join(name))
+    );
+    assert_eq!(report.signed().collect::<Vec<_>>(), mock.signed());
+    // nothing was touched
+    for fixture in FIXTURES {
+        let original = Path::new("tests/fixtures").join(fixture);
+        assert_eq!(
+            fs::read(boot.join(fixture)).unwrap(),
+            fs::read(original).unwrap()
+        );
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn filters_apply_to_fixtures() {
+    let dir = tree("filters");
+    let mut options = options(&dir, Backend::Mock(MockSigner::new()));
+    options.arch = vec![Machine::X64];
+    options.skip_signed_by = vec!["CN=sbsevery test*".to_string()];
+    let report = sbsevery::run(&options).unwrap();
+
+    assert_eq!(action(&report, &dir, "x64.efi"), Action::Signed);
+    for name in ["ia32.efi", "aa64.efi"] {
+        assert_eq!(
+            action(&report, &dir, name),
+            Action::Skipped(Skip::Architecture),
+            "{name}"
+        );
+    }
+    assert_eq!(
+        action(&report, &dir, "signed.efi"),
+        Action::Skipped(Skip::SignedBy)
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn native_run_signs_fixtures() {
+    common::fake_sbsign();
+    let dir = tree("native");
+    let mut options = options(&dir, Backend::Sbsign);
+    options.resign = true;
+    let report = sbsevery::run(&options).unwrap();
+
+    let boot = dir.join("EFI/BOOT");
+    // the fake signs whatever it is given, broken or not
+    for fixture in FIXTURES {
+        assert_eq!(action(&report, &dir, fixture), Action::Signed, "{fixture}");
+        assert!(common::signed(&boot.join(fixture)), "{fixture}");
+    }
+    assert_eq!((report.files, report.failures), (5, 0));
+    assert!(report.succeeded());
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["files"], 5);
+    assert_eq!(
+        json["entries"].as_array().unwrap().len(),
+        FIXTURES.len() + 1
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn system_day_limit_holds_whatever_counts_another_config_names() {
+    let dir = tree("usage");
+    let system = usage::Policy {
+        max_per_day: Some(5),
+        counts: Some(dir.join("system-counts")),
+        ..usage::Policy::default()
+    };
+    let given = |name: &str| usage::Policy {
+        counts: Some(dir.join(name)),
+        ..usage::Policy::default()
+    };
+    assert_eq!(system.clone().and(given("fresh")).counts, system.counts);
+    let builtin = usage::Policy {
+        counts: None,
+        ..system.clone()
+    };
+    assert_eq!(builtin.and(given("fresh")).counts, None);
+
+    let mut options = options(&dir, Backend::Mock(MockSigner::new()));
+    // the five images each count, whether signed or not
+    options.usage = system.clone().and(given("first"));
+    let report = sbsevery::run(&options).unwrap();
+    assert_eq!(report.signed().count(), 3);
+
+    // a fresh file every run doesn't start the day over
+    options.usage = system.and(given("second"));
+    let report = sbsevery::run(&options).unwrap();
+    assert_eq!(report.signed().count(), 0);
+    for name in ["x64.efi", "ia32.efi", "aa64.efi"] {
+        assert_eq!(action(&report, &dir, name), Action::Failed, "{name}");
+    }
+    assert!(!dir.join("first").exists() && !dir.join("second").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sign_stream_needs_stream_to_sign_despite_allow() {
+    let dir = tree("stream");
+    let image = fs::read(dir.join("EFI/BOOT/x64.efi")).unwrap();
+    let mock = MockSigner::new();
+    let mut options = options(&dir, Backend::Mock(mock.clone()));
+    options.usage = usage::Policy {
+        allow: vec![dir.join("EFI")],
+        max_per_run: Some(1),
+        ..usage::Policy::default()
+    };
+
+    let mut signed = Vec::new();
+    let e =
+        sbsevery::stream::sign_stream(&options, &mut image.as_slice(), &mut signed).unwrap_err();
+    assert!(e.to_string().contains("doesn't allow sign-stream"), "{e}");
+    assert!(signed.is_empty() && mock.signed().is_empty());
+
+    // a config leaving it out of the two together leaves it out
+    let given = usage::Policy {
+        allow: vec![dir.join("EFI/BOOT")],
+        ..usage::Policy::default()
+    };
+    options.usage.stream = true;
+    assert!(!options.usage.clone().and(given).stream);
+    assert!(usage::Policy::default().and(options.usage.clone()).stream);
+
+    sbsevery::stream::sign_stream(&options, &mut image.as_slice(), &mut signed).unwrap();
+    // the mock signer leaves the image as it was
+    assert_eq!((signed, mock.signed().len()), (image, 1));
+
+    fs::remove_dir_all(&dir).unwrap();
+}